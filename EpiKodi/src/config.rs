@@ -1,3 +1,4 @@
+use crate::constants::{METADATA_CACHE_TTL_HOURS, PLUGIN_CALL_TIMEOUT_SECS};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
@@ -6,6 +7,23 @@ use std::path::PathBuf;
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AppConfig {
     pub media_path: String,
+    // durée de vie des infos des plugins en cache, après quoi elles sont rafraîchies
+    #[serde(default = "default_metadata_ttl")]
+    pub metadata_ttl_hours: u64,
+    // temps maximum d'un appel de plugin, en secondes
+    #[serde(default = "default_plugin_timeout")]
+    pub plugin_timeout_secs: u64,
+    // chaque plugin tourne dans son propre processus : un plantage ne ferme pas NeoKodi
+    #[serde(default)]
+    pub plugins_out_of_process: bool,
+}
+
+fn default_metadata_ttl() -> u64 {
+    METADATA_CACHE_TTL_HOURS
+}
+
+fn default_plugin_timeout() -> u64 {
+    PLUGIN_CALL_TIMEOUT_SECS
 }
 
 impl AppConfig {
@@ -29,6 +47,9 @@ impl AppConfig {
 
         let new_config = AppConfig {
             media_path: default_path,
+            metadata_ttl_hours: METADATA_CACHE_TTL_HOURS,
+            plugin_timeout_secs: PLUGIN_CALL_TIMEOUT_SECS,
+            plugins_out_of_process: false,
         };
         new_config.save(); // On le crée tout de suite !
         new_config
@@ -5,17 +5,61 @@ TODO: check the if there is a correct way to do that and good practices
 
 pub const SOURCE_FILE: &str = "db/sources.json";
 pub const MEDIA_DB_FILE: &str = "db/library.db";
+// images named after the sha256 of their content, served at /artwork/<file name>
+pub const ARTWORK_CACHE_DIR: &str = "db/artwork";
 
 pub const DEBUG: bool = true;
 pub const LOG_FILE: &str = "epikodi.log";
 pub const LOG_FILE_MEDIA_ITEMS: &str = "media_items.log";
 pub const LOG_IN_CONSOLE: bool = false;
 
-pub const AUDIO_EXTS: [&str; 5] = ["mp3", "wav", "flac", "ogg", "mp4"];
-pub const VIDEO_EXTS: [&str; 4] = ["mp4", "mkv", "avi", "mov"];
-pub const IMAGE_EXTS: [&str; 4] = ["jpg", "png", "bmp", "gif"];
+// default extensions looked at by the scan, the content of the file gives its type
+// (a .mp4 with only sound is audio), see scan/classify.rs
+pub const AUDIO_EXTS: [&str; 11] = ["mp3", "wav", "flac", "ogg", "oga", "opus", "m4a", "aac", "wma", "aiff", "mka"];
+pub const VIDEO_EXTS: [&str; 12] = ["mp4", "m4v", "mkv", "webm", "avi", "mov", "ts", "m2ts", "ogv", "wmv", "mpg", "mpeg"];
+pub const IMAGE_EXTS: [&str; 11] = ["jpg", "jpeg", "png", "bmp", "gif", "webp", "heic", "heif", "avif", "tif", "tiff"];
+// subtitle files a plugin may save next to a video, by extension
+pub const SUBTITLE_EXTS: [&str; 5] = ["srt", "ass", "ssa", "vtt", "sub"];
+
+// quiet period before the file watcher applies a burst of changes
+pub const WATCH_DEBOUNCE_MS: u64 = 500;
+
+// background scan: max number of worker threads, and a progress event every N files
+pub const MAX_SCAN_WORKERS: usize = 8;
+pub const SCAN_PROGRESS_EVERY: usize = 25;
+
+// folders skipped by the scan when they contain one of these files
+pub const IGNORE_MARKERS: [&str; 2] = [".nomedia", ".ignore"];
+// exclude patterns of a new source: hidden files and folders, NAS thumbnails, trash and samples
+pub const DEFAULT_SOURCE_EXCLUDES: [&str; 5] = [".*", "@eaDir", "#recycle", "sample", "samples"];
+
+// plugin answers older than this are served from the cache and refreshed in the background
+// (metadata_ttl_hours in config.json)
+pub const METADATA_CACHE_TTL_HOURS: u64 = 24 * 7;
+// an artist no plugin knows is not asked again before this delay
+pub const ARTIST_NOT_FOUND_TTL_HOURS: u64 = 24;
 
 pub const PLUGIN_DIR: &str = "./plugins/";
+// a plugin call longer than this is abandoned (plugin_timeout_secs in config.json)
+pub const PLUGIN_CALL_TIMEOUT_SECS: u64 = 15;
+// a plugin failing this many calls in a row is suspended until enabled again or reloaded
+pub const PLUGIN_MAX_FAILURES: u32 = 3;
+// threads running the plugin lookups of the media thread, the next commands wait in a queue
+pub const PLUGIN_WORKERS: usize = 4;
+// set on the child process running a plugin out of process, holds the path of the plugin
+// (plugins_out_of_process in config.json)
+pub const PLUGIN_HOST_ENV: &str = "EPIKODI_PLUGIN_HOST";
+// settings of each plugin, under the config folder of the user (~/.config on Linux)
+pub const PLUGIN_SETTINGS_DIR: &str = "EpiKodi/plugins";
+// plugins turned off and order of the plugins per capability, in the same config folder
+pub const PLUGIN_REGISTRY_FILE: &str = "EpiKodi/plugin_registry.json";
+// a track is scrobbled once played past half of it or SCROBBLE_AFTER_SECS,
+// when it lasts SCROBBLE_MIN_TRACK_SECS at least (the rules of Last.fm)
+pub const SCROBBLE_AFTER_SECS: f32 = 240.0;
+pub const SCROBBLE_MIN_TRACK_SECS: f32 = 30.0;
+// host events waiting for the plugins, per plugin and before the dispatch: the next ones are
+// dropped when full, a slow plugin never holds the player up
+pub const PLUGIN_EVENT_QUEUE: usize = 64;
 pub const PLUGIN_EXT: &str = if cfg!(target_os = "windows") {
     "dll"
 } else if cfg!(target_os = "macos") {
@@ -23,11 +67,25 @@ pub const PLUGIN_EXT: &str = if cfg!(target_os = "windows") {
 } else {
     "so"
 };
+// folder of the temp dir where the plugin libraries are copied before they are loaded
+pub const PLUGIN_COPY_DIR: &str = "epikodi_plugins";
+// WebAssembly plugins (see plugin/wasm.rs), the same on every system: memory of an instance,
+// fuel of one call (about one instruction per unit), tick of the clock ending a call at its
+// timeout, and limits of their HTTP requests with the fuel each one costs
+pub const WASM_PLUGIN_EXT: &str = "wasm";
+pub const PLUGIN_WASM_MEMORY_MB: usize = 64;
+pub const PLUGIN_WASM_FUEL: u64 = 2_000_000_000;
+pub const PLUGIN_WASM_EPOCH_MS: u64 = 10;
+pub const PLUGIN_HTTP_TIMEOUT_SECS: u64 = 10;
+pub const PLUGIN_HTTP_MAX_BYTES: u64 = 8 * 1024 * 1024;
+pub const PLUGIN_HTTP_FUEL: u64 = 10_000_000;
 
 // Media status constants
 pub const NOT_STARTED: i32 = 0;
 pub const PLAYING: i32 = 1;
 pub const FINISHED: i32 = 2;
+// an episode stopped after this part of its duration counts as watched (end credits)
+pub const EPISODE_WATCHED_RATIO: f64 = 0.9;
 
 
 pub const MUSIC_OUTPUT_DIR: &str = "./media/music";
@@ -2,10 +2,22 @@
 
 */
 
-use rusqlite::{Connection, Result};
-
-use crate::library::media_library::ScannedMedia;
-use crate::media::data::MediaType;
+use rusqlite::{Connection, OptionalExtension, Result};
+
+use super::migrations::{self, MigrationError};
+use crate::library::artwork::{ArtKind, ArtOwner, ArtworkLink, CachedImage};
+use crate::library::media_library::{ScanReport, ScannedMedia};
+use crate::library::metadata_cache::{
+    musicbrainz_id, normalize_key, CacheKind, CachedArtist, CachedMetadata,
+};
+use crate::library::movies::ParsedMovie;
+use crate::library::music::{Album, AlbumTrack, Artist, ParsedTrack};
+use crate::library::nfo::{Nfo, NfoActor, NfoArtwork, NfoFile, NfoKind};
+use crate::library::search::{self, SearchFilters, DEFAULT_SEARCH_LIMIT};
+use crate::library::series::{Episode, ParsedEpisode};
+use crate::media::data::{MediaTags, MediaType};
+use plugin_api::metadata::MetadataResponse;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 
@@ -17,6 +29,39 @@ pub struct MediaRow {
     pub duration: Option<f32>,
     pub media_type: MediaType,
     pub last_position: f32,
+    pub tags: MediaTags,
+}
+
+#[derive(Debug)]
+pub struct SearchHit {
+    pub media_id: i64,
+    pub snippet: String,
+    pub rank: f64,
+}
+
+// id, file_size, mtime, media_type of a media row, used to detect changed files
+type StoredFile = (i64, Option<i64>, Option<i64>, Option<String>);
+
+// What the database knows of a scanned file, trusted by a scan while its size and mtime are
+// the same: its tags are not read again and its type is not sniffed again
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub mtime: i64,
+    pub media_type: MediaType,
+}
+
+impl FileStamp {
+    pub fn matches(&self, size: u64, mtime: i64) -> bool {
+        self.size == size && self.mtime == mtime
+    }
+}
+
+// Which media rows a scan removes when it did not see their file
+enum Removal<'a> {
+    Nothing,                                   // partial scan
+    Missing,                                   // all of them
+    Unchanged(&'a HashMap<String, FileStamp>), // those still as in these stamps
 }
 
 pub struct DB {
@@ -43,74 +88,9 @@ impl DB {
         Ok(())
     }
 
-    //TODO: check if this is a corrct/clean way to do this
-    pub fn init_db(&mut self) -> Result<()> {
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS artist_metadata (
-                id INTEGER PRIMARY KEY,
-                artist_name TEXT UNIQUE NOT NULL,
-                info TEXT,
-                last_updated DATETIME DEFAULT CURRENT_TIMESTAMP
-            )",
-            [],
-        )?;
-        self.conn.execute(
-            "
-                CREATE TABLE IF NOT EXISTS media (
-                    id INTEGER PRIMARY KEY,
-                    path TEXT UNIQUE NOT NULL,
-                    title TEXT,
-                    duration REAL,
-                    media_type TEXT,
-                    status INTEGER DEFAULT 0,
-                    time_stop FLOAT DEFAULT 0.0
-                )
-            ",
-            [],
-        )?;
-        self.conn.execute(
-            "
-                CREATE TABLE IF NOT EXISTS tags (
-                    id INTEGER PRIMARY KEY,
-                    name TEXT UNIQUE NOT NULL
-                );
-            ",
-            [],
-        )?;
-        self.conn.execute(
-            "
-                CREATE TABLE IF NOT EXISTS media_tags (
-                    media_id INTEGER NOT NULL,
-                    tag_id INTEGER NOT NULL,
-                    PRIMARY KEY (media_id, tag_id),
-                    FOREIGN KEY (media_id) REFERENCES media(id) ON DELETE CASCADE,
-                    FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE
-                );
-            ",
-            [],
-        )?;
-        self.conn.execute(
-            "
-                CREATE TABLE IF NOT EXISTS playlists (
-                    id INTEGER PRIMARY KEY,
-                    name TEXT NOT NULL UNIQUE COLLATE NOCASE
-                );
-            ",
-            [],
-        )?;
-        self.conn.execute(
-            "
-                CREATE TABLE IF NOT EXISTS playlist_items (
-                    playlist_id INTEGER NOT NULL,
-                    media_id INTEGER NOT NULL,
-                    position INTEGER DEFAULT 0,
-                    PRIMARY KEY (playlist_id, media_id),
-                    FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE,
-                    FOREIGN KEY (media_id) REFERENCES media(id) ON DELETE CASCADE
-                );
-            ",
-            [],
-        )?;
+    // Creates or upgrades the schema, see migrations.rs
+    pub fn init_db(&mut self) -> Result<(), MigrationError> {
+        migrations::migrate(&mut self.conn)?;
         Ok(())
     }
 
@@ -152,10 +132,15 @@ impl DB {
     }
 
     pub fn get_all_media(&mut self) -> Result<&Vec<MediaRow>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id, path, title, duration, media_type, time_stop FROM media")?;
+        let mut stmt = self.conn.prepare(
+            "
+            SELECT id, path, title, duration, media_type, time_stop,
+                   artist, album, album_artist, track_number, disc_number, year, genre
+            FROM media
+            ",
+        )?;
 
+        self.media_rows.clear();
         let rows = stmt.query_map([], |row| {
             Ok(MediaRow {
                 id: row.get(0)?,
@@ -164,6 +149,15 @@ impl DB {
                 duration: row.get(3)?,
                 media_type: MediaType::from_db(&row.get::<_, String>(4)?).unwrap(),
                 last_position: row.get(5)?,
+                tags: MediaTags {
+                    artist: row.get(6)?,
+                    album: row.get(7)?,
+                    album_artist: row.get(8)?,
+                    track_number: row.get(9)?,
+                    disc_number: row.get(10)?,
+                    year: row.get(11)?,
+                    genre: row.get(12)?,
+                },
             })
         })?;
 
@@ -240,6 +234,288 @@ impl DB {
         Ok(())
     }
 
+    // Size and mtime of every media already scanned, rows without stamps are left out
+    // so their tags are read again by the next scan
+    pub fn get_file_stamps(&self) -> Result<HashMap<String, FileStamp>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, file_size, mtime, media_type FROM media WHERE file_size IS NOT NULL AND mtime IS NOT NULL",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)? as u64,
+                row.get::<_, i64>(2)?,
+                MediaType::from_db(&row.get::<_, String>(3)?),
+            ))
+        })?;
+        let mut stamps = HashMap::new();
+        for row in rows {
+            if let (path, size, mtime, Some(media_type)) = row? {
+                stamps.insert(
+                    path,
+                    FileStamp {
+                        size,
+                        mtime,
+                        media_type,
+                    },
+                );
+            }
+        }
+        Ok(stamps)
+    }
+
+    // Synchronises the media table with a full scan: new files are inserted, files whose
+    // size, mtime or type changed are updated and missing files are removed.
+    // Unchanged files are not written at all.
+    pub fn apply_scan(&mut self, scanned_media: Vec<ScannedMedia>) -> Result<ScanReport> {
+        self.sync_scanned_media(scanned_media, Removal::Missing)
+    }
+
+    // Same as apply_scan for a scan that ran in the background from the stamps `known_files`
+    // (get_file_stamps): a row the watcher added or changed meanwhile is kept even when the
+    // scan did not see its file
+    pub fn apply_scan_since(
+        &mut self,
+        scanned_media: Vec<ScannedMedia>,
+        known_files: &HashMap<String, FileStamp>,
+    ) -> Result<ScanReport> {
+        self.sync_scanned_media(scanned_media, Removal::Unchanged(known_files))
+    }
+
+    // Same as apply_scan for a partial scan (watcher), nothing is removed
+    pub fn upsert_scanned_media(&mut self, scanned_media: Vec<ScannedMedia>) -> Result<ScanReport> {
+        self.sync_scanned_media(scanned_media, Removal::Nothing)
+    }
+
+    fn sync_scanned_media(
+        &mut self,
+        scanned_media: Vec<ScannedMedia>,
+        removal: Removal,
+    ) -> Result<ScanReport> {
+        let mut report = ScanReport::default();
+
+        // the same path can be scanned by several sources, the last one wins
+        let mut unique: Vec<ScannedMedia> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for media in scanned_media {
+            match positions.get(&media.path) {
+                Some(&index) => unique[index] = media,
+                None => {
+                    positions.insert(media.path.clone(), unique.len());
+                    unique.push(media);
+                }
+            }
+        }
+
+        let tx = self.conn.transaction()?;
+        {
+            let mut existing: HashMap<String, StoredFile> = HashMap::new();
+            let mut stmt =
+                tx.prepare("SELECT id, path, file_size, mtime, media_type FROM media")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(1)?,
+                    (row.get(0)?, row.get(2)?, row.get(3)?, row.get(4)?),
+                ))
+            })?;
+            for row in rows {
+                let (path, entry) = row?;
+                existing.insert(path, entry);
+            }
+
+            let mut insert = tx.prepare(
+                "
+                INSERT INTO media (
+                    path, title, duration, media_type, file_size, mtime,
+                    artist, album, album_artist, track_number, disc_number, year, genre
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+                ",
+            )?;
+            // the file was read again: title, duration and tags are replaced
+            let mut update = tx.prepare(
+                "
+                UPDATE media SET
+                    title = ?2,
+                    duration = CASE WHEN ?3 > 0 THEN ?3 ELSE duration END,
+                    media_type = ?4,
+                    file_size = ?5,
+                    mtime = ?6,
+                    artist = ?7,
+                    album = ?8,
+                    album_artist = ?9,
+                    track_number = ?10,
+                    disc_number = ?11,
+                    year = ?12,
+                    genre = ?13
+                WHERE id = ?1
+                ",
+            )?;
+            // the file was not read (image, or tags already known): title and tags are kept
+            let mut update_stamps = tx.prepare(
+                "UPDATE media SET media_type = ?2, file_size = ?3, mtime = ?4 WHERE id = ?1",
+            )?;
+            let mut delete = tx.prepare("DELETE FROM media WHERE id = ?1")?;
+
+            let mut seen = HashSet::new();
+            for media in &unique {
+                let media_type = media.media_type.to_string();
+                let size = media.size as i64;
+
+                let tags = media.tags.clone().unwrap_or_default();
+
+                match existing.get(&media.path) {
+                    None => {
+                        insert.execute((
+                            &media.path,
+                            &media.name,
+                            media.duration,
+                            &media_type,
+                            size,
+                            media.mtime,
+                            &tags.artist,
+                            &tags.album,
+                            &tags.album_artist,
+                            tags.track_number,
+                            tags.disc_number,
+                            tags.year,
+                            &tags.genre,
+                        ))?;
+                        let id = tx.last_insert_rowid();
+                        if media.tags.is_some() {
+                            set_embedded_artwork(&tx, id, media.artwork.as_ref())?;
+                        }
+                        report.added_ids.push(id);
+                    }
+                    Some((id, old_size, old_mtime, old_type)) => {
+                        seen.insert(*id);
+                        if *old_size == Some(size)
+                            && *old_mtime == Some(media.mtime)
+                            && old_type.as_deref() == Some(media_type.as_str())
+                        {
+                            report.unchanged += 1;
+                            continue;
+                        }
+                        if media.tags.is_some() {
+                            update.execute((
+                                id,
+                                &media.name,
+                                media.duration,
+                                &media_type,
+                                size,
+                                media.mtime,
+                                &tags.artist,
+                                &tags.album,
+                                &tags.album_artist,
+                                tags.track_number,
+                                tags.disc_number,
+                                tags.year,
+                                &tags.genre,
+                            ))?;
+                            set_embedded_artwork(&tx, *id, media.artwork.as_ref())?;
+                        } else {
+                            update_stamps.execute((id, &media_type, size, media.mtime))?;
+                        }
+                        report.updated_ids.push(*id);
+                    }
+                }
+            }
+
+            for (path, (id, size, mtime, _)) in &existing {
+                let removable = match removal {
+                    Removal::Nothing => false,
+                    Removal::Missing => true,
+                    // rows without stamps were never scanned since, they cannot be the watcher's
+                    Removal::Unchanged(known) => match (size, mtime) {
+                        (Some(size), Some(mtime)) => known
+                            .get(path)
+                            .is_some_and(|stamp| stamp.matches(*size as u64, *mtime)),
+                        _ => true,
+                    },
+                };
+                if removable && !seen.contains(id) {
+                    delete.execute([id])?;
+                    report.removed_ids.push(*id);
+                }
+            }
+        }
+        tx.commit()?;
+
+        Ok(report)
+    }
+
+    // Removes the media at these paths, or inside them when the path was a folder
+    pub fn remove_media_paths(&mut self, paths: &[String]) -> Result<Vec<i64>> {
+        let mut removed = Vec::new();
+        let tx = self.conn.transaction()?;
+        {
+            let mut select = tx.prepare(
+                "
+                SELECT id FROM media
+                WHERE path = ?1
+                   OR substr(path, 1, length(?1) + 1) IN (?1 || '/', ?1 || '\\')
+                ",
+            )?;
+            let mut delete = tx.prepare("DELETE FROM media WHERE id = ?1")?;
+
+            for path in paths {
+                let ids: Vec<i64> = select
+                    .query_map([path], |row| row.get(0))?
+                    .collect::<Result<_>>()?;
+                for id in ids {
+                    delete.execute([id])?;
+                    removed.push(id);
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    // Renames the media at these paths, or inside them when the path was a folder, keeping
+    // their ids and so their tags, playlists, progress and episodes. Media already at a
+    // destination were overwritten by the move and are removed.
+    // Returns the moved ids and the removed ones.
+    pub fn move_media_paths(&mut self, moves: &[(String, String)]) -> Result<(Vec<i64>, Vec<i64>)> {
+        let mut moved = Vec::new();
+        let mut removed = Vec::new();
+        let tx = self.conn.transaction()?;
+        {
+            let mut select = tx.prepare(
+                "
+                SELECT id, path FROM media
+                WHERE path = ?1
+                   OR substr(path, 1, length(?1) + 1) IN (?1 || '/', ?1 || '\\')
+                ",
+            )?;
+            let mut delete = tx.prepare("DELETE FROM media WHERE id = ?1")?;
+            let mut rename = tx.prepare("UPDATE media SET path = ?2 WHERE id = ?1")?;
+
+            for (from, to) in moves {
+                let overwritten: Vec<i64> = select
+                    .query_map([to], |row| row.get(0))?
+                    .collect::<Result<_>>()?;
+                for id in overwritten {
+                    delete.execute([id])?;
+                    removed.push(id);
+                }
+
+                let rows: Vec<(i64, String)> = select
+                    .query_map([from], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<_>>()?;
+                for (id, path) in rows {
+                    rename.execute(rusqlite::params![
+                        id,
+                        format!("{}{}", to, &path[from.len()..])
+                    ])?;
+                    moved.push(id);
+                }
+            }
+        }
+        tx.commit()?;
+        Ok((moved, removed))
+    }
+
     pub fn cleanup_missing_media(
         &mut self,
         scanned_media: Vec<ScannedMedia>,
@@ -261,6 +537,90 @@ impl DB {
         Ok(())
     }
 
+    //========= SEARCH METHODS========
+
+    // Returns the media matching the query, best matches first
+    pub fn search_media(&self, query: &str, filters: &SearchFilters) -> Result<Vec<SearchHit>> {
+        let words = search::tokenize(query);
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut alternatives = Vec::new();
+        for word in &words {
+            alternatives.push(self.fuzzy_terms(word, search::max_typos(word))?);
+        }
+        let expression = search::match_expression(&words, &alternatives);
+
+        let mut stmt = self.conn.prepare(
+            "
+                SELECT media.id,
+                       snippet(media_search, -1, ?2, ?3, '…', 12),
+                       bm25(media_search, 10.0, 2.0, 5.0, 4.0, 3.0, 1.0) AS score
+                FROM media_search
+                JOIN media ON media.id = media_search.rowid
+                WHERE media_search MATCH ?1
+                  AND (?4 IS NULL OR media.media_type = ?4 COLLATE NOCASE)
+                  AND (?5 IS NULL OR media.id IN (
+                        SELECT media_tags.media_id
+                        FROM media_tags JOIN tags ON tags.id = media_tags.tag_id
+                        WHERE tags.name = ?5
+                  ))
+                ORDER BY score
+                LIMIT ?6
+            ",
+        )?;
+
+        let media_type = filters.media_type.as_ref().map(|t| t.to_string());
+        let limit = filters.limit.unwrap_or(DEFAULT_SEARCH_LIMIT) as i64;
+        let rows = stmt.query_map(
+            (
+                &expression,
+                search::HIGHLIGHT_START,
+                search::HIGHLIGHT_END,
+                &media_type,
+                &filters.tag,
+                limit,
+            ),
+            |row| {
+                Ok(SearchHit {
+                    media_id: row.get(0)?,
+                    snippet: row.get(1)?,
+                    rank: row.get(2)?,
+                })
+            },
+        )?;
+
+        rows.collect()
+    }
+
+    // Indexed words close enough to `word` to be considered a typo of it
+    fn fuzzy_terms(&self, word: &str, max_typos: usize) -> Result<Vec<String>> {
+        if max_typos == 0 {
+            return Ok(Vec::new());
+        }
+
+        let len = word.chars().count();
+        let mut stmt = self.conn.prepare(
+            "
+                SELECT term FROM media_search_vocab
+                WHERE length(term) BETWEEN ?1 AND ?2
+            ",
+        )?;
+        let terms = stmt.query_map(
+            (
+                len.saturating_sub(max_typos) as i64,
+                (len + max_typos) as i64,
+            ),
+            |row| row.get::<_, String>(0),
+        )?;
+
+        Ok(terms
+            .filter_map(|t| t.ok())
+            .filter(|term| search::levenshtein(word, term) <= max_typos)
+            .collect())
+    }
+
     //========= TAGS TABLE METHODS========
 
     pub fn get_or_create_tag(&mut self, name: &str) -> rusqlite::Result<i64> {
@@ -441,84 +801,985 @@ impl DB {
         Ok(rows.filter_map(Result::ok).collect())
     }
 
-    //========= PLAYLIST TABLE METHODS========
+    //========= SERIES TABLE METHODS========
 
-    pub fn save_artist_metadata(&self, name: &str, info: &str) -> Result<()> {
-        self.conn.execute(
-            "INSERT OR REPLACE INTO artist_metadata (artist_name, info, last_updated) VALUES (?1, ?2, CURRENT_TIMESTAMP)",
-            (name, info),
-        )?;
-        Ok(())
+    // Replaces the episodes of the given media with the ones parsed from their paths
+    // (media id, episode), a media missing from `episodes` is no longer an episode.
+    // The other episodes and the ids of the shows and seasons that still have some are kept.
+    pub fn sync_series(
+        &mut self,
+        media_ids: &[i64],
+        episodes: &[(i64, ParsedEpisode)],
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert_show = tx.prepare("INSERT OR IGNORE INTO shows (title) VALUES (?1)")?;
+            let mut select_show = tx.prepare("SELECT id FROM shows WHERE title = ?1")?;
+            let mut insert_season =
+                tx.prepare("INSERT OR IGNORE INTO seasons (show_id, number) VALUES (?1, ?2)")?;
+            let mut select_season =
+                tx.prepare("SELECT id FROM seasons WHERE show_id = ?1 AND number = ?2")?;
+            let mut delete_episode = tx.prepare("DELETE FROM episodes WHERE media_id = ?1")?;
+            let mut insert_episode = tx.prepare(
+                "
+                INSERT INTO episodes (media_id, season_id, number, last_number, absolute, title)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ",
+            )?;
+
+            for media_id in media_ids {
+                delete_episode.execute([media_id])?;
+            }
+            for (media_id, episode) in episodes {
+                insert_show.execute([&episode.show])?;
+                let show_id: i64 = select_show.query_row([&episode.show], |row| row.get(0))?;
+                insert_season.execute((show_id, episode.season))?;
+                let season_id: i64 =
+                    select_season.query_row((show_id, episode.season), |row| row.get(0))?;
+                insert_episode.execute((
+                    media_id,
+                    season_id,
+                    episode.episode,
+                    episode.last_episode,
+                    episode.absolute,
+                    &episode.title,
+                ))?;
+            }
+
+            tx.execute(
+                "DELETE FROM seasons WHERE id NOT IN (SELECT season_id FROM episodes)",
+                [],
+            )?;
+            tx.execute(
+                "DELETE FROM shows WHERE id NOT IN (SELECT show_id FROM seasons)",
+                [],
+            )?;
+        }
+        tx.commit()
     }
 
-    pub fn get_all_artist_metadata(&self) -> Result<Vec<String>> {
+    pub fn get_shows(&self) -> Result<Vec<(i64, String)>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT info FROM artist_metadata ORDER BY last_updated ASC")?;
-        let rows = stmt.query_map([], |row| row.get(0))?;
-        Ok(rows.filter_map(|r| r.ok()).collect())
-    }
-
-    //========TESTING PURPOSES ONLY========
-
-    pub fn clear_media_table(&mut self) -> Result<()> {
-        self.conn.execute("DELETE FROM media", [])?;
-        Ok(())
+            .prepare("SELECT id, title FROM shows ORDER BY title COLLATE NOCASE ASC")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
     }
 
-    pub fn add_sample_data(&mut self) -> Result<()> {
-        self.insert_media("path/to/media1.mp4", "Sample Media 1", 300.0, "video")?;
-        self.insert_media("path/to/media2.mp3", "Sample Media 2", 200.0, "audio")?;
-        self.insert_media("path/to/media3.jpg", "Sample Media 3", 0.0, "image")?;
-        Ok(())
+    // Episodes of one show, or of every show, sorted by show, season and episode
+    pub fn get_episodes(&self, show_id: Option<i64>) -> Result<Vec<Episode>> {
+        let mut stmt = self.conn.prepare(
+            "
+            SELECT episodes.media_id, seasons.show_id, seasons.number, episodes.number,
+                   episodes.last_number, episodes.absolute, episodes.title,
+                   media.path, media.status, media.time_stop, media.duration
+            FROM episodes
+            JOIN seasons ON seasons.id = episodes.season_id
+            JOIN media ON media.id = episodes.media_id
+            WHERE ?1 IS NULL OR seasons.show_id = ?1
+            ORDER BY seasons.show_id, seasons.number, episodes.number, media.path
+            ",
+        )?;
+        let rows = stmt.query_map([show_id], |row| {
+            Ok(Episode {
+                media_id: row.get(0)?,
+                show_id: row.get(1)?,
+                season: row.get(2)?,
+                episode: row.get(3)?,
+                last_episode: row.get(4)?,
+                absolute: row.get(5)?,
+                title: row.get(6)?,
+                path: row.get(7)?,
+                status: row.get::<_, Option<i32>>(8)?.unwrap_or(0),
+                time_stop: row.get::<_, Option<f64>>(9)?.unwrap_or(0.0),
+                duration: row.get::<_, Option<f32>>(10)?.unwrap_or(0.0),
+            })
+        })?;
+        rows.collect()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rusqlite::Connection;
+    //========= MUSIC TABLE METHODS========
+
+    // Updates the album tracks from the ones grouped from the audio tags (media id, track),
+    // only the rows that changed are written. An album is a title, an album artist and a folder.
+    // The ids of the artists and albums that still have tracks are kept.
+    pub fn sync_music(&mut self, tracks: &[(i64, ParsedTrack)]) -> Result<()> {
+        // the year of an album is the oldest one of its tracks
+        let mut albums: HashMap<(&str, &str, &str), (bool, Option<u32>)> = HashMap::new();
+        for (_, track) in tracks {
+            let key = (
+                track.album.as_str(),
+                track.album_artist.as_str(),
+                track.folder.as_str(),
+            );
+            let (_, year) = albums.entry(key).or_insert((track.compilation, track.year));
+            *year = match (*year, track.year) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+        }
 
-    fn create_test_db() -> DB {
-        let conn = Connection::open_in_memory().unwrap();
-        let mut db = DB {
-            conn,
-            media_rows: Vec::new(),
-        };
-        db.init_db().unwrap();
-        db
-    }
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert_artist =
+                tx.prepare("INSERT OR IGNORE INTO artists (name) VALUES (?1)")?;
+            let mut select_artist = tx.prepare("SELECT id FROM artists WHERE name = ?1")?;
+            let mut upsert_album = tx.prepare(
+                "
+                INSERT INTO albums (title, artist_id, folder, compilation, year)
+                VALUES (?1, ?2, ?3, ?4, ?5)
+                ON CONFLICT (title, artist_id, folder)
+                DO UPDATE SET compilation = excluded.compilation, year = excluded.year
+                WHERE compilation IS NOT excluded.compilation OR year IS NOT excluded.year
+                ",
+            )?;
+            let mut select_album = tx.prepare(
+                "SELECT id FROM albums WHERE title = ?1 AND artist_id = ?2 AND folder = ?3",
+            )?;
+            let mut upsert_track = tx.prepare(
+                "
+                INSERT OR REPLACE INTO album_tracks (media_id, album_id, artist_id, disc, track)
+                VALUES (?1, ?2, ?3, ?4, ?5)
+                ",
+            )?;
+            let mut delete_track = tx.prepare("DELETE FROM album_tracks WHERE media_id = ?1")?;
+
+            let mut artist_ids: HashMap<String, i64> = HashMap::new();
+            let mut artist_id = |name: &str| -> Result<i64> {
+                if let Some(id) = artist_ids.get(name) {
+                    return Ok(*id);
+                }
+                insert_artist.execute([name])?;
+                let id = select_artist.query_row([name], |row| row.get(0))?;
+                artist_ids.insert(name.to_string(), id);
+                Ok(id)
+            };
+
+            let mut album_ids = HashMap::new();
+            for ((title, album_artist, folder), (compilation, year)) in &albums {
+                let album_artist_id = artist_id(album_artist)?;
+                upsert_album.execute((title, album_artist_id, folder, compilation, year))?;
+                let album_id: i64 =
+                    select_album.query_row((title, album_artist_id, folder), |row| row.get(0))?;
+                album_ids.insert((*title, *album_artist, *folder), album_id);
+            }
 
-    #[test]
-    fn test_init_db() {
-        let mut db = create_test_db();
+            type StoredTrack = (i64, Option<i64>, Option<u32>, Option<u32>);
+            let mut stored: HashMap<i64, StoredTrack> = tx
+                .prepare("SELECT media_id, album_id, artist_id, disc, track FROM album_tracks")?
+                .query_map([], |row| {
+                    Ok((
+                        row.get(0)?,
+                        (row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?),
+                    ))
+                })?
+                .collect::<Result<_>>()?;
+            for (media_id, track) in tracks {
+                let key = (
+                    track.album.as_str(),
+                    track.album_artist.as_str(),
+                    track.folder.as_str(),
+                );
+                let track_artist_id = match &track.artist {
+                    Some(name) => Some(artist_id(name)?),
+                    None => None,
+                };
+                let row = (album_ids[&key], track_artist_id, track.disc, track.track);
+                if stored.remove(media_id) != Some(row) {
+                    upsert_track.execute((media_id, row.0, row.1, row.2, row.3))?;
+                }
+            }
+            for media_id in stored.keys() {
+                delete_track.execute([media_id])?;
+            }
 
-        assert!(db.init_db().is_ok());
+            tx.execute_batch(
+                "
+                DELETE FROM albums WHERE id NOT IN (SELECT album_id FROM album_tracks);
+                DELETE FROM artists
+                WHERE id NOT IN (SELECT artist_id FROM albums)
+                AND id NOT IN (SELECT artist_id FROM album_tracks WHERE artist_id IS NOT NULL);
+                ",
+            )?;
+        }
+        link_artist_metadata(&tx)?;
+        tx.commit()
     }
 
-    #[test]
-    fn test_insert_media() {
-        let mut db = create_test_db();
-
-        assert!(db
-            .insert_media("path/to/video.mp4", "Test Video", 120.5, "video")
-            .is_ok());
+    // Every artist, with the albums they released or appear on
+    pub fn get_artists(&self) -> Result<Vec<Artist>> {
+        let mut stmt = self.conn.prepare(
+            "
+            SELECT artists.id, artists.name, artists.metadata_id,
+                (SELECT COUNT(*) FROM albums WHERE albums.artist_id = artists.id
+                    OR albums.id IN (SELECT album_id FROM album_tracks WHERE artist_id = artists.id)),
+                (SELECT COUNT(*) FROM album_tracks WHERE album_tracks.artist_id = artists.id
+                    OR album_id IN (SELECT id FROM albums WHERE albums.artist_id = artists.id))
+            FROM artists
+            ORDER BY artists.name COLLATE NOCASE ASC
+            ",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Artist {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                metadata_id: row.get(2)?,
+                album_count: row.get::<_, i64>(3)? as usize,
+                track_count: row.get::<_, i64>(4)? as usize,
+            })
+        })?;
+        rows.collect()
     }
 
-    #[test]
-    fn test_get_all_media() {
-        let mut db = create_test_db();
-        db.insert_media("path/to/video.mp4", "Test Video", 120.5, "video")
-            .unwrap();
+    // Albums of an artist (compilations they appear on included), or every album
+    pub fn get_albums(&self, artist_id: Option<i64>) -> Result<Vec<Album>> {
+        let mut stmt = self.conn.prepare(
+            "
+            SELECT albums.id, albums.title, albums.artist_id, artists.name, albums.compilation,
+                   albums.year, COUNT(album_tracks.media_id), COALESCE(SUM(media.duration), 0)
+            FROM albums
+            JOIN artists ON artists.id = albums.artist_id
+            JOIN album_tracks ON album_tracks.album_id = albums.id
+            JOIN media ON media.id = album_tracks.media_id
+            WHERE ?1 IS NULL OR albums.artist_id = ?1
+                OR albums.id IN (SELECT album_id FROM album_tracks WHERE artist_id = ?1)
+            GROUP BY albums.id
+            ORDER BY artists.name COLLATE NOCASE, albums.year, albums.title COLLATE NOCASE
+            ",
+        )?;
+        let rows = stmt.query_map([artist_id], |row| {
+            Ok(Album {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                artist_id: row.get(2)?,
+                artist: row.get(3)?,
+                compilation: row.get(4)?,
+                year: row.get(5)?,
+                track_count: row.get::<_, i64>(6)? as usize,
+                duration: row.get(7)?,
+            })
+        })?;
+        rows.collect()
+    }
 
-        let media = db.get_all_media().unwrap();
+    // Tracks of an album sorted by disc and track number
+    pub fn get_album_tracks(&self, album_id: i64) -> Result<Vec<AlbumTrack>> {
+        let mut stmt = self.conn.prepare(
+            "
+            SELECT album_tracks.media_id, album_tracks.album_id, album_tracks.disc,
+                   album_tracks.track, media.title, artists.name, media.path, media.duration
+            FROM album_tracks
+            JOIN media ON media.id = album_tracks.media_id
+            LEFT JOIN artists ON artists.id = album_tracks.artist_id
+            WHERE album_tracks.album_id = ?1
+            ORDER BY COALESCE(album_tracks.disc, 1), COALESCE(album_tracks.track, 0), media.path
+            ",
+        )?;
+        let rows = stmt.query_map([album_id], |row| {
+            Ok(AlbumTrack {
+                media_id: row.get(0)?,
+                album_id: row.get(1)?,
+                disc: row.get(2)?,
+                track: row.get(3)?,
+                title: row.get(4)?,
+                artist: row.get(5)?,
+                path: row.get(6)?,
+                duration: row.get::<_, Option<f32>>(7)?.unwrap_or(0.0),
+            })
+        })?;
+        rows.collect()
+    }
 
-        assert_eq!(media.len(), 1);
-        assert_eq!(media[0].path, "path/to/video.mp4");
+    // Plugin answer cached for an artist
+    pub fn get_artist_metadata(&self, artist_id: i64) -> Result<Option<String>> {
+        self.conn
+            .query_row(
+                "
+                SELECT artist_metadata.info FROM artists
+                JOIN artist_metadata ON artist_metadata.id = artists.metadata_id
+                WHERE artists.id = ?1
+                ",
+                [artist_id],
+                |row| row.get(0),
+            )
+            .optional()
     }
 
-    #[test]
-    fn test_get_or_create_tag() {
+    //========= MOVIE NAMES METHODS========
+
+    // Stores the names parsed from the movie paths (media id, movie), the other rows are cleared.
+    // Rows whose name did not change are not written.
+    pub fn sync_movie_names(&mut self, movies: &[(i64, ParsedMovie)]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut update = tx.prepare(
+                "
+                UPDATE media SET movie_title = ?2, movie_year = ?3, movie_edition = ?4, movie_part = ?5
+                WHERE id = ?1
+                  AND (movie_title IS NOT ?2 OR movie_year IS NOT ?3
+                       OR movie_edition IS NOT ?4 OR movie_part IS NOT ?5)
+                ",
+            )?;
+            let mut clear = tx.prepare(
+                "
+                UPDATE media SET movie_title = NULL, movie_year = NULL, movie_edition = NULL, movie_part = NULL
+                WHERE id = ?1
+                ",
+            )?;
+
+            let mut named: Vec<i64> = tx
+                .prepare("SELECT id FROM media WHERE movie_title IS NOT NULL")?
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_>>()?;
+            let ids: HashSet<i64> = movies.iter().map(|(id, _)| *id).collect();
+            named.retain(|id| !ids.contains(id));
+
+            for (media_id, movie) in movies {
+                update.execute((
+                    media_id,
+                    &movie.title,
+                    movie.year,
+                    &movie.edition,
+                    movie.part,
+                ))?;
+            }
+            for media_id in named {
+                clear.execute([media_id])?;
+            }
+        }
+        tx.commit()
+    }
+
+    pub fn get_movie_name(&self, media_id: i64) -> Result<Option<ParsedMovie>> {
+        self.conn
+            .query_row(
+                "
+                SELECT movie_title, movie_year, movie_edition, movie_part
+                FROM media WHERE id = ?1 AND movie_title IS NOT NULL
+                ",
+                [media_id],
+                |row| {
+                    Ok(ParsedMovie {
+                        title: row.get(0)?,
+                        year: row.get(1)?,
+                        edition: row.get(2)?,
+                        part: row.get(3)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    //========= NFO TABLE METHODS========
+
+    // path -> mtime of every nfo file already read
+    pub fn get_nfo_stamps(&self) -> Result<HashMap<String, i64>> {
+        let mut stmt = self.conn.prepare("SELECT path, mtime FROM nfo")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    // Synchronises the nfo tables with the files found after a scan: the files in `parsed`
+    // are replaced, the others only get their media id updated. The stored files missing from
+    // `files` are removed, only those among `checked` when it is given.
+    // An unreadable file (None) keeps a row without content, it is not read again until it changes.
+    pub fn sync_nfo(
+        &mut self,
+        files: &[NfoFile],
+        parsed: &HashMap<String, Option<Nfo>>,
+        checked: Option<&HashSet<String>>,
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut delete = tx.prepare("DELETE FROM nfo WHERE path = ?1")?;
+            let mut insert = tx.prepare(
+                "
+                INSERT INTO nfo (
+                    path, mtime, kind, media_id, title, original_title, show_title,
+                    plot, year, rating, genres, season, episode
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+                ",
+            )?;
+            let mut insert_unreadable = tx
+                .prepare("INSERT INTO nfo (path, mtime, kind, media_id) VALUES (?1, ?2, '', ?3)")?;
+            let mut insert_actor = tx.prepare(
+                "INSERT INTO nfo_actors (nfo_id, position, name, role, thumb) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            let mut insert_artwork = tx.prepare(
+                "INSERT INTO nfo_artwork (nfo_id, position, aspect, url) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            let mut link = tx.prepare("UPDATE nfo SET media_id = ?2 WHERE path = ?1")?;
+
+            let found: HashSet<&str> = files.iter().map(|f| f.path.as_str()).collect();
+            let stored: Vec<String> = tx
+                .prepare("SELECT path FROM nfo")?
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_>>()?;
+            let missing = stored.iter().filter(|p| !found.contains(p.as_str()));
+            for path in missing.filter(|p| checked.is_none_or(|checked| checked.contains(*p))) {
+                delete.execute([path])?;
+            }
+
+            for file in files {
+                let Some(parsed) = parsed.get(&file.path) else {
+                    link.execute((&file.path, file.media_id))?;
+                    continue;
+                };
+                delete.execute([&file.path])?;
+                let Some(nfo) = parsed else {
+                    insert_unreadable.execute((&file.path, file.mtime, file.media_id))?;
+                    continue;
+                };
+                insert.execute(rusqlite::params![
+                    &file.path,
+                    file.mtime,
+                    nfo.kind.root_tag(),
+                    file.media_id,
+                    &nfo.title,
+                    &nfo.original_title,
+                    &nfo.show_title,
+                    &nfo.plot,
+                    nfo.year,
+                    nfo.rating,
+                    (!nfo.genres.is_empty()).then(|| nfo.genres.join(" / ")),
+                    nfo.season,
+                    nfo.episode,
+                ])?;
+                let nfo_id = tx.last_insert_rowid();
+                for (position, actor) in nfo.actors.iter().enumerate() {
+                    insert_actor.execute((
+                        nfo_id,
+                        position,
+                        &actor.name,
+                        &actor.role,
+                        &actor.thumb,
+                    ))?;
+                }
+                for (position, art) in nfo.artwork.iter().enumerate() {
+                    insert_artwork.execute((nfo_id, position, &art.aspect, &art.url))?;
+                }
+            }
+        }
+        tx.commit()
+    }
+
+    // Forgets the nfo files of removed media
+    pub fn remove_media_nfo(&mut self, media_ids: &[i64]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut delete = tx.prepare("DELETE FROM nfo WHERE media_id = ?1")?;
+            for media_id in media_ids {
+                delete.execute([media_id])?;
+            }
+        }
+        tx.commit()
+    }
+
+    // The nfo of a movie or an episode
+    pub fn get_media_nfo(&self, media_id: i64) -> Result<Option<Nfo>> {
+        self.read_nfo("media_id = ?1", [media_id])
+    }
+
+    // Path of the nfo read for a movie or an episode
+    pub fn get_media_nfo_path(&self, media_id: i64) -> Result<Option<String>> {
+        self.conn
+            .query_row(
+                "SELECT path FROM nfo WHERE kind <> '' AND media_id = ?1 ORDER BY id LIMIT 1",
+                [media_id],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    // The nfo at this path (tvshow.nfo)
+    pub fn get_nfo_at(&self, path: &str) -> Result<Option<Nfo>> {
+        self.read_nfo("path = ?1", [path])
+    }
+
+    fn read_nfo<P: rusqlite::Params>(&self, filter: &str, params: P) -> Result<Option<Nfo>> {
+        let sql = format!(
+            "
+            SELECT id, kind, title, original_title, show_title, plot, year, rating, genres, season, episode
+            FROM nfo WHERE kind <> '' AND {} ORDER BY id LIMIT 1
+            ",
+            filter
+        );
+        let row = self
+            .conn
+            .query_row(&sql, params, |row| {
+                let kind: String = row.get(1)?;
+                let mut nfo = Nfo::new(NfoKind::from_root_tag(&kind).unwrap_or(NfoKind::Movie));
+                nfo.title = row.get(2)?;
+                nfo.original_title = row.get(3)?;
+                nfo.show_title = row.get(4)?;
+                nfo.plot = row.get(5)?;
+                nfo.year = row.get(6)?;
+                nfo.rating = row.get(7)?;
+                nfo.genres = row
+                    .get::<_, Option<String>>(8)?
+                    .map(|genres| genres.split(" / ").map(String::from).collect())
+                    .unwrap_or_default();
+                nfo.season = row.get(9)?;
+                nfo.episode = row.get(10)?;
+                Ok((row.get::<_, i64>(0)?, nfo))
+            })
+            .optional()?;
+        let Some((nfo_id, mut nfo)) = row else {
+            return Ok(None);
+        };
+
+        let mut stmt = self.conn.prepare(
+            "SELECT name, role, thumb FROM nfo_actors WHERE nfo_id = ?1 ORDER BY position",
+        )?;
+        nfo.actors = stmt
+            .query_map([nfo_id], |row| {
+                Ok(NfoActor {
+                    name: row.get(0)?,
+                    role: row.get(1)?,
+                    thumb: row.get(2)?,
+                })
+            })?
+            .collect::<Result<_>>()?;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT aspect, url FROM nfo_artwork WHERE nfo_id = ?1 ORDER BY position")?;
+        nfo.artwork = stmt
+            .query_map([nfo_id], |row| {
+                Ok(NfoArtwork {
+                    aspect: row.get(0)?,
+                    url: row.get(1)?,
+                })
+            })?
+            .collect::<Result<_>>()?;
+        Ok(Some(nfo))
+    }
+
+    //========= ARTWORK TABLE METHODS========
+
+    // Id of a cached image, the row is created the first time
+    pub fn add_artwork(&self, image: &CachedImage) -> Result<i64> {
+        artwork_id(&self.conn, image)
+    }
+
+    pub fn get_artwork(&self, artwork_id: i64) -> Result<Option<CachedImage>> {
+        self.conn
+            .query_row(
+                "SELECT hash, mime, size FROM artwork WHERE id = ?1",
+                [artwork_id],
+                |row| {
+                    Ok(CachedImage {
+                        hash: row.get(0)?,
+                        mime: row.get(1)?,
+                        size: row.get::<_, i64>(2)? as u64,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    // Covers found in the tags, by media id
+    pub fn get_embedded_artwork(&self) -> Result<HashMap<i64, i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT media_id, artwork_id FROM embedded_artwork")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    pub fn get_artwork_links(&self) -> Result<Vec<ArtworkLink>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT owner_type, owner_key, kind, artwork_id, source, source_mtime FROM artwork_links",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let owner_type: String = row.get(0)?;
+            let owner_key: String = row.get(1)?;
+            let kind: String = row.get(2)?;
+            Ok((
+                ArtOwner::from_db(&owner_type, &owner_key),
+                ArtKind::from_db(&kind),
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+            ))
+        })?;
+
+        let mut links = Vec::new();
+        for row in rows {
+            if let (Some(owner), Some(kind), artwork_id, source, source_mtime) = row? {
+                links.push(ArtworkLink {
+                    owner,
+                    kind,
+                    artwork_id,
+                    source,
+                    source_mtime,
+                });
+            }
+        }
+        Ok(links)
+    }
+
+    // Replaces the links of `owners`, every link when None, with `links`, then removes the
+    // images nothing points to anymore.
+    // Returns the removed images so their files can be deleted from the cache.
+    pub fn sync_artwork_links(
+        &mut self,
+        links: &[ArtworkLink],
+        owners: Option<&HashSet<ArtOwner>>,
+    ) -> Result<Vec<CachedImage>> {
+        let tx = self.conn.transaction()?;
+        let mut removed = Vec::new();
+        {
+            match owners {
+                None => {
+                    tx.execute("DELETE FROM artwork_links", [])?;
+                }
+                Some(owners) => {
+                    let mut delete = tx.prepare(
+                        "DELETE FROM artwork_links WHERE owner_type = ?1 AND owner_key = ?2",
+                    )?;
+                    for owner in owners {
+                        delete.execute(owner.as_db())?;
+                    }
+                }
+            }
+            let mut insert = tx.prepare(
+                "
+                INSERT OR REPLACE INTO artwork_links
+                    (owner_type, owner_key, kind, artwork_id, source, source_mtime)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ",
+            )?;
+            for link in links {
+                let (owner_type, owner_key) = link.owner.as_db();
+                insert.execute((
+                    owner_type,
+                    owner_key,
+                    link.kind.as_db(),
+                    link.artwork_id,
+                    &link.source,
+                    link.source_mtime,
+                ))?;
+            }
+
+            let unused = "
+                FROM artwork
+                WHERE id NOT IN (SELECT artwork_id FROM artwork_links)
+                AND id NOT IN (SELECT artwork_id FROM embedded_artwork)
+            ";
+            let mut stmt = tx.prepare(&format!("SELECT hash, mime, size {}", unused))?;
+            let rows = stmt.query_map([], |row| {
+                Ok(CachedImage {
+                    hash: row.get(0)?,
+                    mime: row.get(1)?,
+                    size: row.get::<_, i64>(2)? as u64,
+                })
+            })?;
+            for row in rows {
+                removed.push(row?);
+            }
+            tx.execute(&format!("DELETE {}", unused), [])?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    // Image shown for each media: its poster or cover, else the cover found in its tags
+    pub fn get_media_artwork(&self) -> Result<HashMap<i64, CachedImage>> {
+        let mut stmt = self.conn.prepare(
+            "
+            SELECT CAST(embedded_artwork.media_id AS TEXT), hash, mime, size, 0
+            FROM embedded_artwork
+            JOIN artwork ON artwork.id = embedded_artwork.artwork_id
+            UNION ALL
+            SELECT owner_key, hash, mime, size, 1
+            FROM artwork_links
+            JOIN artwork ON artwork.id = artwork_links.artwork_id
+            WHERE owner_type = 'media' AND kind IN ('poster', 'cover')
+            ORDER BY 5
+            ",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let image = CachedImage {
+                hash: row.get(1)?,
+                mime: row.get(2)?,
+                size: row.get::<_, i64>(3)? as u64,
+            };
+            Ok((row.get::<_, String>(0)?, image))
+        })?;
+        let mut artwork = HashMap::new();
+        for row in rows {
+            let (owner_key, image) = row?;
+            if let Ok(media_id) = owner_key.parse() {
+                artwork.insert(media_id, image);
+            }
+        }
+        Ok(artwork)
+    }
+
+    //========= METADATA CACHE METHODS========
+
+    // Most recent answer cached for the query, whatever the plugin that gave it
+    pub fn get_cached_metadata(
+        &self,
+        kind: CacheKind,
+        key: &str,
+    ) -> Result<Option<CachedMetadata>> {
+        let key = normalize_key(key);
+        self.conn
+            .query_row(
+                "
+                SELECT plugin, response, fetched_at FROM metadata_cache
+                WHERE kind = ?1 AND key = ?2
+                ORDER BY fetched_at DESC LIMIT 1
+                ",
+                (kind.as_str(), &key),
+                |row| {
+                    Ok(CachedMetadata {
+                        plugin: row.get(0)?,
+                        kind,
+                        key: key.clone(),
+                        response: row.get(1)?,
+                        fetched_at: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    // The answer only replaces the previous one of the same plugin, the newest row wins
+    pub fn save_cached_metadata(&mut self, entry: &CachedMetadata) -> Result<()> {
+        self.conn.execute(
+            "
+            INSERT OR REPLACE INTO metadata_cache (plugin, kind, key, response, fetched_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ",
+            (
+                &entry.plugin,
+                entry.kind.as_str(),
+                &entry.key,
+                &entry.response,
+                entry.fetched_at,
+            ),
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_cached_metadata(&self, kind: CacheKind, key: &str) -> Result<usize> {
+        self.conn.execute(
+            "DELETE FROM metadata_cache WHERE kind = ?1 AND key = ?2",
+            (kind.as_str(), normalize_key(key)),
+        )
+    }
+
+    //========= PLAYLIST TABLE METHODS========
+
+    //========= ARTIST METADATA METHODS========
+
+    pub fn get_artist_metadata_by_name(&self, name: &str) -> Result<Option<CachedArtist>> {
+        self.get_cached_artist("name_key", &normalize_key(name))
+    }
+
+    pub fn get_artist_metadata_by_mbid(
+        &self,
+        musicbrainz_id: &str,
+    ) -> Result<Option<CachedArtist>> {
+        self.get_cached_artist("musicbrainz_id", musicbrainz_id)
+    }
+
+    fn get_cached_artist(&self, column: &str, value: &str) -> Result<Option<CachedArtist>> {
+        self.conn
+            .query_row(
+                &format!(
+                    "
+                    SELECT id, artist_name, musicbrainz_id, info,
+                        CAST(strftime('%s', last_updated) AS INTEGER)
+                    FROM artist_metadata WHERE {} = ?1
+                    ORDER BY last_updated DESC LIMIT 1
+                    ",
+                    column
+                ),
+                [value],
+                |row| {
+                    Ok(CachedArtist {
+                        id: row.get(0)?,
+                        name: row.get(1)?,
+                        musicbrainz_id: row.get(2)?,
+                        response: row.get(3)?,
+                        fetched_at: row.get::<_, Option<i64>>(4)?.unwrap_or(0),
+                    })
+                },
+            )
+            .optional()
+    }
+
+    // Stores the answer for an artist, found or not found, under its normalized name.
+    // An existing row keeps its id and first spelling, so the links and the search index hold.
+    pub fn upsert_artist_metadata(
+        &mut self,
+        name: &str,
+        response: &MetadataResponse,
+    ) -> Result<i64> {
+        let key = normalize_key(name);
+        let info = response.to_json();
+        let musicbrainz_id = musicbrainz_id(response);
+
+        let tx = self.conn.transaction()?;
+        let updated = tx.execute(
+            "
+            UPDATE artist_metadata
+            SET info = ?1, musicbrainz_id = ?2, last_updated = CURRENT_TIMESTAMP
+            WHERE name_key = ?3
+            ",
+            (&info, musicbrainz_id, &key),
+        )?;
+        if updated == 0 {
+            tx.execute(
+                "
+                INSERT INTO artist_metadata (artist_name, name_key, info, musicbrainz_id)
+                VALUES (?1, ?2, ?3, ?4)
+                ",
+                (name, &key, &info, musicbrainz_id),
+            )?;
+        }
+        let id = tx.query_row(
+            "SELECT id FROM artist_metadata WHERE name_key = ?1",
+            [&key],
+            |row| row.get(0),
+        )?;
+        link_artist_metadata(&tx)?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    // Forgets the answer for an artist, the next lookup asks the plugins again
+    pub fn invalidate_artist_metadata(&self, name: &str) -> Result<usize> {
+        self.conn.execute(
+            "DELETE FROM artist_metadata WHERE name_key = ?1",
+            [normalize_key(name)],
+        )
+    }
+
+    // (artist name, plugin answer as a plugin_api::metadata::MetadataResponse JSON)
+    pub fn get_all_artist_metadata(&self) -> Result<Vec<(String, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT artist_name, info FROM artist_metadata ORDER BY last_updated ASC")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.filter_map(|r| r.ok()).collect())
+    }
+
+    //========TESTING PURPOSES ONLY========
+
+    pub fn clear_media_table(&mut self) -> Result<()> {
+        self.conn.execute("DELETE FROM media", [])?;
+        Ok(())
+    }
+
+    pub fn add_sample_data(&mut self) -> Result<()> {
+        self.insert_media("path/to/media1.mp4", "Sample Media 1", 300.0, "video")?;
+        self.insert_media("path/to/media2.mp3", "Sample Media 2", 200.0, "audio")?;
+        self.insert_media("path/to/media3.jpg", "Sample Media 3", 0.0, "image")?;
+        Ok(())
+    }
+}
+
+fn artwork_id(conn: &Connection, image: &CachedImage) -> Result<i64> {
+    conn.execute(
+        "INSERT OR IGNORE INTO artwork (hash, mime, size) VALUES (?1, ?2, ?3)",
+        (&image.hash, &image.mime, image.size as i64),
+    )?;
+    conn.query_row(
+        "SELECT id FROM artwork WHERE hash = ?1",
+        [&image.hash],
+        |row| row.get(0),
+    )
+}
+
+// The cover read from the tags of a media, replaces the previous one
+fn set_embedded_artwork(
+    conn: &Connection,
+    media_id: i64,
+    image: Option<&CachedImage>,
+) -> Result<()> {
+    conn.execute(
+        "DELETE FROM embedded_artwork WHERE media_id = ?1",
+        [media_id],
+    )?;
+    if let Some(image) = image {
+        let artwork_id = artwork_id(conn, image)?;
+        conn.execute(
+            "INSERT INTO embedded_artwork (media_id, artwork_id) VALUES (?1, ?2)",
+            (media_id, artwork_id),
+        )?;
+    }
+    Ok(())
+}
+
+// Links every artist to the metadata row of its normalized name, if any
+fn link_artist_metadata(conn: &Connection) -> Result<()> {
+    let keys: HashMap<String, i64> = conn
+        .prepare("SELECT name_key, id FROM artist_metadata")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+    let artists: Vec<(i64, String)> = conn
+        .prepare("SELECT id, name FROM artists")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+
+    let mut update = conn
+        .prepare("UPDATE artists SET metadata_id = ?1 WHERE id = ?2 AND metadata_id IS NOT ?1")?;
+    for (id, name) in artists {
+        update.execute((keys.get(&normalize_key(&name)), id))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn create_test_db() -> DB {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut db = DB {
+            conn,
+            media_rows: Vec::new(),
+        };
+        db.init_db().unwrap();
+        db
+    }
+
+    #[test]
+    fn test_init_db() {
+        let mut db = create_test_db();
+
+        assert!(db.init_db().is_ok());
+    }
+
+    #[test]
+    fn test_insert_media() {
+        let mut db = create_test_db();
+
+        assert!(db
+            .insert_media("path/to/video.mp4", "Test Video", 120.5, "video")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_get_all_media() {
+        let mut db = create_test_db();
+        db.insert_media("path/to/video.mp4", "Test Video", 120.5, "video")
+            .unwrap();
+
+        let media = db.get_all_media().unwrap();
+
+        assert_eq!(media.len(), 1);
+        assert_eq!(media[0].path, "path/to/video.mp4");
+    }
+
+    #[test]
+    fn test_get_or_create_tag() {
         let mut db = create_test_db();
         let tag_id1 = db.get_or_create_tag("action").unwrap();
         let tag_id2 = db.get_or_create_tag("action").unwrap();
@@ -662,4 +1923,726 @@ mod tests {
         let media = db.get_media_from_playlist(pid).unwrap();
         assert_eq!(media.len(), 0); // La playlist a disparu
     }
+
+    #[test]
+    fn test_search_media_by_prefix() {
+        let mut db = create_test_db();
+        db.insert_media(
+            "/music/Daft Punk/Around the World.mp3",
+            "Around the World",
+            0.0,
+            "Audio",
+        )
+        .unwrap();
+        db.insert_media("/music/Muse/Uprising.mp3", "Uprising", 0.0, "Audio")
+            .unwrap();
+
+        let hits = db.search_media("arou", &SearchFilters::default()).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].media_id, 1);
+        assert!(hits[0].snippet.contains("<mark>Around</mark>"));
+
+        // path components are indexed too
+        let hits = db.search_media("daft", &SearchFilters::default()).unwrap();
+        assert_eq!(hits.len(), 1);
+    }
+
+    #[test]
+    fn test_search_media_fuzzy() {
+        let mut db = create_test_db();
+        db.insert_media("/music/Radiohead/Creep.mp3", "Creep", 0.0, "Audio")
+            .unwrap();
+
+        let hits = db
+            .search_media("radiohed", &SearchFilters::default())
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+
+        let hits = db.search_media("xyzzy", &SearchFilters::default()).unwrap();
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn test_search_media_ranks_title_first() {
+        let mut db = create_test_db();
+        db.insert_media("/music/Uprising/Other.mp3", "Other", 0.0, "Audio")
+            .unwrap();
+        db.insert_media("/music/Muse/Track.mp3", "Uprising", 0.0, "Audio")
+            .unwrap();
+
+        let hits = db
+            .search_media("uprising", &SearchFilters::default())
+            .unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].media_id, 2);
+    }
+
+    #[test]
+    fn test_search_media_filters() {
+        let mut db = create_test_db();
+        db.insert_media("/media/holiday.mp4", "Holiday", 0.0, "Video")
+            .unwrap();
+        db.insert_media("/media/holiday.jpg", "Holiday", 0.0, "Image")
+            .unwrap();
+        let tag_id = db.get_or_create_tag("summer").unwrap();
+        db.add_tag_to_media(2, tag_id).unwrap();
+
+        let videos = SearchFilters {
+            media_type: Some(MediaType::Video),
+            ..Default::default()
+        };
+        let hits = db.search_media("holiday", &videos).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].media_id, 1);
+
+        let tagged = SearchFilters {
+            tag: Some("summer".to_string()),
+            ..Default::default()
+        };
+        let hits = db.search_media("holiday", &tagged).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].media_id, 2);
+
+        // tags are searchable, and removed from the index with the tag
+        assert_eq!(
+            db.search_media("summer", &SearchFilters::default())
+                .unwrap()
+                .len(),
+            1
+        );
+        db.remove_tag_from_media(2, tag_id).unwrap();
+        assert!(db
+            .search_media("summer", &SearchFilters::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_search_index_follows_deletes() {
+        let mut db = create_test_db();
+        db.insert_media("/media/clip.mp4", "Clip", 0.0, "Video")
+            .unwrap();
+
+        db.clear_media_table().unwrap();
+
+        assert!(db
+            .search_media("clip", &SearchFilters::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    fn scanned(path: &str, size: u64, mtime: i64) -> ScannedMedia {
+        ScannedMedia {
+            path: path.to_string(),
+            name: path.rsplit('/').next().unwrap().to_string(),
+            duration: 0.0,
+            media_type: MediaType::Audio,
+            size,
+            mtime,
+            tags: None,
+            artwork: None,
+        }
+    }
+
+    fn track(album: &str, album_artist: &str, artist: &str, number: u32) -> ParsedTrack {
+        ParsedTrack {
+            album: album.to_string(),
+            album_artist: album_artist.to_string(),
+            folder: format!("/m/{}", album.to_lowercase()),
+            compilation: album_artist == "Various Artists",
+            artist: Some(artist.to_string()),
+            disc: Some(1),
+            track: Some(number),
+            year: Some(2000 + number),
+        }
+    }
+
+    #[test]
+    fn test_sync_music_builds_artists_and_albums() {
+        let mut db = create_test_db();
+        let ids = db
+            .apply_scan(vec![
+                scanned("/m/discovery/2.mp3", 1, 1),
+                scanned("/m/discovery/1.mp3", 1, 1),
+                scanned("/m/hits/1.mp3", 1, 1),
+            ])
+            .unwrap()
+            .added_ids;
+        db.upsert_artist_metadata("daft  punk", &MetadataResponse::not_found())
+            .unwrap();
+        db.sync_music(&[
+            (ids[0], track("Discovery", "Daft Punk", "Daft Punk", 2)),
+            (ids[1], track("Discovery", "Daft Punk", "Daft Punk", 1)),
+            (ids[2], track("Hits", "Various Artists", "Daft Punk", 1)),
+        ])
+        .unwrap();
+
+        let artists = db.get_artists().unwrap();
+        let names: Vec<&str> = artists.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, vec!["Daft Punk", "Various Artists"]);
+        assert_eq!((artists[0].album_count, artists[0].track_count), (2, 3));
+        assert!(db.get_artist_metadata(artists[0].id).unwrap().is_some());
+        assert_eq!(db.get_artist_metadata(artists[1].id).unwrap(), None);
+
+        let albums = db.get_albums(Some(artists[0].id)).unwrap();
+        assert_eq!(albums.len(), 2);
+        assert_eq!(
+            (albums[0].title.as_str(), albums[0].year),
+            ("Discovery", Some(2001))
+        );
+        assert!(albums[1].compilation);
+        let tracks = db.get_album_tracks(albums[0].id).unwrap();
+        let order: Vec<i64> = tracks.iter().map(|t| t.media_id).collect();
+        assert_eq!(order, vec![ids[1], ids[0]]);
+
+        // the compilation is gone with its file, the album of Daft Punk keeps its id
+        db.apply_scan(vec![
+            scanned("/m/discovery/2.mp3", 1, 1),
+            scanned("/m/discovery/1.mp3", 1, 1),
+        ])
+        .unwrap();
+        db.sync_music(&[
+            (ids[0], track("Discovery", "Daft Punk", "Daft Punk", 2)),
+            (ids[1], track("Discovery", "Daft Punk", "Daft Punk", 1)),
+        ])
+        .unwrap();
+        assert_eq!(db.get_albums(None).unwrap()[0].id, albums[0].id);
+        assert_eq!(db.get_artists().unwrap().len(), 1);
+
+        // the same title by the same artist in another folder is another album
+        let mut other = track("Discovery", "Daft Punk", "Daft Punk", 1);
+        other.folder = "/m/live".to_string();
+        db.sync_music(&[
+            (ids[0], track("Discovery", "Daft Punk", "Daft Punk", 2)),
+            (ids[1], other),
+        ])
+        .unwrap();
+        let albums_now = db.get_albums(None).unwrap();
+        assert_eq!(albums_now.len(), 2);
+        assert!(albums_now.iter().any(|album| album.id == albums[0].id));
+        assert_eq!(db.get_album_tracks(albums[0].id).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_apply_scan_reports_changes() {
+        let mut db = create_test_db();
+        let first = db
+            .apply_scan(vec![scanned("/a.mp3", 10, 1), scanned("/b.mp3", 20, 1)])
+            .unwrap();
+        assert_eq!(first.added_ids.len(), 2);
+
+        let second = db
+            .apply_scan(vec![
+                scanned("/a.mp3", 10, 1),
+                scanned("/c.mp3", 30, 1),
+                scanned("/b.mp3", 25, 2),
+            ])
+            .unwrap();
+        assert_eq!(second.added_ids.len(), 1);
+        assert_eq!(second.updated_ids, first.added_ids[1..].to_vec());
+        assert!(second.removed_ids.is_empty());
+        assert_eq!(second.unchanged, 1);
+
+        let third = db.apply_scan(vec![scanned("/c.mp3", 30, 1)]).unwrap();
+        assert_eq!(third.removed_ids.len(), 2);
+        assert_eq!(third.unchanged, 1);
+        assert_eq!(db.get_all_media().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_second_scan_of_unchanged_tree_touches_no_rows() {
+        let mut db = create_test_db();
+        let tree = vec![scanned("/a.mp3", 10, 1), scanned("/b.mp3", 20, 1)];
+        db.apply_scan(tree.clone()).unwrap();
+
+        let total_changes = |db: &DB| -> i64 {
+            db.conn
+                .query_row("SELECT total_changes()", [], |row| row.get(0))
+                .unwrap()
+        };
+        let changes_before = total_changes(&db);
+        let report = db.apply_scan(tree).unwrap();
+
+        assert_eq!(total_changes(&db), changes_before);
+        assert!(report.added_ids.is_empty());
+        assert!(report.updated_ids.is_empty());
+        assert!(report.removed_ids.is_empty());
+        assert_eq!(report.unchanged, 2);
+    }
+
+    #[test]
+    fn test_apply_scan_keeps_watch_progress() {
+        let mut db = create_test_db();
+        db.apply_scan(vec![scanned("/a.mp3", 10, 1)]).unwrap();
+        db.update_media_status_and_time(1, 1, 42.0, 100.0).unwrap();
+
+        let report = db.apply_scan(vec![scanned("/a.mp3", 11, 2)]).unwrap();
+
+        assert_eq!(report.updated_ids, vec![1]);
+        let media = db.get_all_media().unwrap();
+        assert_eq!(media[0].last_position, 42.0);
+        assert_eq!(media[0].duration, Some(100.0));
+    }
+
+    #[test]
+    fn test_apply_scan_stores_tags() {
+        let mut db = create_test_db();
+        let mut song = scanned("/music/uprising.mp3", 10, 1);
+        song.name = "Uprising".to_string();
+        song.duration = 305.0;
+        song.tags = Some(MediaTags {
+            artist: Some("Muse".to_string()),
+            album: Some("The Resistance".to_string()),
+            album_artist: Some("Muse".to_string()),
+            track_number: Some(1),
+            disc_number: Some(1),
+            year: Some(2009),
+            genre: Some("Rock".to_string()),
+        });
+        db.apply_scan(vec![song.clone()]).unwrap();
+
+        let row = &db.get_all_media().unwrap()[0];
+        assert_eq!(row.title.as_deref(), Some("Uprising"));
+        assert_eq!(row.duration, Some(305.0));
+        assert_eq!(row.tags, song.tags.clone().unwrap());
+        assert_eq!(
+            db.get_file_stamps().unwrap().get("/music/uprising.mp3"),
+            Some(&FileStamp {
+                size: 10,
+                mtime: 1,
+                media_type: MediaType::Audio,
+            })
+        );
+
+        // the tags are searchable
+        let hits = db
+            .search_media("resistance", &SearchFilters::default())
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+    }
+
+    #[test]
+    fn test_apply_scan_keeps_tags_when_file_not_read() {
+        let mut db = create_test_db();
+        let mut song = scanned("/music/clip.mp4", 10, 1);
+        song.name = "Clip".to_string();
+        song.tags = Some(MediaTags {
+            artist: Some("Muse".to_string()),
+            ..Default::default()
+        });
+        db.apply_scan(vec![song]).unwrap();
+
+        // same stamps, only the type changed: the file was not read again
+        let mut video = scanned("/music/clip.mp4", 10, 1);
+        video.media_type = MediaType::Video;
+        let report = db.apply_scan(vec![video]).unwrap();
+
+        assert_eq!(report.updated_ids.len(), 1);
+        let row = &db.get_all_media().unwrap()[0];
+        assert_eq!(row.media_type, MediaType::Video);
+        assert_eq!(row.title.as_deref(), Some("Clip"));
+        assert_eq!(row.tags.artist.as_deref(), Some("Muse"));
+    }
+
+    #[test]
+    fn test_apply_scan_deduplicates_paths() {
+        let mut db = create_test_db();
+        let mut video = scanned("/clip.mp4", 10, 1);
+        video.media_type = MediaType::Video;
+
+        let report = db
+            .apply_scan(vec![scanned("/clip.mp4", 10, 1), video])
+            .unwrap();
+
+        assert_eq!(report.added_ids.len(), 1);
+        assert_eq!(db.get_all_media().unwrap()[0].media_type, MediaType::Video);
+    }
+
+    #[test]
+    fn test_upsert_scanned_media_keeps_other_rows() {
+        let mut db = create_test_db();
+        db.apply_scan(vec![scanned("/a.mp3", 10, 1)]).unwrap();
+
+        let report = db
+            .upsert_scanned_media(vec![scanned("/b.mp3", 20, 1)])
+            .unwrap();
+
+        assert_eq!(report.added_ids.len(), 1);
+        assert!(report.removed_ids.is_empty());
+        assert_eq!(db.get_all_media().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_remove_media_paths_handles_folders() {
+        let mut db = create_test_db();
+        db.apply_scan(vec![
+            scanned("/music/album/a.mp3", 10, 1),
+            scanned("/music/album/b.mp3", 10, 1),
+            scanned("/music/album2/c.mp3", 10, 1),
+            scanned("/music/single.mp3", 10, 1),
+        ])
+        .unwrap();
+
+        let removed = db
+            .remove_media_paths(&["/music/album".to_string(), "/music/single.mp3".to_string()])
+            .unwrap();
+
+        assert_eq!(removed.len(), 3);
+        let left = db.get_all_media().unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].path, "/music/album2/c.mp3");
+    }
+
+    #[test]
+    fn test_move_media_paths_keeps_ids() {
+        let mut db = create_test_db();
+        db.apply_scan(vec![
+            scanned("/music/album/a.mp3", 10, 1),
+            scanned("/music/album/b.mp3", 10, 1),
+            scanned("/music/album2/c.mp3", 10, 1),
+            scanned("/music/single.mp3", 10, 1),
+            scanned("/music/other.mp3", 10, 1),
+        ])
+        .unwrap();
+        let id_of = |db: &mut DB, path: &str| {
+            db.get_all_media()
+                .unwrap()
+                .iter()
+                .find(|row| row.path == path)
+                .map(|row| row.id)
+        };
+        let album_a = id_of(&mut db, "/music/album/a.mp3");
+        let single = id_of(&mut db, "/music/single.mp3");
+        let other = id_of(&mut db, "/music/other.mp3");
+
+        let (moved, removed) = db
+            .move_media_paths(&[
+                ("/music/album".to_string(), "/music/renamed".to_string()),
+                // renamed over an existing file
+                (
+                    "/music/single.mp3".to_string(),
+                    "/music/other.mp3".to_string(),
+                ),
+            ])
+            .unwrap();
+
+        assert_eq!(moved.len(), 3);
+        assert_eq!(removed, vec![other.unwrap()]);
+        assert_eq!(id_of(&mut db, "/music/renamed/a.mp3"), album_a);
+        assert_eq!(id_of(&mut db, "/music/other.mp3"), single);
+        assert!(id_of(&mut db, "/music/renamed/b.mp3").is_some());
+        // only the folder itself, not the folders sharing its name as a prefix
+        assert!(id_of(&mut db, "/music/album2/c.mp3").is_some());
+        assert_eq!(db.get_all_media().unwrap().len(), 4);
+    }
+
+    fn parsed(show: &str, season: u32, episode: u32) -> ParsedEpisode {
+        ParsedEpisode {
+            show: show.to_string(),
+            season,
+            episode,
+            last_episode: None,
+            absolute: false,
+            title: None,
+        }
+    }
+
+    #[test]
+    fn test_sync_series_keeps_ids_and_drops_empty_shows() {
+        let mut db = create_test_db();
+        let mut files = Vec::new();
+        for path in ["/tv/a1.mkv", "/tv/a2.mkv", "/tv/b1.mkv"] {
+            let mut media = scanned(path, 10, 1);
+            media.media_type = MediaType::Video;
+            files.push(media);
+        }
+        let ids = db.apply_scan(files).unwrap().added_ids;
+
+        db.sync_series(
+            &ids,
+            &[
+                (ids[0], parsed("Show A", 1, 1)),
+                (ids[1], parsed("show a", 1, 2)),
+                (ids[2], parsed("Show B", 2, 1)),
+            ],
+        )
+        .unwrap();
+        let shows = db.get_shows().unwrap();
+        assert_eq!(shows.len(), 2);
+        let show_a = shows[0].0;
+        assert_eq!(db.get_episodes(Some(show_a)).unwrap().len(), 2);
+        assert_eq!(db.get_episodes(None).unwrap().len(), 3);
+
+        // "Show B" lost its only episode, "Show A" keeps its id
+        db.sync_series(&[ids[2]], &[]).unwrap();
+        assert_eq!(
+            db.get_shows().unwrap(),
+            vec![(show_a, "Show A".to_string())]
+        );
+
+        // only the given media are touched
+        db.sync_series(&[ids[1]], &[(ids[1], parsed("Show A", 1, 3))])
+            .unwrap();
+        let episodes = db.get_episodes(None).unwrap();
+        let numbers: Vec<(i64, u32)> = episodes.iter().map(|e| (e.media_id, e.episode)).collect();
+        assert_eq!(numbers, vec![(ids[0], 1), (ids[1], 3)]);
+
+        // removing the media removes its episode
+        db.remove_media_paths(&["/tv/a1.mkv".to_string(), "/tv/a2.mkv".to_string()])
+            .unwrap();
+        assert!(db.get_episodes(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sync_movie_names_clears_the_other_rows() {
+        let mut db = create_test_db();
+        let mut files = Vec::new();
+        for path in ["/films/Heat.1995.mkv", "/films/Alien.mkv"] {
+            let mut media = scanned(path, 10, 1);
+            media.media_type = MediaType::Video;
+            files.push(media);
+        }
+        let ids = db.apply_scan(files).unwrap().added_ids;
+
+        let heat = ParsedMovie {
+            title: "Heat".to_string(),
+            year: Some(1995),
+            edition: Some("Director's Cut".to_string()),
+            part: None,
+        };
+        let alien = ParsedMovie {
+            title: "Alien".to_string(),
+            ..Default::default()
+        };
+        db.sync_movie_names(&[(ids[0], heat.clone()), (ids[1], alien)])
+            .unwrap();
+        assert_eq!(db.get_movie_name(ids[0]).unwrap(), Some(heat.clone()));
+
+        // the second file became an episode
+        db.sync_movie_names(&[(ids[0], heat.clone())]).unwrap();
+        assert_eq!(db.get_movie_name(ids[0]).unwrap(), Some(heat));
+        assert_eq!(db.get_movie_name(ids[1]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_sync_nfo_replaces_changed_files_and_drops_missing_ones() {
+        let mut db = create_test_db();
+        let mut media = scanned("/films/Heat.mkv", 10, 1);
+        media.media_type = MediaType::Video;
+        let id = db.apply_scan(vec![media]).unwrap().added_ids[0];
+
+        let mut heat = Nfo::new(NfoKind::Movie);
+        heat.title = Some("Heat".to_string());
+        heat.year = Some(1995);
+        heat.genres = vec!["Action".to_string(), "Crime".to_string()];
+        heat.actors = vec![NfoActor {
+            name: "Al Pacino".to_string(),
+            role: Some("Vincent Hanna".to_string()),
+            thumb: None,
+        }];
+        heat.artwork = vec![NfoArtwork {
+            aspect: "poster".to_string(),
+            url: "https://poster.jpg".to_string(),
+        }];
+        let mut show = Nfo::new(NfoKind::TvShow);
+        show.title = Some("Fargo".to_string());
+
+        let files = vec![
+            NfoFile {
+                path: "/films/Heat.nfo".to_string(),
+                mtime: 1,
+                media_id: Some(id),
+            },
+            NfoFile {
+                path: "/tv/Fargo/tvshow.nfo".to_string(),
+                mtime: 1,
+                media_id: None,
+            },
+        ];
+        let parsed = HashMap::from([
+            ("/films/Heat.nfo".to_string(), Some(heat.clone())),
+            ("/tv/Fargo/tvshow.nfo".to_string(), Some(show.clone())),
+        ]);
+        db.sync_nfo(&files, &parsed, None).unwrap();
+        assert_eq!(db.get_media_nfo(id).unwrap(), Some(heat.clone()));
+        assert_eq!(db.get_nfo_at("/tv/Fargo/tvshow.nfo").unwrap(), Some(show));
+        assert_eq!(db.get_nfo_stamps().unwrap().len(), 2);
+
+        // only the checked paths can be removed
+        let checked = HashSet::from(["/films/Heat.nfo".to_string()]);
+        db.sync_nfo(&[], &HashMap::new(), Some(&checked)).unwrap();
+        assert_eq!(db.get_media_nfo(id).unwrap(), None);
+        assert_eq!(db.get_nfo_stamps().unwrap().len(), 1);
+        db.sync_nfo(&files, &parsed, None).unwrap();
+
+        // unchanged file: kept without being parsed again, the show is gone
+        db.sync_nfo(&files[..1], &HashMap::new(), None).unwrap();
+        assert_eq!(db.get_media_nfo(id).unwrap(), Some(heat));
+        assert_eq!(
+            db.get_media_nfo_path(id).unwrap().as_deref(),
+            Some("/films/Heat.nfo")
+        );
+        assert_eq!(db.get_nfo_at("/tv/Fargo/tvshow.nfo").unwrap(), None);
+        let actors: i64 = db
+            .conn
+            .query_row("SELECT COUNT(*) FROM nfo_actors", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(actors, 1);
+
+        // an unreadable file is stamped but has no content
+        let parsed = HashMap::from([("/films/Heat.nfo".to_string(), None)]);
+        db.sync_nfo(&files[..1], &parsed, None).unwrap();
+        assert_eq!(
+            db.get_nfo_stamps().unwrap().get("/films/Heat.nfo"),
+            Some(&1)
+        );
+        assert_eq!(db.get_media_nfo(id).unwrap(), None);
+        assert_eq!(db.get_media_nfo_path(id).unwrap(), None);
+
+        db.remove_media_nfo(&[id]).unwrap();
+        assert!(db.get_nfo_stamps().unwrap().is_empty());
+    }
+
+    fn image(hash: &str) -> CachedImage {
+        CachedImage {
+            hash: hash.to_string(),
+            mime: "image/jpeg".to_string(),
+            size: 3,
+        }
+    }
+
+    #[test]
+    fn test_artwork_links_and_embedded_covers() {
+        let mut db = create_test_db();
+        let mut song = scanned("/music/a.mp3", 10, 1);
+        song.tags = Some(MediaTags::default());
+        song.artwork = Some(image("embedded"));
+        let song_id = db.apply_scan(vec![song.clone()]).unwrap().added_ids[0];
+        let embedded_id = db.get_embedded_artwork().unwrap()[&song_id];
+        assert_eq!(
+            db.get_artwork(embedded_id).unwrap(),
+            Some(image("embedded"))
+        );
+        assert_eq!(db.get_media_artwork().unwrap()[&song_id], image("embedded"));
+
+        // a folder cover wins over the embedded one
+        let folder_id = db.add_artwork(&image("folder")).unwrap();
+        assert_eq!(db.add_artwork(&image("folder")).unwrap(), folder_id);
+        let link = ArtworkLink {
+            owner: ArtOwner::Media(song_id),
+            kind: ArtKind::Cover,
+            artwork_id: folder_id,
+            source: "/music/cover.jpg".to_string(),
+            source_mtime: Some(1),
+        };
+        assert_eq!(
+            db.sync_artwork_links(std::slice::from_ref(&link), None)
+                .unwrap(),
+            vec![]
+        );
+        assert_eq!(db.get_artwork_links().unwrap(), vec![link]);
+        assert_eq!(db.get_media_artwork().unwrap()[&song_id], image("folder"));
+
+        // the file is read again without a picture, and the folder cover is gone
+        song.mtime = 2;
+        song.artwork = None;
+        db.apply_scan(vec![song]).unwrap();
+        let removed = db.sync_artwork_links(&[], None).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(db.get_artwork(folder_id).unwrap(), None);
+        assert!(db.get_media_artwork().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_metadata_cache() {
+        let mut db = create_test_db();
+        assert_eq!(
+            db.get_cached_metadata(CacheKind::Movie, "Inception (2010)")
+                .unwrap(),
+            None
+        );
+
+        let mut missing = CachedMetadata::not_found(CacheKind::Movie, "Inception (2010)");
+        missing.fetched_at = 10;
+        db.save_cached_metadata(&missing).unwrap();
+        let mut found = missing.clone();
+        found.plugin = "TMDB".to_string();
+        found.response = "{}".to_string();
+        found.fetched_at = 20;
+        db.save_cached_metadata(&found).unwrap();
+
+        // same query with other spaces and case, the newest answer is served
+        let cached = db.get_cached_metadata(CacheKind::Movie, " inception  (2010)");
+        assert_eq!(cached.unwrap(), Some(found.clone()));
+
+        // another plugin answering does not drop the rows of TMDB
+        let mut other = found.clone();
+        other.plugin = "OMDb".to_string();
+        other.fetched_at = 30;
+        db.save_cached_metadata(&other).unwrap();
+        found.fetched_at = 40;
+        db.save_cached_metadata(&found).unwrap();
+        let cached = db.get_cached_metadata(CacheKind::Movie, "Inception (2010)");
+        assert_eq!(cached.unwrap(), Some(found));
+        assert_eq!(
+            db.get_cached_metadata(CacheKind::Artist, "Inception (2010)")
+                .unwrap(),
+            None
+        );
+
+        assert_eq!(
+            db.delete_cached_metadata(CacheKind::Movie, "INCEPTION (2010)")
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            db.get_cached_metadata(CacheKind::Movie, "Inception (2010)")
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_artist_metadata_keys() {
+        use plugin_api::metadata::{ArtistMetadata, ExternalIds, Metadata};
+
+        let mut db = create_test_db();
+        let muse = MetadataResponse::found(Metadata::Artist(ArtistMetadata {
+            name: "Muse".to_string(),
+            external_ids: ExternalIds {
+                musicbrainz: Some("9c9f1380-2516-4fc9-a3e6-f9f61941d090".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        let muse_id = db.upsert_artist_metadata("Muse", &muse).unwrap();
+        let museum_id = db
+            .upsert_artist_metadata("Museum", &MetadataResponse::not_found())
+            .unwrap();
+        assert_ne!(muse_id, museum_id);
+
+        // exact keys: no prefix or substring match between the two artists
+        let cached = db.get_artist_metadata_by_name("  MUSE ").unwrap().unwrap();
+        assert_eq!((cached.id, cached.name.as_str()), (muse_id, "Muse"));
+        assert_eq!(cached.response, muse.to_json());
+        assert!(db
+            .get_artist_metadata_by_name("museum")
+            .unwrap()
+            .unwrap()
+            .is_not_found());
+        assert_eq!(db.get_artist_metadata_by_name("Mus").unwrap(), None);
+        assert_eq!(db.get_artist_metadata_by_name("Muse Museum").unwrap(), None);
+
+        let by_mbid = db
+            .get_artist_metadata_by_mbid("9c9f1380-2516-4fc9-a3e6-f9f61941d090")
+            .unwrap();
+        assert_eq!(by_mbid.map(|a| a.id), Some(muse_id));
+        assert_eq!(db.get_artist_metadata_by_mbid("9c9f1380").unwrap(), None);
+
+        // the upsert keeps the row, the invalidation removes only one key
+        assert_eq!(db.upsert_artist_metadata("muse", &muse).unwrap(), muse_id);
+        assert_eq!(db.invalidate_artist_metadata("MUSEUM").unwrap(), 1);
+        assert_eq!(db.get_artist_metadata_by_name("Museum").unwrap(), None);
+        assert!(db.get_artist_metadata_by_name("Muse").unwrap().is_some());
+    }
 }
@@ -0,0 +1,1040 @@
+/*
+This file handles the versioned schema migrations of the library database.
+The schema version is stored in `PRAGMA user_version`, every step runs in its own transaction
+so a failing migration leaves the database at the previous version.
+*/
+
+use rusqlite::{Connection, Transaction};
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt;
+
+use crate::constants::LOG_FILE;
+use crate::library::metadata_cache::normalize_key;
+use crate::logger::logger::Logger;
+
+pub struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    pub up: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+// Ordered list of every schema change, NEVER edit a migration that has been released,
+// add a new one at the end instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        up: v1_initial_schema,
+    },
+    Migration {
+        version: 2,
+        description: "full-text search index",
+        up: v2_search_index,
+    },
+    Migration {
+        version: 3,
+        description: "file size and mtime for incremental scans",
+        up: v3_file_stamps,
+    },
+    Migration {
+        version: 4,
+        description: "tag metadata columns",
+        up: v4_tag_metadata,
+    },
+    Migration {
+        version: 5,
+        description: "tv shows, seasons and episodes",
+        up: v5_series,
+    },
+    Migration {
+        version: 6,
+        description: "movie names parsed from the file names",
+        up: v6_movie_names,
+    },
+    Migration {
+        version: 7,
+        description: "kodi nfo sidecar files",
+        up: v7_nfo,
+    },
+    Migration {
+        version: 8,
+        description: "artwork cache",
+        up: v8_artwork,
+    },
+    Migration {
+        version: 9,
+        description: "artists, albums and album tracks",
+        up: v9_albums,
+    },
+    Migration {
+        version: 10,
+        description: "structured artist metadata",
+        up: v10_metadata_json,
+    },
+    Migration {
+        version: 11,
+        description: "plugin metadata cache",
+        up: v11_metadata_cache,
+    },
+    Migration {
+        version: 12,
+        description: "artist metadata keys",
+        up: v12_artist_metadata_keys,
+    },
+    Migration {
+        version: 13,
+        description: "albums by folder",
+        up: v13_album_folders,
+    },
+    Migration {
+        version: 14,
+        description: "search the artist overview and genres",
+        up: v14_artist_search_text,
+    },
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlite(rusqlite::Error),
+    // the database was written by a newer version of the app
+    DatabaseTooNew { found: i32, supported: i32 },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::Sqlite(e) => write!(f, "SQLite error: {}", e),
+            MigrationError::DatabaseTooNew { found, supported } => write!(
+                f,
+                "database schema version {} is newer than the supported version {}",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(e: rusqlite::Error) -> Self {
+        MigrationError::Sqlite(e)
+    }
+}
+
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+pub fn current_version(conn: &Connection) -> rusqlite::Result<i32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+// Brings the database up to the latest schema version, returns the final version
+pub fn migrate(conn: &mut Connection) -> Result<i32, MigrationError> {
+    migrate_to(conn, latest_version())
+}
+
+pub fn migrate_to(conn: &mut Connection, target: i32) -> Result<i32, MigrationError> {
+    run_migrations(conn, MIGRATIONS, target)
+}
+
+fn run_migrations(
+    conn: &mut Connection,
+    migrations: &[Migration],
+    target: i32,
+) -> Result<i32, MigrationError> {
+    let logger = Logger::new(LOG_FILE);
+    let current = current_version(conn)?;
+    let supported = migrations.last().map(|m| m.version).unwrap_or(0);
+
+    if current > supported {
+        return Err(MigrationError::DatabaseTooNew {
+            found: current,
+            supported,
+        });
+    }
+
+    for migration in migrations
+        .iter()
+        .filter(|m| m.version > current && m.version <= target)
+    {
+        let tx = conn.transaction()?;
+        (migration.up)(&tx)?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+
+        logger.info(&format!(
+            "Database migrated to version {} ({})",
+            migration.version, migration.description
+        ));
+    }
+
+    Ok(current_version(conn)?)
+}
+
+//========MIGRATIONS========
+
+// Databases created before migrations existed have user_version = 0 and already
+// contain these tables, hence the IF NOT EXISTS.
+fn v1_initial_schema(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            CREATE TABLE IF NOT EXISTS artist_metadata (
+                id INTEGER PRIMARY KEY,
+                artist_name TEXT UNIQUE NOT NULL,
+                info TEXT,
+                last_updated DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+
+            CREATE TABLE IF NOT EXISTS media (
+                id INTEGER PRIMARY KEY,
+                path TEXT UNIQUE NOT NULL,
+                title TEXT,
+                duration REAL,
+                media_type TEXT,
+                status INTEGER DEFAULT 0,
+                time_stop FLOAT DEFAULT 0.0
+            );
+
+            CREATE TABLE IF NOT EXISTS tags (
+                id INTEGER PRIMARY KEY,
+                name TEXT UNIQUE NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS media_tags (
+                media_id INTEGER NOT NULL,
+                tag_id INTEGER NOT NULL,
+                PRIMARY KEY (media_id, tag_id),
+                FOREIGN KEY (media_id) REFERENCES media(id) ON DELETE CASCADE,
+                FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE
+            );
+
+            CREATE TABLE IF NOT EXISTS playlists (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE
+            );
+
+            CREATE TABLE IF NOT EXISTS playlist_items (
+                playlist_id INTEGER NOT NULL,
+                media_id INTEGER NOT NULL,
+                position INTEGER DEFAULT 0,
+                PRIMARY KEY (playlist_id, media_id),
+                FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE,
+                FOREIGN KEY (media_id) REFERENCES media(id) ON DELETE CASCADE
+            );
+        ",
+    )
+}
+
+// FTS5 index kept in sync with media, media_tags and artist_metadata by triggers.
+// The rowid of media_search is the id of the media row.
+fn v2_search_index(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            ALTER TABLE media ADD COLUMN artist TEXT;
+            ALTER TABLE media ADD COLUMN album TEXT;
+
+            CREATE VIRTUAL TABLE media_search USING fts5(
+                title, path, artist, album, tags, metadata,
+                tokenize = 'unicode61 remove_diacritics 2'
+            );
+            CREATE VIRTUAL TABLE media_search_vocab USING fts5vocab(media_search, 'row');
+
+            CREATE TRIGGER media_search_ai AFTER INSERT ON media BEGIN
+                INSERT INTO media_search (rowid, title, path, artist, album, tags, metadata)
+                VALUES (
+                    new.id, new.title, new.path, new.artist, new.album, '',
+                    (SELECT info FROM artist_metadata WHERE artist_name = new.artist)
+                );
+            END;
+
+            CREATE TRIGGER media_search_au AFTER UPDATE OF title, path, artist, album ON media BEGIN
+                UPDATE media_search
+                SET title = new.title,
+                    path = new.path,
+                    artist = new.artist,
+                    album = new.album,
+                    metadata = (SELECT info FROM artist_metadata WHERE artist_name = new.artist)
+                WHERE rowid = new.id;
+            END;
+
+            CREATE TRIGGER media_search_ad AFTER DELETE ON media BEGIN
+                DELETE FROM media_search WHERE rowid = old.id;
+            END;
+
+            CREATE TRIGGER media_search_tags_ai AFTER INSERT ON media_tags BEGIN
+                UPDATE media_search
+                SET tags = (
+                    SELECT group_concat(tags.name, ' ')
+                    FROM media_tags JOIN tags ON tags.id = media_tags.tag_id
+                    WHERE media_tags.media_id = new.media_id
+                )
+                WHERE rowid = new.media_id;
+            END;
+
+            CREATE TRIGGER media_search_tags_ad AFTER DELETE ON media_tags BEGIN
+                UPDATE media_search
+                SET tags = (
+                    SELECT group_concat(tags.name, ' ')
+                    FROM media_tags JOIN tags ON tags.id = media_tags.tag_id
+                    WHERE media_tags.media_id = old.media_id
+                )
+                WHERE rowid = old.media_id;
+            END;
+
+            CREATE TRIGGER media_search_artist_ai AFTER INSERT ON artist_metadata BEGIN
+                UPDATE media_search SET metadata = new.info
+                WHERE rowid IN (SELECT id FROM media WHERE artist = new.artist_name);
+            END;
+
+            CREATE TRIGGER media_search_artist_au AFTER UPDATE ON artist_metadata BEGIN
+                UPDATE media_search SET metadata = new.info
+                WHERE rowid IN (SELECT id FROM media WHERE artist = new.artist_name);
+            END;
+
+            INSERT INTO media_search (rowid, title, path, artist, album, tags, metadata)
+            SELECT
+                media.id, media.title, media.path, media.artist, media.album,
+                (
+                    SELECT group_concat(tags.name, ' ')
+                    FROM media_tags JOIN tags ON tags.id = media_tags.tag_id
+                    WHERE media_tags.media_id = media.id
+                ),
+                (SELECT info FROM artist_metadata WHERE artist_name = media.artist)
+            FROM media;
+        ",
+    )
+}
+
+// NULL stamps mean "unknown", those rows are refreshed by the next scan
+fn v3_file_stamps(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            ALTER TABLE media ADD COLUMN file_size INTEGER;
+            ALTER TABLE media ADD COLUMN mtime INTEGER;
+        ",
+    )
+}
+
+// The stamps of audio and video rows are reset so the next scan reads their tags
+fn v4_tag_metadata(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            ALTER TABLE media ADD COLUMN album_artist TEXT;
+            ALTER TABLE media ADD COLUMN track_number INTEGER;
+            ALTER TABLE media ADD COLUMN disc_number INTEGER;
+            ALTER TABLE media ADD COLUMN year INTEGER;
+            ALTER TABLE media ADD COLUMN genre TEXT;
+            UPDATE media SET file_size = NULL, mtime = NULL WHERE media_type IN ('Audio', 'Video');
+        ",
+    )
+}
+
+// Filled from the video paths after each scan (see library/series.rs).
+// An episode row is the media row of its file, removed with it by a trigger
+// since the foreign keys are not enforced.
+fn v5_series(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            CREATE TABLE shows (
+                id INTEGER PRIMARY KEY,
+                title TEXT NOT NULL UNIQUE COLLATE NOCASE
+            );
+
+            CREATE TABLE seasons (
+                id INTEGER PRIMARY KEY,
+                show_id INTEGER NOT NULL,
+                number INTEGER NOT NULL,
+                UNIQUE (show_id, number),
+                FOREIGN KEY (show_id) REFERENCES shows(id) ON DELETE CASCADE
+            );
+
+            CREATE TABLE episodes (
+                media_id INTEGER PRIMARY KEY,
+                season_id INTEGER NOT NULL,
+                number INTEGER NOT NULL,
+                last_number INTEGER,
+                absolute INTEGER NOT NULL DEFAULT 0,
+                title TEXT,
+                FOREIGN KEY (media_id) REFERENCES media(id) ON DELETE CASCADE,
+                FOREIGN KEY (season_id) REFERENCES seasons(id) ON DELETE CASCADE
+            );
+            CREATE INDEX episodes_season ON episodes(season_id, number);
+
+            CREATE TRIGGER episodes_media_ad AFTER DELETE ON media BEGIN
+                DELETE FROM episodes WHERE media_id = old.id;
+            END;
+        ",
+    )
+}
+
+// Filled from the video paths after each scan (see library/movies.rs), NULL for episodes
+fn v6_movie_names(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            ALTER TABLE media ADD COLUMN movie_title TEXT;
+            ALTER TABLE media ADD COLUMN movie_year INTEGER;
+            ALTER TABLE media ADD COLUMN movie_edition TEXT;
+            ALTER TABLE media ADD COLUMN movie_part INTEGER;
+        ",
+    )
+}
+
+// One row per .nfo file found next to the media (see library/nfo.rs), media_id is NULL
+// for tvshow.nfo. The mtime tells the next refresh which files must be read again.
+fn v7_nfo(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            CREATE TABLE nfo (
+                id INTEGER PRIMARY KEY,
+                path TEXT NOT NULL UNIQUE,
+                mtime INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                media_id INTEGER,
+                title TEXT,
+                original_title TEXT,
+                show_title TEXT,
+                plot TEXT,
+                year INTEGER,
+                rating REAL,
+                genres TEXT,
+                season INTEGER,
+                episode INTEGER,
+                FOREIGN KEY (media_id) REFERENCES media(id) ON DELETE SET NULL
+            );
+            CREATE INDEX nfo_media ON nfo(media_id);
+
+            CREATE TABLE nfo_actors (
+                nfo_id INTEGER NOT NULL,
+                position INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                role TEXT,
+                thumb TEXT,
+                PRIMARY KEY (nfo_id, position),
+                FOREIGN KEY (nfo_id) REFERENCES nfo(id) ON DELETE CASCADE
+            );
+
+            CREATE TABLE nfo_artwork (
+                nfo_id INTEGER NOT NULL,
+                position INTEGER NOT NULL,
+                aspect TEXT NOT NULL,
+                url TEXT NOT NULL,
+                PRIMARY KEY (nfo_id, position),
+                FOREIGN KEY (nfo_id) REFERENCES nfo(id) ON DELETE CASCADE
+            );
+
+            CREATE TRIGGER nfo_ad AFTER DELETE ON nfo BEGIN
+                DELETE FROM nfo_actors WHERE nfo_id = old.id;
+                DELETE FROM nfo_artwork WHERE nfo_id = old.id;
+            END;
+
+            CREATE TRIGGER nfo_media_ad AFTER DELETE ON media BEGIN
+                UPDATE nfo SET media_id = NULL WHERE media_id = old.id;
+            END;
+        ",
+    )
+}
+
+// The images themselves are in the cache directory (see library/artwork.rs).
+// embedded_artwork is written by the scan, artwork_links by the refresh after it.
+// The stamps of audio and video rows are reset so the next scan reads their embedded covers.
+fn v8_artwork(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            CREATE TABLE artwork (
+                id INTEGER PRIMARY KEY,
+                hash TEXT NOT NULL UNIQUE,
+                mime TEXT NOT NULL,
+                size INTEGER NOT NULL
+            );
+
+            CREATE TABLE embedded_artwork (
+                media_id INTEGER PRIMARY KEY,
+                artwork_id INTEGER NOT NULL,
+                FOREIGN KEY (media_id) REFERENCES media(id) ON DELETE CASCADE,
+                FOREIGN KEY (artwork_id) REFERENCES artwork(id)
+            );
+
+            CREATE TABLE artwork_links (
+                owner_type TEXT NOT NULL,
+                owner_key TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                artwork_id INTEGER NOT NULL,
+                source TEXT NOT NULL,
+                source_mtime INTEGER,
+                PRIMARY KEY (owner_type, owner_key, kind),
+                FOREIGN KEY (artwork_id) REFERENCES artwork(id)
+            );
+
+            CREATE TRIGGER artwork_media_ad AFTER DELETE ON media BEGIN
+                DELETE FROM embedded_artwork WHERE media_id = old.id;
+                DELETE FROM artwork_links WHERE owner_type = 'media' AND owner_key = CAST(old.id AS TEXT);
+            END;
+
+            UPDATE media SET file_size = NULL, mtime = NULL WHERE media_type IN ('Audio', 'Video');
+        ",
+    )
+}
+
+// Filled from the audio tags after each scan (see library/music.rs).
+// An artist row is linked to the plugin answer cached in artist_metadata, by name.
+fn v9_albums(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            CREATE TABLE artists (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                metadata_id INTEGER,
+                FOREIGN KEY (metadata_id) REFERENCES artist_metadata(id) ON DELETE SET NULL
+            );
+
+            CREATE TABLE albums (
+                id INTEGER PRIMARY KEY,
+                title TEXT NOT NULL COLLATE NOCASE,
+                artist_id INTEGER NOT NULL,
+                compilation INTEGER NOT NULL DEFAULT 0,
+                year INTEGER,
+                UNIQUE (title, artist_id),
+                FOREIGN KEY (artist_id) REFERENCES artists(id) ON DELETE CASCADE
+            );
+
+            CREATE TABLE album_tracks (
+                media_id INTEGER PRIMARY KEY,
+                album_id INTEGER NOT NULL,
+                artist_id INTEGER,
+                disc INTEGER,
+                track INTEGER,
+                FOREIGN KEY (media_id) REFERENCES media(id) ON DELETE CASCADE,
+                FOREIGN KEY (album_id) REFERENCES albums(id) ON DELETE CASCADE,
+                FOREIGN KEY (artist_id) REFERENCES artists(id) ON DELETE SET NULL
+            );
+            CREATE INDEX album_tracks_album ON album_tracks(album_id, disc, track);
+            CREATE INDEX album_tracks_artist ON album_tracks(artist_id);
+
+            CREATE TRIGGER album_tracks_media_ad AFTER DELETE ON media BEGIN
+                DELETE FROM album_tracks WHERE media_id = old.id;
+            END;
+        ",
+    )
+}
+
+// artist_metadata.info holds a plugin_api::metadata::MetadataResponse as JSON.
+// The free text answers of the old plugins cannot be read anymore, they are fetched again.
+fn v10_metadata_json(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            DELETE FROM artist_metadata WHERE info IS NULL OR json_valid(info) = 0;
+            UPDATE artists SET metadata_id = NULL
+            WHERE metadata_id NOT IN (SELECT id FROM artist_metadata);
+        ",
+    )
+}
+
+// Answers of the plugins by (plugin, kind, normalized query), see library/metadata_cache.rs.
+// fetched_at is in unix seconds, the TTL is applied when reading.
+fn v11_metadata_cache(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            CREATE TABLE metadata_cache (
+                plugin TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                key TEXT NOT NULL,
+                response TEXT NOT NULL,
+                fetched_at INTEGER NOT NULL,
+                PRIMARY KEY (plugin, kind, key)
+            );
+            CREATE INDEX metadata_cache_key ON metadata_cache(kind, key, fetched_at);
+        ",
+    )
+}
+
+// artist_metadata is looked up by normalized name or MusicBrainz id instead of searching the
+// text of every row. Rows whose names only differ by case or spaces are merged, the newest kept.
+fn v12_artist_metadata_keys(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            ALTER TABLE artist_metadata ADD COLUMN name_key TEXT;
+            ALTER TABLE artist_metadata ADD COLUMN musicbrainz_id TEXT;
+            UPDATE artist_metadata
+            SET musicbrainz_id = json_extract(info, '$.metadata.external_ids.musicbrainz');
+        ",
+    )?;
+
+    let rows: Vec<(i64, String)> = tx
+        .prepare("SELECT id, artist_name FROM artist_metadata ORDER BY last_updated DESC, id DESC")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut keys = HashMap::new();
+    for (id, name) in rows {
+        match keys.entry(normalize_key(&name)) {
+            Entry::Occupied(_) => {
+                tx.execute("DELETE FROM artist_metadata WHERE id = ?1", [id])?;
+            }
+            Entry::Vacant(entry) => {
+                tx.execute(
+                    "UPDATE artist_metadata SET name_key = ?1 WHERE id = ?2",
+                    (entry.key(), id),
+                )?;
+                entry.insert(id);
+            }
+        }
+    }
+
+    let artists: Vec<(i64, String)> = tx
+        .prepare("SELECT id, name FROM artists")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    for (id, name) in artists {
+        tx.execute(
+            "UPDATE artists SET metadata_id = ?1 WHERE id = ?2",
+            (keys.get(&normalize_key(&name)), id),
+        )?;
+    }
+
+    tx.execute_batch(
+        "
+            CREATE UNIQUE INDEX artist_metadata_name_key ON artist_metadata(name_key);
+            CREATE INDEX artist_metadata_musicbrainz ON artist_metadata(musicbrainz_id);
+
+            CREATE TRIGGER artist_metadata_ad AFTER DELETE ON artist_metadata BEGIN
+                UPDATE artists SET metadata_id = NULL WHERE metadata_id = old.id;
+                UPDATE media_search SET metadata = NULL
+                WHERE rowid IN (SELECT id FROM media WHERE artist = old.artist_name);
+            END;
+        ",
+    )
+}
+
+// Two albums with the same title and album artist in two folders are two albums.
+// The albums are built again from the tags when the library is opened.
+fn v13_album_folders(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            DROP TABLE album_tracks;
+            DROP TABLE albums;
+
+            CREATE TABLE albums (
+                id INTEGER PRIMARY KEY,
+                title TEXT NOT NULL COLLATE NOCASE,
+                artist_id INTEGER NOT NULL,
+                folder TEXT NOT NULL,
+                compilation INTEGER NOT NULL DEFAULT 0,
+                year INTEGER,
+                UNIQUE (title, artist_id, folder),
+                FOREIGN KEY (artist_id) REFERENCES artists(id) ON DELETE CASCADE
+            );
+
+            CREATE TABLE album_tracks (
+                media_id INTEGER PRIMARY KEY,
+                album_id INTEGER NOT NULL,
+                artist_id INTEGER,
+                disc INTEGER,
+                track INTEGER,
+                FOREIGN KEY (media_id) REFERENCES media(id) ON DELETE CASCADE,
+                FOREIGN KEY (album_id) REFERENCES albums(id) ON DELETE CASCADE,
+                FOREIGN KEY (artist_id) REFERENCES artists(id) ON DELETE SET NULL
+            );
+            CREATE INDEX album_tracks_album ON album_tracks(album_id, disc, track);
+            CREATE INDEX album_tracks_artist ON album_tracks(artist_id);
+        ",
+    )
+}
+
+// The search index held the whole JSON answer of the plugin (keys, urls, ids...),
+// only the overview and the genres of the artist are indexed now.
+fn v14_artist_search_text(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+            CREATE VIEW artist_search_text (artist_name, text) AS
+            SELECT artist_name,
+                CASE WHEN json_valid(info) THEN trim(
+                    COALESCE(json_extract(info, '$.metadata.overview'), '') || ' ' ||
+                    COALESCE((SELECT group_concat(value, ' ')
+                              FROM json_each(info, '$.metadata.genres')), '')
+                ) END
+            FROM artist_metadata;
+
+            DROP TRIGGER media_search_ai;
+            DROP TRIGGER media_search_au;
+            DROP TRIGGER media_search_artist_ai;
+            DROP TRIGGER media_search_artist_au;
+
+            CREATE TRIGGER media_search_ai AFTER INSERT ON media BEGIN
+                INSERT INTO media_search (rowid, title, path, artist, album, tags, metadata)
+                VALUES (
+                    new.id, new.title, new.path, new.artist, new.album, '',
+                    (SELECT text FROM artist_search_text WHERE artist_name = new.artist)
+                );
+            END;
+
+            CREATE TRIGGER media_search_au AFTER UPDATE OF title, path, artist, album ON media BEGIN
+                UPDATE media_search
+                SET title = new.title,
+                    path = new.path,
+                    artist = new.artist,
+                    album = new.album,
+                    metadata = (SELECT text FROM artist_search_text WHERE artist_name = new.artist)
+                WHERE rowid = new.id;
+            END;
+
+            CREATE TRIGGER media_search_artist_ai AFTER INSERT ON artist_metadata BEGIN
+                UPDATE media_search
+                SET metadata = (
+                    SELECT text FROM artist_search_text WHERE artist_name = new.artist_name
+                )
+                WHERE rowid IN (SELECT id FROM media WHERE artist = new.artist_name);
+            END;
+
+            CREATE TRIGGER media_search_artist_au AFTER UPDATE ON artist_metadata BEGIN
+                UPDATE media_search
+                SET metadata = (
+                    SELECT text FROM artist_search_text WHERE artist_name = new.artist_name
+                )
+                WHERE rowid IN (SELECT id FROM media WHERE artist = new.artist_name);
+            END;
+
+            UPDATE media_search
+            SET metadata = (
+                SELECT text FROM artist_search_text
+                JOIN media ON media.artist = artist_search_text.artist_name
+                WHERE media.id = media_search.rowid
+            );
+        ",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Schema shipped before migrations existed (user_version = 0), with some user data in it.
+    fn legacy_fixture() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "
+                CREATE TABLE artist_metadata (
+                    id INTEGER PRIMARY KEY,
+                    artist_name TEXT UNIQUE NOT NULL,
+                    info TEXT,
+                    last_updated DATETIME DEFAULT CURRENT_TIMESTAMP
+                );
+                CREATE TABLE media (
+                    id INTEGER PRIMARY KEY,
+                    path TEXT UNIQUE NOT NULL,
+                    title TEXT,
+                    duration REAL,
+                    media_type TEXT,
+                    status INTEGER DEFAULT 0,
+                    time_stop FLOAT DEFAULT 0.0
+                );
+                CREATE TABLE tags (id INTEGER PRIMARY KEY, name TEXT UNIQUE NOT NULL);
+                CREATE TABLE media_tags (
+                    media_id INTEGER NOT NULL,
+                    tag_id INTEGER NOT NULL,
+                    PRIMARY KEY (media_id, tag_id)
+                );
+                CREATE TABLE playlists (id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE COLLATE NOCASE);
+                CREATE TABLE playlist_items (
+                    playlist_id INTEGER NOT NULL,
+                    media_id INTEGER NOT NULL,
+                    position INTEGER DEFAULT 0,
+                    PRIMARY KEY (playlist_id, media_id)
+                );
+
+                INSERT INTO media (id, path, title, duration, media_type, status, time_stop)
+                VALUES (1, '/films/movie.mkv', 'movie.mkv', 7200.0, 'Video', 1, 3600.0);
+                INSERT INTO tags (id, name) VALUES (1, 'action');
+                INSERT INTO media_tags (media_id, tag_id) VALUES (1, 1);
+                INSERT INTO playlists (id, name) VALUES (1, 'Soirée');
+                INSERT INTO playlist_items (playlist_id, media_id) VALUES (1, 1);
+            ",
+        )
+        .unwrap();
+        conn
+    }
+
+    // Checks that the user data inserted by the fixtures survived the upgrade
+    fn assert_user_data_kept(conn: &Connection) {
+        let (status, time_stop): (i32, f64) = conn
+            .query_row(
+                "SELECT status, time_stop FROM media WHERE path = '/films/movie.mkv'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(status, 1);
+        assert_eq!(time_stop, 3600.0);
+
+        let tagged: i64 = conn
+            .query_row("SELECT COUNT(*) FROM media_tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tagged, 1);
+
+        let in_playlist: i64 = conn
+            .query_row("SELECT COUNT(*) FROM playlist_items", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(in_playlist, 1);
+    }
+
+    #[test]
+    fn migrations_are_ordered_and_unique() {
+        for pair in MIGRATIONS.windows(2) {
+            assert_eq!(pair[1].version, pair[0].version + 1);
+        }
+        assert_eq!(MIGRATIONS[0].version, 1);
+    }
+
+    #[test]
+    fn migrate_empty_database_to_latest() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        let version = migrate(&mut conn).unwrap();
+
+        assert_eq!(version, latest_version());
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn migrate_is_idempotent() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        assert_eq!(migrate(&mut conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn migrate_legacy_unversioned_database() {
+        let mut conn = legacy_fixture();
+        assert_eq!(current_version(&conn).unwrap(), 0);
+
+        migrate(&mut conn).unwrap();
+
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+        assert_user_data_kept(&conn);
+
+        // existing rows are backfilled into the search index
+        let indexed: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM media_search WHERE media_search MATCH 'movie AND action'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(indexed, 1);
+    }
+
+    #[test]
+    fn tag_columns_force_a_new_read_of_audio_and_video() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_to(&mut conn, 3).unwrap();
+        conn.execute_batch(
+            "
+                INSERT INTO media (path, title, media_type, file_size, mtime)
+                VALUES ('/music/song.mp3', 'song.mp3', 'Audio', 10, 1),
+                       ('/photos/cover.jpg', 'cover.jpg', 'Image', 10, 1);
+            ",
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+
+        let stamped: Vec<String> = conn
+            .prepare("SELECT path FROM media WHERE file_size IS NOT NULL")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(stamped, vec!["/photos/cover.jpg".to_string()]);
+    }
+
+    #[test]
+    fn only_the_artist_overview_and_genres_are_searched() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_to(&mut conn, 13).unwrap();
+        conn.execute_batch(
+            r#"
+                INSERT INTO media (id, path, title, media_type, artist)
+                VALUES (1, '/music/Lithium.mp3', 'Lithium', 'Audio', 'Nirvana');
+                INSERT INTO artist_metadata (artist_name, name_key, info)
+                VALUES ('Nirvana', 'nirvana', '{"schema_version": 1, "status": "found",
+                    "metadata": {"kind": "artist", "name": "Nirvana",
+                                 "overview": "Band from Aberdeen", "genres": ["grunge"]}}');
+            "#,
+        )
+        .unwrap();
+        let count = |conn: &Connection, query: &str| -> i64 {
+            conn.query_row(
+                "SELECT COUNT(*) FROM media_search WHERE media_search MATCH ?1",
+                [query],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(count(&conn, "metadata:found"), 1);
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(count(&conn, "metadata:found"), 0);
+        assert_eq!(count(&conn, "metadata:aberdeen"), 1);
+
+        // the triggers index the new answers the same way
+        conn.execute(
+            r#"UPDATE artist_metadata SET info = '{"schema_version": 1, "status": "found",
+                "metadata": {"kind": "artist", "name": "Nirvana", "genres": ["rock"]}}'"#,
+            [],
+        )
+        .unwrap();
+        assert_eq!(count(&conn, "metadata:rock"), 1);
+        assert_eq!(count(&conn, "metadata:kind OR metadata:aberdeen"), 0);
+        conn.execute(
+            "INSERT INTO media (path, title, media_type, artist) VALUES ('/b.mp3', 'B', 'Audio', 'Nirvana')",
+            [],
+        )
+        .unwrap();
+        assert_eq!(count(&conn, "metadata:rock"), 2);
+    }
+
+    #[test]
+    fn free_text_artist_metadata_is_dropped() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_to(&mut conn, 9).unwrap();
+        conn.execute_batch(
+            r#"
+                INSERT INTO artist_metadata (id, artist_name, info)
+                VALUES (1, 'muse', 'Muse: English rock band'),
+                       (2, 'nirvana', '{"schema_version": 1, "status": "not_found"}');
+                INSERT INTO artists (name, metadata_id) VALUES ('muse', 1), ('nirvana', 2);
+            "#,
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+
+        let linked: Vec<(String, Option<i64>)> = conn
+            .prepare("SELECT name, metadata_id FROM artists ORDER BY name")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            linked,
+            vec![("muse".to_string(), None), ("nirvana".to_string(), Some(2))]
+        );
+    }
+
+    #[test]
+    fn artist_metadata_gets_one_row_per_key() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_to(&mut conn, 11).unwrap();
+        conn.execute_batch(
+            r#"
+                INSERT INTO artist_metadata (id, artist_name, info, last_updated) VALUES
+                    (1, 'muse', '{"schema_version": 1, "status": "not_found"}', '2024-01-01'),
+                    (2, 'Muse', '{"schema_version": 1, "status": "found", "metadata":
+                        {"kind": "artist", "name": "Muse",
+                         "external_ids": {"musicbrainz": "9c9f1380"}}}', '2024-02-01'),
+                    (3, 'Museum', '{"schema_version": 1, "status": "not_found"}', '2024-01-01');
+                INSERT INTO artists (name, metadata_id) VALUES ('MUSE', 1), ('Museum', NULL);
+            "#,
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+
+        let rows: Vec<(i64, String, Option<String>)> = conn
+            .prepare("SELECT id, name_key, musicbrainz_id FROM artist_metadata ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (2, "muse".to_string(), Some("9c9f1380".to_string())),
+                (3, "museum".to_string(), None),
+            ]
+        );
+        let linked: Vec<Option<i64>> = conn
+            .prepare("SELECT metadata_id FROM artists ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(linked, vec![Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn migrate_from_every_past_version() {
+        for from in 1..=latest_version() {
+            let mut conn = Connection::open_in_memory().unwrap();
+            migrate_to(&mut conn, from).unwrap();
+            conn.execute_batch(
+                "
+                    INSERT INTO media (path, title, duration, media_type, status, time_stop)
+                    VALUES ('/films/movie.mkv', 'movie.mkv', 7200.0, 'Video', 1, 3600.0);
+                    INSERT INTO tags (name) VALUES ('action');
+                    INSERT INTO media_tags (media_id, tag_id) VALUES (1, 1);
+                    INSERT INTO playlists (name) VALUES ('Soirée');
+                    INSERT INTO playlist_items (playlist_id, media_id) VALUES (1, 1);
+                ",
+            )
+            .unwrap();
+
+            migrate(&mut conn).unwrap();
+
+            assert_eq!(current_version(&conn).unwrap(), latest_version());
+            assert_user_data_kept(&conn);
+        }
+    }
+
+    #[test]
+    fn refuse_database_newer_than_binary() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1)
+            .unwrap();
+
+        match migrate(&mut conn) {
+            Err(MigrationError::DatabaseTooNew { found, supported }) => {
+                assert_eq!(found, latest_version() + 1);
+                assert_eq!(supported, latest_version());
+            }
+            _ => panic!("Expected DatabaseTooNew error"),
+        }
+    }
+
+    #[test]
+    fn failing_migration_is_rolled_back() {
+        fn broken(tx: &Transaction) -> rusqlite::Result<()> {
+            tx.execute_batch("CREATE TABLE broken (id INTEGER); SELECT * FROM missing_table;")
+        }
+        let migrations = [
+            Migration {
+                version: 1,
+                description: "initial schema",
+                up: v1_initial_schema,
+            },
+            Migration {
+                version: 2,
+                description: "broken",
+                up: broken,
+            },
+        ];
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        assert!(run_migrations(&mut conn, &migrations, 2).is_err());
+
+        // the first step is kept, the broken one left nothing behind
+        assert_eq!(current_version(&conn).unwrap(), 1);
+        let broken_tables: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'broken'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(broken_tables, 0);
+    }
+}
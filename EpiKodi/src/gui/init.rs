@@ -6,12 +6,19 @@ use tokio::sync::broadcast;
 use crate::threading::command::{Command, Event};
 use crate::threading::media_thread::launch_media_thread;
 
-use crate::gui::pages::PluginSearchResult;
+use crate::gui::pages::{PluginSearchResult, ProviderAnswers};
 use crate::gui::route::Route;
+use crate::library::music::{Album, AlbumTrack, Artist};
+use crate::library::nfo::NfoExport;
+use crate::library::search::SearchResult;
+use crate::library::series::{Episode, Show};
 use crate::media::data::{MediaInfo, MediaType};
+use crate::scan::scan::ScanProgress;
+use crate::scan::scanner::ScanSummary;
 
 use crate::config::AppConfig;
 use crate::iptv::parser::TVChannel;
+use crate::plugin::plugin_manager::PluginStatus;
 use std::cell::RefCell;
 use std::path::PathBuf;
 use std::rc::Rc;
@@ -27,6 +34,7 @@ fn startup_commands(root_path: PathBuf) -> Vec<Command> {
         Command::GetAllMedia(),
         Command::GetAllPlaylists(),
         Command::GetPluginHistory,
+        Command::GetPlugins(),
     ]
 }
 
@@ -66,8 +74,32 @@ pub fn App() -> Element {
     let mut playlists = use_context_provider(|| Signal::new(Vec::<(i64, String)>::new()));
     let mut loaded_ids = use_context_provider(|| Signal::new(Vec::<i64>::new()));
 
+    // Résultats de la recherche plein texte
+    let mut search_results = use_context_provider(|| Signal::new(Vec::<SearchResult>::new()));
+
+    // Séries : liste des séries, et épisodes de la série ouverte
+    let mut shows = use_context_provider(|| Signal::new(Vec::<Show>::new()));
+    let mut show_episodes = use_context_provider(|| Signal::new(Vec::<Episode>::new()));
+
+    // Musique : artistes, albums de l'artiste ouvert et pistes de l'album ouvert
+    let mut artists = use_context_provider(|| Signal::new(Vec::<Artist>::new()));
+    let mut albums = use_context_provider(|| Signal::new(Vec::<Album>::new()));
+    let mut album_tracks = use_context_provider(|| Signal::new(Vec::<AlbumTrack>::new()));
+
+    // Avancement du scan en cours, et résumé du dernier scan
+    let mut scan_progress = use_context_provider(|| Signal::new(Option::<ScanProgress>::None));
+    let mut scan_summary = use_context_provider(|| Signal::new(Option::<ScanSummary>::None));
+
+    // Résultat du dernier export des fichiers NFO
+    let mut nfo_export = use_context_provider(|| Signal::new(Option::<NfoExport>::None));
+
     // Plugin Result
-    let mut plugin_result = use_context_provider(|| Signal::new(Vec::<String>::new()));
+    let mut plugin_result =
+        use_context_provider(|| Signal::new(Vec::<PluginSearchResult>::new()));
+    // Plugins chargés (nom, version, auteur, capacités)
+    let mut plugins = use_context_provider(|| Signal::new(Vec::<PluginStatus>::new()));
+    // Paroles, sous-titres et pochette du média en cours
+    let mut provider_answers = use_context_provider(|| Signal::new(ProviderAnswers::default()));
 
     let mut root_path_signal = use_context_provider(|| Signal::new(String::new()));
     let current_config = AppConfig::load();
@@ -94,15 +126,101 @@ pub fn App() -> Element {
                                 media_list.set(list);
                             }
 
+                            Event::SearchResults(results) => {
+                                search_results.set(results);
+                            }
+
+                            Event::ShowList(list) => {
+                                shows.set(list);
+                            }
+                            Event::EpisodeList(_, episodes) => {
+                                show_episodes.set(episodes);
+                            }
+
+                            Event::ArtistList(list) => {
+                                artists.set(list);
+                            }
+                            Event::AlbumList(_, list) => {
+                                albums.set(list);
+                            }
+                            Event::AlbumTrackList(_, tracks) => {
+                                album_tracks.set(tracks);
+                            }
+
+                            // des fichiers ont changé sur le disque, on rafraîchit les pages ouvertes
+                            Event::LibraryChanged(_) => {
+                                let _ = backend.tx.send(Command::GetAllMedia());
+                                let _ = backend.tx.send(Command::GetShows());
+                            }
+
+                            Event::ScanProgress {
+                                source,
+                                files_seen,
+                                files_indexed,
+                            } => {
+                                scan_progress.set(Some(ScanProgress {
+                                    source,
+                                    files_seen,
+                                    files_indexed,
+                                }));
+                            }
+
+                            Event::ScanFinished(summary) => {
+                                scan_progress.set(None);
+                                if !summary.cancelled {
+                                    let _ = backend.tx.send(Command::GetAllMedia());
+                                    let _ = backend.tx.send(Command::GetShows());
+                                }
+                                scan_summary.set(Some(summary));
+                            }
+
+                            Event::NfoExported(export) => {
+                                nfo_export.set(Some(export));
+                            }
+
                             Event::PlaylistList(list) => {
                                 playlists.set(list);
                             }
+                            Event::PluginList(list) => {
+                                plugins.set(list);
+                            }
                             Event::IDList(ids) => {
                                 loaded_ids.set(ids);
                             }
 
-                            Event::PluginDataReceived(info) => {
-                                plugin_result.write().insert(0, info);
+                            Event::Metadata {
+                                query,
+                                result,
+                                cached_at,
+                            } => {
+                                let mut history = plugin_result.write();
+                                // la réponse remplace les "chargement..." en attente,
+                                // et l'ancienne réponse quand une info en cache a été rafraîchie
+                                history.retain(|entry| entry.result.is_some() && entry.query != query);
+                                history.insert(
+                                    0,
+                                    PluginSearchResult {
+                                        query,
+                                        result: Some(result.map_err(|e| e.to_string())),
+                                        cached_at,
+                                    },
+                                );
+                            }
+
+                            Event::Lyrics { media_id, result } => {
+                                provider_answers.write().of(media_id).lyrics =
+                                    Some(Some(result.map_err(|e| e.to_string())));
+                            }
+                            Event::Subtitles { media_id, result } => {
+                                provider_answers.write().of(media_id).subtitles =
+                                    Some(Some(result.map_err(|e| e.to_string())));
+                            }
+                            Event::SubtitleSaved { media_id, result } => {
+                                provider_answers.write().of(media_id).subtitle_saved = Some(result);
+                            }
+                            Event::Artwork { media_id, result } => {
+                                provider_answers.write().of(media_id).artwork =
+                                    Some(Some(result.map_err(|e| e.to_string())));
                             }
 
                             Event::NowPlaying(id) => println!("▶️ Lecture ID: {}", id),
@@ -135,7 +253,7 @@ mod tests {
         let root_path = PathBuf::from("C:/media");
         let commands = startup_commands(root_path.clone());
 
-        assert_eq!(commands.len(), 6);
+        assert_eq!(commands.len(), 7);
 
         assert!(matches!(
             &commands[0],
@@ -152,6 +270,7 @@ mod tests {
         assert!(matches!(commands[3], Command::GetAllMedia()));
         assert!(matches!(commands[4], Command::GetAllPlaylists()));
         assert!(matches!(commands[5], Command::GetPluginHistory));
+        assert!(matches!(commands[6], Command::GetPlugins()));
     }
 
     #[test]
@@ -1,20 +1,172 @@
 use super::route::Route;
 use crate::constants::SOURCE_FILE;
-use crate::library::sources::LibraryConfig;
+use crate::library::music::{Album, AlbumTrack, Artist};
+use crate::library::nfo::NfoExport;
+use crate::library::search::{highlighted_parts, SearchFilters, SearchResult};
+use crate::library::series::{Episode, Show};
+use crate::library::sources::{LibraryConfig, MediaSource};
 use crate::media::data::{MediaInfo, MediaType};
+use crate::scan::scan::ScanProgress;
+use crate::scan::scanner::ScanSummary;
 use crate::threading::command::Command;
+use crate::threading::provider_lookup::SubtitleError;
 use base64::{engine::general_purpose, Engine as _};
 use dioxus::prelude::*;
+use crate::plugin::plugin_manager::{PluginMetadata, PluginStatus};
+use plugin_api::metadata::{Metadata, MetadataImage};
+use plugin_api::providers::{Lyrics, SubtitleResult};
+use plugin_api::settings::{PluginSettings, SettingField, SettingKind, SettingValue};
 use rand::Rng;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::PathBuf;
-use std::time::Duration;
 use urlencoding::encode;
 
 // 👇 STRUCTURE POUR LES PLUGINS
 #[derive(Clone, PartialEq)]
 pub struct PluginSearchResult {
-    pub text: String,
+    pub query: String,
+    pub result: Option<Result<Metadata, String>>, // None tant que le plugin n'a pas répondu
+    pub cached_at: Option<i64>,                   // réponse lue dans le cache (secondes unix)
+}
+
+impl PluginSearchResult {
+    pub fn pending(query: &str) -> Self {
+        Self {
+            query: query.to_string(),
+            result: None,
+            cached_at: None,
+        }
+    }
+
+    pub fn cached_label(&self) -> Option<String> {
+        let fetched = chrono::DateTime::from_timestamp(self.cached_at?, 0)?;
+        let fetched = fetched.with_timezone(&chrono::Local);
+        Some(format!("📦 En cache depuis le {}", fetched.format("%d/%m/%Y à %H:%M")))
+    }
+
+    // Une ligne pour la barre du lecteur
+    pub fn summary(&self) -> String {
+        match &self.result {
+            None => "...".to_string(),
+            Some(Err(e)) => format!("{} : {}", self.query, e),
+            Some(Ok(Metadata::Artist(artist))) => {
+                let mut parts = vec![artist.name.clone()];
+                parts.extend(artist.artist_type.clone());
+                parts.extend(artist.country.clone());
+                if let Some(begin) = &artist.begin {
+                    parts.push(format!("depuis {}", begin));
+                }
+                parts.extend(artist.genres.first().cloned());
+                parts.join(" · ")
+            }
+            Some(Ok(Metadata::Movie(movie))) => match movie.year {
+                Some(year) => format!("{} ({})", movie.title, year),
+                None => movie.title.clone(),
+            },
+        }
+    }
+}
+
+// 👇 RÉPONSES DES PLUGINS POUR LE MÉDIA EN COURS (paroles, sous-titres, pochette)
+// None tant que rien n'a été demandé, Some(None) pendant le chargement
+#[derive(Clone, PartialEq, Default)]
+pub struct ProviderAnswers {
+    pub media_id: i64,
+    pub lyrics: Option<Option<Result<PluginMetadata<Lyrics>, String>>>,
+    pub subtitles: Option<Option<Result<Vec<PluginMetadata<SubtitleResult>>, String>>>,
+    pub subtitle_saved: Option<Result<PathBuf, SubtitleError>>,
+    pub subtitle_asked: Option<(String, String)>, // plugin et id du dernier sous-titre demandé
+    pub artwork: Option<Option<Result<PluginMetadata<Vec<MetadataImage>>, String>>>,
+}
+
+impl ProviderAnswers {
+    // Les réponses d'un autre média sont oubliées
+    pub fn of(&mut self, media_id: i64) -> &mut Self {
+        if self.media_id != media_id {
+            *self = Self {
+                media_id,
+                ..Default::default()
+            };
+        }
+        self
+    }
+}
+
+fn poster_url(images: &[MetadataImage]) -> Option<String> {
+    ["poster", "thumb", "fanart"].iter().find_map(|kind| {
+        images
+            .iter()
+            .find(|image| image.kind == *kind)
+            .map(|image| image.url.clone())
+    })
+}
+
+// Fiche d'un résultat de plugin (artiste ou film)
+#[component]
+fn MetadataCard(entry: PluginSearchResult) -> Element {
+    match entry.result {
+        None => rsx! { p { style: "color: #888; font-style: italic;", "Chargement des infos..." } },
+        Some(Err(e)) => rsx! { p { style: "color: #e67e22;", "{entry.query} : {e}" } },
+        Some(Ok(Metadata::Artist(artist))) => {
+            let details: Vec<String> = [&artist.artist_type, &artist.country, &artist.begin]
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect();
+            let details = details.join(" · ");
+            let genres = artist.genres.join(", ");
+            rsx! {
+                div { style: "display: flex; gap: 20px;",
+                    if let Some(url) = poster_url(&artist.images) {
+                        img { src: "{url}", style: "width: 120px; height: 120px; object-fit: cover; border-radius: 8px;" }
+                    }
+                    div { style: "flex: 1;",
+                        h2 { style: "margin: 0 0 5px 0; color: #fff;", "{artist.name}" }
+                        if !details.is_empty() {
+                            div { style: "color: #aaa; margin-bottom: 10px;", "{details}" }
+                        }
+                        if !genres.is_empty() {
+                            div { style: "color: #007acc; margin-bottom: 10px;", "{genres}" }
+                        }
+                        if let Some(overview) = &artist.overview {
+                            p { style: "color: #ccc; line-height: 1.6; margin: 0;", "{overview}" }
+                        }
+                    }
+                }
+            }
+        }
+        Some(Ok(Metadata::Movie(movie))) => {
+            let mut details = Vec::new();
+            details.extend(movie.year.map(|year| year.to_string()));
+            details.extend(movie.runtime.map(|minutes| format!("{} min", minutes)));
+            details.extend(movie.rating.map(|rating| format!("⭐ {:.1}/10", rating)));
+            let details = details.join(" · ");
+            let genres = movie.genres.join(", ");
+            rsx! {
+                div { style: "display: flex; gap: 20px;",
+                    if let Some(url) = poster_url(&movie.images) {
+                        img { src: "{url}", style: "width: 140px; aspect-ratio: 2 / 3; object-fit: cover; border-radius: 8px;" }
+                    }
+                    div { style: "flex: 1;",
+                        h2 { style: "margin: 0 0 5px 0; color: #fff;", "{movie.title}" }
+                        if let Some(original) = movie.original_title.as_ref().filter(|t| **t != movie.title) {
+                            div { style: "color: #888; font-style: italic;", "{original}" }
+                        }
+                        if !details.is_empty() {
+                            div { style: "color: #aaa; margin: 5px 0 10px 0;", "{details}" }
+                        }
+                        if !genres.is_empty() {
+                            div { style: "color: #e50914; margin-bottom: 10px;", "{genres}" }
+                        }
+                        if let Some(overview) = &movie.overview {
+                            p { style: "color: #ccc; line-height: 1.6; margin: 0;", "{overview}" }
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -97,14 +249,206 @@ fn make_url(full_path: &str, _root_path: &str) -> String {
     format!("http://127.0.0.1:3030/media/{}", encoded_path)
 }
 
+// Image du cache d'artwork servie par warp (poster, pochette)
+// Infos de l'artiste du morceau lancé, rien si le fichier n'a pas de tag artiste
+fn request_artist_metadata(tx: &std::sync::mpsc::Sender<Command>, track: &MediaInfo) {
+    if let Some(artist) = track.artist.as_ref().filter(|a| !a.trim().is_empty()) {
+        let _ = tx.send(Command::GetArtistMetadataFromPlugin(artist.clone()));
+    }
+}
+
+fn artwork_url(file_name: &str) -> String {
+    format!("http://127.0.0.1:3030/artwork/{}", file_name)
+}
+
+// Pistes audio affichées : celles de l'album ouvert dans l'ordre du disque,
+// sinon celles de la playlist active, sinon toutes
+fn shown_tracks(
+    list: &[MediaInfo],
+    album: Option<&Album>,
+    album_tracks: &[AlbumTrack],
+    playlist_active: bool,
+    playlist_ids: &[i64],
+) -> Vec<MediaInfo> {
+    let audios = list.iter().filter(|i| i.media_type == MediaType::Audio);
+    match album {
+        Some(album) => album_tracks
+            .iter()
+            .filter(|t| t.album_id == album.id)
+            .filter_map(|t| audios.clone().find(|i| i.id == t.media_id).cloned())
+            .collect(),
+        None if playlist_active => audios.filter(|i| playlist_ids.contains(&i.id)).cloned().collect(),
+        None => audios.cloned().collect(),
+    }
+}
+
+// Formulaire généré à partir des réglages déclarés par le plugin
+#[component]
+fn PluginSettingsForm(status: PluginStatus) -> Element {
+    let cmd_tx = use_context::<std::sync::mpsc::Sender<Command>>();
+    let initial = status.values.clone();
+    let mut values = use_signal(move || initial);
+    let name = status.manifest.name.clone();
+    let secret_keys: Vec<String> = status
+        .schema
+        .iter()
+        .filter(|field| field.is_secret())
+        .map(|field| field.key.clone())
+        .collect();
+
+    rsx! {
+        div { style: "background: #181818; padding: 12px 20px; border-radius: 8px; border: 1px solid #333; display: flex; flex-direction: column; gap: 10px;",
+            for field in status.schema.iter().cloned() {
+                SettingInput {
+                    key: "{field.key}",
+                    secret_set: status.secrets_set.contains(&field.key),
+                    field: field.clone(),
+                    values: values,
+                }
+            }
+            if let Some(error) = &status.settings_error {
+                p { style: "color: #ff8a80; margin: 0;", "⚠ {error}" }
+            }
+            div { style: "display: flex; justify-content: flex-end;",
+                button {
+                    class: "btn-nav",
+                    style: "position: relative; transform: none; top: auto; left: auto; background: #27ae60; padding: 6px 14px; font-size: 0.9rem;",
+                    onclick: move |_| {
+                        cmd_tx.send(Command::SavePluginSettings(name.clone(), values())).unwrap();
+                        // les secrets saisis ne restent pas dans la page
+                        values.write().values.retain(|key, _| !secret_keys.contains(key));
+                    },
+                    "💾 Enregistrer"
+                }
+            }
+        }
+    }
+}
+
+// Un champ du formulaire d'un plugin, selon le type du réglage
+#[component]
+fn SettingInput(field: SettingField, secret_set: bool, values: Signal<PluginSettings>) -> Element {
+    let key = field.key.clone();
+    let text = match values().values.get(&key) {
+        Some(SettingValue::Text(text)) => text.clone(),
+        _ => String::new(),
+    };
+    let input_style = "padding: 6px; border-radius: 4px; border: 1px solid #333; background: #1e1e1e; color: white; flex: 1;";
+
+    let input = match field.kind.clone() {
+        SettingKind::Boolean => {
+            let checked = values().boolean(&key).unwrap_or(false);
+            rsx! {
+                input {
+                    r#type: "checkbox",
+                    checked,
+                    onchange: move |_| values.write().set(&key, SettingValue::Boolean(!checked)),
+                }
+            }
+        }
+        SettingKind::Enum { options } => rsx! {
+            select {
+                style: input_style,
+                value: "{text}",
+                onchange: move |evt| values.write().set(&key, SettingValue::Text(evt.value())),
+                for option in options {
+                    option { value: "{option}", selected: option == text, "{option}" }
+                }
+            }
+        },
+        SettingKind::Secret => rsx! {
+            input {
+                style: input_style,
+                r#type: "password",
+                value: "{text}",
+                // vide : la valeur enregistrée est gardée
+                placeholder: if secret_set { "•••••• (enregistré)" } else { "" },
+                oninput: move |evt| values.write().set(&key, SettingValue::Text(evt.value())),
+            }
+        },
+        SettingKind::String => rsx! {
+            input {
+                style: input_style,
+                value: "{text}",
+                oninput: move |evt| values.write().set(&key, SettingValue::Text(evt.value())),
+            }
+        },
+    };
+
+    rsx! {
+        div { style: "display: flex; gap: 10px; align-items: center;",
+            label { style: "color: #ccc; width: 200px;", title: "{field.description}", "{field.label}" }
+            {input}
+        }
+    }
+}
+
+// Un résultat de la recherche, vers la page de son type
+#[component]
+fn SearchResultRow(result: SearchResult) -> Element {
+    let (icon, route) = match result.media.media_type {
+        MediaType::Audio => ("🎵", Route::Music {}),
+        MediaType::Video => ("🎬", Route::Videos {}),
+        MediaType::Image => ("🖼️", Route::Images {}),
+    };
+    let title = result.media.title.clone().unwrap_or_else(|| {
+        PathBuf::from(&result.media.path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    });
+    rsx! {
+        Link { to: route, style: "background: #1e1e1e; padding: 10px 16px; border-radius: 8px; border: 1px solid #333; text-decoration: none; display: flex; gap: 12px; align-items: center;",
+            span { style: "font-size: 1.4rem;", "{icon}" }
+            div {
+                div { style: "color: white; font-weight: bold;", "{title}" }
+                div { style: "color: #aaa; font-size: 0.9rem;",
+                    for (text, matched) in highlighted_parts(&result.snippet) {
+                        if matched {
+                            mark { style: "background: #007acc; color: white;", "{text}" }
+                        } else {
+                            span { "{text}" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
 // --- ACCUEIL ---
 #[component]
 pub fn Home() -> Element {
+    let cmd_tx = use_context::<std::sync::mpsc::Sender<Command>>();
+    let search_results = use_context::<Signal<Vec<SearchResult>>>();
+    let mut query = use_signal(String::new);
+
     rsx! {
         div { class: "container",
             div { style: "display: flex; flex-direction: column; align-items: center; justify-content: center; padding-top: 50px;",
                 h1 { style: "font-size: 4rem; margin-bottom: 50px; color: #007acc; text-transform: uppercase; letter-spacing: 5px;", "NeoKodi" }
 
+                // recherche plein texte dans toute la bibliothèque
+                input {
+                    style: "width: 100%; max-width: 900px; padding: 12px; margin-bottom: 20px; border-radius: 8px; border: 1px solid #333; background: #1e1e1e; color: white; font-size: 1.1rem;",
+                    placeholder: "🔍 Rechercher un titre, un artiste, un album...",
+                    value: "{query}",
+                    oninput: move |evt| {
+                        query.set(evt.value());
+                        let _ = cmd_tx.send(Command::Search(evt.value(), SearchFilters::default()));
+                    },
+                }
+                if !query().trim().is_empty() {
+                    div { style: "width: 100%; max-width: 900px; display: flex; flex-direction: column; gap: 8px; margin-bottom: 30px;",
+                        if search_results().is_empty() {
+                            p { style: "color: #888; font-style: italic; text-align: center;", "Aucun résultat" }
+                        }
+                        for result in search_results().into_iter() {
+                            SearchResultRow { key: "{result.media.id}", result: result.clone() }
+                        }
+                    }
+                }
+
                 div { class: "media-grid", style: "width: 100%; max-width: 900px;",
                     Link { to: Route::Videos {}, class: "media-card", div { class: "card-icon", "🎬" } div { class: "card-text", "Vidéos" } }
                     Link { to: Route::Images {}, class: "media-card", div { class: "card-icon", "🖼️" } div { class: "card-text", "Images" } }
@@ -123,7 +467,10 @@ pub fn Music() -> Element {
     let list_signal = use_context::<Signal<Vec<MediaInfo>>>();
     let root_path_signal = use_context::<Signal<String>>();
     let root_path = root_path_signal();
-    let plugin_history = use_context::<Signal<Vec<String>>>();
+    let plugin_history = use_context::<Signal<Vec<PluginSearchResult>>>();
+    let mut answers = use_context::<Signal<ProviderAnswers>>();
+    let mut show_lyrics = use_signal(|| false);
+    let provider_tx = cmd_tx.clone(); // cmd_tx part dans la fin de piste du lecteur
 
     // Contextes Playlist
     let mut all_playlists = use_context::<Signal<Vec<(i64, String)>>>(); // (ID, Nom)
@@ -143,6 +490,14 @@ pub fn Music() -> Element {
     // 👇 NOUVEAU : Pour savoir si on est en mode "Vue Playlist"
     let mut active_playlist_name = use_signal(|| Option::<String>::None);
 
+    // Navigateur d'albums : artiste choisi, et album ouvert (ses pistes remplacent la liste)
+    let artists = use_context::<Signal<Vec<Artist>>>();
+    let albums = use_context::<Signal<Vec<Album>>>();
+    let album_tracks = use_context::<Signal<Vec<AlbumTrack>>>();
+    let mut show_album_browser = use_signal(|| false);
+    let mut browsed_artist = use_signal(|| Option::<i64>::None);
+    let mut active_album = use_signal(|| Option::<Album>::None);
+
     // État pour le menu "Ajouter à..." (ID du média en cours d'ajout)
     let mut adding_media_to_playlist = use_signal(|| Option::<i64>::None);
 
@@ -227,11 +582,37 @@ pub fn Music() -> Element {
                     }
                 }
 
+                // Bouton Albums / Artistes
+                div { style: "z-index: 2; position: absolute; left: 290px;",
+                    button {
+                        class: "btn-nav",
+                        style: "position: relative; transform: none; top: auto; left: auto; background: #d35400;",
+                        onclick: {
+                            let tx = cmd_tx.clone();
+                            move |_| {
+                                tx.send(Command::GetArtists()).unwrap();
+                                tx.send(Command::GetAlbums(browsed_artist())).unwrap();
+                                show_album_browser.set(true);
+                            }
+                        },
+                        "💿 Albums"
+                    }
+                }
+
                 // TITRE CENTRAL DYNAMIQUE
                 div {
                     style: "position: absolute; left: 50%; transform: translateX(-50%); display: flex; align-items: center; gap: 10px; z-index: 2;",
 
-                    if let Some(name) = active_playlist_name() {
+                    if let Some(album) = active_album() {
+                        // MODE ALBUM OUVERT
+                        span { style: "color: #e67e22; font-weight: bold; font-size: 1.2rem;", "💿 {album.title} — {album.artist}" }
+                        button {
+                            style: "background: none; border: none; color: #e74c3c; cursor: pointer; font-size: 1.2rem; padding: 0 5px;",
+                            title: "Fermer l'album",
+                            onclick: move |_| active_album.set(None),
+                            "✖"
+                        }
+                    } else if let Some(name) = active_playlist_name() {
                         // MODE PLAYLIST ACTIVE
                         span { style: "color: #2ecc71; font-weight: bold; font-size: 1.2rem;", "📂 {name}" }
                         button {
@@ -258,25 +639,17 @@ pub fn Music() -> Element {
                 }
             }
 
-            // --- LISTE PRINCIPALE (AVEC FILTRE PLAYLIST) ---
+            // --- LISTE PRINCIPALE (AVEC FILTRE PLAYLIST OU ALBUM) ---
             div { class: "audio-list",
-                {list_signal().iter()
-                    .filter(|i| i.media_type == MediaType::Audio)
+                {shown_tracks(&list_signal(), active_album().as_ref(), &album_tracks(), active_playlist_name().is_some(), &loaded_ids_signal())
+                    .into_iter()
                     // 1. Filtre Recherche
                     .filter(|i| {
                         let query = search_text().to_lowercase();
                         if query.is_empty() { return true; }
                         i.title.as_deref().unwrap_or(&i.path).to_lowercase().contains(&query)
                     })
-                    // 2. Filtre Playlist (Si active)
-                    .filter(|i| {
-                        if active_playlist_name().is_some() {
-                            loaded_ids_signal().contains(&i.id)
-                        } else {
-                            true
-                        }
-                    })
-                    // 3. Mapping pour l'affichage
+                    // 2. Mapping pour l'affichage
                     .map(|item| {
                         // Calcul du Z-Index dynamique
                         let is_menu_open = adding_media_to_playlist() == Some(item.id);
@@ -294,12 +667,11 @@ pub fn Music() -> Element {
                                     let tx = cmd_tx.clone();
                                     let mut history = plugin_history.clone(); // ✅ On utilise le nouveau nom
                                     move |_| {
-                                        history.write().insert(0, "...".to_string());
-
                                         current_audio.set(Some(track.clone()));
                                         tx.send(Command::Play(i)).unwrap();
 
                                         if let Some(ref artist) = track.artist {
+                                            history.write().insert(0, PluginSearchResult::pending(artist));
                                             let _ = tx.send(Command::GetArtistMetadataFromPlugin(artist.clone()));
                                         }
                                     }
@@ -307,7 +679,13 @@ pub fn Music() -> Element {
 
                                 div { style: "display: flex; align-items: center; flex: 1;",
                                     div { class: "audio-icon",
-                                        if current_audio().as_ref().map(|c| c.id) == Some(item.id) { "🔊" } else { "🎵" }
+                                        if current_audio().as_ref().map(|c| c.id) == Some(item.id) {
+                                            "🔊"
+                                        } else if let Some(artwork) = &item.artwork {
+                                            img { src: "{artwork_url(artwork)}", style: "width: 40px; height: 40px; object-fit: cover; border-radius: 4px;" }
+                                        } else {
+                                            "🎵"
+                                        }
                                     }
                                     div { class: "audio-info",
                                         div {
@@ -318,6 +696,9 @@ pub fn Music() -> Element {
                                         div {
                                             class: "audio-artist",
                                             "{item.artist.as_deref().unwrap_or(\"Artiste inconnu\")}"
+                                            if let Some(album) = &item.album {
+                                                " • {album}"
+                                            }
                                         }
                                     }
                                 },
@@ -477,6 +858,7 @@ pub fn Music() -> Element {
                                                 }
 
                                                 // 2. Activer le mode visuel
+                                                active_album.set(None);
                                                 active_playlist_name.set(Some(pname.clone()));
 
                                                 // 3. Fermer la fenêtre
@@ -519,13 +901,137 @@ pub fn Music() -> Element {
                 }
             }
 
+            // --- NAVIGATEUR ARTISTES → ALBUMS (MODAL) ---
+            if show_album_browser() {
+                div { class: "playlist-overlay", onclick: move |_| show_album_browser.set(false),
+                    div { class: "playlist-modal", style: "width: 700px;", onclick: |evt| evt.stop_propagation(),
+                        h2 { style: "margin-top: 0; color: white;", "Artistes & Albums" }
+
+                        div { style: "display: flex; gap: 15px;",
+                            // Artistes
+                            div { style: "width: 40%; max-height: 400px; overflow-y: auto;",
+                                div {
+                                    class: "pl-item",
+                                    style: if browsed_artist().is_none() { "color: #e67e22;" } else { "" },
+                                    onclick: {
+                                        let tx = cmd_tx.clone();
+                                        move |_| {
+                                            browsed_artist.set(None);
+                                            tx.send(Command::GetAlbums(None)).unwrap();
+                                        }
+                                    },
+                                    "Tous les albums"
+                                }
+                                for artist in artists() {
+                                    div {
+                                        class: "pl-item",
+                                        style: if browsed_artist() == Some(artist.id) { "color: #e67e22;" } else { "" },
+                                        onclick: {
+                                            let tx = cmd_tx.clone();
+                                            let artist_id = artist.id;
+                                            move |_| {
+                                                browsed_artist.set(Some(artist_id));
+                                                tx.send(Command::GetAlbums(Some(artist_id))).unwrap();
+                                            }
+                                        },
+                                        span { "🎤 {artist.name}" }
+                                        span { style: "color: #777; font-size: 0.8rem;", "{artist.album_count} album(s)" }
+                                    }
+                                }
+                            }
+
+                            // Albums
+                            div { style: "flex: 1; max-height: 400px; overflow-y: auto;",
+                                for album in albums() {
+                                    div {
+                                        class: "pl-item",
+                                        onclick: {
+                                            let tx = cmd_tx.clone();
+                                            let album = album.clone();
+                                            move |_| {
+                                                tx.send(Command::GetAlbumTracks(album.id)).unwrap();
+                                                active_playlist_name.set(None);
+                                                active_album.set(Some(album.clone()));
+                                                show_album_browser.set(false);
+                                            }
+                                        },
+                                        div {
+                                            div { style: "font-weight: bold;", "💿 {album.title}" }
+                                            div { style: "color: #999; font-size: 0.8rem;",
+                                                "{album.artist}"
+                                                if let Some(year) = album.year { " • {year}" }
+                                                if album.compilation { " • Compilation" }
+                                            }
+                                        }
+                                        span { style: "color: #777; font-size: 0.8rem;", "{album.track_count} titre(s)" }
+                                    }
+                                }
+                                if albums().is_empty() {
+                                    div { style: "color: #777; text-align: center; padding: 20px;", "Aucun album." }
+                                }
+                            }
+                        }
+
+                        div { style: "margin-top: 20px; text-align: right;",
+                            button {
+                                class: "btn-nav",
+                                style: "position: relative; transform: none; top: auto; left: auto; background: #444;",
+                                onclick: move |_| show_album_browser.set(false),
+                                "Fermer"
+                            }
+                        }
+                    }
+                }
+            }
+
+            // --- PAROLES (au-dessus du lecteur) ---
+            if let (true, Some(track)) = (show_lyrics(), current_audio()) {
+                div {
+                    style: "position: fixed; bottom: 100px; right: 20px; width: 380px; max-height: 60vh; overflow-y: auto; background: #202020; border: 1px solid #333; border-radius: 10px; padding: 20px; z-index: 1500; box-shadow: 0 5px 20px rgba(0,0,0,0.8);",
+                    h3 { style: "margin-top: 0;", "📝 {track.title.as_deref().unwrap_or(&track.path)}" }
+                    {match Some(answers()).filter(|a| a.media_id == track.id).and_then(|a| a.lyrics) {
+                        // Piste changée depuis la dernière demande
+                        None => rsx! {
+                            button {
+                                class: "btn-nav",
+                                style: "position: relative; transform: none; top: auto; left: auto;",
+                                onclick: {
+                                    let tx = provider_tx.clone();
+                                    let id = track.id;
+                                    move |_| {
+                                        answers.write().of(id).lyrics = Some(None);
+                                        tx.send(Command::GetLyrics(id)).unwrap();
+                                    }
+                                },
+                                "Chercher les paroles"
+                            }
+                        },
+                        Some(None) => rsx! { p { style: "color: #888;", "Recherche des paroles..." } },
+                        Some(Some(Err(e))) => rsx! { p { style: "color: #e74c3c;", "Paroles introuvables : {e}" } },
+                        Some(Some(Ok(found))) => rsx! {
+                            pre { style: "white-space: pre-wrap; font-family: inherit; line-height: 1.6; color: #ddd;",
+                                "{found.metadata.plain.clone().or(found.metadata.synced.clone()).unwrap_or_default()}"
+                            }
+                            div { style: "color: #666; font-size: 0.8rem; text-align: right;", "Source : {found.plugin}" }
+                        },
+                    }}
+                }
+            }
+
             // --- LECTEUR AUDIO (FIXED BOTTOM) ---
             if let Some(track) = current_audio() {
                 div {
                     style: "position: fixed; bottom: 0; left: 0; width: 100%; height: 90px; background: #181818; border-top: 1px solid #282828; display: flex; align-items: center; justify-content: space-between; padding: 0 20px; z-index: 1000; box-shadow: 0 -5px 15px rgba(0,0,0,0.5);",
 
                     // Partie Gauche (Infos)
-                    div { style: "width: 25%; position: relative;",
+                    div { style: "width: 25%; position: relative; display: flex; align-items: center; gap: 12px;",
+                        // Pochette trouvée par un plugin
+                        if let Some(Some(Ok(found))) = Some(answers()).filter(|a| a.media_id == track.id).and_then(|a| a.artwork) {
+                            if let Some(url) = poster_url(&found.metadata) {
+                                img { src: "{url}", title: "Source : {found.plugin}", style: "width: 60px; height: 60px; object-fit: cover; border-radius: 4px;" }
+                            }
+                        }
+                        div { style: "min-width: 0; flex: 1;",
                         div { class: "marquee-container",
                             div { class: "marquee-text", style: "font-weight: bold; font-size: 1.1rem;",
                                 "{track.title.as_deref().unwrap_or(&track.path)}"
@@ -556,9 +1062,10 @@ pub fn Music() -> Element {
                             div {
                                     style: "color: #b3b3b3; font-size: 0.9rem; margin-top: 4px;",
                                     // On affiche le premier élément de l'historique (le plus récent)
-                                    "{plugin_history().first().cloned().unwrap_or_default()}"
+                                    "{plugin_history().first().map(PluginSearchResult::summary).unwrap_or_default()}"
                             }
                         }
+                        }
                     },
 
                     // Partie Centrale (Player HTML5)
@@ -575,7 +1082,7 @@ pub fn Music() -> Element {
                                     let next_song = queue.write().remove(0);
                                     current_audio.set(Some(next_song.clone()));
                                     cmd_tx.send(Command::Play(next_song.id)).unwrap();
-                                    cmd_tx.send(Command::GetArtistMetadataFromPlugin(next_song.path)).unwrap();
+                                    request_artist_metadata(&cmd_tx, &next_song);
                                     return;
                                 }
 
@@ -584,16 +1091,8 @@ pub fn Music() -> Element {
                                 let mode = play_mode();
                                 let list = list_signal();
 
-                                // On filtre selon ce qui est affiché à l'écran
-                                let audios: Vec<&MediaInfo> = list.iter()
-                                    .filter(|i| i.media_type == MediaType::Audio)
-                                    // On applique le même filtre que l'affichage pour la suite logique
-                                    .filter(|i| {
-                                        if active_playlist_name().is_some() {
-                                            loaded_ids_signal().contains(&i.id)
-                                        } else { true }
-                                    })
-                                    .collect();
+                                // On applique le même filtre que l'affichage pour la suite logique
+                                let audios = shown_tracks(&list, active_album().as_ref(), &album_tracks(), active_playlist_name().is_some(), &loaded_ids_signal());
 
                                 match mode {
                                     PlayMode::StopAtEnd => current_audio.set(None),
@@ -604,6 +1103,7 @@ pub fn Music() -> Element {
                                                 let next = audios[idx + 1].clone();
                                                 current_audio.set(Some(next.clone()));
                                                 cmd_tx.send(Command::Play(next.id)).unwrap();
+                                                request_artist_metadata(&cmd_tx, &next);
                                             } else { current_audio.set(None); }
                                         }
                                     },
@@ -614,6 +1114,7 @@ pub fn Music() -> Element {
                                             let next = audios[random_idx].clone();
                                             current_audio.set(Some(next.clone()));
                                             cmd_tx.send(Command::Play(next.id)).unwrap();
+                                            request_artist_metadata(&cmd_tx, &next);
                                         }
                                     }
                                 }
@@ -636,6 +1137,41 @@ pub fn Music() -> Element {
                                 "🗑️"
                             }
                         }
+                        button {
+                            style: "background: transparent; border: 1px solid #9b59b6; color: #9b59b6; padding: 8px 12px; border-radius: 20px; cursor: pointer;",
+                            title: "Paroles",
+                            onclick: {
+                                let tx = provider_tx.clone();
+                                let id = track.id;
+                                move |_| {
+                                    if show_lyrics() {
+                                        show_lyrics.set(false);
+                                        return;
+                                    }
+                                    show_lyrics.set(true);
+                                    let mut answers = answers.write();
+                                    let answers = answers.of(id);
+                                    if answers.lyrics.is_none() {
+                                        answers.lyrics = Some(None);
+                                        tx.send(Command::GetLyrics(id)).unwrap();
+                                    }
+                                }
+                            },
+                            "📝"
+                        }
+                        button {
+                            style: "background: transparent; border: 1px solid #e67e22; color: #e67e22; padding: 8px 12px; border-radius: 20px; cursor: pointer;",
+                            title: "Chercher la pochette",
+                            onclick: {
+                                let tx = provider_tx.clone();
+                                let id = track.id;
+                                move |_| {
+                                    answers.write().of(id).artwork = Some(None);
+                                    tx.send(Command::GetArtwork(id)).unwrap();
+                                }
+                            },
+                            "🖼️"
+                        }
                         button {
                             style: "background: transparent; border: 1px solid {play_mode().color()}; color: {play_mode().color()}; padding: 8px 15px; border-radius: 20px; cursor: pointer; font-weight: bold; transition: all 0.2s;",
                             onclick: move |_| play_mode.set(play_mode().next()),
@@ -661,7 +1197,9 @@ pub fn Videos() -> Element {
     let root_path_signal = use_context::<Signal<String>>();
     let root_path = root_path_signal();
 
-    let plugin_history = use_context::<Signal<Vec<String>>>();
+    let plugin_history = use_context::<Signal<Vec<PluginSearchResult>>>();
+    let mut answers = use_context::<Signal<ProviderAnswers>>();
+    let mut show_subtitles = use_signal(|| false);
 
     // 👇 On stocke l'objet COMPLET pour garder l'ID sous la main
     let mut playing_video = use_signal(|| Option::<MediaInfo>::None);
@@ -763,14 +1301,107 @@ pub fn Videos() -> Element {
                                     }
 
                                     playing_video.set(None);
+                                    show_subtitles.set(false);
                                     current_time.set(0.0);
                                     current_duration.set(0.0);
                                 }
                             },
                             "⬅ Retour"
                         }
+
+                        button {
+                            class: "btn-nav",
+                            style: "position: relative; transform: none; top: auto; left: auto; margin-left: 15px;",
+                            onclick: {
+                                let tx = cmd_tx.clone();
+                                let id = media.id;
+                                move |_| {
+                                    if show_subtitles() {
+                                        show_subtitles.set(false);
+                                        return;
+                                    }
+                                    show_subtitles.set(true);
+                                    let mut answers = answers.write();
+                                    let answers = answers.of(id);
+                                    if answers.subtitles.is_none() {
+                                        answers.subtitles = Some(None);
+                                        tx.send(Command::SearchSubtitles(id, vec!["fr".to_string(), "en".to_string()])).unwrap();
+                                    }
+                                }
+                            },
+                            "💬 Sous-titres"
+                        }
                     },
 
+                    // --- SOUS-TITRES TROUVÉS PAR LES PLUGINS ---
+                    if show_subtitles() {
+                        div {
+                            style: "position: absolute; top: 90px; left: 20px; width: 420px; max-height: 60vh; overflow-y: auto; background: rgba(24,24,24,0.95); border: 1px solid #333; border-radius: 10px; padding: 15px; z-index: 10001; color: white;",
+                            {match Some(answers()).filter(|a| a.media_id == media.id).and_then(|a| a.subtitles) {
+                                None | Some(None) => rsx! { p { style: "color: #888;", "Recherche des sous-titres..." } },
+                                Some(Some(Err(e))) => rsx! { p { style: "color: #e74c3c;", "Aucun sous-titre : {e}" } },
+                                Some(Some(Ok(found))) => rsx! {
+                                    for sub in found {
+                                        div {
+                                            key: "{sub.plugin}-{sub.metadata.id}",
+                                            style: "display: flex; align-items: center; gap: 10px; padding: 8px 0; border-bottom: 1px solid #333;",
+                                            span { style: "background: #3498db; padding: 2px 6px; border-radius: 4px; font-size: 0.8rem; text-transform: uppercase;", "{sub.metadata.language}" }
+                                            div { style: "flex: 1; min-width: 0;",
+                                                div { style: "white-space: nowrap; overflow: hidden; text-overflow: ellipsis;", title: "{sub.metadata.name}", "{sub.metadata.name}" }
+                                                div { style: "color: #888; font-size: 0.8rem;", "{sub.plugin} · {sub.metadata.format}" }
+                                            }
+                                            button {
+                                                style: "background: #2ecc71; border: none; color: white; padding: 5px 10px; border-radius: 4px; cursor: pointer;",
+                                                onclick: {
+                                                    let tx = cmd_tx.clone();
+                                                    let id = media.id;
+                                                    let plugin = sub.plugin.clone();
+                                                    let subtitle = sub.metadata.id.clone();
+                                                    move |_| {
+                                                        let mut answers = answers.write();
+                                                        let answers = answers.of(id);
+                                                        answers.subtitle_saved = None;
+                                                        answers.subtitle_asked = Some((plugin.clone(), subtitle.clone()));
+                                                        tx.send(Command::DownloadSubtitle(id, plugin.clone(), subtitle.clone(), false)).unwrap();
+                                                    }
+                                                },
+                                                "⬇"
+                                            }
+                                        }
+                                    }
+                                },
+                            }}
+                            // Le lecteur HTML5 ne lit pas les .srt : le fichier est rangé à côté de la vidéo
+                            {match Some(answers()).filter(|a| a.media_id == media.id).and_then(|a| a.subtitle_saved) {
+                                Some(Ok(path)) => rsx! { p { style: "color: #2ecc71; font-size: 0.85rem;", "✅ Enregistré : {path.display()}" } },
+                                // Un fichier du même nom existe : on demande avant de l'écraser
+                                Some(Err(SubtitleError::Exists(path))) => rsx! {
+                                    div { style: "display: flex; align-items: center; gap: 10px; font-size: 0.85rem;",
+                                        span { style: "color: #f39c12;", "⚠️ {path.display()} existe déjà" }
+                                        button {
+                                            style: "background: #e67e22; border: none; color: white; padding: 3px 8px; border-radius: 4px; cursor: pointer;",
+                                            onclick: {
+                                                let tx = cmd_tx.clone();
+                                                let id = media.id;
+                                                move |_| {
+                                                    let mut answers = answers.write();
+                                                    let answers = answers.of(id);
+                                                    if let Some((plugin, subtitle)) = answers.subtitle_asked.clone() {
+                                                        answers.subtitle_saved = None;
+                                                        tx.send(Command::DownloadSubtitle(id, plugin, subtitle, true)).unwrap();
+                                                    }
+                                                }
+                                            },
+                                            "Remplacer"
+                                        }
+                                    }
+                                },
+                                Some(Err(e)) => rsx! { p { style: "color: #e74c3c; font-size: 0.85rem;", "❌ {e}" } },
+                                None => rsx! {},
+                            }}
+                        }
+                    }
+
                     // 📡 INPUT INVISIBLE (Sert de pont entre le Javascript du lecteur et Rust)
                     input {
                         id: "neokodi-time-tracker",
@@ -864,17 +1495,20 @@ pub fn Videos() -> Element {
                                     onclick: {
                                         let selected = item.clone();
                                         let tx = cmd_tx.clone();
-                                        let raw_title = item.title.as_deref().unwrap_or(&item.path);
-                                        let clean_title = std::path::Path::new(raw_title).file_stem().and_then(|s| s.to_str()).unwrap_or(raw_title).to_string();
                                         let mut history = plugin_history.clone();
                                         move |_| {
-                                            history.write().insert(0, "Chargement des infos...".to_string());
+                                            let title = selected.title.as_deref().unwrap_or(&selected.path);
+                                            history.write().insert(0, PluginSearchResult::pending(title));
                                             selected_media.set(Some(selected.clone()));
-                                            tx.send(Command::GetfilmMetadataFromPlugin(clean_title.clone())).unwrap();
+                                            tx.send(Command::GetMovieMetadata(selected.id)).unwrap();
                                         }
                                     },
 
-                                    div { class: "card-icon", "🎬" }
+                                    if let Some(artwork) = &item.artwork {
+                                        img { src: "{artwork_url(artwork)}", style: "width: 100%; aspect-ratio: 2 / 3; object-fit: cover;" }
+                                    } else {
+                                        div { class: "card-icon", "🎬" }
+                                    }
                                     div { class: "card-text", "{item.title.as_deref().unwrap_or(&item.path)}" }
 
                                     if progress > 0.0 {
@@ -920,20 +1554,43 @@ pub fn Videos() -> Element {
                             div { style: "display: flex; gap: 15px; margin-bottom: 20px;",
                                 button {
                                     class: "btn-play",
-                                    onclick: move |_| {
-                                        let id = media.id;
-                                        // 👇 CORRECTION : On passe l'objet média et non plus juste le path !
-                                        playing_video.set(Some(media.clone()));
-                                        selected_media.set(None);
-                                        cmd_tx.send(Command::Play(id)).unwrap();
+                                    onclick: {
+                                        let tx = cmd_tx.clone();
+                                        move |_| {
+                                            let id = media.id;
+                                            // 👇 CORRECTION : On passe l'objet média et non plus juste le path !
+                                            playing_video.set(Some(media.clone()));
+                                            selected_media.set(None);
+                                            tx.send(Command::Play(id)).unwrap();
+                                        }
                                     },
                                     "▶ Lecture"
                                 }
+                                button {
+                                    class: "btn-nav",
+                                    style: "position: relative; transform: none; top: auto; left: auto;",
+                                    title: "Interroger à nouveau les plugins",
+                                    onclick: {
+                                        let tx = cmd_tx.clone();
+                                        let mut history = plugin_history.clone();
+                                        move |_| {
+                                            if let Some(selected) = selected_media() {
+                                                let title = selected.title.as_deref().unwrap_or(&selected.path);
+                                                history.write().insert(0, PluginSearchResult::pending(title));
+                                                tx.send(Command::RefreshMovieMetadata(selected.id)).unwrap();
+                                            }
+                                        }
+                                    },
+                                    "🔄 Rafraîchir les infos"
+                                }
                             }
                             div {
                                 style: "background: #222; padding: 20px; border-radius: 8px; min-height: 100px;",
-                                if let Some(info) = plugin_history().first() {
-                                    pre { style: "white-space: pre-wrap; font-family: 'Segoe UI', sans-serif; color: #ccc; line-height: 1.6;", "{info}" }
+                                if let Some(entry) = plugin_history().first() {
+                                    MetadataCard { entry: entry.clone() }
+                                    if let Some(label) = entry.cached_label() {
+                                        div { style: "color: #666; font-size: 0.8rem; margin-top: 15px;", "{label}" }
+                                    }
                                 }
                             }
                         }
@@ -1052,17 +1709,83 @@ pub fn Images() -> Element {
     }
 }
 
+// Ordre dans lequel les plugins sont interrogés, pour chaque capacité partagée par plusieurs plugins
+#[component]
+fn PluginPriorities(plugins: Vec<PluginStatus>) -> Element {
+    let cmd_tx = use_context::<std::sync::mpsc::Sender<Command>>();
+    let mut positions: BTreeMap<String, Vec<(usize, String)>> = BTreeMap::new();
+    for status in plugins.iter() {
+        for (capability, position) in status.priority.iter() {
+            positions.entry(capability.clone()).or_default().push((*position, status.manifest.name.clone()));
+        }
+    }
+    let orders: Vec<(String, Vec<String>)> = positions
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .map(|(capability, mut names)| {
+            names.sort();
+            (capability, names.into_iter().map(|(_, name)| name).collect())
+        })
+        .collect();
+
+    rsx! {
+        for (capability, names) in orders.into_iter() {
+            div { style: "background: #181818; padding: 12px 20px; border-radius: 8px; border: 1px solid #333; display: flex; flex-direction: column; gap: 6px;",
+                div { style: "color: #aaa; font-size: 0.9rem;", "Ordre pour {capability}" }
+                for (position, name) in names.iter().cloned().enumerate() {
+                    div { style: "display: flex; align-items: center; gap: 10px;",
+                        span { style: "color: #666; width: 20px;", "{position + 1}." }
+                        span { style: "color: white; flex: 1;", "{name}" }
+                        button {
+                            style: "background: #333; color: white; border: none; border-radius: 4px; padding: 2px 8px; cursor: pointer;",
+                            disabled: position == 0,
+                            onclick: {
+                                let tx = cmd_tx.clone();
+                                let capability = capability.clone();
+                                let names = names.clone();
+                                move |_| {
+                                    let mut order = names.clone();
+                                    order.swap(position - 1, position);
+                                    tx.send(Command::SetPluginPriority(capability.clone(), order)).unwrap();
+                                }
+                            },
+                            "▲"
+                        }
+                        button {
+                            style: "background: #333; color: white; border: none; border-radius: 4px; padding: 2px 8px; cursor: pointer;",
+                            disabled: position + 1 == names.len(),
+                            onclick: {
+                                let tx = cmd_tx.clone();
+                                let capability = capability.clone();
+                                let names = names.clone();
+                                move |_| {
+                                    let mut order = names.clone();
+                                    order.swap(position, position + 1);
+                                    tx.send(Command::SetPluginPriority(capability.clone(), order)).unwrap();
+                                }
+                            },
+                            "▼"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
 // --- PLUGINS (ADDONS) ---
 #[component]
 pub fn Plugins() -> Element {
     let cmd_tx = use_context::<std::sync::mpsc::Sender<Command>>();
-    let plugin_history = use_context::<Signal<Vec<String>>>();
+    let plugin_history = use_context::<Signal<Vec<PluginSearchResult>>>();
+    let plugins = use_context::<Signal<Vec<PluginStatus>>>();
 
     // On garde le texte de recherche
     let mut search_text = use_signal(|| String::from("Inception"));
 
     // 👇 NOUVEAU : On gère le mode de recherche (Music ou Film)
     let mut search_mode = use_signal(|| "music"); // "music" ou "film"
+    let plugin_tx = cmd_tx.clone();
 
     rsx! {
         div { class: "container",
@@ -1073,6 +1796,106 @@ pub fn Plugins() -> Element {
 
             div { style: "display: flex; flex-direction: column; align-items: center; gap: 30px; margin-top: 50px; padding-bottom: 50px;",
 
+                // --- PLUGINS INSTALLÉS ---
+                div { style: "width: 80%; max-width: 800px; display: flex; flex-direction: column; gap: 10px;",
+                    // relit le dossier des plugins, sans redémarrer
+                    button {
+                        class: "btn-nav",
+                        style: "position: relative; transform: none; top: auto; left: auto; align-self: flex-end;",
+                        onclick: {
+                            let tx = plugin_tx.clone();
+                            move |_| tx.send(Command::ReloadPlugins()).unwrap()
+                        },
+                        "🔄 Recharger"
+                    }
+                    if plugins().is_empty() {
+                        div { style: "text-align: center; color: #666; font-style: italic;",
+                            "Aucun plugin compatible chargé (voir epikodi.log)"
+                        }
+                    }
+                    for status in plugins().iter() {
+                        div { style: "background: #1e1e1e; padding: 12px 20px; border-radius: 8px; border: 1px solid #333; display: flex; justify-content: space-between; align-items: center;",
+                            div {
+                                span { style: "font-weight: bold; color: white;", "{status.manifest.name}" }
+                                span { style: "color: #888; margin-left: 10px;", "v{status.manifest.version}" }
+                                if !status.manifest.author.is_empty() {
+                                    span { style: "color: #666; margin-left: 10px; font-size: 0.9rem;", "par {status.manifest.author}" }
+                                }
+                            }
+                            div { style: "display: flex; gap: 6px;",
+                                // suspendu après trop d'appels en échec, jusqu'à sa réactivation
+                                if status.suspended {
+                                    span { style: "background: #5a1d1d; color: #ff8a80; padding: 3px 8px; border-radius: 4px; font-size: 0.8rem;", "en échec" }
+                                }
+                                if status.out_of_process {
+                                    span { style: "background: #1d3a5a; color: #8ac7ff; padding: 3px 8px; border-radius: 4px; font-size: 0.8rem;", "isolé" }
+                                }
+                                // module WebAssembly : bac à sable, réseau limité aux domaines déclarés et autorisés
+                                if status.wasm {
+                                    span { style: "background: #3a1d5a; color: #c8a2ff; padding: 3px 8px; border-radius: 4px; font-size: 0.8rem;", "wasm" }
+                                }
+                                for capability in status.manifest.capabilities.iter() {
+                                    span { style: "background: #333; color: #ccc; padding: 3px 8px; border-radius: 4px; font-size: 0.8rem;", "{capability}" }
+                                }
+                                // événements de lecture reçus, et ceux perdus quand le plugin était trop lent
+                                if !status.subscriptions.is_empty() {
+                                    span {
+                                        style: "background: #333; color: #ccc; padding: 3px 8px; border-radius: 4px; font-size: 0.8rem; cursor: help;",
+                                        title: status.subscriptions.join(", "),
+                                        "🔔 {status.subscriptions.len()}"
+                                    }
+                                }
+                                if status.events_dropped > 0 {
+                                    span { style: "background: #5a4a1d; color: #ffd480; padding: 3px 8px; border-radius: 4px; font-size: 0.8rem;", "{status.events_dropped} événements perdus" }
+                                }
+                                button {
+                                    style: if status.enabled {
+                                        "background: #27ae60; color: white; border: none; border-radius: 4px; padding: 3px 10px; cursor: pointer; font-size: 0.8rem;"
+                                    } else {
+                                        "background: #444; color: #aaa; border: none; border-radius: 4px; padding: 3px 10px; cursor: pointer; font-size: 0.8rem;"
+                                    },
+                                    onclick: {
+                                        let tx = plugin_tx.clone();
+                                        let name = status.manifest.name.clone();
+                                        let enabled = status.enabled && !status.suspended;
+                                        move |_| tx.send(Command::SetPluginEnabled(name.clone(), !enabled)).unwrap()
+                                    },
+                                    if status.enabled && !status.suspended { "Activé" } else { "Désactivé" }
+                                }
+                            }
+                        }
+                        // domaines que le module déclare, injoignables tant que l'utilisateur ne les a pas autorisés
+                        if !status.domains.is_empty() {
+                            div { style: "background: #1e1e1e; padding: 8px 20px; border-radius: 8px; border: 1px solid #333; display: flex; justify-content: space-between; align-items: center; gap: 10px;",
+                                div { style: "display: flex; flex-wrap: wrap; gap: 6px; align-items: center;",
+                                    span { style: "color: #888; font-size: 0.9rem;", "🌐 Accès réseau :" }
+                                    for domain in status.domains.iter() {
+                                        span { style: "background: #333; color: #ccc; padding: 3px 8px; border-radius: 4px; font-size: 0.8rem;", "{domain}" }
+                                    }
+                                }
+                                button {
+                                    style: if status.approved_domains == status.domains {
+                                        "background: #27ae60; color: white; border: none; border-radius: 4px; padding: 3px 10px; cursor: pointer; font-size: 0.8rem;"
+                                    } else {
+                                        "background: #5a4a1d; color: #ffd480; border: none; border-radius: 4px; padding: 3px 10px; cursor: pointer; font-size: 0.8rem;"
+                                    },
+                                    onclick: {
+                                        let tx = plugin_tx.clone();
+                                        let name = status.manifest.name.clone();
+                                        let approved = status.approved_domains == status.domains;
+                                        move |_| tx.send(Command::ApprovePluginDomains(name.clone(), !approved)).unwrap()
+                                    },
+                                    if status.approved_domains == status.domains { "Autorisé" } else { "Autoriser" }
+                                }
+                            }
+                        }
+                        if !status.schema.is_empty() {
+                            PluginSettingsForm { key: "{status.manifest.name}", status: status.clone() }
+                        }
+                    }
+                    PluginPriorities { plugins: plugins() }
+                }
+
                 // --- SÉLECTEUR DE TYPE ---
                 div { style: "display: flex; gap: 20px; background: #1e1e1e; padding: 5px; border-radius: 8px; border: 1px solid #333;",
                     button {
@@ -1130,17 +1953,14 @@ pub fn Plugins() -> Element {
 
                     for res in plugin_history().iter() {
                         div {
-                            // Petit changement de style selon le contenu (simple detection)
-                            style: if res.contains("Year:") {
+                            // Rouge pour les films, bleu pour la musique
+                            style: if matches!(res.result, Some(Ok(Metadata::Movie(_)))) {
                                 "background: #1e1e1e; padding: 20px; border-radius: 8px; border: 1px solid #333; border-left: 5px solid #e50914;" // Rouge pour Films
                             } else {
                                 "background: #1e1e1e; padding: 20px; border-radius: 8px; border: 1px solid #333; border-left: 5px solid #007acc;" // Bleu pour Musique
                             },
-                            h3 { style: "margin-top: 0; color: #aaa; font-size: 0.9rem; text-transform: uppercase;", "Résultat :" }
-                            pre {
-                                style: "color: #eee; white-space: pre-wrap; font-family: 'Segoe UI', sans-serif; font-size: 1rem; margin: 0;",
-                                "{res}"
-                            }
+                            h3 { style: "margin-top: 0; color: #aaa; font-size: 0.9rem; text-transform: uppercase;", "Résultat : {res.query}" }
+                            MetadataCard { entry: res.clone() }
                         }
                     }
                 }
@@ -1154,9 +1974,240 @@ pub fn Plugins() -> Element {
 pub fn Iptv() -> Element {
     rsx! { div { class: "container", div { class: "top-bar", Link { to: Route::Home {}, class: "btn-nav", "🏠 Accueil" }, div { class: "page-title", "Séries" } } } }
 }
+// --- SÉRIES ---
 #[component]
 pub fn Series() -> Element {
-    rsx! { div { class: "container", div { class: "top-bar", Link { to: Route::Home {}, class: "btn-nav", "🏠 Accueil" }, div { class: "page-title", "Séries" } } } }
+    let cmd_tx = use_context::<std::sync::mpsc::Sender<Command>>();
+    let shows = use_context::<Signal<Vec<Show>>>();
+    let episodes = use_context::<Signal<Vec<Episode>>>();
+    let root_path_signal = use_context::<Signal<String>>();
+    let root_path = root_path_signal();
+
+    let mut selected_show = use_signal(|| Option::<Show>::None);
+    let mut selected_season = use_signal(|| Option::<u32>::None);
+    let mut playing_episode = use_signal(|| Option::<Episode>::None);
+
+    // temps du lecteur HTML5, renvoyé au backend en quittant le lecteur
+    let mut current_time = use_signal(|| 0.0f32);
+    let mut current_duration = use_signal(|| 0.0f32);
+
+    let tx_init = cmd_tx.clone();
+    use_hook(move || {
+        tx_init.send(Command::GetShows()).unwrap();
+    });
+
+    // la série ouverte suit la liste (épisode suivant mis à jour après un visionnage)
+    let current_show = selected_show().and_then(|show| shows().into_iter().find(|s| s.id == show.id).or(Some(show)));
+
+    rsx! {
+        div { class: "container",
+
+            // ==========================================
+            // 1. LE LECTEUR
+            // ==========================================
+            if let Some(episode) = playing_episode() {
+                div {
+                    style: "position: fixed; top: 0; left: 0; width: 100vw; height: 100vh; background: black; z-index: 9999; display: flex; flex-direction: column;",
+
+                    div { style: "position: absolute; top: 20px; left: 20px; z-index: 10000;",
+                        button {
+                            class: "btn-nav",
+                            onclick: {
+                                let tx = cmd_tx.clone();
+                                move |_| {
+                                    tx.send(Command::UpdateProgress(episode.media_id, current_time(), current_duration())).unwrap();
+                                    // rafraîchit l'épisode suivant et les barres de progression
+                                    tx.send(Command::GetShows()).unwrap();
+                                    tx.send(Command::GetShowEpisodes(episode.show_id)).unwrap();
+
+                                    playing_episode.set(None);
+                                    current_time.set(0.0);
+                                    current_duration.set(0.0);
+                                }
+                            },
+                            "⬅ Retour"
+                        }
+                    }
+
+                    input {
+                        id: "neokodi-time-tracker",
+                        r#type: "hidden",
+                        oninput: move |evt| {
+                            if let Some((t, d)) = evt.value().split_once(',') {
+                                if let (Ok(time), Ok(dur)) = (t.parse::<f32>(), d.parse::<f32>()) {
+                                    current_time.set(time);
+                                    current_duration.set(dur);
+                                }
+                            }
+                        }
+                    }
+
+                    {
+                        let mut url = make_url(&episode.path, &root_path);
+                        if episode.in_progress() && episode.time_stop > 2.0 {
+                            url = format!("{}#t={}", url, episode.time_stop);
+                        }
+
+                        rsx! {
+                            video {
+                                id: "neokodi-player",
+                                src: "{url}",
+                                controls: true,
+                                autoplay: true,
+                                style: "width: 100%; height: 100%; object-fit: contain;"
+                            }
+                            script {
+                                "
+                                setTimeout(function() {{
+                                    var v = document.getElementById('neokodi-player');
+                                    var i = document.getElementById('neokodi-time-tracker');
+                                    if(v && i) {{
+                                        v.ontimeupdate = function() {{
+                                            if(!isNaN(v.duration)) {{
+                                                i.value = v.currentTime + ',' + v.duration;
+                                                i.dispatchEvent(new Event('input', {{ bubbles: true }}));
+                                            }}
+                                        }};
+                                    }}
+                                }}, 500);
+                                "
+                            }
+                        }
+                    }
+                }
+            }
+
+            // ==========================================
+            // 2. LES ÉPISODES D'UNE SÉRIE
+            // ==========================================
+            else if let Some(show) = current_show {
+                div { class: "top-bar",
+                    button {
+                        class: "btn-nav",
+                        onclick: move |_| {
+                            selected_show.set(None);
+                            selected_season.set(None);
+                        },
+                        "⬅ Séries"
+                    }
+                    div { class: "page-title", "{show.title}" }
+                }
+
+                div { style: "max-width: 900px; margin: 30px auto; display: flex; flex-direction: column; gap: 20px;",
+                    div { style: "color: #aaa;", "{show.watched_count} / {show.episode_count} épisodes vus" }
+
+                    if let Some(next) = show.next_episode.clone() {
+                        button {
+                            style: "align-self: flex-start; background: white; color: black; padding: 12px 30px; font-size: 1.1rem; font-weight: bold; border-radius: 4px; border: none; cursor: pointer;",
+                            onclick: {
+                                let tx = cmd_tx.clone();
+                                let next = next.clone();
+                                move |_| {
+                                    tx.send(Command::Play(next.media_id)).unwrap();
+                                    playing_episode.set(Some(next.clone()));
+                                }
+                            },
+                            if next.in_progress() { "▶ Reprendre {next.label()}" } else { "▶ Lire {next.label()}" }
+                        }
+                    }
+
+                    // onglets des saisons
+                    div { style: "display: flex; gap: 10px; flex-wrap: wrap;",
+                        for season in show.seasons.iter().copied() {
+                            {
+                                let active = selected_season().unwrap_or(show.seasons[0]) == season;
+                                rsx! {
+                                    button {
+                                        class: "btn-nav",
+                                        style: if active { "position: relative; transform: none; top: auto; left: auto; background: #007acc;" } else { "position: relative; transform: none; top: auto; left: auto; background: #333;" },
+                                        onclick: move |_| selected_season.set(Some(season)),
+                                        if season == 0 { "Spéciaux" } else { "Saison {season}" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    for episode in episodes().into_iter().filter(|e| e.show_id == show.id && e.season == selected_season().unwrap_or(show.seasons[0])) {
+                        {
+                            let progress = if episode.duration > 0.0 { (episode.time_stop / episode.duration as f64 * 100.0).min(100.0) } else { 0.0 };
+                            let is_next = show.next_episode.as_ref().map(|n| n.media_id) == Some(episode.media_id);
+                            rsx! {
+                                div {
+                                    style: if is_next { "background: #1e1e1e; padding: 15px; border-radius: 8px; border: 1px solid #007acc; cursor: pointer; position: relative; overflow: hidden;" } else { "background: #1e1e1e; padding: 15px; border-radius: 8px; border: 1px solid #333; cursor: pointer; position: relative; overflow: hidden;" },
+                                    onclick: {
+                                        let tx = cmd_tx.clone();
+                                        let episode = episode.clone();
+                                        move |_| {
+                                            tx.send(Command::Play(episode.media_id)).unwrap();
+                                            playing_episode.set(Some(episode.clone()));
+                                        }
+                                    },
+                                    div { style: "display: flex; justify-content: space-between; align-items: center;",
+                                        div {
+                                            span { style: "color: #007acc; font-family: monospace; margin-right: 15px;", "{episode.label()}" }
+                                            span { "{episode.title.clone().unwrap_or_default()}" }
+                                        }
+                                        if episode.is_watched() {
+                                            span { style: "color: #2ecc71;", "✔" }
+                                        }
+                                    }
+                                    if episode.in_progress() {
+                                        div { style: "position: absolute; bottom: 0; left: 0; width: 100%; height: 4px; background: rgba(0,0,0,0.8);",
+                                            div { style: "height: 100%; background: #e50914; width: {progress.max(2.0):.1}%;" }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            // ==========================================
+            // 3. LA GRILLE DES SÉRIES
+            // ==========================================
+            else {
+                div { class: "top-bar",
+                    Link { to: Route::Home {}, class: "btn-nav", "🏠 Accueil" }
+                    div { class: "page-title", "Séries" }
+                }
+
+                if shows().is_empty() {
+                    div { style: "text-align: center; font-style: italic; color: #666; padding: 40px;",
+                        "Aucune série trouvée. Les épisodes sont reconnus d'après leur nom (S01E02, 1x02, Saison 1/Episode 02...)."
+                    }
+                }
+
+                div { class: "media-grid",
+                    for show in shows().into_iter() {
+                        div {
+                            class: "media-card",
+                            title: "{show.title}",
+                            onclick: {
+                                let tx = cmd_tx.clone();
+                                let show = show.clone();
+                                move |_| {
+                                    tx.send(Command::GetShowEpisodes(show.id)).unwrap();
+                                    selected_season.set(show.next_episode.as_ref().map(|e| e.season).filter(|s| show.seasons.contains(s)));
+                                    selected_show.set(Some(show.clone()));
+                                }
+                            },
+                            div { class: "card-icon", "📺" }
+                            div { class: "card-text", "{show.title}" }
+                            div { style: "font-size: 0.8rem; color: #aaa; text-align: center; padding-bottom: 10px;",
+                                if let Some(next) = show.next_episode.as_ref() {
+                                    "{show.watched_count}/{show.episode_count} • à voir : {next.label()}"
+                                } else {
+                                    "{show.watched_count}/{show.episode_count} • terminé"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
 #[component]
 pub fn PageNotFound(route: Vec<String>) -> Element {
@@ -1168,7 +2219,9 @@ pub fn PageNotFound(route: Vec<String>) -> Element {
 pub fn Settings() -> Element {
     let cmd_tx = use_context::<std::sync::mpsc::Sender<Command>>();
 
-    let mut scan_message = use_signal(|| String::new());
+    let scan_progress = use_context::<Signal<Option<ScanProgress>>>();
+    let mut scan_summary = use_context::<Signal<Option<ScanSummary>>>();
+    let mut nfo_export = use_context::<Signal<Option<NfoExport>>>();
 
     let mut sources_signal = use_signal(|| {
         let config = LibraryConfig::load(SOURCE_FILE);
@@ -1187,6 +2240,13 @@ pub fn Settings() -> Element {
         paths
     });
 
+    // Règles de scan de la source en cours d'édition
+    let mut editing = use_signal(|| None::<MediaSource>);
+    let mut include_input = use_signal(String::new);
+    let mut exclude_input = use_signal(String::new);
+    let mut depth_input = use_signal(String::new);
+    let mut size_input = use_signal(String::new);
+
     rsx! {
         div { class: "container",
             div { class: "top-bar",
@@ -1212,22 +2272,130 @@ pub fn Settings() -> Element {
 
                             div { style: "font-family: monospace; color: #007acc; font-size: 1.1rem;", "📂 {path}" }
 
-                            button {
-                                class: "btn-nav",
-                                style: "position: relative; transform: none; top: auto; left: auto; background: #c0392b; padding: 8px 15px; font-size: 0.9rem;",
-                                onclick: {
-                                    let p = path.clone();
-                                    let tx = cmd_tx.clone();
-                                    move |_| {
-                                        let path_buf = PathBuf::from(&p);
-                                        tx.send(Command::RemoveSource(path_buf.clone(), MediaType::Video)).unwrap();
-                                        tx.send(Command::RemoveSource(path_buf.clone(), MediaType::Audio)).unwrap();
-                                        tx.send(Command::RemoveSource(path_buf.clone(), MediaType::Image)).unwrap();
+                            div { style: "display: flex; gap: 10px;",
+                                button {
+                                    class: "btn-nav",
+                                    style: "position: relative; transform: none; top: auto; left: auto; background: #555; padding: 8px 15px; font-size: 0.9rem;",
+                                    onclick: {
+                                        let p = path.clone();
+                                        move |_| {
+                                            let config = LibraryConfig::load(SOURCE_FILE);
+                                            let source = config
+                                                .find_source(&PathBuf::from(&p))
+                                                .cloned()
+                                                .unwrap_or_else(|| MediaSource::new(PathBuf::from(&p)));
+                                            include_input.set(source.include.join(", "));
+                                            exclude_input.set(source.exclude.join(", "));
+                                            depth_input.set(source.max_depth.map(|d| d.to_string()).unwrap_or_default());
+                                            size_input.set(source.min_size.to_string());
+                                            editing.set(Some(source));
+                                        }
+                                    },
+                                    "⚙️"
+                                }
+                                button {
+                                    class: "btn-nav",
+                                    style: "position: relative; transform: none; top: auto; left: auto; background: #c0392b; padding: 8px 15px; font-size: 0.9rem;",
+                                    onclick: {
+                                        let p = path.clone();
+                                        let tx = cmd_tx.clone();
+                                        move |_| {
+                                            let path_buf = PathBuf::from(&p);
+                                            tx.send(Command::RemoveSource(path_buf.clone(), MediaType::Video)).unwrap();
+                                            tx.send(Command::RemoveSource(path_buf.clone(), MediaType::Audio)).unwrap();
+                                            tx.send(Command::RemoveSource(path_buf.clone(), MediaType::Image)).unwrap();
+
+                                            sources_signal.write().retain(|x| x != &p);
+                                        }
+                                    },
+                                    "🗑️"
+                                }
+                            }
+                        }
+
+                        if editing().is_some_and(|source| source.path == PathBuf::from(path)) {
+                            div {
+                                style: "background: #161616; padding: 15px; border-radius: 8px; border: 1px solid #333; display: flex; flex-direction: column; gap: 10px;",
+
+                                label { "Inclure (motifs séparés par des virgules, vide = tous les médias)" }
+                                input {
+                                    class: "search-input",
+                                    value: "{include_input}",
+                                    placeholder: "*.flac, Albums/**",
+                                    oninput: move |evt| include_input.set(evt.value()),
+                                }
+                                label { "Exclure" }
+                                input {
+                                    class: "search-input",
+                                    value: "{exclude_input}",
+                                    oninput: move |evt| exclude_input.set(evt.value()),
+                                }
+                                div { style: "display: flex; gap: 20px; align-items: center;",
+                                    label { "Profondeur max" }
+                                    input {
+                                        class: "search-input",
+                                        r#type: "number",
+                                        min: "0",
+                                        value: "{depth_input}",
+                                        placeholder: "illimitée",
+                                        oninput: move |evt| depth_input.set(evt.value()),
+                                    }
+                                    label { "Taille min (octets)" }
+                                    input {
+                                        class: "search-input",
+                                        r#type: "number",
+                                        min: "0",
+                                        value: "{size_input}",
+                                        oninput: move |evt| size_input.set(evt.value()),
+                                    }
+                                }
+                                label {
+                                    input {
+                                        r#type: "checkbox",
+                                        checked: editing().map(|source| source.follow_symlinks).unwrap_or(false),
+                                        onchange: move |_| {
+                                            if let Some(source) = editing.write().as_mut() {
+                                                source.follow_symlinks = !source.follow_symlinks;
+                                            }
+                                        },
+                                    }
+                                    " Suivre les liens symboliques"
+                                }
+                                p { style: "color: #888; font-size: 0.85rem;", "Un fichier .nomedia ou .ignore dans un dossier l'exclut du scan." }
 
-                                        sources_signal.write().retain(|x| x != &p);
+                                div { style: "display: flex; gap: 10px; justify-content: flex-end;",
+                                    button {
+                                        class: "btn-nav",
+                                        style: "position: relative; transform: none; top: auto; left: auto; background: #555; padding: 8px 15px; font-size: 0.9rem;",
+                                        onclick: move |_| editing.set(None),
+                                        "Annuler"
                                     }
-                                },
-                                "🗑️"
+                                    button {
+                                        class: "btn-nav",
+                                        style: "position: relative; transform: none; top: auto; left: auto; background: #27ae60; padding: 8px 15px; font-size: 0.9rem;",
+                                        onclick: {
+                                            let tx = cmd_tx.clone();
+                                            move |_| {
+                                                let split = |text: String| -> Vec<String> {
+                                                    text.split(',')
+                                                        .map(|p| p.trim().to_string())
+                                                        .filter(|p| !p.is_empty())
+                                                        .collect()
+                                                };
+                                                if let Some(mut source) = editing() {
+                                                    source.include = split(include_input());
+                                                    source.exclude = split(exclude_input());
+                                                    source.max_depth = depth_input().trim().parse().ok();
+                                                    source.min_size = size_input().trim().parse().unwrap_or(0);
+                                                    scan_summary.set(None);
+                                                    tx.send(Command::UpdateSource(source)).unwrap();
+                                                }
+                                                editing.set(None);
+                                            }
+                                        },
+                                        "💾 Enregistrer"
+                                    }
+                                }
                             }
                         }
                     }
@@ -1266,19 +2434,61 @@ pub fn Settings() -> Element {
                             onclick: {
                                 let tx = cmd_tx.clone();
                                 move |_| {
-                                    scan_message.set("⏳ Analyse des fichiers en cours...".to_string());
+                                    scan_summary.set(None);
                                     tx.send(Command::Reload()).unwrap();
-                                    spawn(async move {
-                                        tokio::time::sleep(Duration::from_secs(3)).await;
-                                        scan_message.set(String::new());
-                                    });
                                 }
                             },
                             "🔄 Forcer le re-scan complet"
                         }
-                        // Message de confirmation
-                        if !scan_message().is_empty() {
-                            div { style: "color: #2ecc71; font-weight: bold; margin-top: 10px;", "{scan_message}" }
+                        // Avancement du scan, envoyé par le backend
+                        if let Some(progress) = scan_progress() {
+                            div { style: "color: #f1c40f; font-weight: bold; margin-top: 10px;",
+                                "⏳ {progress.source.display()} : {progress.files_indexed} / {progress.files_seen} fichiers"
+                            }
+                            button {
+                                class: "btn-nav",
+                                style: "position: relative; transform: none; top: auto; left: auto; background: #c0392b; padding: 8px 15px; font-size: 0.9rem;",
+                                onclick: {
+                                    let tx = cmd_tx.clone();
+                                    move |_| {
+                                        tx.send(Command::CancelScan()).unwrap();
+                                    }
+                                },
+                                "⏹️ Annuler le scan"
+                            }
+                        }
+                        if let Some(summary) = scan_summary() {
+                            if summary.cancelled {
+                                div { style: "color: #e67e22; font-weight: bold; margin-top: 10px;", "⏹️ Scan annulé, la bibliothèque n'a pas été modifiée." }
+                            } else {
+                                div { style: "color: #2ecc71; font-weight: bold; margin-top: 10px;",
+                                    "✅ Scan terminé : {summary.report.added_ids.len()} ajoutés, {summary.report.updated_ids.len()} mis à jour, {summary.report.removed_ids.len()} supprimés ({summary.elapsed_ms} ms)"
+                                }
+                            }
+                        }
+
+                        // Fichiers .nfo (Kodi) écrits à côté des films et des épisodes
+                        button {
+                            class: "btn-nav",
+                            style: "position: relative; transform: none; top: auto; left: auto; font-size: 1.1rem; padding: 15px 30px; background-color: #8e44ad;",
+                            onclick: {
+                                let tx = cmd_tx.clone();
+                                move |_| {
+                                    nfo_export.set(None);
+                                    tx.send(Command::ExportNfo(false)).unwrap();
+                                }
+                            },
+                            "📝 Exporter les fichiers NFO"
+                        }
+                        if let Some(export) = nfo_export() {
+                            div { style: "color: #2ecc71; font-weight: bold; margin-top: 10px;",
+                                "✅ NFO : {export.written} écrits, {export.skipped} déjà présents"
+                            }
+                            if !export.failed.is_empty() {
+                                div { style: "color: #e74c3c; margin-top: 5px;",
+                                    "❌ {export.failed.len()} fichiers n'ont pas pu être écrits"
+                                }
+                            }
                         }
                     }
                 }
@@ -0,0 +1,398 @@
+/*
+This file handles the artwork of the library: the images found next to the media (poster.jpg,
+folder.jpg, <video>-fanart.jpg...) and the covers embedded in the tags.
+Every image is stored once in a cache directory, named after the sha256 of its content.
+*/
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// extensions of the local artwork files, the content is checked when they are stored
+const ARTWORK_EXTS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "gif"];
+
+// numbers the files being written to the cache, see ArtworkCache::store
+static PARTIAL_FILES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArtKind {
+    Poster,
+    Fanart,
+    Banner,
+    Cover, // albums and audio files
+    Thumb, // episodes
+}
+
+impl ArtKind {
+    pub fn as_db(&self) -> &'static str {
+        match self {
+            ArtKind::Poster => "poster",
+            ArtKind::Fanart => "fanart",
+            ArtKind::Banner => "banner",
+            ArtKind::Cover => "cover",
+            ArtKind::Thumb => "thumb",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Option<Self> {
+        match value {
+            "poster" => Some(ArtKind::Poster),
+            "fanart" => Some(ArtKind::Fanart),
+            "banner" => Some(ArtKind::Banner),
+            "cover" => Some(ArtKind::Cover),
+            "thumb" => Some(ArtKind::Thumb),
+            _ => None,
+        }
+    }
+}
+
+// What an artwork is linked to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ArtOwner {
+    Media(i64),
+    Show(i64),
+    Album(String), // "album artist / album"
+}
+
+impl ArtOwner {
+    pub fn album(artist: Option<&str>, album: &str) -> Self {
+        ArtOwner::Album(format!("{} / {}", artist.unwrap_or(""), album))
+    }
+
+    // (owner_type, owner_key) columns of artwork_links
+    pub fn as_db(&self) -> (&'static str, String) {
+        match self {
+            ArtOwner::Media(id) => ("media", id.to_string()),
+            ArtOwner::Show(id) => ("show", id.to_string()),
+            ArtOwner::Album(key) => ("album", key.clone()),
+        }
+    }
+
+    pub fn from_db(owner_type: &str, owner_key: &str) -> Option<Self> {
+        match owner_type {
+            "media" => owner_key.parse().ok().map(ArtOwner::Media),
+            "show" => owner_key.parse().ok().map(ArtOwner::Show),
+            "album" => Some(ArtOwner::Album(owner_key.to_string())),
+            _ => None,
+        }
+    }
+}
+
+// An image of the cache, the hash is its file name
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedImage {
+    pub hash: String,
+    pub mime: String,
+    pub size: u64,
+}
+
+impl CachedImage {
+    // "<hash>.<ext>", also the url of the image (/artwork/<file name>)
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.hash, extension_of(&self.mime))
+    }
+}
+
+// Artwork of an owner, `source` is the local file or "embedded"
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtworkLink {
+    pub owner: ArtOwner,
+    pub kind: ArtKind,
+    pub artwork_id: i64,
+    pub source: String,
+    pub source_mtime: Option<i64>,
+}
+
+pub const EMBEDDED_SOURCE: &str = "embedded";
+
+// Where the image of a link comes from before it is in the cache
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArtSource {
+    File(PathBuf),
+    Embedded(i64), // artwork id of the cover read from the tags
+}
+
+#[derive(Debug, Clone)]
+pub struct ArtworkCache {
+    dir: PathBuf,
+}
+
+impl ArtworkCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn path_of(&self, image: &CachedImage) -> PathBuf {
+        self.dir.join(image.file_name())
+    }
+
+    // Stores the image unless the same content is already there, None when it is not an image
+    pub fn store(&self, data: &[u8]) -> Option<CachedImage> {
+        let mime = image_mime(data)?;
+        let hash = Sha256::digest(data)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        let image = CachedImage {
+            hash,
+            mime: mime.to_string(),
+            size: data.len() as u64,
+        };
+
+        let path = self.path_of(&image);
+        if !path.exists() {
+            fs::create_dir_all(&self.dir).ok()?;
+            // written under a name of this writer first, a reader never sees half an image and
+            // the scan workers storing the same cover do not write to the same file
+            let partial = self.dir.join(format!(
+                "{}.{}-{}.part",
+                image.hash,
+                process::id(),
+                PARTIAL_FILES.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::write(&partial, data).ok()?;
+            // another writer may have stored it meanwhile (rename fails then on Windows)
+            if fs::rename(&partial, &path).is_err() {
+                let _ = fs::remove_file(&partial);
+                if !path.exists() {
+                    return None;
+                }
+            }
+        }
+        Some(image)
+    }
+
+    // A cache in the temp folder, the tests never write to the db/artwork of the current folder
+    #[cfg(test)]
+    pub(crate) fn for_tests() -> Self {
+        Self::new(std::env::temp_dir().join("epikodi_test_artwork"))
+    }
+
+    pub fn store_file(&self, path: &Path) -> Option<CachedImage> {
+        self.store(&fs::read(path).ok()?)
+    }
+
+    pub fn read(&self, image: &CachedImage) -> Option<Vec<u8>> {
+        fs::read(self.path_of(image)).ok()
+    }
+
+    pub fn remove(&self, image: &CachedImage) {
+        let _ = fs::remove_file(self.path_of(image));
+    }
+}
+
+fn image_mime(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+fn extension_of(mime: &str) -> &'static str {
+    match mime {
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        _ => "jpg",
+    }
+}
+
+// Artwork files of a folder by lowercase name without extension ("poster" -> .../Poster.JPG)
+pub fn folder_images(folder: &Path) -> HashMap<String, PathBuf> {
+    let mut images = HashMap::new();
+    let Ok(entries) = fs::read_dir(folder) else {
+        return images;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_artwork = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .is_some_and(|ext| ARTWORK_EXTS.contains(&ext.as_str()));
+        if !is_artwork || !path.is_file() {
+            continue;
+        }
+        if let Some(stem) = path.file_stem() {
+            images
+                .entry(stem.to_string_lossy().to_lowercase())
+                .or_insert(path);
+        }
+    }
+    images
+}
+
+// Kodi names: "<video>-poster.jpg", "<video>-fanart.jpg"... then, when the video has
+// its own folder, "poster.jpg" / "folder.jpg", "fanart.jpg" and "banner.jpg"
+pub fn video_artwork(
+    media: &Path,
+    images: &HashMap<String, PathBuf>,
+    own_folder: bool,
+) -> Vec<(ArtKind, PathBuf)> {
+    let stem = media
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let find = |names: &[String]| names.iter().find_map(|name| images.get(name)).cloned();
+    let mut artwork = Vec::new();
+
+    let kinds = [
+        (ArtKind::Poster, vec!["poster", "folder", "cover"]),
+        (ArtKind::Fanart, vec!["fanart", "backdrop"]),
+        (ArtKind::Banner, vec!["banner"]),
+        (ArtKind::Thumb, vec!["thumb"]),
+    ];
+    for (kind, names) in kinds {
+        let mut candidates: Vec<String> = vec![format!("{}-{}", stem, names[0])];
+        if kind == ArtKind::Thumb {
+            candidates.push(stem.clone());
+        } else if own_folder {
+            candidates.extend(names.iter().map(|name| name.to_string()));
+        }
+        if let Some(path) = find(&candidates) {
+            artwork.push((kind, path));
+        }
+    }
+    artwork
+}
+
+// Cover of an album folder
+pub fn album_cover(images: &HashMap<String, PathBuf>) -> Option<PathBuf> {
+    ["cover", "folder", "front", "albumart"]
+        .iter()
+        .find_map(|name| images.get(*name))
+        .cloned()
+}
+
+// Artwork of a show folder
+pub fn show_artwork(images: &HashMap<String, PathBuf>) -> Vec<(ArtKind, PathBuf)> {
+    let kinds: [(ArtKind, &[&str]); 3] = [
+        (ArtKind::Poster, &["poster", "folder"]),
+        (ArtKind::Fanart, &["fanart", "backdrop"]),
+        (ArtKind::Banner, &["banner"]),
+    ];
+    kinds
+        .iter()
+        .filter_map(|(kind, names)| {
+            let path = names.iter().find_map(|name| images.get(*name))?;
+            Some((*kind, path.clone()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const JPEG: &[u8] = &[0xFF, 0xD8, 0xFF, 0xE0, 0, 0x10, b'J', b'F', b'I', b'F'];
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("epikodi_artwork_{label}_{stamp}"));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn cache_stores_each_content_once() {
+        let dir = temp_dir("cache");
+        let cache = ArtworkCache::new(dir.join("cache"));
+
+        let first = cache.store(JPEG).unwrap();
+        let second = cache.store(JPEG).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first.mime, "image/jpeg");
+        assert_eq!(first.hash.len(), 64);
+        assert_eq!(cache.read(&first).unwrap(), JPEG);
+        assert_eq!(fs::read_dir(dir.join("cache")).unwrap().count(), 1);
+
+        // not an image
+        assert_eq!(cache.store(b"<html>"), None);
+
+        cache.remove(&first);
+        assert_eq!(cache.read(&first), None);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn writers_of_the_same_cover_all_get_it() {
+        let dir = temp_dir("writers");
+        let cache = ArtworkCache::new(dir.join("cache"));
+        let barrier = std::sync::Barrier::new(8);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    barrier.wait();
+                    assert!(cache.store(JPEG).is_some());
+                });
+            }
+        });
+        // one image and no file left half written
+        assert_eq!(fs::read_dir(dir.join("cache")).unwrap().count(), 1);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn finds_kodi_artwork_names() {
+        let dir = temp_dir("names");
+        for name in [
+            "Heat-poster.jpg",
+            "Fanart.JPG",
+            "folder.png",
+            "Heat.jpg",
+            "notes.txt",
+        ] {
+            fs::write(dir.join(name), JPEG).unwrap();
+        }
+        let images = folder_images(&dir);
+        assert_eq!(images.len(), 4);
+
+        let movie = dir.join("Heat.mkv");
+        let kinds = |own_folder| -> Vec<ArtKind> {
+            video_artwork(&movie, &images, own_folder)
+                .into_iter()
+                .map(|(kind, _)| kind)
+                .collect()
+        };
+        assert_eq!(
+            kinds(true),
+            vec![ArtKind::Poster, ArtKind::Fanart, ArtKind::Thumb]
+        );
+        // folder images belong to the folder when it is shared
+        assert_eq!(kinds(false), vec![ArtKind::Poster, ArtKind::Thumb]);
+        assert_eq!(
+            video_artwork(&movie, &images, true)[0].1,
+            dir.join("Heat-poster.jpg")
+        );
+
+        assert_eq!(album_cover(&images), Some(dir.join("folder.png")));
+        let show: Vec<ArtKind> = show_artwork(&images).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(show, vec![ArtKind::Poster, ArtKind::Fanart]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn owners_round_trip_through_the_database_columns() {
+        for owner in [
+            ArtOwner::Media(3),
+            ArtOwner::Show(7),
+            ArtOwner::album(Some("Daft Punk"), "Discovery"),
+        ] {
+            let (owner_type, owner_key) = owner.as_db();
+            assert_eq!(ArtOwner::from_db(owner_type, &owner_key), Some(owner));
+        }
+    }
+}
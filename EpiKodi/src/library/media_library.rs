@@ -1,19 +1,35 @@
-use crate::database::db::DB;
+use crate::database::db::{FileStamp, MediaRow, DB};
 
 use crate::media;
-use crate::media::audio::Audio;
+use crate::media::audio::{Audio, Metadata};
 use crate::media::data::Media;
 use crate::media::data::MediaInfo;
+use crate::media::data::MediaTags;
 use crate::media::data::MediaType;
 use crate::media::image::Image;
 use crate::media::video::Video;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 
 use crate::constants::LOG_FILE;
+use crate::library::artwork::{
+    album_cover, folder_images, show_artwork, video_artwork, ArtKind, ArtOwner, ArtSource,
+    ArtworkLink, CachedImage, EMBEDDED_SOURCE,
+};
+use crate::library::movies::{parse_movie_path, ParsedMovie};
+use crate::library::music::{group_tracks, Album, AlbumTrack, Artist};
+use crate::library::nfo::{
+    media_nfo_paths, parse_nfo, show_nfo_path, update_nfo, write_nfo, Nfo, NfoExport, NfoFile,
+    NfoKind,
+};
+use crate::library::search::{SearchFilters, SearchResult};
+use crate::library::series::{
+    build_shows, parse_episode_path, show_folder, Episode, ParsedEpisode, Show,
+};
 use crate::scan::scan::Scan;
+use crate::watcher::watcher::FileChange;
 use lazy_static::lazy_static;
 use std::fs::File;
 use std::path::PathBuf;
@@ -32,6 +48,25 @@ pub struct ScannedMedia {
     pub name: String,
     pub duration: f32,
     pub media_type: MediaType,
+    pub size: u64,
+    pub mtime: i64,                   // milliseconds since UNIX epoch
+    pub tags: Option<MediaTags>,      // None when the file was not read (unchanged or image)
+    pub artwork: Option<CachedImage>, // embedded cover, already in the cache
+}
+
+// What changed in the database after a scan
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanReport {
+    pub added_ids: Vec<i64>,
+    pub updated_ids: Vec<i64>,
+    pub removed_ids: Vec<i64>,
+    pub unchanged: usize,
+}
+
+impl ScanReport {
+    pub fn is_empty(&self) -> bool {
+        self.added_ids.is_empty() && self.updated_ids.is_empty() && self.removed_ids.is_empty()
+    }
 }
 pub struct MediaLibrary {
     pub items: HashMap<i64, Box<dyn Media>>,
@@ -48,28 +83,170 @@ impl MediaLibrary {
         }
     }
 
-    pub fn init(&mut self) {
-        // scan the libraries
-        self.database.init_db().unwrap();
+    // Opens the database and loads the media already known, without scanning the sources
+    pub fn open(&mut self) {
+        let logger = Logger::new(LOG_FILE);
+
+        if let Err(e) = self.database.init_db() {
+            logger.error(&format!("Cannot open the library database: {}", e));
+            panic!("Cannot open the library database: {}", e);
+        }
+
+        self.refresh_items(&ScanReport::default(), None);
+    }
+
+    // Opens the library and runs a full scan right away, see LibraryScanner for the background scan
+    pub fn init(&mut self) -> ScanReport {
+        let logger = Logger::new(LOG_FILE);
+        self.open();
+
+        // scan the libraries, the tags are only read for new or modified files
+        self.scan_lib.known_files = self.database.get_file_stamps().unwrap_or_else(|e| {
+            logger.error(&format!("Cannot read the file stamps: {}", e));
+            HashMap::new()
+        });
         self.scan_lib.scan_libraries();
 
-        // update the database
-        self.database
-            .upsert_media_from_scan(self.scan_lib.scan.clone())
-            .unwrap(); //TODO: ce clone me fait chier, il faudrait qu'on utilise juste scan (ca serait meme mieux si on donne la valeur direct comme ca il se fait drop (on en a plus besoin ) et mm en terme de performance c'est pas terrible parce que c'est un gros object )
-        self.database
-            .cleanup_missing_media(self.scan_lib.scan.clone())
-            .unwrap(); // TODO to implement, shuld be called every scans
+        let scanned = std::mem::take(&mut self.scan_lib.scan);
+        self.apply_scan_results(scanned)
+    }
+
+    // Writes a finished scan to the database in one transaction and refreshes the media objects
+    pub fn apply_scan_results(&mut self, scanned: Vec<ScannedMedia>) -> ScanReport {
+        // update the database, only the files whose size or mtime changed are touched
+        let result = self.database.apply_scan(scanned);
+        self.apply_report(result)
+    }
+
+    // Same for a scan that ran in the background from `known_files`, see DB::apply_scan_since
+    pub fn apply_scan_results_since(
+        &mut self,
+        scanned: Vec<ScannedMedia>,
+        known_files: &HashMap<String, FileStamp>,
+    ) -> ScanReport {
+        let result = self.database.apply_scan_since(scanned, known_files);
+        self.apply_report(result)
+    }
+
+    fn apply_report(&mut self, result: rusqlite::Result<ScanReport>) -> ScanReport {
+        let logger = Logger::new(LOG_FILE);
+        let report = match result {
+            Ok(report) => report,
+            Err(e) => {
+                logger.error(&format!("Error updating the library from the scan: {}", e));
+                ScanReport::default()
+            }
+        };
+        logger.info(&format!(
+            "Scan done: {} added, {} updated, {} removed, {} unchanged",
+            report.added_ids.len(),
+            report.updated_ids.len(),
+            report.removed_ids.len(),
+            report.unchanged
+        ));
+
+        self.refresh_items(&report, None);
+
+        report
+    }
+
+    // Applies the files changed on disk reported by the watcher, without a full rescan
+    pub fn apply_file_changes(&mut self, changes: &[FileChange]) -> ScanReport {
+        let logger = Logger::new(LOG_FILE);
+
+        // moves first and in order, the moved media keep their ids
+        let moves: Vec<(String, String)> = changes
+            .iter()
+            .filter_map(|change| match change {
+                FileChange::Move(from, to) => Some((
+                    from.to_string_lossy().to_string(),
+                    to.to_string_lossy().to_string(),
+                )),
+                _ => None,
+            })
+            .collect();
+        let (moved_ids, mut removed_ids) =
+            self.database.move_media_paths(&moves).unwrap_or_else(|e| {
+                logger.error(&format!("Error moving renamed media: {}", e));
+                (Vec::new(), Vec::new())
+            });
+        // their nfo sidecars moved with them, they are read again
+        if let Err(e) = self.database.remove_media_nfo(&moved_ids) {
+            logger.error(&format!("Error removing the nfo files: {}", e));
+        }
+
+        // then removals, a folder can be removed and re-created in the same batch
+        let removed: Vec<String> = changes
+            .iter()
+            .filter_map(|change| match change {
+                FileChange::Remove(path) => Some(path.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect();
+        removed_ids.extend(
+            self.database
+                .remove_media_paths(&removed)
+                .unwrap_or_else(|e| {
+                    logger.error(&format!("Error removing deleted media: {}", e));
+                    Vec::new()
+                }),
+        );
+
+        // a moved file is scanned again at its new path, it may have changed in the meantime
+        let mut scanned = Vec::new();
+        for change in changes {
+            if let FileChange::Upsert(path) | FileChange::Move(_, path) = change {
+                scanned.extend(self.scan_lib.scan_path(path));
+            }
+        }
+        let mut report = self
+            .database
+            .upsert_scanned_media(scanned)
+            .unwrap_or_else(|e| {
+                logger.error(&format!("Error updating changed media: {}", e));
+                ScanReport::default()
+            });
+        report.removed_ids = removed_ids;
+        for id in moved_ids {
+            if !report.updated_ids.contains(&id) {
+                report.updated_ids.push(id);
+            }
+        }
+
+        self.refresh_items(&report, Some(changes));
+        report
+    }
+
+    // Drops the removed media and (re)builds the media objects that are new or changed on disk.
+    // `changes` are the files the watcher reported, None after a full scan.
+    fn refresh_items(&mut self, report: &ScanReport, changes: Option<&[FileChange]>) {
+        for id in &report.removed_ids {
+            self.items.remove(id);
+        }
+
         self.database.get_all_media().unwrap();
         //self.database.print_media_rows();
 
+        let updated: HashSet<i64> = report.updated_ids.iter().copied().collect();
+        let mut rebuilt = Vec::new();
         for row in self.database.media_rows.iter() {
+            if self.items.contains_key(&row.id) && !updated.contains(&row.id) {
+                continue;
+            }
+            rebuilt.push(row.id);
+
             let media: Box<dyn Media> = match row.media_type {
-                MediaType::Audio => Box::new(Audio::new(
+                // the tags come from the database, the file is not read again
+                MediaType::Audio => Box::new(Audio::with_metadata(
                     row.id,
                     &row.path,
                     &row.title.as_deref().unwrap_or(""),
                     row.last_position,
+                    Metadata {
+                        title: row.title.clone(),
+                        duration: row.duration.unwrap_or(0.0),
+                        tags: row.tags.clone(),
+                    },
                 )),
 
                 MediaType::Video => Box::new(Video::new(
@@ -87,11 +264,578 @@ impl MediaLibrary {
             };
             self.items.insert(row.id, media);
         }
+
+        self.refresh_series(&rebuilt, !report.removed_ids.is_empty());
+        self.refresh_music();
+        self.refresh_movie_names();
+        match changes {
+            // a full scan reads every file, so do the nfo and artwork refreshes
+            None => {
+                self.refresh_nfo(None);
+                self.refresh_artwork(None);
+            }
+            Some(changes) => {
+                let folders = self.changed_folders(&rebuilt, changes);
+                let mut videos: HashSet<i64> = rebuilt.into_iter().collect();
+                videos.extend(self.videos_next_to_nfo(changes));
+                if let Err(e) = self.database.remove_media_nfo(&report.removed_ids) {
+                    Logger::new(LOG_FILE).error(&format!("Error removing the nfo files: {}", e));
+                }
+                self.refresh_nfo(Some(&videos));
+                self.refresh_artwork(Some(&folders));
+            }
+        }
     }
 
-    pub fn reload(&mut self) {
+    // Folders of the rebuilt media and of the changed files, a changed path may be a folder
+    fn changed_folders(&self, rebuilt: &[i64], changes: &[FileChange]) -> HashSet<PathBuf> {
+        let rebuilt: HashSet<i64> = rebuilt.iter().copied().collect();
+        let mut folders = HashSet::new();
+        for row in &self.database.media_rows {
+            if rebuilt.contains(&row.id) {
+                folders.extend(Path::new(&row.path).parent().map(Path::to_path_buf));
+            }
+        }
+        for path in changes.iter().flat_map(FileChange::paths) {
+            folders.insert(path.clone());
+            folders.extend(path.parent().map(Path::to_path_buf));
+        }
+        folders
+    }
+
+    // Updates the shows, seasons and episodes from the paths of the media rebuilt by
+    // refresh_items, every media when the library is opened. The episodes of removed media
+    // are dropped by the database, only their empty shows are left to remove.
+    fn refresh_series(&mut self, changed: &[i64], removed: bool) {
+        if changed.is_empty() && !removed {
+            return;
+        }
+        let logger = Logger::new(LOG_FILE);
+        let changed: HashSet<i64> = changed.iter().copied().collect();
+        let episodes: Vec<(i64, ParsedEpisode)> = self
+            .database
+            .media_rows
+            .iter()
+            .filter(|row| row.media_type == MediaType::Video && changed.contains(&row.id))
+            .filter_map(|row| Some((row.id, parse_episode_path(Path::new(&row.path))?)))
+            .collect();
+        let media_ids: Vec<i64> = changed.into_iter().collect();
+
+        if let Err(e) = self.database.sync_series(&media_ids, &episodes) {
+            logger.error(&format!("Error updating the series: {}", e));
+        }
+    }
+
+    // Rebuilds the artists, albums and album tracks from the audio tags
+    fn refresh_music(&mut self) {
+        let logger = Logger::new(LOG_FILE);
+        let audio: Vec<(i64, &Path, &MediaTags)> = self
+            .database
+            .media_rows
+            .iter()
+            .filter(|row| row.media_type == MediaType::Audio)
+            .map(|row| (row.id, Path::new(&row.path), &row.tags))
+            .collect();
+        let tracks = group_tracks(&audio);
+
+        if let Err(e) = self.database.sync_music(&tracks) {
+            logger.error(&format!("Error updating the albums: {}", e));
+        }
+    }
+
+    // Every video that is not an episode is a movie
+    fn refresh_movie_names(&mut self) {
         let logger = Logger::new(LOG_FILE);
-        self.init();
+        let movies: Vec<(i64, ParsedMovie)> = self
+            .database
+            .media_rows
+            .iter()
+            .filter(|row| row.media_type == MediaType::Video)
+            .filter(|row| parse_episode_path(Path::new(&row.path)).is_none())
+            .map(|row| (row.id, parse_movie_path(Path::new(&row.path))))
+            .collect();
+
+        if let Err(e) = self.database.sync_movie_names(&movies) {
+            logger.error(&format!("Error updating the movie names: {}", e));
+        }
+    }
+
+    // Name used to look the movie up, parsed again from the path if it was not stored.
+    // The title and year of a movie.nfo win over the file name.
+    pub fn get_movie_name(&self, media_id: i64) -> Option<ParsedMovie> {
+        let mut movie = match self.database.get_movie_name(media_id) {
+            Ok(Some(movie)) => Some(movie),
+            Ok(None) => self
+                .items
+                .get(&media_id)
+                .filter(|media| media.media_type() == MediaType::Video)
+                .map(|media| parse_movie_path(Path::new(&media.get_path()))),
+            Err(e) => {
+                Logger::new(LOG_FILE).error(&format!("Error reading the movie name: {}", e));
+                None
+            }
+        }?;
+        if let Ok(Some(nfo)) = self.database.get_media_nfo(media_id) {
+            if let Some(title) = nfo.title {
+                movie.title = title;
+                movie.year = nfo.year.map(|year| year as u32).or(movie.year);
+            }
+        }
+        Some(movie)
+    }
+
+    // Videos whose sidecar or tvshow.nfo is among the changed files
+    fn videos_next_to_nfo(&self, changes: &[FileChange]) -> Vec<i64> {
+        let folders: HashSet<&Path> = changes
+            .iter()
+            .flat_map(FileChange::paths)
+            .map(PathBuf::as_path)
+            .filter(|path| path.extension().is_some_and(|ext| ext == "nfo"))
+            .filter_map(Path::parent)
+            .collect();
+        if folders.is_empty() {
+            return Vec::new();
+        }
+        self.database
+            .media_rows
+            .iter()
+            .filter(|row| row.media_type == MediaType::Video)
+            .filter(|row| {
+                let path = Path::new(&row.path);
+                path.parent().is_some_and(|parent| folders.contains(parent))
+                    || show_folder(path).is_some_and(|show| folders.contains(show.as_path()))
+            })
+            .map(|row| row.id)
+            .collect()
+    }
+
+    // Reads the .nfo sidecars of the videos and the tvshow.nfo of their show folders, of every
+    // video or only of `videos`. Only the files changed since the last refresh are parsed again.
+    fn refresh_nfo(&mut self, videos: Option<&HashSet<i64>>) {
+        let logger = Logger::new(LOG_FILE);
+        let stamps = match self.database.get_nfo_stamps() {
+            Ok(stamps) => stamps,
+            Err(e) => {
+                logger.error(&format!("Error reading the nfo stamps: {}", e));
+                return;
+            }
+        };
+
+        let mut files: Vec<NfoFile> = Vec::new();
+        let mut checked = HashSet::new();
+        let mut show_folders = HashSet::new();
+        for row in &self.database.media_rows {
+            if row.media_type != MediaType::Video
+                || videos.is_some_and(|videos| !videos.contains(&row.id))
+            {
+                continue;
+            }
+            let path = Path::new(&row.path);
+            let kind = if parse_episode_path(path).is_some() {
+                show_folders.extend(show_folder(path));
+                NfoKind::Episode
+            } else {
+                NfoKind::Movie
+            };
+            // a movie.nfo shared by several videos belongs to the first one
+            for nfo in media_nfo_paths(path, kind) {
+                let nfo = nfo.to_string_lossy().to_string();
+                if !checked.insert(nfo.clone()) {
+                    break;
+                }
+                if let Some(file) = NfoFile::find(Path::new(&nfo), Some(row.id)) {
+                    files.push(file);
+                    break;
+                }
+            }
+        }
+        for folder in show_folders {
+            let nfo = show_nfo_path(&folder);
+            checked.insert(nfo.to_string_lossy().to_string());
+            files.extend(NfoFile::find(&nfo, None));
+        }
+
+        let mut parsed = HashMap::new();
+        for file in files
+            .iter()
+            .filter(|f| stamps.get(&f.path) != Some(&f.mtime))
+        {
+            let nfo = fs::read_to_string(&file.path)
+                .ok()
+                .and_then(|text| parse_nfo(&text));
+            if nfo.is_none() {
+                logger.warning(&format!("Unreadable nfo file: {}", file.path));
+            }
+            // stamped even when unreadable, it is read again once it changes
+            parsed.insert(file.path.clone(), nfo);
+        }
+
+        let checked = videos.map(|_| &checked);
+        if let Err(e) = self.database.sync_nfo(&files, &parsed, checked) {
+            logger.error(&format!("Error updating the nfo files: {}", e));
+        }
+    }
+
+    // Links the artwork files found next to the media and the covers embedded in the tags
+    // to the media, albums and shows: of every media, or of the media in `folders` with their
+    // albums and shows, the other links are kept. Files whose path and mtime did not change
+    // are not read again.
+    fn refresh_artwork(&mut self, folders: Option<&HashSet<PathBuf>>) {
+        let logger = Logger::new(LOG_FILE);
+        let (old_links, embedded) = match (
+            self.database.get_artwork_links(),
+            self.database.get_embedded_artwork(),
+        ) {
+            (Ok(links), Ok(embedded)) => (links, embedded),
+            (Err(e), _) | (_, Err(e)) => {
+                logger.error(&format!("Error reading the artwork: {}", e));
+                return;
+            }
+        };
+        let episodes: HashMap<i64, i64> = self
+            .database
+            .get_episodes(None)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|episode| (episode.media_id, episode.show_id))
+            .collect();
+
+        // a video alone in its folder owns the folder artwork (poster.jpg, fanart.jpg...)
+        let mut videos_per_folder: HashMap<PathBuf, usize> = HashMap::new();
+        let mut show_folders: HashMap<i64, PathBuf> = HashMap::new();
+        for row in &self.database.media_rows {
+            let path = Path::new(&row.path);
+            if let Some(folder) = path.parent() {
+                if row.media_type == MediaType::Video {
+                    *videos_per_folder.entry(folder.to_path_buf()).or_default() += 1;
+                }
+            }
+            if let (Some(show_id), Some(show)) = (episodes.get(&row.id), show_folder(path)) {
+                show_folders.entry(*show_id).or_insert(show);
+            }
+        }
+
+        // the media of the folders, with every track of their albums so an album gets the
+        // same cover as after a full refresh, and the shows of their episodes or folders
+        let in_folders = |path: &str| {
+            folders.is_none_or(|folders| {
+                Path::new(path)
+                    .parent()
+                    .is_some_and(|parent| folders.contains(parent))
+            })
+        };
+        let albums: HashSet<ArtOwner> = self
+            .database
+            .media_rows
+            .iter()
+            .filter(|row| in_folders(&row.path))
+            .filter_map(album_owner)
+            .collect();
+        let rows: Vec<&MediaRow> = self
+            .database
+            .media_rows
+            .iter()
+            .filter(|row| {
+                in_folders(&row.path) || album_owner(row).is_some_and(|key| albums.contains(&key))
+            })
+            .collect();
+        if let Some(folders) = folders {
+            let shows: HashSet<i64> = rows
+                .iter()
+                .filter_map(|row| episodes.get(&row.id).copied())
+                .collect();
+            show_folders.retain(|show_id, show| shows.contains(show_id) || folders.contains(show));
+        }
+
+        let mut images_of: HashMap<PathBuf, HashMap<String, PathBuf>> = HashMap::new();
+        let mut wanted: HashMap<(ArtOwner, ArtKind), ArtSource> = HashMap::new();
+        for row in &rows {
+            let path = Path::new(&row.path);
+            let Some(folder) = path.parent() else {
+                continue;
+            };
+            let images = images_of
+                .entry(folder.to_path_buf())
+                .or_insert_with(|| folder_images(folder));
+            let owner = ArtOwner::Media(row.id);
+            let embedded = embedded.get(&row.id).map(|id| ArtSource::Embedded(*id));
+
+            match row.media_type {
+                MediaType::Video => {
+                    let own_folder = !episodes.contains_key(&row.id)
+                        && videos_per_folder.get(folder) == Some(&1);
+                    for (kind, image) in video_artwork(path, images, own_folder) {
+                        wanted.insert((owner.clone(), kind), ArtSource::File(image));
+                    }
+                    // the cover stored in the file when there is no poster next to it
+                    if let Some(source) = embedded {
+                        wanted.entry((owner, ArtKind::Poster)).or_insert(source);
+                    }
+                }
+                MediaType::Audio => {
+                    let cover = album_cover(images).map(ArtSource::File);
+                    // the own cover of a track first, the folder one is shared by the album
+                    if let Some(source) = embedded.clone().or(cover.clone()) {
+                        wanted.insert((owner, ArtKind::Cover), source);
+                    }
+                    if let Some(album) = album_owner(row) {
+                        let key = (album, ArtKind::Cover);
+                        match cover {
+                            Some(source) => {
+                                wanted.insert(key, source);
+                            }
+                            None => {
+                                if let Some(source) = embedded {
+                                    wanted.entry(key).or_insert(source);
+                                }
+                            }
+                        }
+                    }
+                }
+                MediaType::Image => {}
+            }
+        }
+        for (show_id, folder) in &show_folders {
+            for (kind, image) in show_artwork(&folder_images(folder)) {
+                wanted.insert((ArtOwner::Show(*show_id), kind), ArtSource::File(image));
+            }
+        }
+
+        // the links replaced: of the owners refreshed, and of the media, albums and shows gone
+        let replaced = folders.map(|_| {
+            let media: HashSet<i64> = self.database.media_rows.iter().map(|row| row.id).collect();
+            let all_albums: HashSet<ArtOwner> = self
+                .database
+                .media_rows
+                .iter()
+                .filter_map(album_owner)
+                .collect();
+            let all_shows: HashSet<i64> = episodes.values().copied().collect();
+            let mut owners: HashSet<ArtOwner> = rows
+                .iter()
+                .map(|row| ArtOwner::Media(row.id))
+                .chain(albums.iter().cloned())
+                .chain(show_folders.keys().map(|id| ArtOwner::Show(*id)))
+                .collect();
+            owners.extend(
+                old_links
+                    .iter()
+                    .map(|link| &link.owner)
+                    .filter(|owner| match owner {
+                        ArtOwner::Media(id) => !media.contains(id),
+                        ArtOwner::Show(id) => !all_shows.contains(id),
+                        ArtOwner::Album(_) => !all_albums.contains(owner),
+                    })
+                    .cloned(),
+            );
+            owners
+        });
+
+        // images already in the cache, by source file and mtime
+        let known: HashMap<(String, Option<i64>), i64> = old_links
+            .iter()
+            .map(|link| ((link.source.clone(), link.source_mtime), link.artwork_id))
+            .collect();
+        let cache = self.scan_lib.artwork.clone();
+        let mut links = Vec::new();
+        for ((owner, kind), source) in wanted {
+            let (artwork_id, source, source_mtime) = match source {
+                ArtSource::Embedded(id) => (id, EMBEDDED_SOURCE.to_string(), None),
+                ArtSource::File(path) => {
+                    let mtime = fs::metadata(&path)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map(|elapsed| elapsed.as_millis() as i64);
+                    let source = path.to_string_lossy().to_string();
+                    let id = match known.get(&(source.clone(), mtime)) {
+                        Some(id) => Some(*id),
+                        None => cache
+                            .store_file(&path)
+                            .and_then(|image| self.database.add_artwork(&image).ok()),
+                    };
+                    let Some(id) = id else {
+                        logger.warning(&format!("Unreadable artwork: {}", source));
+                        continue;
+                    };
+                    (id, source, mtime)
+                }
+            };
+            links.push(ArtworkLink {
+                owner,
+                kind,
+                artwork_id,
+                source,
+                source_mtime,
+            });
+        }
+
+        match self.database.sync_artwork_links(&links, replaced.as_ref()) {
+            Ok(removed) => removed.iter().for_each(|image| cache.remove(image)),
+            Err(e) => logger.error(&format!("Error updating the artwork: {}", e)),
+        }
+    }
+
+    // Cached file of the image to show for each media
+    fn with_artwork(&self, mut media: Vec<MediaInfo>) -> Vec<MediaInfo> {
+        let artwork = self.database.get_media_artwork().unwrap_or_default();
+        for info in media.iter_mut() {
+            info.artwork = artwork.get(&info.id).map(CachedImage::file_name);
+        }
+        media
+    }
+
+    // Writes the nfo of every movie and episode back to the file it was read from, or to
+    // "<video>.nfo", and tvshow.nfo in the show folders, from the imported nfo completed with
+    // the library metadata.
+    // Existing files are kept unless overwrite is set, only the elements the library reads are
+    // then replaced. A file that is no nfo of the same kind is never overwritten.
+    pub fn export_nfo(&mut self, overwrite: bool) -> NfoExport {
+        let logger = Logger::new(LOG_FILE);
+        let mut export = NfoExport::default();
+        let episodes: HashMap<i64, Episode> = self
+            .database
+            .get_episodes(None)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|episode| (episode.media_id, episode))
+            .collect();
+        let shows: HashMap<i64, String> = self
+            .database
+            .get_shows()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+
+        let mut show_folders: HashMap<PathBuf, String> = HashMap::new();
+        let mut exports: Vec<(PathBuf, Nfo)> = Vec::new();
+        for row in &self.database.media_rows {
+            if row.media_type != MediaType::Video {
+                continue;
+            }
+            let path = Path::new(&row.path);
+            let stored = self.database.get_media_nfo(row.id).ok().flatten();
+            let mut nfo = match episodes.get(&row.id) {
+                Some(episode) => {
+                    let mut nfo = stored.unwrap_or_else(|| Nfo::new(NfoKind::Episode));
+                    let show = shows.get(&episode.show_id).cloned();
+                    nfo.title = nfo.title.or_else(|| episode.title.clone());
+                    nfo.show_title = nfo.show_title.or_else(|| show.clone());
+                    nfo.season = nfo.season.or(Some(episode.season));
+                    nfo.episode = nfo.episode.or(Some(episode.episode));
+                    if let (Some(folder), Some(show)) = (show_folder(path), show) {
+                        show_folders.entry(folder).or_insert(show);
+                    }
+                    nfo
+                }
+                None => {
+                    let mut nfo = stored.unwrap_or_else(|| Nfo::new(NfoKind::Movie));
+                    if let Some(movie) = self.get_movie_name(row.id) {
+                        nfo.title = nfo.title.or(Some(movie.title));
+                        nfo.year = nfo.year.or(movie.year.map(|year| year as i32));
+                    }
+                    nfo
+                }
+            };
+            if nfo.genres.is_empty() {
+                nfo.genres.extend(row.tags.genre.clone());
+            }
+            let target = match self.database.get_media_nfo_path(row.id) {
+                Ok(Some(read)) => PathBuf::from(read),
+                _ => path.with_extension("nfo"),
+            };
+            exports.push((target, nfo));
+        }
+        for (folder, title) in show_folders {
+            let path = show_nfo_path(&folder);
+            let stored = self
+                .database
+                .get_nfo_at(&path.to_string_lossy())
+                .ok()
+                .flatten();
+            let mut nfo = stored.unwrap_or_else(|| Nfo::new(NfoKind::TvShow));
+            nfo.title = nfo.title.or(Some(title));
+            exports.push((path, nfo));
+        }
+
+        for (path, nfo) in exports {
+            let xml = if path.exists() {
+                // what the library does not read of the file is kept, a file it cannot read is left
+                let updated = fs::read_to_string(&path)
+                    .ok()
+                    .filter(|_| overwrite)
+                    .and_then(|original| update_nfo(&original, &nfo));
+                match updated {
+                    Some(xml) => xml,
+                    None => {
+                        export.skipped += 1;
+                        continue;
+                    }
+                }
+            } else {
+                write_nfo(&nfo)
+            };
+            match fs::write(&path, xml) {
+                Ok(()) => export.written += 1,
+                Err(e) => {
+                    logger.error(&format!("Error writing {}: {}", path.display(), e));
+                    export.failed.push(path.to_string_lossy().to_string());
+                }
+            }
+        }
+
+        // the written files are the new reference
+        self.refresh_nfo(None);
+        export
+    }
+
+    pub fn get_shows(&self) -> Vec<Show> {
+        let logger = Logger::new(LOG_FILE);
+        let shows = self.database.get_shows();
+        let episodes = self.database.get_episodes(None);
+        match (shows, episodes) {
+            (Ok(shows), Ok(episodes)) => build_shows(shows, &episodes),
+            (Err(e), _) | (_, Err(e)) => {
+                logger.error(&format!("Error reading the series: {}", e));
+                Vec::new()
+            }
+        }
+    }
+
+    pub fn get_show_episodes(&self, show_id: i64) -> Vec<Episode> {
+        self.database
+            .get_episodes(Some(show_id))
+            .unwrap_or_else(|e| {
+                Logger::new(LOG_FILE).error(&format!("Error reading the episodes: {}", e));
+                Vec::new()
+            })
+    }
+
+    pub fn get_artists(&self) -> Vec<Artist> {
+        self.database.get_artists().unwrap_or_else(|e| {
+            Logger::new(LOG_FILE).error(&format!("Error reading the artists: {}", e));
+            Vec::new()
+        })
+    }
+
+    pub fn get_albums(&self, artist_id: Option<i64>) -> Vec<Album> {
+        self.database.get_albums(artist_id).unwrap_or_else(|e| {
+            Logger::new(LOG_FILE).error(&format!("Error reading the albums: {}", e));
+            Vec::new()
+        })
+    }
+
+    pub fn get_album_tracks(&self, album_id: i64) -> Vec<AlbumTrack> {
+        self.database
+            .get_album_tracks(album_id)
+            .unwrap_or_else(|e| {
+                Logger::new(LOG_FILE).error(&format!("Error reading the album tracks: {}", e));
+                Vec::new()
+            })
+    }
+
+    pub fn reload(&mut self) -> ScanReport {
+        self.init()
     }
 
     pub fn update_media_status_and_time(
@@ -293,8 +1037,6 @@ impl MediaLibrary {
             MediaType::Video => self.scan_lib.libraries.add_video_source(path),
             MediaType::Image => self.scan_lib.libraries.add_image_source(path),
         }
-
-        self.reload();
     }
 
     pub fn remove_source(&mut self, path: PathBuf, media_type: MediaType) {
@@ -303,8 +1045,6 @@ impl MediaLibrary {
             MediaType::Video => self.scan_lib.libraries.remove_video_source(path),
             MediaType::Image => self.scan_lib.libraries.remove_image_source(path),
         }
-
-        self.reload();
     }
 
     pub fn get_media_from_path(&mut self, path: PathBuf) -> Vec<MediaInfo> {
@@ -316,7 +1056,7 @@ impl MediaLibrary {
             }
         }
 
-        result
+        self.with_artwork(result)
     }
 
     pub fn get_all_media(&self) -> Vec<MediaInfo> {
@@ -326,7 +1066,7 @@ impl MediaLibrary {
             result.push(item.info());
         }
 
-        result
+        self.with_artwork(result)
     }
 
     pub fn get_media_by_type(&self, media_type: MediaType) -> Vec<MediaInfo> {
@@ -338,7 +1078,28 @@ impl MediaLibrary {
             }
         }
 
-        result
+        self.with_artwork(result)
+    }
+
+    pub fn search(&self, query: &str, filters: &SearchFilters) -> Vec<SearchResult> {
+        let logger = Logger::new(LOG_FILE);
+
+        match self.database.search_media(query, filters) {
+            Ok(hits) => hits
+                .into_iter()
+                .filter_map(|hit| {
+                    self.info_id(hit.media_id).map(|media| SearchResult {
+                        media,
+                        snippet: hit.snippet,
+                        rank: hit.rank,
+                    })
+                })
+                .collect(),
+            Err(e) => {
+                logger.error(&format!("Error searching for '{}': {}", query, e));
+                Vec::new()
+            }
+        }
     }
 
     pub fn get_media_from_tag(&mut self, tag_id: &str) -> Vec<i64> {
@@ -391,7 +1152,7 @@ impl MediaLibrary {
 
     pub fn info_id(&self, id: i64) -> Option<MediaInfo> {
         if let Some(item) = self.items.get(&id) {
-            Some(item.info())
+            self.with_artwork(vec![item.info()]).pop()
         } else {
             None
         }
@@ -435,9 +1196,17 @@ impl MediaLibrary {
     }
 }
 
+// The album of a track, by its album artist or else its artist
+fn album_owner(row: &MediaRow) -> Option<ArtOwner> {
+    let album = row.tags.album.as_ref()?;
+    let artist = row.tags.album_artist.as_ref().or(row.tags.artist.as_ref());
+    Some(ArtOwner::album(artist.map(String::as_str), album))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::library::artwork::ArtworkCache;
     use std::collections::HashMap;
 
     struct TestMedia {
@@ -487,10 +1256,12 @@ mod tests {
                 path: self.path.clone(),
                 title: Some(self.name.clone()),
                 artist: None,
+                album: None,
                 duration: None,
                 media_type: MediaType::Audio,
                 last_position: 0.0,
                 tags: Vec::new(),
+                artwork: None,
             }
         }
         fn media_type(&self) -> MediaType {
@@ -510,6 +1281,8 @@ mod tests {
             scan_lib: Scan {
                 libraries: crate::library::sources::LibraryConfig::load("db/sources.json"),
                 scan: Vec::new(),
+                known_files: HashMap::new(),
+                artwork: ArtworkCache::for_tests(),
             },
             database: DB {
                 conn: rusqlite::Connection::open_in_memory().unwrap(),
@@ -588,4 +1361,328 @@ mod tests {
         lib.clear();
         assert_eq!(lib.get_all_media().len(), 0); // La RAM doit être vide
     }
+
+    #[test]
+    fn apply_file_changes_updates_items_incrementally() {
+        let dir = std::env::temp_dir().join(format!(
+            "epikodi_watch_{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut lib = test_library(vec![]);
+        lib.database.init_db().unwrap();
+        lib.scan_lib.libraries.image_sources =
+            vec![crate::library::sources::MediaSource::new(dir.clone())];
+
+        let photo = dir.join("photo.jpg");
+        fs::write(&photo, b"").unwrap();
+        let report = lib.apply_file_changes(&[FileChange::Upsert(photo.clone())]);
+        assert_eq!(report.added_ids.len(), 1);
+        assert_eq!(lib.get_all_media().len(), 1);
+
+        fs::remove_file(&photo).unwrap();
+        let report = lib.apply_file_changes(&[FileChange::Remove(photo)]);
+        assert_eq!(report.removed_ids.len(), 1);
+        assert!(lib.get_all_media().is_empty());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn renamed_files_keep_their_id_and_tags() {
+        let dir = std::env::temp_dir().join(format!(
+            "epikodi_rename_{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis()
+        ));
+        fs::create_dir_all(dir.join("holidays")).unwrap();
+
+        let mut lib = test_library(vec![]);
+        lib.database.init_db().unwrap();
+        lib.scan_lib.libraries.image_sources =
+            vec![crate::library::sources::MediaSource::new(dir.clone())];
+
+        let photo = dir.join("holidays").join("beach.jpg");
+        fs::write(&photo, b"").unwrap();
+        let id = lib
+            .apply_file_changes(&[FileChange::Upsert(photo.clone())])
+            .added_ids[0];
+        let tag = lib.database.get_or_create_tag("Summer").unwrap();
+        lib.database.add_tag_to_media(id, tag).unwrap();
+
+        let renamed = dir.join("holidays").join("sea.jpg");
+        fs::rename(&photo, &renamed).unwrap();
+        let report = lib.apply_file_changes(&[FileChange::Move(photo, renamed.clone())]);
+        assert!(report.added_ids.is_empty() && report.removed_ids.is_empty());
+        assert_eq!(lib.info_id(id).unwrap().path, renamed.to_string_lossy());
+
+        // the folder around it
+        let folder = dir.join("2024");
+        fs::rename(dir.join("holidays"), &folder).unwrap();
+        let report =
+            lib.apply_file_changes(&[FileChange::Move(dir.join("holidays"), folder.clone())]);
+        assert_eq!(report.updated_ids, vec![id]);
+        let moved = folder.join("sea.jpg").to_string_lossy().to_string();
+        assert_eq!(lib.info_id(id).unwrap().path, moved);
+        assert_eq!(lib.database.get_media_by_tag("Summer").unwrap(), vec![id]);
+        assert_eq!(lib.get_all_media().len(), 1);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn nfo_files_are_imported_and_exported() {
+        let dir = std::env::temp_dir().join(format!(
+            "epikodi_nfo_{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis()
+        ));
+        let season = dir.join("Fargo").join("Season 1");
+        fs::create_dir_all(&season).unwrap();
+        let movie = dir.join("heat.mkv");
+        let episode = season.join("Fargo.S01E01.mkv");
+        fs::write(
+            dir.join("heat.nfo"),
+            "<movie><title>Heat</title><year>1995</year><genre>Crime</genre></movie>",
+        )
+        .unwrap();
+
+        let mut lib = test_library(vec![]);
+        lib.database.init_db().unwrap();
+        let video = |path: &Path| ScannedMedia {
+            path: path.to_string_lossy().to_string(),
+            name: path.file_name().unwrap().to_string_lossy().to_string(),
+            duration: 1000.0,
+            media_type: MediaType::Video,
+            size: 10,
+            mtime: 1,
+            tags: None,
+            artwork: None,
+        };
+        let report = lib.apply_scan_results(vec![video(&movie), video(&episode)]);
+        let movie_id = report.added_ids[0];
+
+        // the nfo title and year are used for the lookups
+        let heat = lib.get_movie_name(movie_id).unwrap();
+        assert_eq!(heat.lookup_query(), "Heat (1995)");
+
+        let export = lib.export_nfo(false);
+        assert_eq!((export.written, export.skipped), (2, 1));
+        let written = fs::read_to_string(season.join("Fargo.S01E01.nfo")).unwrap();
+        let nfo = parse_nfo(&written).unwrap();
+        assert_eq!(nfo.kind, NfoKind::Episode);
+        assert_eq!((nfo.season, nfo.episode), (Some(1), Some(1)));
+        let show = parse_nfo(&fs::read_to_string(dir.join("Fargo").join("tvshow.nfo")).unwrap());
+        assert_eq!(show.unwrap().title.as_deref(), Some("Fargo"));
+
+        // the exported files are imported back
+        assert!(lib
+            .database
+            .get_media_nfo(report.added_ids[1])
+            .unwrap()
+            .is_some());
+
+        // the watcher reports the sidecars, only their videos are looked at again
+        fs::remove_file(dir.join("heat.nfo")).unwrap();
+        fs::write(
+            dir.join("movie.nfo"),
+            "<movie><title>Heat</title><year>1996</year><director>Michael Mann</director></movie>",
+        )
+        .unwrap();
+        let episode_nfo = season.join("Fargo.S01E01.nfo");
+        fs::remove_file(&episode_nfo).unwrap();
+        lib.apply_file_changes(&[
+            FileChange::Remove(dir.join("heat.nfo")),
+            FileChange::Upsert(dir.join("movie.nfo")),
+            FileChange::Remove(episode_nfo.clone()),
+        ]);
+        let heat = lib.get_movie_name(movie_id).unwrap();
+        assert_eq!(heat.lookup_query(), "Heat (1996)");
+
+        // an unreadable file is stamped and not read again
+        fs::write(&episode_nfo, "https://www.thetvdb.com/series/fargo").unwrap();
+        lib.apply_file_changes(&[FileChange::Upsert(episode_nfo.clone())]);
+        let stamps = lib.database.get_nfo_stamps().unwrap();
+        assert!(stamps.contains_key(episode_nfo.to_string_lossy().as_ref()));
+        assert_eq!(
+            lib.database.get_media_nfo(report.added_ids[1]).unwrap(),
+            None
+        );
+
+        // the export writes back to the file that was read, and leaves the unreadable one
+        let export = lib.export_nfo(true);
+        assert_eq!((export.written, export.skipped), (2, 1));
+        assert!(!dir.join("heat.nfo").exists());
+        let written = fs::read_to_string(dir.join("movie.nfo")).unwrap();
+        assert_eq!(parse_nfo(&written).unwrap().year, Some(1996));
+        assert!(written.contains("<director>Michael Mann</director>"));
+        assert_eq!(
+            fs::read_to_string(&episode_nfo).unwrap(),
+            "https://www.thetvdb.com/series/fargo"
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn local_artwork_is_linked_and_cached() {
+        let dir = std::env::temp_dir().join(format!(
+            "epikodi_artwork_{}",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis()
+        ));
+        let movie_dir = dir.join("Heat (1995)");
+        let season = dir.join("Fargo").join("Season 1");
+        let album = dir.join("Discovery");
+        for folder in [&movie_dir, &season, &album] {
+            fs::create_dir_all(folder).unwrap();
+        }
+        let jpeg = |n: u8| vec![0xFF, 0xD8, 0xFF, 0xE0, n];
+        fs::write(movie_dir.join("poster.jpg"), jpeg(1)).unwrap();
+        fs::write(movie_dir.join("fanart.jpg"), jpeg(2)).unwrap();
+        fs::write(dir.join("Fargo").join("poster.jpg"), jpeg(3)).unwrap();
+        fs::write(album.join("cover.jpg"), jpeg(4)).unwrap();
+
+        let mut lib = test_library(vec![]);
+        lib.scan_lib.artwork = ArtworkCache::new(dir.join("cache"));
+        lib.database.init_db().unwrap();
+        let scanned =
+            |path: PathBuf, media_type: MediaType, tags: Option<MediaTags>| ScannedMedia {
+                path: path.to_string_lossy().to_string(),
+                name: path.file_name().unwrap().to_string_lossy().to_string(),
+                duration: 1000.0,
+                media_type,
+                size: 10,
+                mtime: 1,
+                tags,
+                artwork: None,
+            };
+        let tags = MediaTags {
+            artist: Some("Daft Punk".to_string()),
+            album: Some("Discovery".to_string()),
+            ..Default::default()
+        };
+        let files = vec![
+            scanned(movie_dir.join("Heat.mkv"), MediaType::Video, None),
+            scanned(season.join("Fargo.S01E01.mkv"), MediaType::Video, None),
+            scanned(album.join("01.mp3"), MediaType::Audio, Some(tags)),
+        ];
+        let report = lib.apply_scan_results(files.clone());
+        let (movie_id, episode_id, song_id) = (
+            report.added_ids[0],
+            report.added_ids[1],
+            report.added_ids[2],
+        );
+
+        let links = lib.database.get_artwork_links().unwrap();
+        assert_eq!(links.len(), 5);
+        assert!(links
+            .iter()
+            .any(|link| link.owner == ArtOwner::album(Some("Daft Punk"), "Discovery")));
+        assert!(links
+            .iter()
+            .any(|link| matches!(link.owner, ArtOwner::Show(_))));
+
+        let poster = lib.info_id(movie_id).unwrap().artwork.unwrap();
+        assert!(poster.ends_with(".jpg"));
+        assert_eq!(fs::read(dir.join("cache").join(&poster)).unwrap(), jpeg(1));
+        assert!(lib.info_id(song_id).unwrap().artwork.is_some());
+        assert_eq!(lib.info_id(episode_id).unwrap().artwork, None);
+        assert_eq!(fs::read_dir(dir.join("cache")).unwrap().count(), 4);
+
+        // the poster is removed: its link and its cached file go away
+        fs::remove_file(movie_dir.join("poster.jpg")).unwrap();
+        lib.apply_scan_results(files);
+        assert_eq!(lib.info_id(movie_id).unwrap().artwork, None);
+        assert!(!dir.join("cache").join(&poster).exists());
+        assert_eq!(fs::read_dir(dir.join("cache")).unwrap().count(), 3);
+
+        // a watcher batch only looks at the changed folders: the album cover, now broken,
+        // is not read again
+        fs::write(album.join("cover.jpg"), b"not an image").unwrap();
+        fs::write(movie_dir.join("poster.jpg"), jpeg(5)).unwrap();
+        lib.apply_file_changes(&[FileChange::Upsert(movie_dir.join("poster.jpg"))]);
+        assert!(lib.info_id(movie_id).unwrap().artwork.is_some());
+        assert!(lib.info_id(song_id).unwrap().artwork.is_some());
+        assert_eq!(lib.database.get_artwork_links().unwrap().len(), 5);
+
+        // the links of a removed track and of its album go with it
+        lib.apply_file_changes(&[FileChange::Remove(album.join("01.mp3"))]);
+        let links = lib.database.get_artwork_links().unwrap();
+        assert_eq!(links.len(), 3);
+        assert!(links.iter().all(
+            |link| matches!(link.owner, ArtOwner::Media(id) | ArtOwner::Show(id) if id != song_id)
+        ));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn scanned_episodes_are_grouped_in_shows() {
+        let mut lib = test_library(vec![]);
+        lib.database.init_db().unwrap();
+        let video = |path: &str| ScannedMedia {
+            path: path.to_string(),
+            name: path.rsplit('/').next().unwrap().to_string(),
+            duration: 1000.0,
+            media_type: MediaType::Video,
+            size: 10,
+            mtime: 1,
+            tags: None,
+            artwork: None,
+        };
+
+        lib.apply_scan_results(vec![
+            video("/tv/Fargo/Season 1/Fargo.S01E01.mkv"),
+            video("/tv/Fargo/Season 1/Fargo.S01E02.mkv"),
+            video("/films/Heat (1995).mkv"),
+        ]);
+
+        let shows = lib.get_shows();
+        assert_eq!(shows.len(), 1);
+        assert_eq!(shows[0].title, "Fargo");
+        assert_eq!(shows[0].seasons, vec![1]);
+        let first = shows[0].next_episode.clone().unwrap();
+        assert_eq!(first.label(), "S01E01");
+
+        // watching the first episode moves "next" to the second one
+        lib.update_media_status_and_time(first.media_id, 1, 950.0, 1000.0);
+        let next = lib.get_shows()[0].next_episode.clone().unwrap();
+        assert_eq!(next.label(), "S01E02");
+        assert_eq!(lib.get_show_episodes(shows[0].id).len(), 2);
+
+        // a later scan only adds the new episode, the others keep their rows
+        lib.apply_scan_results(vec![
+            video("/tv/Fargo/Season 1/Fargo.S01E01.mkv"),
+            video("/tv/Fargo/Season 1/Fargo.S01E02.mkv"),
+            video("/tv/Fargo/Season 1/Fargo.S01E03.mkv"),
+            video("/films/Heat (1995).mkv"),
+        ]);
+        assert_eq!(lib.get_show_episodes(shows[0].id).len(), 3);
+        assert_eq!(lib.get_shows()[0].id, shows[0].id);
+
+        // only the movie gets a movie name
+        let heat_id = lib
+            .items
+            .iter()
+            .find(|(_, media)| media.get_path() == "/films/Heat (1995).mkv")
+            .map(|(id, _)| *id)
+            .unwrap();
+        let heat = lib.get_movie_name(heat_id);
+        assert_eq!(
+            heat.map(|m| m.lookup_query()),
+            Some("Heat (1995)".to_string())
+        );
+        assert_eq!(lib.database.get_movie_name(first.media_id).unwrap(), None);
+    }
 }
@@ -0,0 +1,197 @@
+/*
+This file holds the cache of the plugin answers (metadata_cache table): one row per
+(plugin, kind, key), with the time it was fetched. A fresh row is served as is, a stale one is
+served and refreshed in the background (see threading/metadata_lookup.rs).
+The artist answers live in artist_metadata, keyed by normalized name (CachedArtist).
+*/
+
+use plugin_api::metadata::{ArtistMetadata, Metadata, MetadataResponse, MetadataResult};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// plugin column of the rows where no plugin found the query
+pub const NO_PLUGIN: &str = "";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheKind {
+    Artist,
+    Movie,
+}
+
+impl CacheKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheKind::Artist => "artist",
+            CacheKind::Movie => "movie",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    Fresh,
+    Stale,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedMetadata {
+    pub plugin: String,
+    pub kind: CacheKind,
+    pub key: String,
+    pub response: String, // plugin_api::metadata::MetadataResponse as JSON
+    pub fetched_at: i64,  // unix seconds
+}
+
+impl CachedMetadata {
+    // Only the answers found or not found are cached, an error is retried at the next lookup
+    pub fn new(
+        kind: CacheKind,
+        key: &str,
+        plugin: &str,
+        response: &MetadataResponse,
+    ) -> Option<Self> {
+        match response.result {
+            MetadataResult::Found { .. } | MetadataResult::NotFound => Some(Self {
+                plugin: plugin.to_string(),
+                kind,
+                key: normalize_key(key),
+                response: response.to_json(),
+                fetched_at: now(),
+            }),
+            MetadataResult::Error { .. } => None,
+        }
+    }
+
+    pub fn found(kind: CacheKind, key: &str, plugin: &str, metadata: Metadata) -> Self {
+        Self::new(kind, key, plugin, &MetadataResponse::found(metadata)).unwrap()
+    }
+
+    pub fn not_found(kind: CacheKind, key: &str) -> Self {
+        Self::new(kind, key, NO_PLUGIN, &MetadataResponse::not_found()).unwrap()
+    }
+
+    pub fn freshness(&self, now: i64, ttl_secs: i64) -> Freshness {
+        if now - self.fetched_at < ttl_secs {
+            Freshness::Fresh
+        } else {
+            Freshness::Stale
+        }
+    }
+}
+
+// Row of artist_metadata: the answer of the plugins for one artist, found or not found
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedArtist {
+    pub id: i64,
+    pub name: String, // as first asked, the key is normalize_key(name)
+    pub musicbrainz_id: Option<String>,
+    pub response: String, // plugin_api::metadata::MetadataResponse as JSON
+    pub fetched_at: i64,  // unix seconds
+}
+
+impl CachedArtist {
+    pub fn is_not_found(&self) -> bool {
+        matches!(
+            MetadataResponse::from_json(&self.response),
+            Ok(MetadataResponse {
+                result: MetadataResult::NotFound,
+                ..
+            })
+        )
+    }
+
+    // A found artist is kept until invalidated, a not found one is asked again after the TTL
+    pub fn freshness(&self, now: i64, not_found_ttl_secs: i64) -> Freshness {
+        if self.is_not_found() && now - self.fetched_at >= not_found_ttl_secs {
+            Freshness::Stale
+        } else {
+            Freshness::Fresh
+        }
+    }
+}
+
+// MusicBrainz id of a found artist, stored next to the answer to look it up by id
+pub fn musicbrainz_id(response: &MetadataResponse) -> Option<&str> {
+    match &response.result {
+        MetadataResult::Found { metadata } => match metadata.as_ref() {
+            Metadata::Artist(ArtistMetadata { external_ids, .. }) => {
+                external_ids.musicbrainz.as_deref()
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// "  Inception   (2010) " and "inception (2010)" are the same entry
+pub fn normalize_key(query: &str) -> String {
+    query
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plugin_api::metadata::MovieMetadata;
+
+    #[test]
+    fn keys_ignore_case_and_spaces() {
+        assert_eq!(normalize_key("  Inception   (2010) "), "inception (2010)");
+        assert_eq!(normalize_key("LÉON"), "léon");
+    }
+
+    #[test]
+    fn entries_expire_after_the_ttl() {
+        let mut entry = CachedMetadata::not_found(CacheKind::Movie, "Inception");
+        entry.fetched_at = 1_000;
+        assert_eq!(entry.freshness(1_000 + 59, 60), Freshness::Fresh);
+        assert_eq!(entry.freshness(1_000 + 60, 60), Freshness::Stale);
+    }
+
+    #[test]
+    fn errors_are_not_cached() {
+        let movie = Metadata::Movie(MovieMetadata {
+            title: "Inception".to_string(),
+            ..Default::default()
+        });
+        let entry = CachedMetadata::found(CacheKind::Movie, "Inception (2010)", "TMDB", movie);
+        assert_eq!(entry.key, "inception (2010)");
+        assert_eq!(entry.plugin, "TMDB");
+
+        let error = MetadataResponse::error("timeout");
+        assert_eq!(
+            CachedMetadata::new(CacheKind::Movie, "x", "TMDB", &error),
+            None
+        );
+    }
+
+    #[test]
+    fn only_not_found_artists_expire() {
+        let mut artist = CachedArtist {
+            id: 1,
+            name: "Muse".to_string(),
+            musicbrainz_id: None,
+            response: MetadataResponse::not_found().to_json(),
+            fetched_at: 1_000,
+        };
+        assert_eq!(artist.freshness(1_000 + 59, 60), Freshness::Fresh);
+        assert_eq!(artist.freshness(1_000 + 60, 60), Freshness::Stale);
+
+        artist.response = MetadataResponse::found(Metadata::Artist(ArtistMetadata {
+            name: "Muse".to_string(),
+            ..Default::default()
+        }))
+        .to_json();
+        assert!(!artist.is_not_found());
+        assert_eq!(artist.freshness(i64::MAX, 60), Freshness::Fresh);
+    }
+}
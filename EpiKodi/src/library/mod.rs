@@ -1,2 +1,9 @@
+pub mod artwork;
 pub mod media_library;
+pub mod metadata_cache;
+pub mod movies;
+pub mod music;
+pub mod nfo;
+pub mod search;
+pub mod series;
 pub mod sources;
@@ -0,0 +1,262 @@
+/*
+This file finds the title of a movie in its file name, dropping the release details
+(resolution, source, codecs, audio, language, release group...) and keeping the year,
+the edition and the part number. The result is stored on the media row and used for the metadata lookups.
+*/
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+lazy_static! {
+    // "[Group]", "{tag}" and "www.site.com -" prefixes
+    static ref BRACKETS: Regex = Regex::new(r"\[[^\]]*\]|\{[^}]*\}|(?i)^www\.[^ ]+\.[a-z]{2,4} *-?").unwrap();
+    // H.264 and friends keep their dot, it is a separator everywhere else
+    static ref DOTTED_CODEC: Regex = Regex::new(r"(?i)\b([hx])\.(26[45])\b").unwrap();
+    static ref YEAR: Regex = Regex::new(r"^\(?((?:19|20)\d\d)\)?$").unwrap();
+    static ref EDITION: Regex = Regex::new(
+        r"(?i)\b(director'?s cut|extended(?: cut| edition)?|unrated|uncut|remastered|theatrical(?: cut)?|imax(?: edition)?|final cut|special edition|ultimate edition|collector'?s edition|criterion(?: collection)?)\b"
+    )
+    .unwrap();
+    // "CD1", "Disc 2", "dvd1" anywhere, "Part 2" / "pt2" only after the title
+    static ref DISC_PART: Regex = Regex::new(r"(?i)\b(?:cd|dvd|disc|disk) ?(\d{1,2})\b").unwrap();
+    static ref NAMED_PART: Regex = Regex::new(r"(?i)\b(?:part|pt) ?(\d{1,2})\b").unwrap();
+}
+
+// tokens that start the release details, the title stops at the first one
+#[rustfmt::skip]
+const RELEASE_TOKENS: [&str; 53] = [
+    "480p", "576p", "720p", "1080p", "1080i", "2160p", "4k", "bluray", "blu-ray", "brrip",
+    "bdrip", "bdremux", "dvdrip", "dvdscr", "dvd9", "dvd5", "webrip", "web-dl", "webdl",
+    "hdtv", "hdrip", "hdcam", "camrip", "x264", "x265", "h264", "h265", "hevc", "avc",
+    "xvid", "divx", "10bit", "8bit", "hdr", "hdr10", "sdr", "aac", "ac3", "eac3", "dts",
+    "dts-hd", "truehd", "atmos", "dd5", "ddp5", "vff", "vfq", "vf", "vostfr",
+    "truefrench", "subfrench", "dubbed", "repack",
+];
+
+// release tokens that are also words of titles ("The French Connection", "Charlotte's Web"):
+// only release details after the year or next to another release token
+#[rustfmt::skip]
+const TITLE_WORD_TOKENS: [&str; 9] = [
+    "uhd", "remux", "web", "dv", "multi", "french", "proper", "limited", "internal",
+];
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ParsedMovie {
+    pub title: String,
+    pub year: Option<u32>,
+    pub edition: Option<String>, // "Director's Cut", "Extended"...
+    pub part: Option<u32>,       // CD1, Part 2...
+}
+
+impl ParsedMovie {
+    // Text sent to the metadata plugins: "Inception (2010)"
+    pub fn lookup_query(&self) -> String {
+        match self.year {
+            Some(year) => format!("{} ({})", self.title, year),
+            None => self.title.clone(),
+        }
+    }
+}
+
+// The folder gives the title when the file name has none ("Heat (1995)/CD1.avi")
+pub fn parse_movie_path(path: &Path) -> ParsedMovie {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let movie = parse_movie_name(&stem);
+    if !movie.title.is_empty() {
+        return movie;
+    }
+
+    let folder = path
+        .parent()
+        .and_then(|p| p.file_name())
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let from_folder = parse_movie_name(&folder);
+    ParsedMovie {
+        title: from_folder.title,
+        year: movie.year.or(from_folder.year),
+        edition: movie.edition.or(from_folder.edition),
+        part: movie.part.or(from_folder.part),
+    }
+}
+
+pub fn parse_movie_name(name: &str) -> ParsedMovie {
+    let name = DOTTED_CODEC.replace_all(name, "${1}${2}");
+    let name = BRACKETS.replace_all(&name, " ").replace(['.', '_'], " ");
+    let tokens: Vec<&str> = name.split_whitespace().collect();
+
+    // the release details start at the first known token, read from the end so a run of
+    // title words ("MULTi FRENCH 1080p") is found from the token after it
+    let after_year = |i: usize| (1..i).any(|j| YEAR.is_match(tokens[j]));
+    let mut release = vec![false; tokens.len() + 1];
+    for i in (0..tokens.len()).rev() {
+        let next_to_release = release[i + 1] || (i > 0 && is_release_token(tokens[i - 1]));
+        release[i] = is_release_token(tokens[i])
+            || (is_title_word_token(tokens[i]) && (after_year(i) || next_to_release));
+    }
+    let details_start = release.iter().position(|&r| r).unwrap_or(tokens.len());
+
+    // the year is the last one before the details, never the first word ("1917 (2019)", "2012")
+    let year_index = (1..details_start).rev().find(|&i| YEAR.is_match(tokens[i]));
+    let year = year_index.and_then(|i| YEAR.captures(tokens[i])?[1].parse().ok());
+    let title_end = year_index.unwrap_or(details_start);
+
+    let mut title = tokens[..title_end].join(" ");
+    let rest = tokens[title_end..].join(" ");
+
+    let edition = EDITION
+        .find(&title)
+        .filter(|m| m.start() > 0)
+        .or_else(|| EDITION.find(&rest))
+        .map(|m| normalize_edition(m.as_str()));
+    if let Some(m) = EDITION.find(&title).filter(|m| m.start() > 0) {
+        title.truncate(m.start());
+    }
+
+    let mut part = None;
+    if let Some(caps) = DISC_PART.captures(&title) {
+        part = caps[1].parse().ok();
+        title.truncate(caps.get(0).unwrap().start());
+    }
+    part = part
+        .or_else(|| DISC_PART.captures(&rest).and_then(|c| c[1].parse().ok()))
+        .or_else(|| NAMED_PART.captures(&rest).and_then(|c| c[1].parse().ok()));
+
+    ParsedMovie {
+        title: clean_title(&title),
+        year,
+        edition,
+        part,
+    }
+}
+
+fn is_release_token(token: &str) -> bool {
+    let token = token.trim_matches(|c: char| c == '(' || c == ')' || c == '-');
+    RELEASE_TOKENS.iter().any(|t| token.eq_ignore_ascii_case(t))
+}
+
+fn is_title_word_token(token: &str) -> bool {
+    let token = token.trim_matches(|c: char| c == '(' || c == ')' || c == '-');
+    TITLE_WORD_TOKENS
+        .iter()
+        .any(|t| token.eq_ignore_ascii_case(t))
+}
+
+// "DIRECTORS CUT" -> "Director's Cut"
+fn normalize_edition(edition: &str) -> String {
+    let lower = edition.to_lowercase().replace("directors", "director's");
+    let lower = lower.replace("collectors", "collector's");
+    lower
+        .split(' ')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn clean_title(title: &str) -> String {
+    let title = title.replace(['(', ')'], " ");
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    title
+        .trim_matches(|c: char| c == '-' || c == ',' || c.is_whitespace())
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // file name, title, year, edition, part
+    type Case = (
+        &'static str,
+        &'static str,
+        Option<u32>,
+        Option<&'static str>,
+        Option<u32>,
+    );
+
+    #[rustfmt::skip]
+    const NAMES: &[Case] = &[
+        ("Inception.2010.1080p.BluRay.x264-SPARKS", "Inception", Some(2010), None, None),
+        ("Inception (2010)", "Inception", Some(2010), None, None),
+        ("The.Dark.Knight.2008.2160p.UHD.BluRay.REMUX.HDR.HEVC.Atmos-EPSiLON", "The Dark Knight", Some(2008), None, None),
+        ("Blade Runner 2049 (2017) [1080p] [YTS.MX]", "Blade Runner 2049", Some(2017), None, None),
+        ("2001.A.Space.Odyssey.1968.720p.BrRip.x264", "2001 A Space Odyssey", Some(1968), None, None),
+        ("1917.2019.MULTi.1080p.WEB.H264", "1917", Some(2019), None, None),
+        ("2012 (2009)", "2012", Some(2009), None, None),
+        ("[YTS] Parasite (2019) [720p] [BluRay]", "Parasite", Some(2019), None, None),
+        ("Amelie.2001.FRENCH.DVDRip.XviD-NoTV", "Amelie", Some(2001), None, None),
+        ("Le.Fabuleux.Destin.d'Amélie.Poulain.2001.TRUEFRENCH.720p", "Le Fabuleux Destin d'Amélie Poulain", Some(2001), None, None),
+        ("Heat.1995.Directors.Cut.1080p.BluRay.x265", "Heat", Some(1995), Some("Director's Cut"), None),
+        ("Kingdom of Heaven Director's Cut (2005)", "Kingdom of Heaven", Some(2005), Some("Director's Cut"), None),
+        ("Aliens.1986.Special.Edition.REMASTERED.1080p", "Aliens", Some(1986), Some("Special Edition"), None),
+        ("The Lord of the Rings The Two Towers EXTENDED 2002 1080p", "The Lord of the Rings The Two Towers", Some(2002), Some("Extended"), None),
+        ("Blade.Runner.1982.The.Final.Cut.720p", "Blade Runner", Some(1982), Some("Final Cut"), None),
+        ("Avatar.2009.IMAX.2160p.WEB-DL.DDP5.1.H.265", "Avatar", Some(2009), Some("Imax"), None),
+        ("Titanic.1997.CD1.DVDRip", "Titanic", Some(1997), None, Some(1)),
+        ("Titanic CD2", "Titanic", None, None, Some(2)),
+        ("Lawrence of Arabia (1962) Disc 2", "Lawrence of Arabia", Some(1962), None, Some(2)),
+        ("Kill.Bill.Vol.1.2003.Part2.1080p", "Kill Bill Vol 1", Some(2003), None, Some(2)),
+        ("Harry Potter and the Deathly Hallows Part 1 (2010)", "Harry Potter and the Deathly Hallows Part 1", Some(2010), None, None),
+        ("Dune Part Two 2024 2160p", "Dune Part Two", Some(2024), None, None),
+        ("Mad_Max_Fury_Road_2015_BDRip_x264", "Mad Max Fury Road", Some(2015), None, None),
+        ("Spider-Man.Into.the.Spider-Verse.2018.1080p.WEBRip", "Spider-Man Into the Spider-Verse", Some(2018), None, None),
+        ("www.Torrent9.com - Le Dîner de Cons 1998 VFF", "Le Dîner de Cons", Some(1998), None, None),
+        ("Alien", "Alien", None, None, None),
+        ("The Matrix 1080p", "The Matrix", None, None, None),
+        ("Se7en 1995 Remastered 1080p BluRay", "Se7en", Some(1995), Some("Remastered"), None),
+        ("Apollo 13 (1995) (1080p BluRay x265 10bit)", "Apollo 13", Some(1995), None, None),
+        ("Ocean's Eleven (2001) {tmdb-161}", "Ocean's Eleven", Some(2001), None, None),
+        ("Wall-E.2008.720p.HDTV.AAC", "Wall-E", Some(2008), None, None),
+        ("Taxi.Driver.1976.40th.Anniversary.Edition.1080p", "Taxi Driver", Some(1976), None, None),
+        ("Fight Club 1999 UNRATED 720p", "Fight Club", Some(1999), Some("Unrated"), None),
+        ("Star.Wars.Episode.IV.A.New.Hope.1977.Theatrical.Cut.720p", "Star Wars Episode IV A New Hope", Some(1977), Some("Theatrical Cut"), None),
+        ("The.Shining.1980.US.Version.720p", "The Shining", Some(1980), None, None),
+        ("The French Connection (1971)", "The French Connection", Some(1971), None, None),
+        ("Charlotte's Web (2006)", "Charlotte's Web", Some(2006), None, None),
+        ("Internal Affairs (1990)", "Internal Affairs", Some(1990), None, None),
+        ("The.Proper.Way.2016.PROPER.WEB.1080p", "The Proper Way", Some(2016), None, None),
+        ("Limitless LIMITED WEB 1080p", "Limitless", None, None, None),
+    ];
+
+    #[test]
+    fn parses_real_world_names() {
+        for (name, title, year, edition, part) in NAMES {
+            let movie = parse_movie_name(name);
+            assert_eq!(movie.title, *title, "title of {name}");
+            assert_eq!(movie.year, *year, "year of {name}");
+            assert_eq!(movie.edition.as_deref(), *edition, "edition of {name}");
+            assert_eq!(movie.part, *part, "part of {name}");
+        }
+    }
+
+    #[test]
+    fn folder_gives_the_title_of_generic_files() {
+        let movie = parse_movie_path(Path::new("/films/Heat (1995)/CD1.avi"));
+        assert_eq!(movie.title, "Heat");
+        assert_eq!(movie.year, Some(1995));
+        assert_eq!(movie.part, Some(1));
+
+        let movie = parse_movie_path(Path::new("/films/Heat (1995)/Heat.mkv"));
+        assert_eq!((movie.title.as_str(), movie.year), ("Heat", None));
+    }
+
+    #[test]
+    fn lookup_query_adds_the_year() {
+        assert_eq!(
+            parse_movie_name("Inception.2010.1080p").lookup_query(),
+            "Inception (2010)"
+        );
+        assert_eq!(parse_movie_name("Alien.DVDRip").lookup_query(), "Alien");
+    }
+}
@@ -0,0 +1,261 @@
+/*
+This file groups the audio files into albums and artists from their tags
+(album artist, compilations, disc and track numbers)
+and holds the music model sent to the GUI.
+*/
+
+use crate::media::data::MediaTags;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+// album artist of the compilations, and the names taggers use for it
+pub const VARIOUS_ARTISTS: &str = "Various Artists";
+pub const UNKNOWN_ARTIST: &str = "Unknown Artist";
+const VARIOUS_NAMES: [&str; 5] = [
+    "various artists",
+    "various",
+    "va",
+    "v.a.",
+    "artistes divers",
+];
+
+// Album and position of a track, computed from the tags of every track of the library
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedTrack {
+    pub album: String,
+    pub album_artist: String,
+    pub folder: String, // an album is a title, an album artist and this folder
+    pub compilation: bool,
+    pub artist: Option<String>, // artist of the track itself
+    pub disc: Option<u32>,
+    pub track: Option<u32>,
+    pub year: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artist {
+    pub id: i64,
+    pub name: String,
+    pub album_count: usize,
+    pub track_count: usize,
+    pub metadata_id: Option<i64>, // row of artist_metadata, when the plugin already answered
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Album {
+    pub id: i64,
+    pub title: String,
+    pub artist_id: i64,
+    pub artist: String,
+    pub compilation: bool,
+    pub year: Option<u32>,
+    pub track_count: usize,
+    pub duration: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlbumTrack {
+    pub media_id: i64,
+    pub album_id: i64,
+    pub disc: Option<u32>,
+    pub track: Option<u32>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub path: String,
+    pub duration: f32,
+}
+
+impl AlbumTrack {
+    // "2-05" on multi-disc albums, "05" otherwise
+    pub fn position(&self, multi_disc: bool) -> String {
+        match (self.track, self.disc) {
+            (Some(track), Some(disc)) if multi_disc => format!("{}-{:02}", disc, track),
+            (Some(track), _) => format!("{:02}", track),
+            (None, _) => String::new(),
+        }
+    }
+}
+
+pub fn is_various_artists(name: &str) -> bool {
+    VARIOUS_NAMES.contains(&name.trim().to_lowercase().as_str())
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+// Folder of the album of a track: the disc folders (CD1, Disc 2...) belong to their parent
+pub fn album_folder(path: &Path) -> &Path {
+    let folder = path.parent().unwrap_or(Path::new(""));
+    let name = folder
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let number = ["cd", "disc", "disk"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .map(|rest| rest.trim_start_matches([' ', '-', '_']));
+    match number {
+        Some(n) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => {
+            folder.parent().unwrap_or(folder)
+        }
+        _ => folder,
+    }
+}
+
+// Places every tagged track (media id, path, tags) in its album. Tracks with an album artist are
+// grouped by it; the others by album name and folder, and a folder mixing several artists
+// is a compilation. Two albums with the same name in two folders stay apart.
+// Tracks without an album tag are left out.
+pub fn group_tracks(tracks: &[(i64, &Path, &MediaTags)]) -> Vec<(i64, ParsedTrack)> {
+    // artists of the tracks without album artist, by (album, folder)
+    let mut folder_artists: HashMap<(String, &Path), HashSet<String>> = HashMap::new();
+    for (_, path, tags) in tracks {
+        if let (Some(album), None) = (non_empty(&tags.album), non_empty(&tags.album_artist)) {
+            let folder = album_folder(path);
+            let artists = folder_artists
+                .entry((album.to_lowercase(), folder))
+                .or_default();
+            artists.extend(non_empty(&tags.artist).map(str::to_lowercase));
+        }
+    }
+
+    let mut result = Vec::new();
+    for (media_id, path, tags) in tracks {
+        let Some(album) = non_empty(&tags.album) else {
+            continue;
+        };
+        let artist = non_empty(&tags.artist);
+        let folder = album_folder(path);
+        let (album_artist, compilation) = match non_empty(&tags.album_artist) {
+            Some(name) if is_various_artists(name) => (VARIOUS_ARTISTS.to_string(), true),
+            Some(name) => (name.to_string(), false),
+            None => {
+                let artists = &folder_artists[&(album.to_lowercase(), folder)];
+                match artist {
+                    Some(name) if artists.len() == 1 => (name.to_string(), false),
+                    None if artists.is_empty() => (UNKNOWN_ARTIST.to_string(), false),
+                    _ => (VARIOUS_ARTISTS.to_string(), true),
+                }
+            }
+        };
+        result.push((
+            *media_id,
+            ParsedTrack {
+                album: album.to_string(),
+                album_artist,
+                folder: folder.to_string_lossy().to_string(),
+                compilation,
+                artist: artist.map(str::to_string),
+                disc: tags.disc_number,
+                track: tags.track_number,
+                year: tags.year,
+            },
+        ));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(artist: Option<&str>, album: Option<&str>, album_artist: Option<&str>) -> MediaTags {
+        MediaTags {
+            artist: artist.map(String::from),
+            album: album.map(String::from),
+            album_artist: album_artist.map(String::from),
+            track_number: Some(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn groups_albums_and_detects_compilations() {
+        let discovery = tags(Some("Daft Punk"), Some("Discovery"), None);
+        let hits_a = tags(Some("Blur"), Some("Hits 97"), None);
+        let hits_b = tags(Some("Oasis"), Some("Hits 97"), None);
+        let tagged = tags(Some("Moby"), Some("Café del Mar"), Some("VA"));
+        let featuring = tags(
+            Some("Daft Punk feat. Romanthony"),
+            Some("Discovery"),
+            Some("Daft Punk"),
+        );
+        let loose = tags(Some("Nobody"), None, None);
+        let tracks = [
+            (1, Path::new("/music/Discovery/01.mp3"), &discovery),
+            (2, Path::new("/music/Hits/01.mp3"), &hits_a),
+            (3, Path::new("/music/Hits/02.mp3"), &hits_b),
+            (4, Path::new("/music/Cafe/01.mp3"), &tagged),
+            (5, Path::new("/music/Discovery/02.mp3"), &featuring),
+            (6, Path::new("/music/loose.mp3"), &loose),
+        ];
+        let parsed: HashMap<i64, ParsedTrack> = group_tracks(&tracks).into_iter().collect();
+
+        assert_eq!(parsed.len(), 5);
+        assert_eq!(parsed[&1].album_artist, "Daft Punk");
+        assert!(!parsed[&1].compilation);
+        assert_eq!(parsed[&5].album_artist, "Daft Punk");
+        assert_eq!(
+            parsed[&5].artist.as_deref(),
+            Some("Daft Punk feat. Romanthony")
+        );
+        for id in [2, 3, 4] {
+            assert_eq!(parsed[&id].album_artist, VARIOUS_ARTISTS);
+            assert!(parsed[&id].compilation);
+        }
+    }
+
+    #[test]
+    fn same_album_name_in_two_folders_is_two_albums() {
+        let weezer = tags(Some("Weezer"), Some("Weezer"), None);
+        let tagged = tags(Some("Weezer"), Some("Weezer"), Some("Weezer"));
+        let gabriel = tags(Some("Peter Gabriel"), Some("Weezer"), None);
+        let hits_a = tags(Some("Blur"), Some("Hits"), Some("VA"));
+        let hits_b = tags(Some("Oasis"), Some("Hits"), Some("VA"));
+        let tracks = [
+            (1, Path::new("/music/Weezer/Blue/01.mp3"), &weezer),
+            (2, Path::new("/music/Weezer/Green/01.mp3"), &tagged),
+            (3, Path::new("/music/Gabriel/01.mp3"), &gabriel),
+            (4, Path::new("/music/Hits 1997/01.mp3"), &hits_a),
+            (5, Path::new("/music/Hits 1998/01.mp3"), &hits_b),
+            (6, Path::new("/music/Weezer/Blue/CD 2/01.mp3"), &weezer),
+        ];
+        let parsed: HashMap<i64, ParsedTrack> = group_tracks(&tracks).into_iter().collect();
+        let album = |id: i64| {
+            let track = &parsed[&id];
+            (
+                track.album.as_str(),
+                track.album_artist.as_str(),
+                track.folder.as_str(),
+            )
+        };
+
+        // two self-titled albums of the same artist
+        assert_eq!(album(1), ("Weezer", "Weezer", "/music/Weezer/Blue"));
+        assert_eq!(album(2), ("Weezer", "Weezer", "/music/Weezer/Green"));
+        assert_eq!(album(3), ("Weezer", "Peter Gabriel", "/music/Gabriel"));
+        assert!(!parsed[&1].compilation && !parsed[&3].compilation);
+        // two compilations with the same name
+        assert_eq!(album(4), ("Hits", VARIOUS_ARTISTS, "/music/Hits 1997"));
+        assert_eq!(album(5), ("Hits", VARIOUS_ARTISTS, "/music/Hits 1998"));
+        // the second disc is in the album folder
+        assert_eq!(album(6), album(1));
+    }
+
+    #[test]
+    fn positions_show_the_disc_when_needed() {
+        let track = AlbumTrack {
+            media_id: 1,
+            album_id: 1,
+            disc: Some(2),
+            track: Some(5),
+            title: None,
+            artist: None,
+            path: String::new(),
+            duration: 0.0,
+        };
+        assert_eq!(track.position(true), "2-05");
+        assert_eq!(track.position(false), "05");
+    }
+}
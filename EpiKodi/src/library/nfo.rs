@@ -0,0 +1,546 @@
+/*
+This file reads and writes the Kodi NFO sidecar files (movie.nfo, <video>.nfo, tvshow.nfo).
+The parsed files are stored in the database after each scan, the export writes them back
+next to the media from the library metadata.
+*/
+
+use roxmltree::{Document, Node, TextPos};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NfoKind {
+    Movie,
+    TvShow,
+    Episode,
+}
+
+impl NfoKind {
+    // name of the root element, also stored in the database
+    pub fn root_tag(&self) -> &'static str {
+        match self {
+            NfoKind::Movie => "movie",
+            NfoKind::TvShow => "tvshow",
+            NfoKind::Episode => "episodedetails",
+        }
+    }
+
+    pub fn from_root_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "movie" => Some(NfoKind::Movie),
+            "tvshow" => Some(NfoKind::TvShow),
+            "episodedetails" => Some(NfoKind::Episode),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NfoActor {
+    pub name: String,
+    pub role: Option<String>,
+    pub thumb: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NfoArtwork {
+    pub aspect: String, // poster, fanart, banner, clearlogo... "thumb" when not given
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nfo {
+    pub kind: NfoKind,
+    pub title: Option<String>,
+    pub original_title: Option<String>,
+    pub show_title: Option<String>, // episodes only
+    pub plot: Option<String>,
+    pub year: Option<i32>,
+    pub rating: Option<f32>,
+    pub genres: Vec<String>,
+    pub actors: Vec<NfoActor>,
+    pub artwork: Vec<NfoArtwork>,
+    pub season: Option<u32>,
+    pub episode: Option<u32>,
+}
+
+impl Nfo {
+    pub fn new(kind: NfoKind) -> Self {
+        Nfo {
+            kind,
+            title: None,
+            original_title: None,
+            show_title: None,
+            plot: None,
+            year: None,
+            rating: None,
+            genres: Vec::new(),
+            actors: Vec::new(),
+            artwork: Vec::new(),
+            season: None,
+            episode: None,
+        }
+    }
+}
+
+// A sidecar found next to the media, media_id is None for tvshow.nfo
+#[derive(Debug, Clone, PartialEq)]
+pub struct NfoFile {
+    pub path: String,
+    pub mtime: i64, // milliseconds since UNIX epoch
+    pub media_id: Option<i64>,
+}
+
+impl NfoFile {
+    pub fn find(path: &Path, media_id: Option<i64>) -> Option<Self> {
+        let metadata = fs::metadata(path).ok().filter(|m| m.is_file())?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        Some(NfoFile {
+            path: path.to_string_lossy().to_string(),
+            mtime,
+            media_id,
+        })
+    }
+}
+
+// Result of an export, sent to the GUI
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NfoExport {
+    pub written: usize,
+    pub skipped: usize, // a file was already there and overwrite was off, or it is no nfo
+    pub failed: Vec<String>, // paths that could not be written
+}
+
+// Sidecars of a video in the order Kodi reads them: "<file name>.nfo", then "movie.nfo" for a movie
+pub fn media_nfo_paths(media: &Path, kind: NfoKind) -> Vec<PathBuf> {
+    let mut paths = vec![media.with_extension("nfo")];
+    if kind == NfoKind::Movie {
+        if let Some(folder) = media.parent() {
+            paths.push(folder.join("movie.nfo"));
+        }
+    }
+    paths
+}
+
+pub fn show_nfo_path(show_folder: &Path) -> PathBuf {
+    show_folder.join("tvshow.nfo")
+}
+
+// Kodi accepts a scraper url after the xml, and several <episodedetails> in one file:
+// only the first root element is read. None when the file has no known root element.
+pub fn parse_nfo(text: &str) -> Option<Nfo> {
+    let doc = parse_document(text)?;
+    let root = doc.root_element();
+    let kind = NfoKind::from_root_tag(root.tag_name().name())?;
+
+    let mut nfo = Nfo::new(kind);
+    nfo.title = child_text(root, "title");
+    nfo.original_title = child_text(root, "originaltitle");
+    nfo.show_title = child_text(root, "showtitle");
+    nfo.plot = child_text(root, "plot").or_else(|| child_text(root, "outline"));
+    nfo.year = child_text(root, "year")
+        .and_then(|year| year.parse().ok())
+        .or_else(|| {
+            let date = child_text(root, "premiered").or_else(|| child_text(root, "aired"))?;
+            date.get(..4)?.parse().ok()
+        });
+    nfo.rating = read_rating(root);
+    nfo.season = child_text(root, "season").and_then(|s| s.parse().ok());
+    nfo.episode = child_text(root, "episode").and_then(|e| e.parse().ok());
+
+    // "Drame / Policier" is split like Kodi does
+    nfo.genres = children(root, "genre")
+        .filter_map(|genre| genre.text())
+        .flat_map(|genre| genre.split(" / "))
+        .map(str::trim)
+        .filter(|genre| !genre.is_empty())
+        .map(String::from)
+        .collect();
+
+    nfo.actors = children(root, "actor")
+        .filter_map(|actor| {
+            Some(NfoActor {
+                name: child_text(actor, "name")?,
+                role: child_text(actor, "role"),
+                thumb: child_text(actor, "thumb"),
+            })
+        })
+        .collect();
+
+    for thumb in children(root, "thumb") {
+        if let Some(url) = node_text(thumb) {
+            let aspect = thumb.attribute("aspect").unwrap_or("thumb").to_string();
+            nfo.artwork.push(NfoArtwork { aspect, url });
+        }
+    }
+    for fanart in children(root, "fanart") {
+        for thumb in children(fanart, "thumb") {
+            if let Some(url) = node_text(thumb) {
+                let aspect = "fanart".to_string();
+                nfo.artwork.push(NfoArtwork { aspect, url });
+            }
+        }
+    }
+
+    Some(nfo)
+}
+
+// What follows the root element stops the parser, the xml before it is read again
+fn parse_document(text: &str) -> Option<Document<'_>> {
+    match Document::parse(text) {
+        Ok(doc) => Some(doc),
+        Err(e) => Document::parse(&text[..byte_offset(text, e.pos())]).ok(),
+    }
+}
+
+// Position of a parse error in the text, its rows and columns (in chars) start at 1
+fn byte_offset(text: &str, pos: TextPos) -> usize {
+    let line: usize = text
+        .split_inclusive('\n')
+        .take(pos.row as usize - 1)
+        .map(str::len)
+        .sum();
+    let column: usize = text[line..]
+        .chars()
+        .take(pos.col as usize - 1)
+        .map(char::len_utf8)
+        .sum();
+    line + column
+}
+
+// <ratings><rating default="true"><value>, or the old <rating> element
+fn read_rating(root: Node) -> Option<f32> {
+    let from_ratings = children(root, "ratings").next().and_then(|ratings| {
+        let rating = children(ratings, "rating")
+            .find(|r| r.attribute("default") == Some("true"))
+            .or_else(|| children(ratings, "rating").next())?;
+        child_text(rating, "value")?.parse().ok()
+    });
+    from_ratings.or_else(|| child_text(root, "rating")?.parse().ok())
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.has_tag_name(name))
+}
+
+fn child_text(node: Node, name: &str) -> Option<String> {
+    children(node, name).next().and_then(node_text)
+}
+
+fn node_text(node: Node) -> Option<String> {
+    let text = node.text()?.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+// Elements written from the Nfo, the other ones of an updated file are kept
+const WRITTEN_ELEMENTS: [&str; 12] = [
+    "title",
+    "originaltitle",
+    "showtitle",
+    "season",
+    "episode",
+    "year",
+    "rating",
+    "plot",
+    "genre",
+    "thumb",
+    "fanart",
+    "actor",
+];
+
+pub fn write_nfo(nfo: &Nfo) -> String {
+    let mut xml = xml_start(nfo.kind);
+    push_elements(&mut xml, nfo);
+    xml.push_str(&format!("</{}>\n", nfo.kind.root_tag()));
+    xml
+}
+
+// The file written again from the Nfo, keeping what the library does not read of it (ids,
+// director, credits, set, tags...) and the scraper url after the xml.
+// None when the file is not an nfo of the same kind, it is then left as it is.
+pub fn update_nfo(original: &str, nfo: &Nfo) -> Option<String> {
+    let doc = parse_document(original)?;
+    let root = doc.root_element();
+    if NfoKind::from_root_tag(root.tag_name().name()) != Some(nfo.kind) {
+        return None;
+    }
+
+    let mut xml = xml_start(nfo.kind);
+    push_elements(&mut xml, nfo);
+    for child in root.children().filter(|child| child.is_element()) {
+        if !WRITTEN_ELEMENTS.contains(&child.tag_name().name()) {
+            xml.push_str(&format!("    {}\n", &original[child.range()]));
+        }
+    }
+    xml.push_str(&format!("</{}>\n", nfo.kind.root_tag()));
+
+    let after = original[root.range().end..].trim();
+    if !after.is_empty() {
+        xml.push_str(after);
+        xml.push('\n');
+    }
+    Some(xml)
+}
+
+fn xml_start(kind: NfoKind) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\" ?>\n<{}>\n",
+        kind.root_tag()
+    )
+}
+
+fn push_elements(xml: &mut String, nfo: &Nfo) {
+    push_element(xml, 1, "title", nfo.title.as_deref());
+    push_element(xml, 1, "originaltitle", nfo.original_title.as_deref());
+    push_element(xml, 1, "showtitle", nfo.show_title.as_deref());
+    push_element(
+        xml,
+        1,
+        "season",
+        nfo.season.map(|s| s.to_string()).as_deref(),
+    );
+    push_element(
+        xml,
+        1,
+        "episode",
+        nfo.episode.map(|e| e.to_string()).as_deref(),
+    );
+    push_element(xml, 1, "year", nfo.year.map(|y| y.to_string()).as_deref());
+    push_element(
+        xml,
+        1,
+        "rating",
+        nfo.rating.map(|r| r.to_string()).as_deref(),
+    );
+    push_element(xml, 1, "plot", nfo.plot.as_deref());
+    for genre in &nfo.genres {
+        push_element(xml, 1, "genre", Some(genre));
+    }
+    for art in nfo.artwork.iter().filter(|art| art.aspect != "fanart") {
+        xml.push_str(&format!(
+            "    <thumb aspect=\"{}\">{}</thumb>\n",
+            escape(&art.aspect),
+            escape(&art.url)
+        ));
+    }
+    let fanart: Vec<&NfoArtwork> = nfo
+        .artwork
+        .iter()
+        .filter(|a| a.aspect == "fanart")
+        .collect();
+    if !fanart.is_empty() {
+        xml.push_str("    <fanart>\n");
+        for art in fanart {
+            push_element(xml, 2, "thumb", Some(&art.url));
+        }
+        xml.push_str("    </fanart>\n");
+    }
+    for actor in &nfo.actors {
+        xml.push_str("    <actor>\n");
+        push_element(xml, 2, "name", Some(&actor.name));
+        push_element(xml, 2, "role", actor.role.as_deref());
+        push_element(xml, 2, "thumb", actor.thumb.as_deref());
+        xml.push_str("    </actor>\n");
+    }
+}
+
+fn push_element(xml: &mut String, depth: usize, name: &str, value: Option<&str>) {
+    if let Some(value) = value {
+        let indent = "    ".repeat(depth);
+        xml.push_str(&format!("{indent}<{name}>{}</{name}>\n", escape(value)));
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOVIE: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes" ?>
+<!-- created by a scraper -->
+<movie>
+    <title>Le Fabuleux Destin d'Amélie Poulain</title>
+    <originaltitle>Le Fabuleux Destin d'Amélie Poulain</originaltitle>
+    <ratings>
+        <rating name="imdb" max="10"><value>8.3</value></rating>
+        <rating name="themoviedb" max="10" default="true"><value>7.9</value></rating>
+    </ratings>
+    <plot>Amélie, une jeune serveuse &amp; rêveuse...</plot>
+    <thumb aspect="poster" preview="">https://image.tmdb.org/poster.jpg</thumb>
+    <fanart><thumb>https://image.tmdb.org/fanart.jpg</thumb></fanart>
+    <genre>Comédie / Romance</genre>
+    <premiered>2001-04-25</premiered>
+    <actor><name>Audrey Tautou</name><role>Amélie Poulain</role><thumb>https://a.jpg</thumb></actor>
+    <actor><name>Mathieu Kassovitz</name><role>Nino Quincampoix</role></actor>
+    <actor><role>nobody</role></actor>
+</movie>
+https://www.themoviedb.org/movie/194
+"#;
+
+    #[test]
+    fn parses_a_kodi_movie_nfo() {
+        let nfo = parse_nfo(MOVIE).unwrap();
+        assert_eq!(nfo.kind, NfoKind::Movie);
+        assert_eq!(
+            nfo.title.as_deref(),
+            Some("Le Fabuleux Destin d'Amélie Poulain")
+        );
+        assert_eq!(nfo.year, Some(2001));
+        assert_eq!(nfo.rating, Some(7.9));
+        assert_eq!(
+            nfo.plot.as_deref(),
+            Some("Amélie, une jeune serveuse & rêveuse...")
+        );
+        assert_eq!(nfo.genres, vec!["Comédie", "Romance"]);
+        assert_eq!(nfo.actors.len(), 2);
+        assert_eq!(nfo.actors[0].role.as_deref(), Some("Amélie Poulain"));
+        assert_eq!(nfo.actors[1].thumb, None);
+        assert_eq!(
+            nfo.artwork,
+            vec![
+                NfoArtwork {
+                    aspect: "poster".to_string(),
+                    url: "https://image.tmdb.org/poster.jpg".to_string()
+                },
+                NfoArtwork {
+                    aspect: "fanart".to_string(),
+                    url: "https://image.tmdb.org/fanart.jpg".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_old_episode_and_show_files() {
+        let episode = parse_nfo(
+            "<episodedetails><title>Pilot</title><showtitle>Fargo</showtitle><season>1</season>\
+             <episode>1</episode><rating>8.5</rating><aired>2014-04-15</aired></episodedetails>\
+             <episodedetails><title>Second</title></episodedetails>",
+        )
+        .unwrap();
+        assert_eq!(episode.kind, NfoKind::Episode);
+        assert_eq!(episode.title.as_deref(), Some("Pilot"));
+        assert_eq!((episode.season, episode.episode), (Some(1), Some(1)));
+        assert_eq!((episode.rating, episode.year), (Some(8.5), Some(2014)));
+
+        let show = parse_nfo("<tvshow><title>Fargo</title><year>2014</year></tvshow>").unwrap();
+        assert_eq!((show.kind, show.year), (NfoKind::TvShow, Some(2014)));
+
+        // a bare url, broken xml, or a root element Kodi does not know
+        assert_eq!(parse_nfo("https://www.imdb.com/title/tt0211915/"), None);
+        assert_eq!(parse_nfo("<movie><title>Heat</movie>"), None);
+        assert_eq!(
+            parse_nfo("<musicvideo><movie>Heat</movie></musicvideo>"),
+            None
+        );
+        assert_eq!(
+            parse_nfo("<!-- <movie> --><tvshow><title>Fargo</title></tvshow>").map(|n| n.kind),
+            Some(NfoKind::TvShow)
+        );
+    }
+
+    #[test]
+    fn written_files_are_read_back() {
+        let nfo = parse_nfo(MOVIE).unwrap();
+        let written = write_nfo(&nfo);
+        assert!(written.starts_with("<?xml"));
+        assert_eq!(parse_nfo(&written), Some(nfo));
+    }
+
+    #[test]
+    fn updated_files_keep_what_the_library_does_not_read() {
+        let original = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes" ?>
+<movie>
+    <title>Heat</title>
+    <uniqueid type="imdb" default="true">tt0113277</uniqueid>
+    <id>tt0113277</id>
+    <rating>8.0</rating>
+    <director>Michael Mann</director>
+    <credits>Michael Mann</credits>
+    <set>
+        <name>Michael Mann Collection</name>
+        <overview></overview>
+    </set>
+    <mpaa>Rated R</mpaa>
+    <runtime>170</runtime>
+    <tag>heist</tag>
+    <studio>Warner Bros.</studio>
+    <genre>Crime</genre>
+</movie>
+https://www.imdb.com/title/tt0113277/
+"#;
+        let mut nfo = parse_nfo(original).unwrap();
+        nfo.title = Some("Heat & Dust".to_string());
+        nfo.genres.push("Drama".to_string());
+
+        let updated = update_nfo(original, &nfo).unwrap();
+        assert_eq!(parse_nfo(&updated), Some(nfo.clone()));
+        for kept in [
+            r#"<uniqueid type="imdb" default="true">tt0113277</uniqueid>"#,
+            "<id>tt0113277</id>",
+            "<director>Michael Mann</director>",
+            "<credits>Michael Mann</credits>",
+            "<name>Michael Mann Collection</name>",
+            "<mpaa>Rated R</mpaa>",
+            "<runtime>170</runtime>",
+            "<tag>heist</tag>",
+            "<studio>Warner Bros.</studio>",
+            "https://www.imdb.com/title/tt0113277/",
+        ] {
+            assert!(updated.contains(kept), "{} lost", kept);
+        }
+        // written once, from the Nfo
+        assert_eq!(updated.matches("<title>").count(), 1);
+        assert_eq!(updated.matches("<genre>").count(), 2);
+        // and the same again when updated twice
+        assert_eq!(
+            update_nfo(&updated, &nfo).as_deref(),
+            Some(updated.as_str())
+        );
+
+        // another kind of nfo, or no nfo at all, is not replaced
+        assert_eq!(
+            update_nfo("<tvshow><title>Heat</title></tvshow>", &nfo),
+            None
+        );
+        assert_eq!(
+            update_nfo("https://www.imdb.com/title/tt0113277/", &nfo),
+            None
+        );
+    }
+
+    #[test]
+    fn sidecar_paths_follow_kodi() {
+        let movie = Path::new("/films/Heat (1995)/Heat.1995.mkv");
+        assert_eq!(
+            media_nfo_paths(movie, NfoKind::Movie),
+            vec![
+                PathBuf::from("/films/Heat (1995)/Heat.1995.nfo"),
+                PathBuf::from("/films/Heat (1995)/movie.nfo"),
+            ]
+        );
+        let episode = Path::new("/tv/Fargo/Season 1/Fargo.S01E01.mkv");
+        assert_eq!(media_nfo_paths(episode, NfoKind::Episode).len(), 1);
+    }
+}
@@ -0,0 +1,160 @@
+/*
+This file contains the helpers used to search the media library,
+turning a user query into an FTS5 MATCH expression with prefix and fuzzy matching.
+*/
+
+use crate::media::data::{MediaInfo, MediaType};
+
+pub const DEFAULT_SEARCH_LIMIT: usize = 50;
+
+// Highlight markers put around the matched words in the snippets
+pub const HIGHLIGHT_START: &str = "<mark>";
+pub const HIGHLIGHT_END: &str = "</mark>";
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SearchFilters {
+    pub media_type: Option<MediaType>,
+    pub tag: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub media: MediaInfo,
+    pub snippet: String,
+    pub rank: f64,
+}
+
+// Splits a query into lowercase words, the same way the FTS5 tokenizer does
+pub fn tokenize(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+// How many typos we tolerate for a word, short words must match exactly
+pub fn max_typos(word: &str) -> usize {
+    match word.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+// Builds the MATCH expression: every word must match, either as a prefix
+// or as one of its fuzzy alternatives found in the index vocabulary.
+pub fn match_expression(words: &[String], alternatives: &[Vec<String>]) -> String {
+    words
+        .iter()
+        .enumerate()
+        .map(|(i, word)| {
+            let mut terms = vec![format!("{}*", quote(word))];
+            if let Some(fuzzy) = alternatives.get(i) {
+                terms.extend(fuzzy.iter().filter(|t| *t != word).map(|t| quote(t)));
+            }
+            format!("({})", terms.join(" OR "))
+        })
+        .collect::<Vec<_>>()
+        .join(" AND ")
+}
+
+// Splits a snippet into (text, highlighted) parts, so the GUI shows the matched words without
+// reading the snippet as HTML
+pub fn highlighted_parts(snippet: &str) -> Vec<(String, bool)> {
+    let mut parts = Vec::new();
+    let mut rest = snippet;
+    while let Some(start) = rest.find(HIGHLIGHT_START) {
+        if start > 0 {
+            parts.push((rest[..start].to_string(), false));
+        }
+        rest = &rest[start + HIGHLIGHT_START.len()..];
+        let end = rest.find(HIGHLIGHT_END).unwrap_or(rest.len());
+        parts.push((rest[..end].to_string(), true));
+        rest = rest.get(end + HIGHLIGHT_END.len()..).unwrap_or("");
+    }
+    if !rest.is_empty() {
+        parts.push((rest.to_string(), false));
+    }
+    parts
+}
+
+fn quote(term: &str) -> String {
+    format!("\"{}\"", term.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_splits_and_lowercases() {
+        assert_eq!(
+            tokenize("Daft Punk - Around_the World!"),
+            vec!["daft", "punk", "around", "the", "world"]
+        );
+        assert!(tokenize("  -- ").is_empty());
+    }
+
+    #[test]
+    fn levenshtein_distances() {
+        assert_eq!(levenshtein("muse", "muse"), 0);
+        assert_eq!(levenshtein("muse", "mose"), 1);
+        assert_eq!(levenshtein("beatles", "betles"), 1);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn max_typos_grows_with_word_length() {
+        assert_eq!(max_typos("abc"), 0);
+        assert_eq!(max_typos("daft"), 1);
+        assert_eq!(max_typos("radiohead"), 2);
+    }
+
+    #[test]
+    fn snippets_are_split_on_the_markers() {
+        assert_eq!(
+            highlighted_parts("Around the <mark>World</mark> <b>"),
+            vec![
+                ("Around the ".to_string(), false),
+                ("World".to_string(), true),
+                (" <b>".to_string(), false),
+            ]
+        );
+        assert_eq!(highlighted_parts("<mark>daft"), vec![("daft".to_string(), true)]);
+        assert!(highlighted_parts("").is_empty());
+    }
+
+    #[test]
+    fn match_expression_with_prefix_and_fuzzy_terms() {
+        let words = vec!["daft".to_string(), "pnk".to_string()];
+        let alternatives = vec![Vec::new(), vec!["punk".to_string()]];
+
+        assert_eq!(
+            match_expression(&words, &alternatives),
+            "(\"daft\"*) AND (\"pnk\"* OR \"punk\")"
+        );
+    }
+}
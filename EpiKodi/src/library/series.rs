@@ -0,0 +1,426 @@
+/*
+This file finds the show, season and episode of a video from its path
+(S01E02, 1x02, Season 1/Episode 02, multi-episode files, absolute anime numbering)
+and holds the series model sent to the GUI, with the "next episode to watch" logic.
+*/
+
+use crate::constants::{EPISODE_WATCHED_RATIO, FINISHED};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::path::{Component, Path, PathBuf};
+
+lazy_static! {
+    // S01E02, S01E02E03, S01E02-E03, S01E02-03
+    static ref SEASON_EPISODE: Regex =
+        Regex::new(r"(?i)\bs(\d{1,2}) ?e(\d{1,3})((?:(?:-|-?e)\d{1,3})*)\b").unwrap();
+    // 1x02, 1x02x03, 1x02-03, 1x02-1x03
+    static ref CROSS_EPISODE: Regex =
+        Regex::new(r"(?i)\b(\d{1,2})x(\d{2,3})((?:(?:-|x|-\d{1,2}x)\d{2,3})*)\b").unwrap();
+    // "Episode 02", "Ep 2", "E02", or a number at the start of the name (inside a season folder)
+    static ref EPISODE_ONLY: Regex =
+        Regex::new(r"(?i)(?:\b(?:episode|ep|e) ?(\d{1,3})\b|^(\d{1,3})\b)").unwrap();
+    // "[Group] Show - 012v2 [1080p]": absolute numbering of anime releases
+    static ref ABSOLUTE_EPISODE: Regex = Regex::new(r"(?i) - (\d{1,4})(?:v\d)?(?: |$)").unwrap();
+    // "Season 1", "Saison 01", "Staffel 2", "S01"
+    static ref SEASON_FOLDER: Regex =
+        Regex::new(r"(?i)^(?:season|saison|series|staffel|s) ?(\d{1,3})$").unwrap();
+    static ref BRACKETS: Regex = Regex::new(r"\[[^\]]*\]|\{[^}]*\}").unwrap();
+    static ref PARENTHESES: Regex = Regex::new(r"\([^)]*\)").unwrap();
+    static ref NUMBERS: Regex = Regex::new(r"\d+").unwrap();
+}
+
+// release tags that end the episode title: "Pilot 720p x264" -> "Pilot"
+const RELEASE_TAGS: [&str; 14] = [
+    "480p", "576p", "720p", "1080p", "2160p", "x264", "x265", "h264", "hevc", "hdtv", "webrip",
+    "web-dl", "bluray", "repack",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedEpisode {
+    pub show: String,
+    pub season: u32, // 0 for specials, 1 for absolute numbering
+    pub episode: u32,
+    pub last_episode: Option<u32>, // multi-episode file: S01E01E02 -> Some(2)
+    pub absolute: bool,            // "Show - 012", numbered from the first episode of the show
+    pub title: Option<String>,
+}
+
+// Episode of the library, with the watch state of its media
+#[derive(Debug, Clone, PartialEq)]
+pub struct Episode {
+    pub media_id: i64,
+    pub show_id: i64,
+    pub season: u32,
+    pub episode: u32,
+    pub last_episode: Option<u32>,
+    pub absolute: bool,
+    pub title: Option<String>,
+    pub path: String,
+    pub status: i32,
+    pub time_stop: f64,
+    pub duration: f32,
+}
+
+impl Episode {
+    pub fn is_watched(&self) -> bool {
+        self.status == FINISHED
+            || (self.duration > 0.0
+                && self.time_stop / self.duration as f64 >= EPISODE_WATCHED_RATIO)
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.time_stop > 0.0 && !self.is_watched()
+    }
+
+    // "S01E02", "S01E02-E03", or "E012" for absolute numbering
+    pub fn label(&self) -> String {
+        let mut label = if self.absolute {
+            format!("E{:03}", self.episode)
+        } else {
+            format!("S{:02}E{:02}", self.season, self.episode)
+        };
+        if let Some(last) = self.last_episode {
+            label.push_str(&format!("-E{:02}", last));
+        }
+        label
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Show {
+    pub id: i64,
+    pub title: String,
+    pub seasons: Vec<u32>,
+    pub episode_count: usize,
+    pub watched_count: usize,
+    pub next_episode: Option<Episode>,
+}
+
+// Groups the episodes (sorted by show, season, episode) under their show
+pub fn build_shows(shows: Vec<(i64, String)>, episodes: &[Episode]) -> Vec<Show> {
+    let mut result: Vec<Show> = shows
+        .into_iter()
+        .map(|(id, title)| {
+            let own: Vec<Episode> = episodes
+                .iter()
+                .filter(|e| e.show_id == id)
+                .cloned()
+                .collect();
+            let mut seasons: Vec<u32> = own.iter().map(|e| e.season).collect();
+            seasons.dedup();
+            Show {
+                id,
+                title,
+                seasons,
+                episode_count: own.len(),
+                watched_count: own.iter().filter(|e| e.is_watched()).count(),
+                next_episode: next_episode(&own).cloned(),
+            }
+        })
+        .filter(|show| show.episode_count > 0)
+        .collect();
+    result.sort_by_key(|show| show.title.to_lowercase());
+    result
+}
+
+// The episode to watch next in a show (episodes sorted by season then number, specials ignored):
+// the first one not watched after the last watched one, or the first not watched at all
+pub fn next_episode(episodes: &[Episode]) -> Option<&Episode> {
+    let regular: Vec<&Episode> = episodes.iter().filter(|e| e.season > 0).collect();
+    let after_last_watched = regular
+        .iter()
+        .rposition(|e| e.is_watched())
+        .map(|index| index + 1)
+        .unwrap_or(0);
+
+    regular[after_last_watched..]
+        .iter()
+        .chain(regular[..after_last_watched].iter())
+        .find(|e| !e.is_watched())
+        .copied()
+}
+
+// None when the path does not look like an episode
+pub fn parse_episode_path(path: &Path) -> Option<ParsedEpisode> {
+    let stem = clean_name(&path.file_stem()?.to_string_lossy());
+    let folders: Vec<String> = path
+        .parent()
+        .map(|parent| {
+            parent
+                .components()
+                .rev()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .take(2)
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    let season_folder = folders.first().and_then(|folder| folder_season(folder));
+    // the show folder is above the season folder when there is one
+    let show_folder = folders
+        .get(if season_folder.is_some() { 1 } else { 0 })
+        .map(|folder| clean_name(folder))
+        .filter(|folder| !folder.is_empty());
+
+    let (before, after, season, episode, last_episode, absolute) =
+        if let Some(caps) = SEASON_EPISODE.captures(&stem) {
+            let m = caps.get(0)?;
+            (
+                &stem[..m.start()],
+                &stem[m.end()..],
+                caps[1].parse().ok()?,
+                caps[2].parse().ok()?,
+                last_number(&caps[3]),
+                false,
+            )
+        } else if let Some(caps) = CROSS_EPISODE.captures(&stem) {
+            let m = caps.get(0)?;
+            (
+                &stem[..m.start()],
+                &stem[m.end()..],
+                caps[1].parse().ok()?,
+                caps[2].parse().ok()?,
+                last_number(&caps[3]),
+                false,
+            )
+        } else if let Some(season) = season_folder {
+            let caps = EPISODE_ONLY.captures(&stem)?;
+            let m = caps.get(0)?;
+            let number = caps.get(1).or(caps.get(2))?.as_str().parse().ok()?;
+            (
+                &stem[..m.start()],
+                &stem[m.end()..],
+                season,
+                number,
+                None,
+                false,
+            )
+        } else {
+            let caps = ABSOLUTE_EPISODE.captures(&stem)?;
+            let m = caps.get(0)?;
+            let number: u32 = caps[1].parse().ok()?;
+            // "Movie - 2012" is a year, not an episode
+            if caps[1].len() == 4 && (1900..2100).contains(&number) {
+                return None;
+            }
+            (&stem[..m.start()], &stem[m.end()..], 1, number, None, true)
+        };
+
+    let show = clean_show(before).or(show_folder)?;
+    Some(ParsedEpisode {
+        show,
+        season,
+        episode,
+        last_episode: last_episode.filter(|last| *last > episode),
+        absolute,
+        title: clean_title(after),
+    })
+}
+
+// Folder of the show (where tvshow.nfo lives): the one above the season folder, or the folder
+// of the episode when it is named after the show. None for files loose in a library root.
+pub fn show_folder(path: &Path) -> Option<PathBuf> {
+    let parent = path.parent()?;
+    let name = parent.file_name()?.to_string_lossy();
+    if folder_season(&name).is_some() {
+        return parent.parent().map(Path::to_path_buf);
+    }
+    let show = parse_episode_path(path)?.show;
+    clean_name(&name)
+        .eq_ignore_ascii_case(&show)
+        .then(|| parent.to_path_buf())
+}
+
+fn folder_season(folder: &str) -> Option<u32> {
+    let folder = clean_name(folder);
+    if folder.eq_ignore_ascii_case("specials") || folder.eq_ignore_ascii_case("special") {
+        return Some(0);
+    }
+    SEASON_FOLDER.captures(&folder)?[1].parse().ok()
+}
+
+// Biggest number in the multi-episode suffix ("E03", "-04", "-1x05")
+fn last_number(suffix: &str) -> Option<u32> {
+    NUMBERS
+        .find_iter(suffix)
+        .filter_map(|n| n.as_str().parse().ok())
+        .last()
+}
+
+// "[Group] The.Show_Name" -> "The Show Name"
+fn clean_name(name: &str) -> String {
+    let name = BRACKETS.replace_all(name, " ").replace(['.', '_'], " ");
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn clean_show(text: &str) -> Option<String> {
+    let show = text.trim().trim_end_matches(['-', ' ']).trim();
+    (!show.is_empty()).then(|| show.to_string())
+}
+
+// The release details after the episode are dropped: "- Pilot (1080p) x264" -> "Pilot"
+fn clean_title(text: &str) -> Option<String> {
+    let text = PARENTHESES.replace_all(text, " ");
+    let mut words = Vec::new();
+    for word in text.split_whitespace() {
+        if RELEASE_TAGS
+            .iter()
+            .any(|tag| word.eq_ignore_ascii_case(tag))
+        {
+            break;
+        }
+        words.push(word);
+    }
+    let title = words.join(" ");
+    let title = title.trim_matches(|c: char| c == '-' || c.is_whitespace());
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(path: &str) -> Option<ParsedEpisode> {
+        parse_episode_path(Path::new(path))
+    }
+
+    fn episode(season: u32, number: u32, status: i32, time_stop: f64) -> Episode {
+        Episode {
+            media_id: (season * 100 + number) as i64,
+            show_id: 1,
+            season,
+            episode: number,
+            last_episode: None,
+            absolute: false,
+            title: None,
+            path: format!("/tv/Show/S{season:02}E{number:02}.mkv"),
+            status,
+            time_stop,
+            duration: 1000.0,
+        }
+    }
+
+    #[test]
+    fn parses_season_episode_names() {
+        let parsed = parse("/tv/The.Office.US.S02E05.Halloween.720p.WEB.x264.mkv").unwrap();
+        assert_eq!(parsed.show, "The Office US");
+        assert_eq!((parsed.season, parsed.episode), (2, 5));
+        assert_eq!(parsed.last_episode, None);
+        assert_eq!(parsed.title.as_deref(), Some("Halloween"));
+        assert!(!parsed.absolute);
+
+        let parsed = parse("/tv/Doctor Who (2005) - 3x07 - 42.avi").unwrap();
+        assert_eq!(parsed.show, "Doctor Who (2005)");
+        assert_eq!((parsed.season, parsed.episode), (3, 7));
+        assert_eq!(parsed.title.as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn parses_multi_episode_files() {
+        for name in [
+            "Show.S01E01E02.mkv",
+            "Show.S01E01-E02.mkv",
+            "Show S01E01-02.mkv",
+            "Show 1x01x02.mkv",
+            "Show 1x01-1x02.mkv",
+        ] {
+            let parsed = parse(&format!("/tv/{name}")).unwrap();
+            assert_eq!(parsed.show, "Show", "{name}");
+            assert_eq!(
+                (parsed.season, parsed.episode, parsed.last_episode),
+                (1, 1, Some(2)),
+                "{name}"
+            );
+        }
+        // the resolution is not an episode range
+        let parsed = parse("/tv/Show.S01E03-720p.mkv").unwrap();
+        assert_eq!((parsed.episode, parsed.last_episode), (3, None));
+    }
+
+    #[test]
+    fn uses_season_and_show_folders() {
+        let parsed = parse("/tv/Breaking Bad/Season 2/Episode 04.mkv").unwrap();
+        assert_eq!(parsed.show, "Breaking Bad");
+        assert_eq!((parsed.season, parsed.episode), (2, 4));
+
+        let parsed = parse("/tv/Breaking Bad/Saison 03/05 - Más.mkv").unwrap();
+        assert_eq!((parsed.season, parsed.episode), (3, 5));
+        assert_eq!(parsed.title.as_deref(), Some("Más"));
+
+        let parsed = parse("/tv/Breaking Bad/Specials/Ep 1.mkv").unwrap();
+        assert_eq!((parsed.show.as_str(), parsed.season), ("Breaking Bad", 0));
+
+        // no show name in the file: the folder gives it
+        let parsed = parse("/tv/Fargo/S01E02.mkv").unwrap();
+        assert_eq!(parsed.show, "Fargo");
+
+        let folder = |path: &str| show_folder(Path::new(path));
+        let fargo = Some(PathBuf::from("/tv/Fargo"));
+        assert_eq!(folder("/tv/Fargo/Season 1/Fargo.S01E01.mkv"), fargo);
+        assert_eq!(folder("/tv/Fargo/Fargo.S01E01.mkv"), fargo);
+        assert_eq!(folder("/tv/Fargo.S01E01.mkv"), None);
+    }
+
+    #[test]
+    fn parses_absolute_anime_numbering() {
+        let parsed = parse("/anime/[SubsPlease] One Piece - 1071 (1080p) [ABCD1234].mkv").unwrap();
+        assert_eq!(parsed.show, "One Piece");
+        assert_eq!((parsed.season, parsed.episode), (1, 1071));
+        assert!(parsed.absolute);
+
+        let parsed = parse("/anime/[Group] Frieren - 12v2 [720p].mkv").unwrap();
+        assert_eq!((parsed.show.as_str(), parsed.episode), ("Frieren", 12));
+    }
+
+    #[test]
+    fn ignores_movies_and_other_videos() {
+        assert_eq!(parse("/films/Blade Runner 2049 (2017).mkv"), None);
+        assert_eq!(parse("/films/Apollo 13 - 1995.mkv"), None);
+        assert_eq!(parse("/films/1920x1080 sample.mp4"), None);
+        assert_eq!(parse("/videos/holidays.mp4"), None);
+    }
+
+    #[test]
+    fn next_episode_follows_the_last_watched_one() {
+        let mut episodes = vec![
+            episode(0, 1, 0, 0.0),
+            episode(1, 1, FINISHED, 0.0),
+            episode(1, 2, 0, 0.0),
+            episode(1, 3, 1, 950.0), // 95%: counts as watched
+            episode(2, 1, 0, 0.0),
+        ];
+        assert_eq!(next_episode(&episodes).unwrap().media_id, 201);
+
+        // nothing left after the last watched one: back to the skipped episode
+        episodes[4].status = FINISHED;
+        assert_eq!(next_episode(&episodes).unwrap().media_id, 102);
+
+        episodes[2].time_stop = 300.0;
+        assert!(next_episode(&episodes).unwrap().in_progress());
+
+        episodes[2].status = FINISHED;
+        assert_eq!(next_episode(&episodes), None);
+    }
+
+    #[test]
+    fn build_shows_counts_episodes_per_show() {
+        let mut other = episode(1, 1, FINISHED, 0.0);
+        other.show_id = 2;
+        let episodes = vec![episode(1, 1, FINISHED, 0.0), episode(1, 2, 0, 0.0), other];
+
+        let shows = build_shows(
+            vec![
+                (1, "b show".to_string()),
+                (2, "A show".to_string()),
+                (3, "empty".to_string()),
+            ],
+            &episodes,
+        );
+
+        assert_eq!(shows.len(), 2);
+        assert_eq!(shows[0].title, "A show");
+        assert_eq!(shows[0].next_episode, None);
+        assert_eq!((shows[1].episode_count, shows[1].watched_count), (2, 1));
+        assert_eq!(shows[1].next_episode.as_ref().unwrap().label(), "S01E02");
+    }
+}
@@ -3,23 +3,169 @@ This file manages media sources for the library,
 loading and saving source configurations to a JSON file.
 */
 
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
-
-use crate::constants::constants::SOURCE_FILE;
-
-#[derive(Serialize, Deserialize, Debug)]
+use std::path::{Path, PathBuf};
+
+use crate::constants::constants::{
+    AUDIO_EXTS, DEFAULT_SOURCE_EXCLUDES, IMAGE_EXTS, LOG_FILE, SOURCE_FILE, VIDEO_EXTS,
+};
+use crate::logger::logger::Logger;
+use crate::media::data::MediaType;
+
+// A folder of the library and the rules used to scan it.
+// The glob patterns are matched against the path relative to the source folder,
+// a pattern without '/' matches a file or folder name at any depth (like .gitignore).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct MediaSource {
     pub path: PathBuf,
+    #[serde(default)]
+    pub include: Vec<String>, // empty: every media file
+    #[serde(default = "default_excludes")]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub max_depth: Option<usize>, // None: no limit, 0: only the files of the source folder
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    #[serde(default)]
+    pub min_size: u64, // bytes
+}
+
+fn default_excludes() -> Vec<String> {
+    DEFAULT_SOURCE_EXCLUDES
+        .iter()
+        .map(|p| p.to_string())
+        .collect()
+}
+
+impl MediaSource {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            include: Vec::new(),
+            exclude: default_excludes(),
+            max_depth: None,
+            follow_symlinks: false,
+            min_size: 0,
+        }
+    }
+}
+
+// Rules of a source compiled once for a scan
+pub struct SourceFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    max_depth: Option<usize>,
+    min_size: u64,
+}
+
+impl SourceFilter {
+    pub fn new(source: &MediaSource) -> Self {
+        Self {
+            include: build_globset(&source.include),
+            exclude: build_globset(&source.exclude),
+            max_depth: source.max_depth,
+            min_size: source.min_size,
+        }
+    }
+
+    // `depth` is the number of folders between the source and this folder (1 for a direct child)
+    pub fn accepts_dir(&self, relative: &Path, depth: usize) -> bool {
+        self.max_depth.is_none_or(|max| depth <= max) && !self.is_excluded(relative)
+    }
+
+    pub fn accepts_file(&self, relative: &Path, size: u64) -> bool {
+        size >= self.min_size
+            && !self.is_excluded(relative)
+            && self
+                .include
+                .as_ref()
+                .is_none_or(|include| include.is_match(relative))
+    }
+
+    fn is_excluded(&self, relative: &Path) -> bool {
+        self.exclude
+            .as_ref()
+            .is_some_and(|exclude| exclude.is_match(relative))
+    }
+}
+
+// None when there is no valid pattern, the invalid ones are logged and ignored
+fn build_globset(patterns: &[String]) -> Option<GlobSet> {
+    let logger = Logger::new(LOG_FILE);
+    let mut builder = GlobSetBuilder::new();
+    let mut count = 0;
+
+    for pattern in patterns.iter().map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let pattern = if pattern.contains('/') {
+            pattern.trim_start_matches('/').to_string()
+        } else {
+            format!("**/{}", pattern)
+        };
+        match GlobBuilder::new(&pattern)
+            .literal_separator(true)
+            .case_insensitive(true)
+            .build()
+        {
+            Ok(glob) => {
+                builder.add(glob);
+                count += 1;
+            }
+            Err(e) => logger.error(&format!("Invalid source pattern '{}': {}", pattern, e)),
+        }
+    }
+
+    if count == 0 {
+        return None;
+    }
+    builder.build().ok()
+}
+
+// Extensions looked at by the scan, per media type. The content of the file decides its type,
+// these lists only give it when the content is unknown: video first, then audio, then image.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct MediaExtensions {
+    pub audio: Vec<String>,
+    pub video: Vec<String>,
+    pub image: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Default for MediaExtensions {
+    fn default() -> Self {
+        let to_vec = |exts: &[&str]| exts.iter().map(|e| e.to_string()).collect();
+        Self {
+            audio: to_vec(&AUDIO_EXTS),
+            video: to_vec(&VIDEO_EXTS),
+            image: to_vec(&IMAGE_EXTS),
+        }
+    }
+}
+
+impl MediaExtensions {
+    // Type given by the extension alone, None if the file is not a media file
+    pub fn type_of(&self, path: &Path) -> Option<MediaType> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        [
+            (&self.video, MediaType::Video),
+            (&self.audio, MediaType::Audio),
+            (&self.image, MediaType::Image),
+        ]
+        .into_iter()
+        .find(|(list, _)| list.iter().any(|e| e.eq_ignore_ascii_case(&ext)))
+        .map(|(_, media_type)| media_type)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LibraryConfig {
     pub sources: Vec<MediaSource>,
     pub music_sources: Vec<MediaSource>,
     pub video_sources: Vec<MediaSource>,
     pub image_sources: Vec<MediaSource>,
+    #[serde(default)]
+    pub extensions: MediaExtensions,
 }
 
 impl LibraryConfig {
@@ -38,6 +184,7 @@ impl LibraryConfig {
             music_sources: Vec::new(),
             video_sources: Vec::new(),
             image_sources: Vec::new(),
+            extensions: MediaExtensions::default(),
         }
     }
 
@@ -48,29 +195,54 @@ impl LibraryConfig {
 
     pub fn add_source(&mut self, folder: PathBuf) {
         if !self.sources.iter().any(|s| s.path == folder) {
-            self.sources.push(MediaSource { path: folder });
+            self.sources.push(MediaSource::new(folder));
         }
         self.save(SOURCE_FILE);
     }
     pub fn add_audio_source(&mut self, folder: PathBuf) {
         if !self.music_sources.iter().any(|s| s.path == folder) {
-            self.music_sources.push(MediaSource { path: folder });
+            self.music_sources.push(MediaSource::new(folder));
         }
         self.save(SOURCE_FILE);
     }
     pub fn add_video_source(&mut self, folder: PathBuf) {
         if !self.video_sources.iter().any(|s| s.path == folder) {
-            self.video_sources.push(MediaSource { path: folder });
+            self.video_sources.push(MediaSource::new(folder));
         }
         self.save(SOURCE_FILE);
     }
     pub fn add_image_source(&mut self, folder: PathBuf) {
         if !self.image_sources.iter().any(|s| s.path == folder) {
-            self.image_sources.push(MediaSource { path: folder });
+            self.image_sources.push(MediaSource::new(folder));
         }
         self.save(SOURCE_FILE);
     }
 
+    // Replaces the rules of every source with the same folder (music, video and image)
+    pub fn update_source(&mut self, source: MediaSource) {
+        for list in [
+            &mut self.sources,
+            &mut self.music_sources,
+            &mut self.video_sources,
+            &mut self.image_sources,
+        ] {
+            for existing in list.iter_mut().filter(|s| s.path == source.path) {
+                *existing = source.clone();
+            }
+        }
+        self.save(SOURCE_FILE);
+    }
+
+    // First source with this folder, whatever its media type
+    pub fn find_source(&self, folder: &Path) -> Option<&MediaSource> {
+        self.sources
+            .iter()
+            .chain(&self.music_sources)
+            .chain(&self.video_sources)
+            .chain(&self.image_sources)
+            .find(|s| s.path == folder)
+    }
+
     pub fn remove_source(&mut self, folder: PathBuf) {
         self.sources.retain(|s| s.path != folder);
         self.save(SOURCE_FILE);
@@ -123,14 +295,11 @@ mod tests {
     fn save_and_load_persists_config() {
         let path = temp_file("persist");
         let mut config = LibraryConfig {
-            sources: vec![MediaSource {
-                path: PathBuf::from("/media"),
-            }],
-            music_sources: vec![MediaSource {
-                path: PathBuf::from("/music"),
-            }],
+            sources: vec![MediaSource::new(PathBuf::from("/media"))],
+            music_sources: vec![MediaSource::new(PathBuf::from("/music"))],
             video_sources: vec![],
             image_sources: vec![],
+            extensions: MediaExtensions::default(),
         };
         config.save(&path);
 
@@ -151,6 +320,7 @@ mod tests {
             music_sources: vec![],
             video_sources: vec![],
             image_sources: vec![],
+            extensions: MediaExtensions::default(),
         };
         config.add_source(PathBuf::from("/media"));
         config.add_source(PathBuf::from("/media"));
@@ -168,6 +338,7 @@ mod tests {
             music_sources: vec![],
             video_sources: vec![],
             image_sources: vec![],
+            extensions: MediaExtensions::default(),
         };
         config.add_audio_source(PathBuf::from("/music"));
         config.add_audio_source(PathBuf::from("/music"));
@@ -185,6 +356,7 @@ mod tests {
             music_sources: vec![],
             video_sources: vec![],
             image_sources: vec![],
+            extensions: MediaExtensions::default(),
         };
         config.add_video_source(PathBuf::from("/videos"));
         config.add_video_source(PathBuf::from("/videos"));
@@ -202,6 +374,7 @@ mod tests {
             music_sources: vec![],
             video_sources: vec![],
             image_sources: vec![],
+            extensions: MediaExtensions::default(),
         };
         config.add_image_source(PathBuf::from("/images"));
         config.add_image_source(PathBuf::from("/images"));
@@ -220,6 +393,7 @@ mod tests {
             music_sources: vec![],
             video_sources: vec![],
             image_sources: vec![],
+            extensions: MediaExtensions::default(),
         };
         config.add_source(PathBuf::from("/media"));
 
@@ -237,6 +411,7 @@ mod tests {
             music_sources: vec![],
             video_sources: vec![],
             image_sources: vec![],
+            extensions: MediaExtensions::default(),
         };
         config.add_audio_source(PathBuf::from("/music1"));
         config.add_audio_source(PathBuf::from("/music2"));
@@ -249,4 +424,56 @@ mod tests {
 
         let _ = fs::remove_file(&path);
     }
+
+    #[test]
+    fn legacy_source_file_gets_default_rules() {
+        let path = temp_file("legacy");
+        fs::write(
+            &path,
+            r#"{"sources":[],"music_sources":[{"path":"/music"}],"video_sources":[],"image_sources":[]}"#,
+        )
+        .unwrap();
+
+        let loaded = LibraryConfig::load(&path);
+        assert_eq!(
+            loaded.music_sources[0],
+            MediaSource::new(PathBuf::from("/music"))
+        );
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn find_and_update_source_rules() {
+        let mut config = LibraryConfig {
+            sources: vec![],
+            music_sources: vec![MediaSource::new(PathBuf::from("/media"))],
+            video_sources: vec![MediaSource::new(PathBuf::from("/media"))],
+            image_sources: vec![MediaSource::new(PathBuf::from("/images"))],
+            extensions: MediaExtensions::default(),
+        };
+        let mut source = config.find_source(Path::new("/media")).unwrap().clone();
+        source.max_depth = Some(2);
+        source.min_size = 1024;
+        config.update_source(source.clone());
+
+        assert_eq!(config.music_sources[0], source);
+        assert_eq!(config.video_sources[0], source);
+        assert_eq!(config.image_sources[0].max_depth, None);
+        assert!(config.find_source(Path::new("/other")).is_none());
+    }
+
+    #[test]
+    fn filter_patterns_match_at_any_depth() {
+        let mut source = MediaSource::new(PathBuf::from("/music"));
+        source.include = vec!["*.flac".to_string()];
+        source.exclude.push("live/bootlegs".to_string());
+        let filter = SourceFilter::new(&source);
+
+        assert!(filter.accepts_file(Path::new("a/b/song.FLAC"), 0));
+        assert!(!filter.accepts_file(Path::new("a/song.mp3"), 0));
+        assert!(!filter.accepts_dir(Path::new("a/.git"), 2));
+        assert!(!filter.accepts_dir(Path::new("live/bootlegs"), 2));
+        assert!(filter.accepts_dir(Path::new("other/live/bootlegs"), 3));
+    }
 }
@@ -11,10 +11,11 @@ mod media;
 mod plugin;
 mod scan;
 mod threading;
+mod watcher;
 
 mod music_download;
 
-use crate::constants::LOG_FILE;
+use crate::constants::{ARTWORK_CACHE_DIR, LOG_FILE, PLUGIN_HOST_ENV};
 use crate::logger::logger::Logger;
 
 use crate::config::AppConfig;
@@ -33,6 +34,11 @@ use warp::Filter;
 use warp::Reply;
 
 fn main() {
+    // processus enfant qui fait tourner un seul plugin, lancé par plugin/host.rs
+    if let Some(plugin_path) = std::env::var_os(PLUGIN_HOST_ENV) {
+        std::process::exit(plugin::host::run_host(Path::new(&plugin_path)));
+    }
+
     let logger = Logger::new(LOG_FILE);
 
     unsafe {
@@ -159,6 +165,15 @@ fn main() {
                         }
                     });
 
+                // Images du cache d'artwork (posters, pochettes...), par nom de fichier
+                let artwork_route = warp::path("artwork")
+                    .and(warp::fs::dir(ARTWORK_CACHE_DIR))
+                    // le nom du fichier est le hash du contenu, il ne change jamais
+                    .map(|file: warp::fs::File| {
+                        let cache_control = "max-age=31536000, immutable";
+                        warp::reply::with_header(file, "Cache-Control", cache_control).into_response()
+                    });
+
                 let file_routes = base_route
                     .or(final_drives_route)
                     .unify()
@@ -168,7 +183,13 @@ fn main() {
                 let thumb_route =
                     thumbnail_route.map(|r: warp::http::Response<Vec<u8>>| r.into_response());
 
-                let routes = file_routes.or(thumb_route).unify().with(cors).with(log);
+                let routes = file_routes
+                    .or(thumb_route)
+                    .unify()
+                    .or(artwork_route)
+                    .unify()
+                    .with(cors)
+                    .with(log);
 
                 let (_addr, server) = warp::serve(routes).bind_with_graceful_shutdown(
                     ([127, 0, 0, 1], 3030),
@@ -2,10 +2,12 @@
 in this file we handle audio playback
 */
 
-use super::data::{Media, MediaInfo, MediaType};
+use super::data::{Media, MediaInfo, MediaTags, MediaType};
 
+use lofty::picture::PictureType;
 use lofty::prelude::*;
 use lofty::read_from_path;
+use lofty::tag::ItemKey;
 
 // use std::fs::File; // Plus besoin
 // use std::io::BufReader; // Plus besoin
@@ -16,33 +18,56 @@ use crate::constants::LOG_FILE;
 use crate::logger::logger::Logger;
 
 // --- STRUCTURE METADATA ---
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Metadata {
     pub title: Option<String>,
-    pub artist: Option<String>,
-    pub album: Option<String>,
     pub duration: f32,
+    pub tags: MediaTags,
 }
 
 impl Metadata {
+    // Reads the tags and the duration, used by the scanner (mp4 videos work too)
     pub fn from_path(path: &str) -> Self {
+        Self::read(path, false).0
+    }
+
+    // Same as from_path, with the embedded cover (front cover first)
+    pub fn with_picture(path: &str) -> (Self, Option<Vec<u8>>) {
+        Self::read(path, true)
+    }
+
+    fn read(path: &str, with_picture: bool) -> (Self, Option<Vec<u8>>) {
         match read_from_path(path) {
             Ok(tagged_file) => {
                 let properties = tagged_file.properties();
-                let tag = tagged_file.primary_tag();
-
-                Metadata {
+                let tag = tagged_file.primary_tag().or(tagged_file.first_tag());
+                let picture = tag.filter(|_| with_picture).and_then(|t| {
+                    let pictures = t.pictures();
+                    pictures
+                        .iter()
+                        .find(|p| p.pic_type() == PictureType::CoverFront)
+                        .or(pictures.first())
+                        .map(|p| p.data().to_vec())
+                });
+
+                let metadata = Metadata {
                     duration: properties.duration().as_secs_f32(),
                     title: tag.and_then(|t| t.title().map(|s| s.to_string())),
-                    artist: tag.and_then(|t| t.artist().map(|s| s.to_string())),
-                    album: tag.and_then(|t| t.album().map(|s| s.to_string())),
-                }
+                    tags: MediaTags {
+                        artist: tag.and_then(|t| t.artist().map(|s| s.to_string())),
+                        album: tag.and_then(|t| t.album().map(|s| s.to_string())),
+                        album_artist: tag.and_then(|t| {
+                            t.get_string(&ItemKey::AlbumArtist).map(|s| s.to_string())
+                        }),
+                        track_number: tag.and_then(|t| t.track()),
+                        disc_number: tag.and_then(|t| t.disk()),
+                        year: tag.and_then(|t| t.year()),
+                        genre: tag.and_then(|t| t.genre().map(|s| s.to_string())),
+                    },
+                };
+                (metadata, picture)
             }
-            Err(_) => Metadata {
-                duration: 0.0,
-                title: None,
-                artist: None,
-                album: None,
-            },
+            Err(_) => (Metadata::default(), None),
         }
     }
 }
@@ -133,6 +158,23 @@ impl Audio {
             last_position: last_pos,
         }
     }
+
+    // Builds the audio from the metadata stored in the database, without reading the file
+    pub fn with_metadata(
+        id: i64,
+        path: &str,
+        name: &str,
+        last_pos: f32,
+        metadata: Metadata,
+    ) -> Self {
+        Self {
+            id,
+            path: path.to_string(),
+            name: name.to_string(),
+            metadata,
+            last_position: last_pos,
+        }
+    }
 }
 
 // 👇 C'EST ICI LA MAGIE : ON NE FAIT RIEN EN RUST
@@ -155,11 +197,13 @@ impl Media for Audio {
             id: self.id,
             path: self.path.clone(),
             title: Some(self.name.clone()),
-            artist: self.metadata.artist.clone(),
+            artist: self.metadata.tags.artist.clone(),
+            album: self.metadata.tags.album.clone(),
             duration: Some(self.metadata.duration),
             media_type: MediaType::Audio,
             last_position: self.last_position,
             tags: Vec::new(),
+            artwork: None,
         }
     }
 
@@ -210,6 +254,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn with_metadata_does_not_read_the_file() {
+        let metadata = Metadata {
+            title: Some("Uprising".to_string()),
+            duration: 305.0,
+            tags: MediaTags {
+                artist: Some("Muse".to_string()),
+                ..Default::default()
+            },
+        };
+        let audio = Audio::with_metadata(3, "/missing/uprising.mp3", "Uprising", 0.0, metadata);
+        let info = audio.info();
+
+        assert_eq!(info.artist, Some("Muse".to_string()));
+        assert_eq!(info.duration, Some(305.0));
+    }
+
+    #[test]
+    fn metadata_from_missing_file_is_empty() {
+        assert_eq!(
+            Metadata::from_path("/missing/file.mp3"),
+            Metadata::default()
+        );
+    }
+
     #[test]
     fn test_queue_flow() {
         let mut q = Queue::new();
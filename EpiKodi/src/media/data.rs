@@ -14,11 +14,27 @@ pub struct MediaInfo {
     pub path: String,
     pub title: Option<String>,
     pub artist: Option<String>,
+    #[serde(default)]
+    pub album: Option<String>,
     pub duration: Option<f32>,
     pub media_type: MediaType,
     pub last_position: f32,
     #[serde(default)]
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub artwork: Option<String>, // cached file served at /artwork/<file name>
+}
+
+// Tags read from the file (ID3, Vorbis comments, MP4 atoms...) and stored in the media table
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct MediaTags {
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
 }
 
 impl MediaType {
@@ -42,10 +42,12 @@ impl Media for Image {
             path: self.path.clone(),
             title: Some(self.name.clone()),
             artist: None,
+            album: None,
             duration: None,
             media_type: MediaType::Image,
             last_position: 0.0,
             tags: Vec::new(),
+            artwork: None,
         }
     }
 
@@ -52,10 +52,12 @@ impl Media for Video {
             path: self.path.clone(),
             title: Some(self.name.clone()),
             artist: None,
+            album: None,
             duration: Some(self.metadata.duration),
             media_type: MediaType::Video,
             last_position: self.last_position,
             tags: Vec::new(),
+            artwork: None,
         }
     }
 
@@ -0,0 +1,377 @@
+/*
+Host events given to the plugins of the `events` capability (see plugin_api::events).
+Nothing here waits for a plugin: EventBus::publish puts the event in a bounded queue read by a
+dispatcher thread, which hands it to the bounded queue of every plugin subscribed to its kind.
+Each plugin has a thread of its own emptying its queue, so a slow plugin only delays its own
+events. It makes one call at a time: a call that timed out still holds it until the plugin
+returns, meanwhile the queue fills. When a queue is full the new events are dropped, counted
+and logged. Timeouts and errors count against the plugin like those of the lookups.
+The dispatcher reads the subscribed plugins from a table of its own (see Subscribers), it never
+locks the PluginManager a slow call may be holding.
+*/
+
+use super::plugin_manager::{
+    parse_answer, to_request, wait_answer, MetadataError, PluginManager, Runner,
+};
+use crate::constants::{LOG_FILE, PLUGIN_EVENT_QUEUE};
+use crate::library::metadata_cache::now;
+use crate::logger::logger::Logger;
+use plugin_api::events::{HostEvent, PluginEvent};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, Weak};
+use std::thread;
+use std::time::Duration;
+
+// What the media thread and the scanner publish to, cheap to clone
+#[derive(Clone)]
+pub struct EventBus {
+    queue: SyncSender<PluginEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl EventBus {
+    // The dispatcher thread stops once every clone is dropped
+    pub fn start(subscribers: Subscribers) -> Self {
+        let (queue, events) = mpsc::sync_channel::<PluginEvent>(PLUGIN_EVENT_QUEUE);
+        thread::spawn(move || {
+            for event in events {
+                subscribers.broadcast(&event);
+            }
+        });
+        Self {
+            queue,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    // Never blocks, false when the event was dropped
+    pub fn publish(&self, event: HostEvent) -> bool {
+        let event = PluginEvent {
+            sent_at: now(),
+            event,
+        };
+        match self.queue.try_send(event) {
+            Ok(()) => true,
+            Err(TrySendError::Full(event)) => {
+                count_dropped(&self.dropped, "the plugins", event.event.kind());
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+// A usable plugin and the kinds of events it wants
+struct Subscriber {
+    kinds: Vec<String>,
+    queue: Arc<EventQueue>,
+}
+
+// The plugins the dispatcher hands the events to, written by the manager whenever its plugins
+// are loaded, enabled, disabled or suspended (see PluginManager::update_subscribers)
+#[derive(Clone, Default)]
+pub struct Subscribers(Arc<RwLock<Vec<Subscriber>>>);
+
+impl Subscribers {
+    pub(crate) fn set(&self, subscribers: Vec<(Vec<String>, Arc<EventQueue>)>) {
+        *self.0.write().unwrap() = subscribers
+            .into_iter()
+            .map(|(kinds, queue)| Subscriber { kinds, queue })
+            .collect();
+    }
+
+    // Hands the event to the queue of every plugin subscribed to its kind, without waiting
+    // for them. Returns the plugins it was queued for.
+    pub fn broadcast(&self, event: &PluginEvent) -> Vec<String> {
+        let kind = event.event.kind();
+        let Ok(input) = to_request(event) else {
+            return Vec::new();
+        };
+        self.0
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|subscriber| subscriber.kinds.iter().any(|k| k == kind))
+            .filter(|subscriber| subscriber.queue.push(kind, &input))
+            .map(|subscriber| subscriber.queue.name.clone())
+            .collect()
+    }
+}
+
+// The manager the event threads record their calls with, set once it is shared (see
+// PluginManager::shared). Weak, the manager owns the queues.
+#[derive(Clone, Default)]
+pub struct ManagerHandle(Arc<OnceLock<Weak<Mutex<PluginManager>>>>);
+
+impl ManagerHandle {
+    pub(crate) fn set(&self, manager: &Arc<Mutex<PluginManager>>) {
+        let _ = self.0.set(Arc::downgrade(manager));
+    }
+
+    fn get(&self) -> Option<Arc<Mutex<PluginManager>>> {
+        self.0.get()?.upgrade()
+    }
+}
+
+// An event for the thread calling the plugin, and where it tells when the call starts and ends
+type Call = (String, Sender<()>, Sender<Result<String, MetadataError>>);
+
+// The events of one plugin and the thread giving them to its `on_event`.
+// Dropping it (from the plugin and the subscribers) stops the thread, the events still queued
+// are not delivered.
+pub struct EventQueue {
+    name: String,
+    queue: SyncSender<String>,
+    closed: Arc<AtomicBool>,
+    dropped: AtomicU64,
+}
+
+impl EventQueue {
+    pub(crate) fn start(
+        name: String,
+        runner: Runner,
+        timeout: Duration,
+        manager: ManagerHandle,
+    ) -> Self {
+        let (queue, events) = mpsc::sync_channel::<String>(PLUGIN_EVENT_QUEUE);
+        let closed = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&closed);
+        let plugin = name.clone();
+
+        // the only thread calling the plugin, it takes an event once the previous call is over
+        let (calls, calls_rx) = mpsc::sync_channel::<Call>(0);
+        let caller = runner.clone();
+        thread::spawn(move || {
+            for (event, started, answer) in calls_rx {
+                let result = caller.call_here("on_event", &event, timeout, || {
+                    let _ = started.send(());
+                });
+                let _ = answer.send(result);
+            }
+        });
+
+        thread::spawn(move || {
+            let logger = Logger::new(LOG_FILE);
+            for event in events {
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let (started_tx, started) = mpsc::channel();
+                let (answer_tx, answer) = mpsc::channel();
+                // waits here while a call that timed out is still running
+                if calls.send((event, started_tx, answer_tx)).is_err() || started.recv().is_err() {
+                    break;
+                }
+                let result = wait_answer(&answer, timeout)
+                    .and_then(|answer| answer)
+                    .and_then(|text| parse_answer::<()>(&text));
+                if let Err(e) = &result {
+                    logger.error(&format!("✗ Plugin {} on_event: {}", plugin, e));
+                }
+                if let Some(manager) = manager.get() {
+                    manager.lock().unwrap().record(&runner, &result);
+                }
+            }
+        });
+        Self {
+            name,
+            queue,
+            closed,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    // The event as JSON, false when the queue is full
+    pub fn push(&self, kind: &str, event: &str) -> bool {
+        match self.queue.try_send(event.to_string()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                count_dropped(&self.dropped, &self.name, kind);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for EventQueue {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+// Logged at the first one and then every hundred, a stuck plugin would fill the log otherwise
+fn count_dropped(dropped: &AtomicU64, queue: &str, kind: &str) {
+    let count = dropped.fetch_add(1, Ordering::Relaxed) + 1;
+    if count == 1 || count.is_multiple_of(100) {
+        Logger::new(LOG_FILE).warning(&format!(
+            "Event queue of {} full, '{}' dropped ({} events dropped so far)",
+            queue, kind, count
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::plugin_manager::{
+        load_plugin, test_plugin_path, LoadedPlugin, ManagedPlugin, PluginManager,
+    };
+    use libloading::Symbol;
+    use plugin_api::events::{kinds, EventMedia};
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    fn test_plugin() -> Arc<LoadedPlugin> {
+        Arc::new(load_plugin(test_plugin_path()).unwrap())
+    }
+
+    // Events the test plugin got, in every test of this process
+    fn received(plugin: &LoadedPlugin) -> usize {
+        unsafe {
+            let func: Symbol<unsafe extern "C" fn() -> usize> =
+                plugin.library.get(b"test_plugin_events\0").unwrap();
+            func()
+        }
+    }
+
+    // Waits until an event blocks the test plugin, returns how many calls it blocks
+    fn wait_blocked(plugin: &LoadedPlugin) -> usize {
+        unsafe {
+            let func: Symbol<unsafe extern "C" fn() -> usize> =
+                plugin.library.get(b"test_plugin_wait_blocked\0").unwrap();
+            func()
+        }
+    }
+
+    fn release_blocked(plugin: &LoadedPlugin) {
+        unsafe {
+            let func: Symbol<unsafe extern "C" fn()> =
+                plugin.library.get(b"test_plugin_release\0").unwrap();
+            func()
+        }
+    }
+
+    fn started(title: &str) -> HostEvent {
+        HostEvent::MediaStarted {
+            media: EventMedia {
+                title: Some(title.to_string()),
+                ..Default::default()
+            },
+            position: 0.0,
+        }
+    }
+
+    #[test]
+    fn events_go_to_the_subscribed_plugins() {
+        let library = test_plugin();
+        let mut plugin = ManagedPlugin::in_process(Arc::clone(&library));
+        assert!(plugin.subscriptions.is_empty());
+        let mut manager = PluginManager::new();
+        plugin.subscribe(manager.timeout, &manager.handle).unwrap();
+        assert!(plugin
+            .subscriptions
+            .iter()
+            .any(|kind| kind == kinds::TAG_ADDED));
+        manager.plugins.push(plugin);
+        manager.update_subscribers();
+        let subscribers = manager.subscribers.clone();
+
+        let before = received(&library);
+        let event = |event: HostEvent| PluginEvent { sent_at: 0, event };
+        assert_eq!(
+            subscribers.broadcast(&event(started("Lithium"))),
+            vec!["Test".to_string()]
+        );
+        // the test plugin does not want the progress
+        let progress = HostEvent::MediaProgress {
+            media: EventMedia::default(),
+            position: 10.0,
+            duration: 200.0,
+        };
+        assert!(subscribers.broadcast(&event(progress)).is_empty());
+        let delivered = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            received(&library) > before
+        });
+        assert!(delivered);
+
+        manager.plugins[0].enabled = false;
+        manager.update_subscribers();
+        assert!(subscribers.broadcast(&event(started("Lithium"))).is_empty());
+    }
+
+    #[test]
+    fn events_do_not_wait_for_the_manager() {
+        let library = test_plugin();
+        let mut plugin = ManagedPlugin::in_process(Arc::clone(&library));
+        let mut manager = PluginManager::new();
+        plugin
+            .subscribe(Duration::from_secs(5), &manager.handle)
+            .unwrap();
+        manager.plugins.push(plugin);
+        manager.update_subscribers();
+        let bus = EventBus::start(manager.subscribers.clone());
+        let manager = Mutex::new(manager);
+
+        // a call to a slow plugin may hold the manager, the events still go through
+        let _held = manager.lock().unwrap();
+        let before = received(&library);
+        assert!(bus.publish(started("Lithium")));
+        let delivered = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            received(&library) > before
+        });
+        assert!(delivered);
+    }
+
+    #[test]
+    fn a_stuck_plugin_gets_one_call_at_a_time() {
+        let library = test_plugin();
+        let mut plugin = ManagedPlugin::in_process(Arc::clone(&library));
+        let mut manager = PluginManager::new();
+        plugin
+            .subscribe(Duration::from_millis(100), &manager.handle)
+            .unwrap();
+        manager.plugins.push(plugin);
+        manager.update_subscribers();
+        let subscribers = manager.subscribers.clone();
+        let manager = manager.shared();
+
+        // the first event blocks the plugin until released, its call times out
+        let event = PluginEvent {
+            sent_at: 0,
+            event: started("blocked"),
+        };
+        assert_eq!(subscribers.broadcast(&event), vec!["Test".to_string()]);
+        assert_eq!(wait_blocked(&library), 1);
+        let timed_out = (0..500).any(|_| {
+            thread::sleep(Duration::from_millis(10));
+            manager.lock().unwrap().plugins[0].failures == 1
+        });
+        assert!(timed_out);
+
+        // the next events wait for it in the queue, no second call is started
+        let bus = EventBus::start(subscribers.clone());
+        for _ in 0..PLUGIN_EVENT_QUEUE * 3 {
+            bus.publish(started("blocked"));
+        }
+        for _ in 0..PLUGIN_EVENT_QUEUE + 2 {
+            subscribers.broadcast(&event);
+        }
+        assert!(manager.lock().unwrap().statuses()[0].events_dropped > 0);
+        assert_eq!(wait_blocked(&library), 1);
+        assert_eq!(manager.lock().unwrap().plugins[0].failures, 1);
+
+        // unloading the plugin stops its thread, the queued events are forgotten
+        release_blocked(&library);
+        let mut manager = manager.lock().unwrap();
+        manager.plugins.clear();
+        manager.update_subscribers();
+    }
+}
@@ -8,8 +8,12 @@ use std::os::raw::c_char;
 pub type GreetFunc = unsafe extern "C" fn(*const c_char) -> *mut c_char;
 pub type GetArtistMetadataFunc = unsafe extern "C" fn(*const c_char) -> *mut c_char;
 pub type GetFilmMetadataFunc = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+// JSON in, JSON out: metadata and the functions of the other capabilities,
+// see plugin_api::abi::capabilities::functions
+pub type PluginCallFunc = unsafe extern "C" fn(*const c_char) -> *mut c_char;
 
-pub type NameFunc = unsafe extern "C" fn() -> *mut c_char;
-pub type VersionFunc = unsafe extern "C" fn() -> *mut c_char;
 pub type FreeStringFunc = unsafe extern "C" fn(*mut c_char);
-pub type PluginTypeFunc = unsafe extern "C" fn() -> *mut c_char;
+// optional exports, see plugin_api::ffi
+pub type SettingsSchemaFunc = unsafe extern "C" fn() -> *mut c_char;
+pub type ConfigureFunc = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+// name, version and capabilities: plugin_api::abi::DescriptorFunc
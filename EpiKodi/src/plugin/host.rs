@@ -0,0 +1,501 @@
+/*
+Out-of-process plugins: each plugin runs in a child NeoKodi process, started with PLUGIN_HOST_ENV,
+which loads the library and answers the requests of the parent as JSON lines over stdin/stdout.
+The child keeps stdout for the replies: what the plugin prints goes to stderr, inherited from the
+parent.
+A crash or a call that never ends only takes the child down, it is started again at the next call
+and given the last settings again.
+*/
+
+use super::plugin_manager::{load_plugin, MetadataError};
+use crate::constants::PLUGIN_HOST_ENV;
+use plugin_api::abi::PluginManifest;
+use plugin_api::settings::{PluginSettings, SettingField};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// parent -> child
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum Request {
+    Call {
+        id: u64,
+        function: String, // see LoadedPlugin::call
+        input: String,
+    },
+    Configure {
+        id: u64,
+        settings: PluginSettings,
+    },
+}
+
+// child -> parent, a line that is not a reply is ignored
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Ready {
+        manifest: PluginManifest,
+        schema: Vec<SettingField>,
+    },
+    Refused {
+        error: String,
+    },
+    Answer {
+        id: u64,
+        result: Result<String, MetadataError>,
+    },
+    Configured {
+        id: u64,
+        result: Result<(), String>,
+    },
+}
+
+impl Reply {
+    fn id(&self) -> Option<u64> {
+        match self {
+            Reply::Answer { id, .. } | Reply::Configured { id, .. } => Some(*id),
+            Reply::Ready { .. } | Reply::Refused { .. } => None,
+        }
+    }
+}
+
+// Entry point of the child process: loads the plugin and answers until stdin is closed
+pub fn run_host(path: &Path) -> i32 {
+    // before the library is loaded, its constructors may print already
+    let mut replies = match reply_output() {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("plugin host without stdout: {}", e);
+            return 1;
+        }
+    };
+    let plugin = match load_plugin(path) {
+        Ok(plugin) => plugin,
+        Err(error) => {
+            write_reply(&mut replies, &Reply::Refused { error });
+            return 1;
+        }
+    };
+    write_reply(
+        &mut replies,
+        &Reply::Ready {
+            manifest: plugin.manifest.clone(),
+            schema: plugin.schema.clone(),
+        },
+    );
+
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else { break };
+        let Ok(request) = serde_json::from_str::<Request>(&line) else {
+            continue;
+        };
+        match request {
+            Request::Call {
+                id,
+                function,
+                input,
+            } => {
+                let result = match CString::new(input.as_str()) {
+                    Ok(c_input) => plugin.call(&function, &c_input),
+                    Err(_) => Err(MetadataError::InvalidQuery(input.replace('\0', ""))),
+                };
+                write_reply(&mut replies, &Reply::Answer { id, result });
+            }
+            Request::Configure { id, settings } => {
+                let result = plugin.configure(&settings);
+                write_reply(&mut replies, &Reply::Configured { id, result });
+            }
+        }
+    }
+    0
+}
+
+// A copy of stdout for the replies, then stdout (fd 1) points at stderr: printf and println!
+// in the plugin can no longer write in the middle of a reply
+fn reply_output() -> io::Result<File> {
+    io::stdout().flush()?;
+    let mut replies = duplicate_stdout()?;
+    // ends the line of what was printed before, if any
+    writeln!(replies)?;
+    Ok(replies)
+}
+
+fn duplicate_stdout() -> io::Result<File> {
+    unsafe {
+        let replies = libc::dup(1);
+        if replies < 0 || libc::dup2(2, 1) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        #[cfg(unix)]
+        {
+            use std::os::fd::FromRawFd;
+            Ok(File::from_raw_fd(replies))
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::io::FromRawHandle;
+            // the handle stays owned by the C runtime, the child exits without closing it
+            match libc::get_osfhandle(replies) {
+                -1 => Err(io::Error::last_os_error()),
+                handle => Ok(File::from_raw_handle(handle as _)),
+            }
+        }
+    }
+}
+
+fn write_reply(replies: &mut File, reply: &Reply) {
+    if let Ok(json) = serde_json::to_string(reply) {
+        let _ = writeln!(replies, "{}", json);
+        let _ = replies.flush();
+    }
+}
+
+// How the child is started: this executable with PLUGIN_HOST_ENV set (see main.rs)
+#[derive(Debug, Clone)]
+pub struct HostCommand {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+impl HostCommand {
+    pub fn current_exe() -> io::Result<Self> {
+        Ok(Self {
+            program: std::env::current_exe()?,
+            args: Vec::new(),
+        })
+    }
+}
+
+struct RunningHost {
+    child: Child,
+    stdin: ChildStdin,
+    replies: mpsc::Receiver<Reply>,
+}
+
+impl RunningHost {
+    fn kill(mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// A plugin running in a child process, started again after a crash or a timeout
+pub struct PluginProcess {
+    pub manifest: PluginManifest,
+    pub schema: Vec<SettingField>,
+    path: PathBuf,
+    command: HostCommand,
+    host: Option<RunningHost>,
+    settings: Option<PluginSettings>, // given again to a restarted child
+    next_id: u64,
+}
+
+impl PluginProcess {
+    // Starts the child and waits for the manifest of the plugin
+    pub fn start(path: &Path, command: HostCommand, timeout: Duration) -> Result<Self, String> {
+        let mut process = Self {
+            manifest: PluginManifest::default(),
+            schema: Vec::new(),
+            path: path.to_path_buf(),
+            command,
+            host: None,
+            settings: None,
+            next_id: 1,
+        };
+        process.spawn(timeout)?;
+        Ok(process)
+    }
+
+    fn spawn(&mut self, timeout: Duration) -> Result<(), String> {
+        let mut child = Command::new(&self.command.program)
+            .args(&self.command.args)
+            .env(PLUGIN_HOST_ENV, &self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| format!("cannot start the plugin host: {}", e))?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            let _ = child.kill();
+            return Err("plugin host without stdin/stdout".to_string());
+        };
+
+        let (reply_tx, replies) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if let Ok(reply) = serde_json::from_str::<Reply>(&line) {
+                    if reply_tx.send(reply).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let host = RunningHost {
+            child,
+            stdin,
+            replies,
+        };
+        match host.replies.recv_timeout(timeout) {
+            Ok(Reply::Ready { manifest, schema }) => {
+                self.host = Some(host);
+                self.manifest = manifest;
+                self.schema = schema;
+                Ok(())
+            }
+            Ok(Reply::Refused { error }) => {
+                host.kill();
+                Err(error)
+            }
+            Ok(_) | Err(_) => {
+                host.kill();
+                Err("the plugin host did not start".to_string())
+            }
+        }
+    }
+
+    // Starts the child again after a crash or a timeout, with the last settings
+    fn ensure_started(&mut self, timeout: Duration) -> Result<(), MetadataError> {
+        if self.host.is_some() {
+            return Ok(());
+        }
+        self.spawn(timeout).map_err(MetadataError::Crashed)?;
+        if let Some(settings) = self.settings.clone() {
+            self.send_configure(&settings, timeout)
+                .map_err(MetadataError::Plugin)?;
+        }
+        Ok(())
+    }
+
+    pub fn metadata(&mut self, query: &str, timeout: Duration) -> Result<String, MetadataError> {
+        self.call("metadata", query, timeout)
+    }
+
+    pub fn call(
+        &mut self,
+        function: &str,
+        input: &str,
+        timeout: Duration,
+    ) -> Result<String, MetadataError> {
+        self.ensure_started(timeout)?;
+        let id = self.next_id();
+        let request = Request::Call {
+            id,
+            function: function.to_string(),
+            input: input.to_string(),
+        };
+        match self.request(id, &request, timeout)? {
+            Reply::Answer { result, .. } => result,
+            _ => Err(MetadataError::InvalidResponse(
+                "unexpected reply of the plugin host".to_string(),
+            )),
+        }
+    }
+
+    pub fn configure(
+        &mut self,
+        settings: &PluginSettings,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        self.ensure_started(timeout).map_err(|e| e.to_string())?;
+        self.settings = Some(settings.clone());
+        self.send_configure(settings, timeout)
+    }
+
+    fn send_configure(
+        &mut self,
+        settings: &PluginSettings,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        let id = self.next_id();
+        let request = Request::Configure {
+            id,
+            settings: settings.clone(),
+        };
+        match self.request(id, &request, timeout) {
+            Ok(Reply::Configured { result, .. }) => result,
+            Ok(_) => Err("unexpected reply of the plugin host".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    // Sends a request to the running child and waits for the reply with the same id
+    fn request(
+        &mut self,
+        id: u64,
+        request: &Request,
+        timeout: Duration,
+    ) -> Result<Reply, MetadataError> {
+        let Some(host) = self.host.as_mut() else {
+            return Err(MetadataError::Crashed("not started".to_string()));
+        };
+
+        let sent = serde_json::to_string(request)
+            .map_err(io::Error::other)
+            .and_then(|json| writeln!(host.stdin, "{}", json))
+            .and_then(|_| host.stdin.flush());
+        let deadline = Instant::now() + timeout;
+        let reply = match sent {
+            Ok(()) => loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match host.replies.recv_timeout(remaining) {
+                    Ok(reply) if reply.id() == Some(id) => break Ok(reply),
+                    Ok(_) => continue,
+                    Err(e) => break Err(e),
+                }
+            },
+            Err(_) => Err(RecvTimeoutError::Disconnected),
+        };
+
+        match reply {
+            Ok(reply) => Ok(reply),
+            Err(RecvTimeoutError::Timeout) => {
+                self.stop();
+                Err(MetadataError::Timeout)
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(self.crashed()),
+        }
+    }
+
+    // The child exited: its exit status becomes the error
+    fn crashed(&mut self) -> MetadataError {
+        let status = self.host.take().and_then(|mut host| host.child.wait().ok());
+        MetadataError::Crashed(match status {
+            Some(status) => status.to_string(),
+            None => "unknown exit status".to_string(),
+        })
+    }
+
+    fn stop(&mut self) {
+        if let Some(host) = self.host.take() {
+            host.kill();
+        }
+    }
+}
+
+impl Drop for PluginProcess {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::plugin_manager::test_plugin_path;
+    use plugin_api::settings::SettingValue;
+
+    // Child side: the test binary started again on this test only, with PLUGIN_HOST_ENV set.
+    // Without the variable (normal test run) there is nothing to do.
+    #[test]
+    fn host_process() {
+        if let Some(path) = std::env::var_os(PLUGIN_HOST_ENV) {
+            std::process::exit(run_host(Path::new(&path)));
+        }
+    }
+
+    // The test binary, running host_process only
+    fn host_command() -> HostCommand {
+        HostCommand {
+            program: std::env::current_exe().unwrap(),
+            args: [
+                "--exact",
+                "plugin::host::tests::host_process",
+                "--nocapture",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+
+    fn start_test_plugin() -> PluginProcess {
+        let process =
+            PluginProcess::start(test_plugin_path(), host_command(), Duration::from_secs(10))
+                .unwrap();
+        assert_eq!(process.manifest.name, "Test");
+        process
+    }
+
+    #[test]
+    fn crashes_and_timeouts_only_stop_the_child() {
+        let mut process = start_test_plugin();
+        let timeout = Duration::from_secs(10);
+
+        assert!(process
+            .metadata("Daft Punk", timeout)
+            .unwrap()
+            .contains("Daft Punk"));
+        assert!(matches!(
+            process.metadata("crash", timeout),
+            Err(MetadataError::Crashed(_))
+        ));
+        assert_eq!(
+            process.metadata("null answer", timeout),
+            Err(MetadataError::NullAnswer)
+        );
+        assert_eq!(
+            process.metadata("slow", Duration::from_millis(200)),
+            Err(MetadataError::Timeout)
+        );
+        assert!(process.metadata("Nirvana", timeout).is_ok());
+        // what the plugin prints does not end up in the replies
+        assert!(process.metadata("print", timeout).is_ok());
+        // the other capabilities go through the same requests
+        let lyrics = process.call(
+            "lyrics",
+            r#"{"artist": "Nirvana", "title": "Lithium"}"#,
+            timeout,
+        );
+        assert!(lyrics.unwrap().contains("Nirvana - Lithium"));
+    }
+
+    #[test]
+    fn restarted_children_get_their_settings_back() {
+        let mut process = start_test_plugin();
+        let timeout = Duration::from_secs(10);
+        assert_eq!(process.schema.len(), 2);
+
+        let mut settings = PluginSettings::default();
+        settings.set("token", SettingValue::Text("refused".to_string()));
+        assert_eq!(
+            process.configure(&settings, timeout),
+            Err("token refused".to_string())
+        );
+        settings.set("token", SettingValue::Text("abc".to_string()));
+        settings.set("prefix", SettingValue::Text("x-".to_string()));
+        assert_eq!(process.configure(&settings, timeout), Ok(()));
+        assert!(process
+            .metadata("Muse", timeout)
+            .unwrap()
+            .contains("x-Muse"));
+
+        assert!(process.metadata("crash", timeout).is_err());
+        assert!(process
+            .metadata("Muse", timeout)
+            .unwrap()
+            .contains("x-Muse"));
+    }
+
+    #[test]
+    fn libraries_that_are_not_plugins_are_refused() {
+        let started = PluginProcess::start(
+            Path::new("missing.so"),
+            host_command(),
+            Duration::from_secs(10),
+        );
+        assert!(started.is_err());
+    }
+}
@@ -1,2 +1,7 @@
+pub mod events;
 pub mod functions;
+pub mod host;
 pub mod plugin_manager;
+pub mod registry;
+pub mod settings_store;
+pub mod wasm;
@@ -1,33 +1,584 @@
-use crate::constants::{PLUGIN_DIR, PLUGIN_EXT};
+use super::events::{EventQueue, ManagerHandle, Subscribers};
+use super::host::{HostCommand, PluginProcess};
+use super::registry::PluginRegistry;
+use super::settings_store::SettingsStore;
+use super::wasm::WasmPlugin;
+use crate::constants::{
+    PLUGIN_CALL_TIMEOUT_SECS, PLUGIN_COPY_DIR, PLUGIN_DIR, PLUGIN_EXT, PLUGIN_MAX_FAILURES,
+    WASM_PLUGIN_EXT,
+};
 use libloading::{Library, Symbol};
+use plugin_api::abi::{capabilities, DescriptorFunc, PluginManifest, DESCRIPTOR_SYMBOL};
+use plugin_api::events::kinds;
+use plugin_api::metadata::{
+    ArtistMetadata, Metadata, MetadataImage, MetadataResponse, MetadataResult, MovieMetadata,
+};
+use plugin_api::providers::{
+    Answer, ArtworkQuery, Lyrics, LyricsQuery, ProviderResponse, ProviderResult, ScrobbleEvent,
+    SubtitleDownload, SubtitleFile, SubtitleQuery, SubtitleResult,
+};
+use plugin_api::settings::{PluginSettings, SettingField, SettingValue};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::ffi::{CStr, CString};
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 // Assure-toi que ces types sont bien définis dans functions.rs
-use super::functions::GetArtistMetadataFunc;
-use super::functions::GetFilmMetadataFunc;
-use super::functions::PluginTypeFunc;
+use super::functions::{ConfigureFunc, FreeStringFunc, PluginCallFunc, SettingsSchemaFunc};
 
 use crate::constants::LOG_FILE;
 use crate::logger::logger::Logger;
 
+// Why a lookup gave no metadata, replaces the "artist not found" / "film not found" strings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MetadataError {
+    NoPlugin,                // no plugin of this type is loaded and enabled
+    NotFound,                // every plugin answered not_found
+    InvalidQuery(String),    // the query cannot be passed to a plugin
+    Plugin(String),          // error reported by the plugin
+    InvalidResponse(String), // answer not matching plugin_api::metadata
+    NullAnswer,              // the plugin returned a null pointer
+    NotUtf8,                 // the plugin returned bytes that are not UTF-8
+    Timeout,                 // the plugin did not answer within PluginManager::timeout
+    Crashed(String),         // the process running the plugin exited, with its exit status
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetadataError::NoPlugin => write!(f, "no plugin loaded for this"),
+            MetadataError::NotFound => write!(f, "not found"),
+            MetadataError::InvalidQuery(query) => write!(f, "invalid query '{}'", query),
+            MetadataError::Plugin(message) => write!(f, "plugin error: {}", message),
+            MetadataError::InvalidResponse(e) => write!(f, "invalid plugin answer: {}", e),
+            MetadataError::NullAnswer => write!(f, "the plugin returned no answer"),
+            MetadataError::NotUtf8 => write!(f, "the plugin answer is not UTF-8"),
+            MetadataError::Timeout => write!(f, "the plugin did not answer in time"),
+            MetadataError::Crashed(status) => write!(f, "the plugin host crashed ({})", status),
+        }
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
+// A loaded library, what its descriptor declared and the functions resolved at load time
+pub struct LoadedPlugin {
+    pub manifest: PluginManifest,
+    pub schema: Vec<SettingField>, // empty when the plugin has no settings
+    functions: BTreeMap<&'static str, PluginCallFunc>, // of the declared capabilities
+    configure: Option<ConfigureFunc>,
+    free_string: FreeStringFunc,
+    pub library: Library, // dropped after the functions above, they point into it
+    copy: LibraryCopy,    // deleted once the library is unloaded
+}
+
+impl LoadedPlugin {
+    pub fn metadata(&self, query: &CStr) -> Result<String, MetadataError> {
+        self.call("metadata", query)
+    }
+
+    // Answer of one of the functions of the capabilities, copied before the plugin frees it
+    pub fn call(&self, function: &str, input: &CStr) -> Result<String, MetadataError> {
+        let func = self
+            .functions
+            .get(function)
+            .ok_or_else(|| MetadataError::Plugin(format!("no '{}' function exported", function)))?;
+        let answer = unsafe { PluginString::new(func(input.as_ptr()), self.free_string) }?;
+        answer.to_str().map(str::to_string)
+    }
+
+    // Gives the settings to the plugin, Err with its reason when it refuses them
+    pub fn configure(&self, settings: &PluginSettings) -> Result<(), String> {
+        let Some(func) = self.configure else {
+            return Ok(());
+        };
+        let settings = CString::new(settings.to_json()).map_err(|e| e.to_string())?;
+        let answer = unsafe { func(settings.as_ptr()) };
+        if answer.is_null() {
+            return Ok(());
+        }
+        let reason =
+            unsafe { PluginString::new(answer, self.free_string) }.map_err(|e| e.to_string())?;
+        Err(reason.to_str().map_err(|e| e.to_string())?.to_string())
+    }
+}
+
+// The file a library is loaded from, a copy of the installed one under a name of its own: the
+// system would give back the library already loaded from the same path on a reload, and
+// Windows would keep the installed file locked against its update
+struct LibraryCopy(PathBuf);
+
+impl LibraryCopy {
+    fn new(path: &Path) -> Result<Self, String> {
+        static COPIES: AtomicU64 = AtomicU64::new(0);
+        let dir = std::env::temp_dir().join(PLUGIN_COPY_DIR);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let copy = dir.join(format!(
+            "{}-{}-{}",
+            std::process::id(),
+            COPIES.fetch_add(1, Ordering::Relaxed),
+            name
+        ));
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::copy(path, &copy))
+            .map_err(|e| format!("cannot copy: {}", e))?;
+        Ok(Self(copy))
+    }
+}
+
+impl Drop for LibraryCopy {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// String allocated by a plugin, given back to its free_string when dropped: the host and the
+// plugin may not share an allocator, so the host never frees it itself
+struct PluginString {
+    ptr: *mut c_char,
+    free: FreeStringFunc,
+}
+
+impl PluginString {
+    unsafe fn new(ptr: *mut c_char, free: FreeStringFunc) -> Result<Self, MetadataError> {
+        if ptr.is_null() {
+            return Err(MetadataError::NullAnswer);
+        }
+        Ok(Self { ptr, free })
+    }
+
+    fn to_str(&self) -> Result<&str, MetadataError> {
+        unsafe { CStr::from_ptr(self.ptr) }
+            .to_str()
+            .map_err(|_| MetadataError::NotUtf8)
+    }
+}
+
+impl Drop for PluginString {
+    fn drop(&mut self) {
+        unsafe { (self.free)(self.ptr) }
+    }
+}
+
+// Calls the plugin on another thread: a call that never returns keeps its thread (and the
+// library, through the Arc it captures) but the caller gets Timeout and goes on
+fn call_with_timeout<T: Send + 'static>(
+    timeout: Duration,
+    call: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, MetadataError> {
+    let (answer_tx, answer_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = answer_tx.send(call());
+    });
+    wait_answer(&answer_rx, timeout)
+}
+
+// Like call_with_timeout, but the time only runs once the module is free: the calls queued
+// behind a slow one are not failures of the plugin. Its epoch deadline ends each call in time.
+fn call_wasm<T: Send + 'static>(
+    plugin: &Arc<Mutex<WasmPlugin>>,
+    timeout: Duration,
+    call: impl FnOnce(&mut WasmPlugin) -> T + Send + 'static,
+) -> Result<T, MetadataError> {
+    let plugin = Arc::clone(plugin);
+    let (locked_tx, locked_rx) = mpsc::channel();
+    let (answer_tx, answer_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut plugin = plugin.lock().unwrap();
+        let _ = locked_tx.send(());
+        let _ = answer_tx.send(call(&mut plugin));
+    });
+    if locked_rx.recv().is_err() {
+        return Err(MetadataError::Crashed(
+            "the plugin call panicked".to_string(),
+        ));
+    }
+    wait_answer(&answer_rx, timeout)
+}
+
+pub(crate) fn wait_answer<T>(
+    answer_rx: &mpsc::Receiver<T>,
+    timeout: Duration,
+) -> Result<T, MetadataError> {
+    match answer_rx.recv_timeout(timeout) {
+        Ok(answer) => Ok(answer),
+        Err(RecvTimeoutError::Timeout) => Err(MetadataError::Timeout),
+        Err(RecvTimeoutError::Disconnected) => Err(MetadataError::Crashed(
+            "the plugin call panicked".to_string(),
+        )),
+    }
+}
+
+// Shared with the thread delivering the events of the plugin (see events.rs)
+#[derive(Clone)]
+pub(crate) enum Runner {
+    InProcess(Arc<LoadedPlugin>),
+    OutOfProcess(Arc<Mutex<PluginProcess>>),
+    Wasm(Arc<Mutex<WasmPlugin>>),
+}
+
+impl Runner {
+    pub(crate) fn call(
+        &self,
+        function: &'static str,
+        input: &str,
+        timeout: Duration,
+    ) -> Result<String, MetadataError> {
+        match self {
+            Runner::InProcess(plugin) => {
+                let plugin = Arc::clone(plugin);
+                let input = CString::new(input)
+                    .map_err(|_| MetadataError::InvalidQuery(input.replace('\0', "")))?;
+                call_with_timeout(timeout, move || plugin.call(function, &input))?
+            }
+            Runner::OutOfProcess(process) => process.lock().unwrap().call(function, input, timeout),
+            Runner::Wasm(plugin) => {
+                let input = input.to_string();
+                call_wasm(plugin, timeout, move |plugin| {
+                    plugin.call(function, &input, timeout)
+                })?
+            }
+        }
+    }
+
+    fn configure(&self, settings: &PluginSettings, timeout: Duration) -> Result<(), String> {
+        match self {
+            Runner::InProcess(plugin) => {
+                let plugin = Arc::clone(plugin);
+                let values = settings.clone();
+                call_with_timeout(timeout, move || plugin.configure(&values))
+                    .unwrap_or_else(|e| Err(e.to_string()))
+            }
+            Runner::OutOfProcess(process) => process.lock().unwrap().configure(settings, timeout),
+            Runner::Wasm(plugin) => {
+                let values = settings.clone();
+                call_wasm(plugin, timeout, move |plugin| {
+                    plugin.configure(&values, timeout)
+                })
+                .unwrap_or_else(|e| Err(e.to_string()))
+            }
+        }
+    }
+
+    // Domains a WebAssembly plugin may reach, nothing to do for the others
+    fn approve_domains(&self, domains: &[String], timeout: Duration) -> Result<(), String> {
+        match self {
+            Runner::Wasm(plugin) => {
+                let domains = domains.to_vec();
+                call_wasm(plugin, timeout, move |plugin| plugin.approve(&domains))
+                    .unwrap_or_else(|e| Err(e.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Calls the plugin on this thread, for as long as it takes. `started` runs once the call
+    // starts, a plugin may be busy with another call until then.
+    pub(crate) fn call_here(
+        &self,
+        function: &'static str,
+        input: &str,
+        timeout: Duration,
+        started: impl FnOnce(),
+    ) -> Result<String, MetadataError> {
+        match self {
+            Runner::InProcess(plugin) => {
+                let input = CString::new(input)
+                    .map_err(|_| MetadataError::InvalidQuery(input.replace('\0', "")))?;
+                started();
+                plugin.call(function, &input)
+            }
+            Runner::OutOfProcess(process) => {
+                let mut process = process.lock().unwrap();
+                started();
+                process.call(function, input, timeout)
+            }
+            Runner::Wasm(plugin) => {
+                let mut plugin = plugin.lock().unwrap();
+                started();
+                plugin.call(function, input, timeout)
+            }
+        }
+    }
+
+    // Same plugin, not only the same library: a reloaded plugin is another one
+    fn is(&self, other: &Runner) -> bool {
+        match (self, other) {
+            (Runner::InProcess(a), Runner::InProcess(b)) => Arc::ptr_eq(a, b),
+            (Runner::OutOfProcess(a), Runner::OutOfProcess(b)) => Arc::ptr_eq(a, b),
+            (Runner::Wasm(a), Runner::Wasm(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+// A plugin as the manager uses it, in this process, in a child process (see host.rs)
+// or in a WebAssembly sandbox (see wasm.rs)
+pub struct ManagedPlugin {
+    pub manifest: PluginManifest,
+    pub schema: Vec<SettingField>,
+    runner: Runner,
+    settings: PluginSettings, // last values given to the plugin, secrets included
+    pub settings_error: Option<String>, // why the plugin refused them
+    pub enabled: bool,        // choice of the user, see registry.rs
+    pub failures: u32,        // failed calls in a row
+    pub suspended: bool,      // after PLUGIN_MAX_FAILURES failures, until enabled again or reloaded
+    pub subscriptions: Vec<String>, // kinds of host events, see subscribe
+    events: Option<Arc<EventQueue>>, // shared with the subscribers of the manager
+    pub domains: Vec<String>, // declared by a WebAssembly plugin, see wasm.rs
+    pub approved_domains: Vec<String>, // of them, what the user let it reach
+}
+
+impl ManagedPlugin {
+    pub fn in_process(plugin: Arc<LoadedPlugin>) -> Self {
+        Self {
+            manifest: plugin.manifest.clone(),
+            schema: plugin.schema.clone(),
+            runner: Runner::InProcess(plugin),
+            settings: PluginSettings::default(),
+            settings_error: None,
+            enabled: true,
+            failures: 0,
+            suspended: false,
+            subscriptions: Vec::new(),
+            events: None,
+            domains: Vec::new(),
+            approved_domains: Vec::new(),
+        }
+    }
+
+    pub fn out_of_process(process: PluginProcess) -> Self {
+        Self {
+            manifest: process.manifest.clone(),
+            schema: process.schema.clone(),
+            runner: Runner::OutOfProcess(Arc::new(Mutex::new(process))),
+            settings: PluginSettings::default(),
+            settings_error: None,
+            enabled: true,
+            failures: 0,
+            suspended: false,
+            subscriptions: Vec::new(),
+            events: None,
+            domains: Vec::new(),
+            approved_domains: Vec::new(),
+        }
+    }
+
+    pub fn wasm(plugin: WasmPlugin) -> Self {
+        Self {
+            manifest: plugin.manifest.clone(),
+            schema: plugin.schema.clone(),
+            domains: plugin.domains.clone(),
+            approved_domains: plugin.approved.clone(),
+            runner: Runner::Wasm(Arc::new(Mutex::new(plugin))),
+            settings: PluginSettings::default(),
+            settings_error: None,
+            enabled: true,
+            failures: 0,
+            suspended: false,
+            subscriptions: Vec::new(),
+            events: None,
+        }
+    }
+
+    fn call(
+        &mut self,
+        function: &'static str,
+        input: &str,
+        timeout: Duration,
+    ) -> Result<String, MetadataError> {
+        self.runner.call(function, input, timeout)
+    }
+
+    // Asks a plugin of the events capability which events it wants, and starts the thread
+    // giving them to it, which records its calls with `manager`. Nothing to do for the other plugins.
+    pub fn subscribe(
+        &mut self,
+        timeout: Duration,
+        manager: &ManagerHandle,
+    ) -> Result<(), MetadataError> {
+        if !self.manifest.has_capability(capabilities::EVENTS) {
+            return Ok(());
+        }
+        let answer = self.call("subscriptions", "{}", timeout);
+        self.subscriptions = answer.and_then(|text| parse_answer(&text))?;
+        self.events = Some(Arc::new(EventQueue::start(
+            self.manifest.name.clone(),
+            self.runner.clone(),
+            timeout,
+            manager.clone(),
+        )));
+        Ok(())
+    }
+
+    fn is_usable(&self) -> bool {
+        self.enabled && !self.suspended
+    }
+
+    fn configure(&mut self, settings: PluginSettings, timeout: Duration) -> Result<(), String> {
+        let result = self.runner.configure(&settings, timeout);
+        self.configured(settings, &result);
+        result
+    }
+
+    // The approved domains among the declared ones
+    fn approve_domains(&mut self, approved: &[String], timeout: Duration) -> Result<(), String> {
+        let approved: Vec<String> = self
+            .domains
+            .iter()
+            .filter(|domain| approved.contains(domain))
+            .cloned()
+            .collect();
+        self.runner.approve_domains(&approved, timeout)?;
+        self.approved_domains = approved;
+        Ok(())
+    }
+
+    fn configured(&mut self, settings: PluginSettings, result: &Result<(), String>) {
+        self.settings = settings;
+        self.settings_error = result.clone().err();
+    }
+
+    // What the GUI may see of the settings: the secrets are replaced by whether they are set
+    fn status(&self) -> PluginStatus {
+        let mut values = self.settings.clone();
+        let mut secrets_set = Vec::new();
+        for field in self.schema.iter().filter(|field| field.is_secret()) {
+            if values.text(&field.key).is_some() {
+                secrets_set.push(field.key.clone());
+            }
+            values.values.remove(&field.key);
+        }
+        PluginStatus {
+            manifest: self.manifest.clone(),
+            out_of_process: matches!(self.runner, Runner::OutOfProcess(_)),
+            wasm: matches!(self.runner, Runner::Wasm(_)),
+            enabled: self.enabled,
+            suspended: self.suspended,
+            priority: BTreeMap::new(),
+            subscriptions: self.subscriptions.clone(),
+            events_dropped: self.events.as_ref().map_or(0, |events| events.dropped()),
+            domains: self.domains.clone(),
+            approved_domains: self.approved_domains.clone(),
+            schema: self.schema.clone(),
+            values,
+            secrets_set,
+            settings_error: self.settings_error.clone(),
+        }
+    }
+
+    // An answer, even not_found, resets the count of failures
+    fn record<T>(&mut self, result: &Result<T, MetadataError>) {
+        match result {
+            Ok(_) | Err(MetadataError::NotFound) => self.failures = 0,
+            Err(_) => {
+                self.failures += 1;
+                if self.failures >= PLUGIN_MAX_FAILURES {
+                    self.suspended = true;
+                    Logger::new(LOG_FILE).warning(&format!(
+                        "Plugin {} suspended after {} failed calls",
+                        self.manifest.name, self.failures
+                    ));
+                }
+            }
+        }
+    }
+}
+
+// What the GUI shows of a plugin
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginStatus {
+    pub manifest: PluginManifest,
+    pub out_of_process: bool,
+    pub wasm: bool, // a WebAssembly module, sandboxed
+    pub enabled: bool,
+    pub suspended: bool,
+    pub priority: BTreeMap<String, usize>, // capability -> position, 0 is asked first
+    pub subscriptions: Vec<String>,        // kinds of host events it gets
+    pub events_dropped: u64,               // events lost because it was too slow to take them
+    pub domains: Vec<String>,              // a WebAssembly plugin may reach once approved
+    pub approved_domains: Vec<String>,
+    pub schema: Vec<SettingField>,
+    pub values: PluginSettings,   // without the secrets
+    pub secrets_set: Vec<String>, // keys of the secrets that have a value
+    pub settings_error: Option<String>,
+}
+
+// Metadata and the plugin that found it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginMetadata<T> {
+    pub plugin: String,
+    pub metadata: T,
+}
+
 pub struct PluginManager {
-    pub metadata_libs: Vec<Library>,
-    pub film_metadata_libs: Vec<Library>,
+    pub plugins: Vec<ManagedPlugin>, // in loading order
+    pub dir: PathBuf,
+    pub timeout: Duration,    // of one call to one plugin
+    pub out_of_process: bool, // for the plugins loaded by load_plugins
+    pub settings: SettingsStore,
+    pub registry: PluginRegistry,
+    pub subscribers: Subscribers, // what the EventBus reads, see update_subscribers
+    pub handle: ManagerHandle,    // what the event threads record their calls with, see shared
 }
 
 impl PluginManager {
     pub fn new() -> Self {
         PluginManager {
-            metadata_libs: Vec::new(),
-            film_metadata_libs: Vec::new(),
+            plugins: Vec::new(),
+            dir: PathBuf::from(PLUGIN_DIR),
+            timeout: Duration::from_secs(PLUGIN_CALL_TIMEOUT_SECS),
+            out_of_process: false,
+            settings: SettingsStore::user_config(),
+            registry: PluginRegistry::user_config(),
+            subscribers: Subscribers::default(),
+            handle: ManagerHandle::default(),
         }
     }
 
+    // The manager as the threads share it, the event threads of its plugins then record their
+    // calls with it like the lookups do
+    pub fn shared(self) -> Arc<Mutex<PluginManager>> {
+        let handle = self.handle.clone();
+        let manager = Arc::new(Mutex::new(self));
+        handle.set(&manager);
+        manager
+    }
+
     pub fn load_plugins(&mut self) {
+        let plugins = self.start_plugins();
+        self.plugins.extend(plugins);
+        self.update_subscribers();
+    }
+
+    // To call whenever a plugin is added, removed, enabled, disabled or suspended, the
+    // events then go to the usable plugins only
+    pub fn update_subscribers(&self) {
+        let subscribers = self
+            .plugins
+            .iter()
+            .filter(|plugin| plugin.is_usable())
+            .filter_map(|plugin| {
+                let events = plugin.events.as_ref()?;
+                Some((plugin.subscriptions.clone(), Arc::clone(events)))
+            })
+            .collect();
+        self.subscribers.set(subscribers);
+    }
+
+    // Loads, configures and subscribes the plugins of the folder, without touching self.plugins
+    fn start_plugins(&self) -> Vec<ManagedPlugin> {
         // 1. On initialise le logger ICI pour pouvoir l'utiliser
         let logger = Logger::new(LOG_FILE);
+        let mut plugins = Vec::new();
 
         println!("🔌 [PLUGIN] Démarrage du chargement des plugins...");
 
@@ -35,156 +586,1038 @@ impl PluginManager {
             println!("📂 [PLUGIN] Dossier de travail actuel : {:?}", cwd);
         }
 
-        let plugin_path = PathBuf::from(PLUGIN_DIR);
-        if !plugin_path.exists() {
-            println!("❌ [PLUGIN] Le dossier '{}' n'existe pas !", PLUGIN_DIR);
-            return;
+        if !self.dir.exists() {
+            println!("❌ [PLUGIN] Le dossier {:?} n'existe pas !", self.dir);
+            return plugins;
         }
 
-        if let Ok(paths) = fs::read_dir(plugin_path) {
-            for entry in paths.flatten() {
-                let path = entry.path();
-
+        if let Ok(entries) = fs::read_dir(&self.dir) {
+            // par nom de fichier, l'ordre du dossier change d'un système à l'autre
+            let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+            paths.sort();
+            for path in paths {
                 let is_plugin = path
                     .extension()
                     .and_then(|ext| ext.to_str())
-                    .map(|ext| PLUGIN_EXT.contains(&ext.to_ascii_lowercase().as_str()))
+                    .map(|ext| {
+                        let ext = ext.to_ascii_lowercase();
+                        PLUGIN_EXT.contains(&ext.as_str()) || ext == WASM_PLUGIN_EXT
+                    })
                     .unwrap_or(false);
 
                 if is_plugin {
                     println!("🔎 [PLUGIN] Tentative de chargement : {:?}", path);
 
-                    // CHARGEMENT DLL
-                    let lib = match unsafe { Library::new(&path) } {
-                        Ok(l) => {
-                            println!("✅ [PLUGIN] DLL chargée en mémoire !");
-                            l
-                        }
-                        Err(e) => {
-                            logger.error(&format!("✗ ÉCHEC chargement DLL : {}", e));
-                            continue;
-                        }
-                    };
-
-                    // VÉRIFICATION DU TYPE
-                    unsafe {
-                        let get_plugin_type: Result<Symbol<PluginTypeFunc>, _> =
-                            lib.get(b"plugin_type\0");
-
-                        match get_plugin_type {
-                            Ok(func) => {
-                                let type_ptr = func();
-                                let result = CStr::from_ptr(type_ptr).to_str().unwrap_or("Error");
-
-                                println!("ℹ️ [PLUGIN] Type détecté : '{}'", result);
-
-                                match result {
-                                    "metadata" => {
-                                        self.metadata_libs.push(lib);
-                                        logger.info("Loaded metadata plugin.");
-                                    }
-                                    "film_metadata" => {
-                                        self.film_metadata_libs.push(lib);
-                                        logger.info("Loaded film metadata plugin.");
-                                    }
-                                    _ => {
-                                        logger.error(&format!("✗ Unknown plugin type: {}", result));
-                                    }
+                    match self.start_plugin(&path) {
+                        Ok(mut plugin) => {
+                            let stored = self.settings.load(&plugin.manifest.name);
+                            if let Err(e) =
+                                plugin.configure(stored.resolve(&plugin.schema), self.timeout)
+                            {
+                                logger.warning(&format!(
+                                    "Plugin {} refused its settings: {}",
+                                    plugin.manifest.name, e
+                                ));
+                            }
+                            let manifest = &plugin.manifest;
+                            logger.info(&format!(
+                                "Loaded plugin {} {} ({})",
+                                manifest.name,
+                                manifest.version,
+                                manifest.capabilities.join(", ")
+                            ));
+                            for capability in &manifest.capabilities {
+                                if !capabilities::ALL.contains(&capability.as_str()) {
+                                    logger.warning(&format!(
+                                        "Plugin {}: unknown capability '{}' ignored",
+                                        manifest.name, capability
+                                    ));
+                                }
+                            }
+                            if let Err(e) = plugin.subscribe(self.timeout, &self.handle) {
+                                logger.warning(&format!(
+                                    "Plugin {} gets no events: {}",
+                                    plugin.manifest.name, e
+                                ));
+                            }
+                            for kind in &plugin.subscriptions {
+                                if !kinds::ALL.contains(&kind.as_str()) {
+                                    logger.warning(&format!(
+                                        "Plugin {}: unknown event '{}' ignored",
+                                        plugin.manifest.name, kind
+                                    ));
                                 }
                             }
-                            Err(e) => {
-                                logger.error(&format!(
-                                    "✗ Failed to load 'plugin_type' function: {} ",
-                                    e
+                            plugin.enabled = self.registry.is_enabled(&plugin.manifest.name);
+                            let approved = self.registry.approved_domains(&plugin.manifest.name);
+                            if let Err(e) = plugin.approve_domains(approved, self.timeout) {
+                                logger.warning(&format!(
+                                    "Plugin {} cannot reach its domains: {}",
+                                    plugin.manifest.name, e
                                 ));
-                                continue;
                             }
-                        };
+                            plugins.push(plugin);
+                        }
+                        Err(e) => {
+                            logger.error(&format!("✗ Plugin {:?} refused: {}", path, e));
+                        }
                     }
                 }
             }
         }
+        plugins
     }
 
-    // Changement ici : on renvoie String, pas &str
-    pub fn get_metadata(&mut self, artist: &str) -> String {
-        let logger = Logger::new(LOG_FILE);
-        let c_artist = CString::new(artist).unwrap();
-
-        for lib in &self.metadata_libs {
-            unsafe {
-                let get_metadata_func: Result<Symbol<GetArtistMetadataFunc>, _> =
-                    lib.get(b"metadata\0");
-
-                match get_metadata_func {
-                    Ok(func) => {
-                        // On appelle la fonction de la DLL
-                        let metadata_ptr = func(c_artist.as_ptr());
-                        let result = CStr::from_ptr(metadata_ptr)
-                            .to_str()
-                            .unwrap_or("Error UTF8");
-
-                        if result == "artist not found" {
-                            continue; // On essaye le plugin suivant
-                        }
+    // In this process, or in a child process running this executable when out_of_process is set.
+    // A WebAssembly module always runs here, its sandbox already keeps it from the host.
+    fn start_plugin(&self, path: &Path) -> Result<ManagedPlugin, String> {
+        let is_wasm = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(WASM_PLUGIN_EXT));
+        if is_wasm {
+            // off the calling thread like every call, it may be inside the async runtime of the GUI
+            let path = path.to_path_buf();
+            let timeout = self.timeout;
+            return call_with_timeout(timeout, move || WasmPlugin::load(&path, timeout))
+                .map_err(|e| e.to_string())?
+                .map(ManagedPlugin::wasm);
+        }
+        if !self.out_of_process {
+            return load_plugin(path).map(|plugin| ManagedPlugin::in_process(Arc::new(plugin)));
+        }
+        let command = HostCommand::current_exe().map_err(|e| e.to_string())?;
+        let process = PluginProcess::start(path, command, self.timeout)?;
+        Ok(ManagedPlugin::out_of_process(process))
+    }
 
-                        // On a trouvé ! On renvoie une String propre
-                        return result.to_string();
-                    }
-                    Err(e) => {
-                        logger.error(&format!("✗ Failed to load 'metadata' function: {}", e));
-                        continue;
-                    }
-                };
+    // A manager without plugins and with the same folder and settings, to load them off the lock
+    fn loader(&self) -> PluginManager {
+        PluginManager {
+            plugins: Vec::new(),
+            dir: self.dir.clone(),
+            timeout: self.timeout,
+            out_of_process: self.out_of_process,
+            settings: self.settings.clone(),
+            registry: self.registry.clone(),
+            subscribers: Subscribers::default(),
+            handle: self.handle.clone(),
+        }
+    }
+
+    // The loaded plugins, in loading order
+    pub fn statuses(&self) -> Vec<PluginStatus> {
+        let mut statuses: Vec<PluginStatus> =
+            self.plugins.iter().map(ManagedPlugin::status).collect();
+        for capability in capabilities::ALL {
+            for (position, index) in self.ordered(capability).into_iter().enumerate() {
+                statuses[index]
+                    .priority
+                    .insert(capability.to_string(), position);
             }
         }
-        return "artist not found".to_string();
+        statuses
     }
 
-    // Changement ici aussi : on renvoie String
-    // Dans src/plugin/plugin_manager.rs
+    // Turning a plugin on again also lifts its suspension after failures
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        let plugin = self
+            .plugins
+            .iter_mut()
+            .find(|plugin| plugin.manifest.name == name)
+            .ok_or_else(|| format!("unknown plugin '{}'", name))?;
+        plugin.enabled = enabled;
+        if enabled {
+            plugin.suspended = false;
+            plugin.failures = 0;
+        }
+        self.update_subscribers();
+        self.registry.set_enabled(name, enabled);
+        self.registry
+            .save()
+            .map_err(|e| format!("cannot save the plugin registry: {}", e))
+    }
 
-    pub fn get_film_metadata(&mut self, film: &str) -> String {
-        let logger = Logger::new(LOG_FILE);
-        let c_film = CString::new(film).unwrap();
+    // Order in which the plugins are asked for the capability, the plugins left out come after
+    pub fn set_priority(&mut self, capability: &str, plugins: Vec<String>) -> Result<(), String> {
+        if !capabilities::ALL.contains(&capability) {
+            return Err(format!("unknown capability '{}'", capability));
+        }
+        self.registry.set_priority(capability, plugins);
+        self.registry
+            .save()
+            .map_err(|e| format!("cannot save the plugin registry: {}", e))
+    }
 
-        // 👇 DEBUG : Combien de plugins de films sont chargés ?
-        println!(
-            "🎞️ [MANAGER] Nombre de plugins 'film' chargés : {}",
-            self.film_metadata_libs.len()
-        );
+    // Lets a WebAssembly plugin reach all the domains it declares, or none of them
+    pub fn approve_domains(&mut self, name: &str, approved: bool) -> Result<(), String> {
+        let plugin = self
+            .plugins
+            .iter_mut()
+            .find(|plugin| plugin.manifest.name == name)
+            .ok_or_else(|| format!("unknown plugin '{}'", name))?;
+        let domains = if approved {
+            plugin.domains.clone()
+        } else {
+            Vec::new()
+        };
+        plugin.approve_domains(&domains, self.timeout)?;
+        self.registry.set_approved_domains(name, domains);
+        self.registry
+            .save()
+            .map_err(|e| format!("cannot save the plugin registry: {}", e))
+    }
+
+    // Indexes of the plugins with the capability, by priority then loading order
+    fn ordered(&self, capability: &str) -> Vec<usize> {
+        let mut indexes: Vec<usize> = (0..self.plugins.len())
+            .filter(|&index| self.plugins[index].manifest.has_capability(capability))
+            .collect();
+        indexes.sort_by_key(|&index| {
+            self.registry
+                .rank(capability, &self.plugins[index].manifest.name)
+        });
+        indexes
+    }
+
+    // Checks and stores the values of the settings form, returns what to give to the plugin.
+    // An empty secret keeps the stored one, the form never gets the secrets back.
+    fn store_settings(
+        &mut self,
+        name: &str,
+        form: PluginSettings,
+    ) -> Result<(Runner, PluginSettings), String> {
+        let plugin = self
+            .plugins
+            .iter_mut()
+            .find(|plugin| plugin.manifest.name == name)
+            .ok_or_else(|| format!("unknown plugin '{}'", name))?;
 
-        for lib in &self.film_metadata_libs {
-            unsafe {
-                let get_film_metadata: Result<Symbol<GetFilmMetadataFunc>, _> =
-                    lib.get(b"metadata\0");
+        let mut stored = self.settings.load(name);
+        let checked = form.values.into_iter().try_for_each(|(key, value)| {
+            let field = plugin
+                .schema
+                .iter()
+                .find(|field| field.key == key)
+                .ok_or_else(|| format!("unknown setting '{}'", key))?;
+            if field.is_secret() && value == SettingValue::Text(String::new()) {
+                return Ok(());
+            }
+            field.check(&value)?;
+            stored.values.insert(key, value);
+            Ok(())
+        });
+        let saved = checked.and_then(|()| {
+            self.settings
+                .save(name, &plugin.schema, &stored)
+                .map_err(|e| format!("cannot save the settings: {}", e))
+        });
+        if let Err(e) = saved {
+            plugin.settings_error = Some(e.clone());
+            return Err(e);
+        }
+        Ok((plugin.runner.clone(), stored.resolve(&plugin.schema)))
+    }
 
-                match get_film_metadata {
-                    Ok(func) => {
-                        println!("🚀 [MANAGER] Appel de la DLL TMDB pour '{}'...", film);
-                        let metadata_ptr = func(c_film.as_ptr());
-                        let result = CStr::from_ptr(metadata_ptr)
-                            .to_str()
-                            .unwrap_or("Error UTF8");
+    // The usable plugins with the capability by priority, or the one named, with their runner
+    // to call them once the lock is released
+    fn pick(&self, capability: &str, name: Option<&str>) -> Vec<(String, Runner)> {
+        self.ordered(capability)
+            .into_iter()
+            .map(|index| &self.plugins[index])
+            .filter(|plugin| plugin.is_usable())
+            .filter(|plugin| name.is_none_or(|name| plugin.manifest.name == name))
+            .map(|plugin| (plugin.manifest.name.clone(), plugin.runner.clone()))
+            .collect()
+    }
 
-                        println!("📥 [MANAGER] Réponse de la DLL : {}", result);
+    // Nothing to record for a plugin unloaded by a reload meanwhile
+    fn plugin_mut(&mut self, runner: &Runner) -> Option<&mut ManagedPlugin> {
+        self.plugins
+            .iter_mut()
+            .find(|plugin| plugin.runner.is(runner))
+    }
 
-                        if result == "film not found" {
-                            continue;
-                        }
-                        return result.to_string();
-                    }
-                    Err(e) => {
-                        logger.error(&format!(
-                            "✗ Failed to load 'metadata' (film) function: {}",
-                            e
-                        ));
-                        continue;
-                    }
+    // How a plugin answered a call, a suspended plugin no longer gets the events.
+    // Nothing to do when the plugin was reloaded or removed meanwhile.
+    pub(crate) fn record<T>(&mut self, runner: &Runner, result: &Result<T, MetadataError>) {
+        let Some(plugin) = self.plugin_mut(runner) else {
+            return;
+        };
+        let suspended = plugin.suspended;
+        plugin.record(result);
+        if plugin.suspended != suspended {
+            self.update_subscribers();
+        }
+    }
+}
+
+// The calls to the plugins, on the manager shared by the threads. Its lock is only held to pick
+// the plugins and to record how they answered, never during a call: a slow plugin does not hold
+// the other lookups, the Plugins page or a reload.
+pub trait PluginCalls {
+    fn get_metadata(&self, artist: &str) -> Result<PluginMetadata<ArtistMetadata>, MetadataError>;
+    fn get_film_metadata(&self, film: &str)
+        -> Result<PluginMetadata<MovieMetadata>, MetadataError>;
+    fn get_lyrics(&self, query: &LyricsQuery) -> Result<PluginMetadata<Lyrics>, MetadataError>;
+    fn search_subtitles(
+        &self,
+        query: &SubtitleQuery,
+    ) -> Result<Vec<PluginMetadata<SubtitleResult>>, MetadataError>;
+    fn download_subtitle(&self, plugin: &str, id: &str) -> Result<SubtitleFile, MetadataError>;
+    fn get_artwork(
+        &self,
+        query: &ArtworkQuery,
+    ) -> Result<PluginMetadata<Vec<MetadataImage>>, MetadataError>;
+    fn scrobble(&self, event: &ScrobbleEvent) -> Result<Vec<String>, MetadataError>;
+    fn save_settings(&self, name: &str, form: PluginSettings) -> Result<(), String>;
+    fn reload_plugins(&self);
+}
+
+impl PluginCalls for Mutex<PluginManager> {
+    fn get_metadata(&self, artist: &str) -> Result<PluginMetadata<ArtistMetadata>, MetadataError> {
+        let found = query_plugins(self, capabilities::ARTIST_METADATA, artist)?;
+        match found.metadata {
+            Metadata::Artist(metadata) => Ok(PluginMetadata {
+                plugin: found.plugin,
+                metadata,
+            }),
+            Metadata::Movie(_) => Err(MetadataError::InvalidResponse(
+                "expected an artist, got a movie".to_string(),
+            )),
+        }
+    }
+
+    fn get_film_metadata(
+        &self,
+        film: &str,
+    ) -> Result<PluginMetadata<MovieMetadata>, MetadataError> {
+        let found = query_plugins(self, capabilities::MOVIE_METADATA, film)?;
+        match found.metadata {
+            Metadata::Movie(metadata) => Ok(PluginMetadata {
+                plugin: found.plugin,
+                metadata,
+            }),
+            Metadata::Artist(_) => Err(MetadataError::InvalidResponse(
+                "expected a movie, got an artist".to_string(),
+            )),
+        }
+    }
+
+    fn get_lyrics(&self, query: &LyricsQuery) -> Result<PluginMetadata<Lyrics>, MetadataError> {
+        let input = to_request(query)?;
+        ask_first(self, capabilities::LYRICS, "lyrics", &input, parse_answer)
+    }
+
+    // The results of every subtitle plugin, each one downloaded from the plugin that listed it
+    fn search_subtitles(
+        &self,
+        query: &SubtitleQuery,
+    ) -> Result<Vec<PluginMetadata<SubtitleResult>>, MetadataError> {
+        let input = to_request(query)?;
+        let plugins = self.lock().unwrap().pick(capabilities::SUBTITLES, None);
+        let found = ask(
+            self,
+            plugins,
+            "search_subtitles",
+            &input,
+            parse_answer::<Vec<SubtitleResult>>,
+            false,
+        )?;
+        Ok(found
+            .into_iter()
+            .flat_map(|found| {
+                found
+                    .metadata
+                    .into_iter()
+                    .map(move |result| PluginMetadata {
+                        plugin: found.plugin.clone(),
+                        metadata: result,
+                    })
+            })
+            .collect())
+    }
+
+    fn download_subtitle(&self, plugin: &str, id: &str) -> Result<SubtitleFile, MetadataError> {
+        let input = to_request(&SubtitleDownload { id: id.to_string() })?;
+        let plugins = self
+            .lock()
+            .unwrap()
+            .pick(capabilities::SUBTITLES, Some(plugin));
+        let mut found = ask(
+            self,
+            plugins,
+            "download_subtitle",
+            &input,
+            parse_answer,
+            true,
+        )?;
+        Ok(found.remove(0).metadata)
+    }
+
+    // Images for a movie, a show, an artist or an album, from the first plugin that has some
+    fn get_artwork(
+        &self,
+        query: &ArtworkQuery,
+    ) -> Result<PluginMetadata<Vec<MetadataImage>>, MetadataError> {
+        let input = to_request(query)?;
+        ask_first(self, capabilities::ARTWORK, "artwork", &input, parse_answer)
+    }
+
+    // Sent to every scrobbler, returns the ones that took it
+    fn scrobble(&self, event: &ScrobbleEvent) -> Result<Vec<String>, MetadataError> {
+        let input = to_request(event)?;
+        let plugins = self.lock().unwrap().pick(capabilities::SCROBBLER, None);
+        let taken = ask(self, plugins, "scrobble", &input, parse_answer::<()>, false)?;
+        Ok(taken.into_iter().map(|taken| taken.plugin).collect())
+    }
+
+    // Stores the values of the settings form (see store_settings), then gives them to the plugin
+    fn save_settings(&self, name: &str, form: PluginSettings) -> Result<(), String> {
+        let (runner, settings, timeout) = {
+            let mut manager = self.lock().unwrap();
+            let (runner, settings) = manager.store_settings(name, form)?;
+            (runner, settings, manager.timeout)
+        };
+        let result = runner.configure(&settings, timeout);
+        if let Some(plugin) = self.lock().unwrap().plugin_mut(&runner) {
+            plugin.configured(settings, &result);
+        }
+        result
+    }
+
+    // Loads the folder again and replaces every plugin: new, removed and updated libraries are
+    // picked up without restarting. The lookups go on with the old plugins while the new ones
+    // load, the calls still running hold an Arc on their library until they are over.
+    fn reload_plugins(&self) {
+        let loader = self.lock().unwrap().loader();
+        let plugins = loader.start_plugins();
+        let mut manager = self.lock().unwrap();
+        manager.plugins = plugins;
+        manager.update_subscribers();
+    }
+}
+
+fn query_plugins(
+    manager: &Mutex<PluginManager>,
+    capability: &str,
+    query: &str,
+) -> Result<PluginMetadata<Metadata>, MetadataError> {
+    ask_first(manager, capability, "metadata", query, parse_response)
+}
+
+fn ask_first<T>(
+    manager: &Mutex<PluginManager>,
+    capability: &str,
+    function: &'static str,
+    input: &str,
+    parse: impl Fn(&str) -> Result<T, MetadataError>,
+) -> Result<PluginMetadata<T>, MetadataError> {
+    let plugins = manager.lock().unwrap().pick(capability, None);
+    let mut found = ask(manager, plugins, function, input, parse, true)?;
+    Ok(found.remove(0))
+}
+
+// Asks the picked plugins in turn: until one finds the query when `first_only` is set,
+// otherwise all of them. Never returns an empty list.
+// When none finds it, the last error is returned rather than NotFound, so a broken plugin is visible.
+fn ask<T>(
+    manager: &Mutex<PluginManager>,
+    plugins: Vec<(String, Runner)>,
+    function: &'static str,
+    input: &str,
+    parse: impl Fn(&str) -> Result<T, MetadataError>,
+    first_only: bool,
+) -> Result<Vec<PluginMetadata<T>>, MetadataError> {
+    let logger = Logger::new(LOG_FILE);
+    if input.contains('\0') {
+        return Err(MetadataError::InvalidQuery(input.replace('\0', "")));
+    }
+    if plugins.is_empty() {
+        return Err(MetadataError::NoPlugin);
+    }
+
+    let timeout = manager.lock().unwrap().timeout;
+    let mut found = Vec::new();
+    let mut last_error = MetadataError::NotFound;
+    for (name, runner) in plugins {
+        let result = runner
+            .call(function, input, timeout)
+            .and_then(|text| parse(&text));
+        manager.lock().unwrap().record(&runner, &result);
+        match result {
+            Ok(metadata) => {
+                found.push(PluginMetadata {
+                    plugin: name,
+                    metadata,
+                });
+                if first_only {
+                    break;
                 }
             }
+            Err(MetadataError::NotFound) => continue, // on essaye le plugin suivant
+            Err(e) => {
+                logger.error(&format!(
+                    "✗ Plugin {} {} for '{}': {}",
+                    name, function, input, e
+                ));
+                last_error = e;
+            }
+        }
+    }
+    match found.is_empty() {
+        false => Ok(found),
+        true => Err(last_error),
+    }
+}
+
+// The requests are plain data, serializing them only fails on a broken Serialize impl
+pub(crate) fn to_request(request: &impl Serialize) -> Result<String, MetadataError> {
+    serde_json::to_string(request).map_err(|e| MetadataError::InvalidQuery(e.to_string()))
+}
+
+// Loads a library and checks its descriptor before anything else is called in it.
+// A library without descriptor was built before the versioned ABI and is refused as well.
+pub(crate) fn load_plugin(path: &Path) -> Result<LoadedPlugin, String> {
+    let copy = LibraryCopy::new(path)?;
+    let library = unsafe { Library::new(&copy.0) }.map_err(|e| format!("cannot load: {}", e))?;
+    println!("✅ [PLUGIN] DLL chargée en mémoire !");
+
+    let manifest = unsafe {
+        let descriptor: Symbol<DescriptorFunc> = library.get(DESCRIPTOR_SYMBOL).map_err(|_| {
+            "no plugin descriptor, built against an older plugin_api: rebuild it".to_string()
+        })?;
+        PluginManifest::from_descriptor(descriptor()).map_err(|e| e.to_string())?
+    };
+    println!(
+        "ℹ️ [PLUGIN] {} {} : {:?}",
+        manifest.name, manifest.version, manifest.capabilities
+    );
+
+    // the symbols stay valid as long as `library` is loaded, LoadedPlugin keeps both together
+    let free_string = unsafe { library.get::<FreeStringFunc>(b"free_string\0") }
+        .map(|func| *func)
+        .map_err(|_| "no 'free_string' function exported".to_string())?;
+    let schema = match unsafe { library.get::<SettingsSchemaFunc>(b"settings_schema\0") } {
+        Ok(func) => {
+            let schema = unsafe { PluginString::new(func(), free_string) }
+                .and_then(|schema| schema.to_str().map(str::to_string))
+                .map_err(|e| format!("no settings schema: {}", e))?;
+            serde_json::from_str(&schema).map_err(|e| format!("invalid settings schema: {}", e))?
         }
-        println!("❌ [MANAGER] Aucun plugin n'a trouvé le film (ou aucun plugin chargé).");
-        return "film not found".to_string();
+        Err(_) => Vec::new(),
+    };
+    let configure = unsafe { library.get::<ConfigureFunc>(b"configure\0") }
+        .map(|func| *func)
+        .ok();
+    let mut functions = BTreeMap::new();
+    for capability in &manifest.capabilities {
+        for &function in capabilities::functions(capability) {
+            let symbol = format!("{}\0", function);
+            let func = unsafe { library.get::<PluginCallFunc>(symbol.as_bytes()) }
+                .map(|func| *func)
+                .map_err(|_| {
+                    format!(
+                        "{} capability without a '{}' function",
+                        capability, function
+                    )
+                })?;
+            functions.insert(function, func);
+        }
+    }
+    Ok(LoadedPlugin {
+        manifest,
+        schema,
+        functions,
+        configure,
+        free_string,
+        library,
+        copy,
+    })
+}
+
+// Answer of the other capabilities, see plugin_api::providers
+pub fn parse_answer<A: Answer>(text: &str) -> Result<A, MetadataError> {
+    let response = ProviderResponse::<A>::from_json(text)
+        .map_err(|e| MetadataError::InvalidResponse(e.to_string()))?;
+    match response.result {
+        ProviderResult::Found { value } => Ok(value),
+        ProviderResult::NotFound => Err(MetadataError::NotFound),
+        ProviderResult::Error { message } => Err(MetadataError::Plugin(message)),
+    }
+}
+
+pub fn parse_response(text: &str) -> Result<Metadata, MetadataError> {
+    let response = MetadataResponse::from_json(text)
+        .map_err(|e| MetadataError::InvalidResponse(e.to_string()))?;
+    match response.result {
+        MetadataResult::Found { metadata } => Ok(*metadata),
+        MetadataResult::NotFound => Err(MetadataError::NotFound),
+        MetadataResult::Error { message } => Err(MetadataError::Plugin(message)),
+    }
+}
+
+// Builds test_plugin/ (next to EpiKodi/) once for the tests of this module and of host.rs
+#[cfg(test)]
+pub(crate) fn test_plugin_path() -> &'static Path {
+    static BUILT: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+    BUILT.get_or_init(|| build_test_plugin("target", "0.1.0"))
+}
+
+// The same plugin as another version, in a target folder of its own
+#[cfg(test)]
+pub(crate) fn test_plugin_next_path() -> &'static Path {
+    static BUILT: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+    BUILT.get_or_init(|| build_test_plugin("target/next", "0.2.0"))
+}
+
+#[cfg(test)]
+fn build_test_plugin(target: &str, version: &str) -> PathBuf {
+    use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../test_plugin");
+    let status = std::process::Command::new(env!("CARGO"))
+        .args(["build", "--quiet", "--offline", "--manifest-path"])
+        .arg(dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(dir.join(target))
+        .env("TEST_PLUGIN_VERSION", version)
+        .status()
+        .expect("cannot run cargo");
+    assert!(status.success(), "test_plugin does not build");
+    dir.join(target)
+        .join("debug")
+        .join(format!("{}test_plugin{}", DLL_PREFIX, DLL_SUFFIX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plugin_api::metadata::ArtistMetadata;
+    use plugin_api::providers::ScrobbleTrack;
+    use std::time::Instant;
+
+    // Loads the test plugin like an installed plugin
+    fn test_plugin() -> Arc<LoadedPlugin> {
+        Arc::new(load_plugin(test_plugin_path()).unwrap())
+    }
+
+    // Strings the test plugin handed to the host and did not get back
+    fn live_strings(plugin: &LoadedPlugin) -> usize {
+        unsafe {
+            let func: Symbol<unsafe extern "C" fn() -> usize> =
+                plugin.library.get(b"test_plugin_live_strings\0").unwrap();
+            func()
+        }
+    }
+
+    // How long the test plugin takes on the slow query
+    fn slow_call(plugin: &LoadedPlugin) -> Duration {
+        unsafe {
+            let func: Symbol<unsafe extern "C" fn() -> u64> =
+                plugin.library.get(b"test_plugin_slow_call_ms\0").unwrap();
+            Duration::from_millis(func())
+        }
+    }
+
+    #[test]
+    fn plugin_answers_are_parsed() {
+        let artist = ArtistMetadata {
+            name: "Daft Punk".to_string(),
+            ..Default::default()
+        };
+        let found = MetadataResponse::found(Metadata::Artist(artist.clone())).to_json();
+        assert_eq!(parse_response(&found), Ok(Metadata::Artist(artist)));
+        assert_eq!(
+            parse_response(&MetadataResponse::not_found().to_json()),
+            Err(MetadataError::NotFound)
+        );
+        assert_eq!(
+            parse_response(&MetadataResponse::error("rate limited").to_json()),
+            Err(MetadataError::Plugin("rate limited".to_string()))
+        );
+        // answer of a plugin built before the schema
+        assert!(matches!(
+            parse_response("artist not found"),
+            Err(MetadataError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn plugin_strings_are_given_back() {
+        let plugin = test_plugin();
+        assert_eq!(plugin.manifest.name, "Test");
+        let query = |text: &str| plugin.metadata(&CString::new(text).unwrap());
+
+        assert!(query("Daft Punk").unwrap().contains("Daft Punk"));
+        assert_eq!(
+            query("not found").map(|text| parse_response(&text)),
+            Ok(Err(MetadataError::NotFound))
+        );
+        assert_eq!(query("null answer"), Err(MetadataError::NullAnswer));
+        assert_eq!(query("not utf8"), Err(MetadataError::NotUtf8));
+        assert_eq!(live_strings(&plugin), 0);
+
+        let mut manager = PluginManager::new();
+        manager
+            .plugins
+            .push(ManagedPlugin::in_process(Arc::clone(&plugin)));
+        let manager = Mutex::new(manager);
+        let found = manager.get_metadata("Nirvana").unwrap();
+        assert_eq!(
+            (found.plugin.as_str(), found.metadata.name.as_str()),
+            ("Test", "Nirvana")
+        );
+        assert_eq!(
+            manager.get_metadata("null answer"),
+            Err(MetadataError::NullAnswer)
+        );
+        // the test plugin answers with artists only
+        assert!(matches!(
+            manager.get_film_metadata("Inception"),
+            Err(MetadataError::InvalidResponse(_))
+        ));
+        assert_eq!(live_strings(&plugin), 0);
+    }
+
+    #[test]
+    fn failing_plugins_are_suspended() {
+        let mut manager = PluginManager::new();
+        manager.timeout = Duration::from_millis(200);
+        manager
+            .plugins
+            .push(ManagedPlugin::in_process(test_plugin()));
+        let manager = Mutex::new(manager);
+
+        assert_eq!(manager.get_metadata("slow"), Err(MetadataError::Timeout));
+        // an answer resets the count
+        assert!(manager.get_metadata("Nirvana").is_ok());
+        assert_eq!(manager.lock().unwrap().plugins[0].failures, 0);
+
+        for _ in 0..PLUGIN_MAX_FAILURES {
+            assert_eq!(
+                manager.get_metadata("null answer"),
+                Err(MetadataError::NullAnswer)
+            );
+        }
+        assert!(manager.lock().unwrap().statuses()[0].suspended);
+        assert_eq!(
+            manager.get_metadata("Nirvana"),
+            Err(MetadataError::NoPlugin)
+        );
+    }
+
+    #[test]
+    fn calls_do_not_hold_the_lock() {
+        let mut manager = PluginManager::new();
+        manager
+            .plugins
+            .push(ManagedPlugin::in_process(test_plugin()));
+        let manager = Arc::new(Mutex::new(manager));
+        let slow = {
+            let manager = Arc::clone(&manager);
+            thread::spawn(move || manager.get_metadata("slow"))
+        };
+        thread::sleep(Duration::from_millis(100));
+
+        // the slow call is still running, the other lookups and the statuses go on
+        let started = Instant::now();
+        assert!(manager.get_metadata("Nirvana").is_ok());
+        assert_eq!(manager.lock().unwrap().statuses().len(), 1);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(slow.join().unwrap(), Err(MetadataError::NullAnswer));
+    }
+
+    #[test]
+    fn settings_are_stored_and_given_to_the_plugin() {
+        let dir = std::env::temp_dir().join("epikodi_plugin_manager_settings");
+        let _ = fs::remove_dir_all(&dir);
+        let mut manager = PluginManager::new();
+        manager.settings = SettingsStore::new(&dir);
+        manager
+            .plugins
+            .push(ManagedPlugin::in_process(test_plugin()));
+        assert_eq!(manager.plugins[0].schema.len(), 2);
+        let manager = Mutex::new(manager);
+
+        let form = |key: &str, value: &str| {
+            let mut settings = PluginSettings::default();
+            settings.set(key, SettingValue::Text(value.to_string()));
+            settings
+        };
+        assert_eq!(
+            manager.save_settings("Test", form("token", "refused")),
+            Err("token refused".to_string())
+        );
+        // kept even when refused, the secret is not shown back
+        let status = &manager.lock().unwrap().statuses()[0];
+        assert_eq!(status.settings_error.as_deref(), Some("token refused"));
+        assert_eq!(status.secrets_set, vec!["token".to_string()]);
+        assert!(!status.values.values.contains_key("token"));
+
+        // an empty secret keeps the stored one
+        assert!(manager.save_settings("Test", form("token", "")).is_err());
+        let stored = manager.lock().unwrap().settings.load("Test");
+        assert_eq!(stored.text("token"), Some("refused"));
+
+        assert!(manager
+            .save_settings("Test", form("colour", "red"))
+            .is_err());
+        assert!(manager
+            .save_settings("Other", form("token", "abc"))
+            .is_err());
+        assert_eq!(manager.save_settings("Test", form("token", "abc")), Ok(()));
+        assert_eq!(manager.lock().unwrap().statuses()[0].settings_error, None);
+    }
+
+    #[test]
+    fn plugins_are_asked_by_priority() {
+        let path = std::env::temp_dir().join("epikodi_plugin_manager_priority/registry.json");
+        let _ = fs::remove_file(&path);
+        let mut manager = PluginManager::new();
+        manager.registry = PluginRegistry::load(&path);
+        for name in ["First", "Second"] {
+            let mut plugin = ManagedPlugin::in_process(test_plugin());
+            plugin.manifest.name = name.to_string();
+            manager.plugins.push(plugin);
+        }
+        let manager = Mutex::new(manager);
+        let answering = || manager.get_metadata("Nirvana").unwrap().plugin;
+
+        assert_eq!(answering(), "First");
+        manager
+            .lock()
+            .unwrap()
+            .set_priority(capabilities::ARTIST_METADATA, vec!["Second".to_string()])
+            .unwrap();
+        assert_eq!(answering(), "Second");
+        let mut locked = manager.lock().unwrap();
+        assert_eq!(
+            locked.statuses()[0].priority[capabilities::ARTIST_METADATA],
+            1
+        );
+        assert!(locked
+            .set_priority("lyrics_or_whatever", Vec::new())
+            .is_err());
+        locked.set_enabled("Second", false).unwrap();
+        assert!(locked.set_enabled("Third", true).is_err());
+        drop(locked);
+        assert_eq!(answering(), "First");
+
+        // kept for the next start
+        let registry = PluginRegistry::load(&path);
+        assert!(!registry.is_enabled("Second"));
+        assert_eq!(registry.rank(capabilities::ARTIST_METADATA, "Second"), 0);
+    }
+
+    #[test]
+    fn reloaded_libraries_outlive_the_calls_in_flight() {
+        let dir = std::env::temp_dir().join("epikodi_plugin_manager_reload");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let library = test_plugin_path();
+        fs::copy(library, dir.join(library.file_name().unwrap())).unwrap();
+
+        let mut manager = PluginManager::new();
+        manager.dir = dir.clone();
+        manager.settings = SettingsStore::new(dir.join("settings"));
+        manager.registry = PluginRegistry::load(dir.join("registry.json"));
+        manager.timeout = Duration::from_millis(200);
+        manager.load_plugins();
+        assert_eq!(manager.plugins.len(), 1);
+        let Runner::InProcess(plugin) = &manager.plugins[0].runner else {
+            panic!("expected an in-process plugin");
+        };
+        let old = Arc::downgrade(plugin);
+        let slow = slow_call(plugin);
+        let manager = Mutex::new(manager);
+
+        assert_eq!(manager.get_metadata("slow"), Err(MetadataError::Timeout));
+        manager.reload_plugins();
+        assert_eq!(manager.lock().unwrap().plugins.len(), 1);
+        assert!(manager.get_metadata("Nirvana").is_ok());
+        // the slow call still runs in the old library, unloaded once it is over
+        assert!(old.upgrade().is_some());
+        let deadline = Instant::now() + slow * 2;
+        while old.upgrade().is_some() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(old.upgrade().is_none());
+
+        // a disabled plugin stays disabled once reloaded
+        manager.lock().unwrap().set_enabled("Test", false).unwrap();
+        manager.reload_plugins();
+        assert!(!manager.lock().unwrap().statuses()[0].enabled);
+        assert_eq!(
+            manager.get_metadata("Nirvana"),
+            Err(MetadataError::NoPlugin)
+        );
+    }
+
+    #[test]
+    fn reload_loads_a_library_replaced_in_place() {
+        let dir = std::env::temp_dir().join("epikodi_plugin_manager_swap");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let installed = dir.join(test_plugin_path().file_name().unwrap());
+        fs::copy(test_plugin_path(), &installed).unwrap();
+
+        let mut manager = PluginManager::new();
+        manager.dir = dir.clone();
+        manager.settings = SettingsStore::new(dir.join("settings"));
+        manager.registry = PluginRegistry::load(dir.join("registry.json"));
+        manager.load_plugins();
+        assert_eq!(manager.statuses()[0].manifest.version, "0.1.0");
+        let Runner::InProcess(plugin) = &manager.plugins[0].runner else {
+            panic!("expected an in-process plugin");
+        };
+        let copy = plugin.copy.0.clone();
+        assert!(copy.exists());
+        let manager = Mutex::new(manager);
+
+        fs::copy(test_plugin_next_path(), &installed).unwrap();
+        manager.reload_plugins();
+        assert_eq!(
+            manager.lock().unwrap().statuses()[0].manifest.version,
+            "0.2.0"
+        );
+        // the copy goes with the last user of the old library, its event threads end by themselves
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while copy.exists() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!copy.exists());
+    }
+
+    #[test]
+    fn other_capabilities_are_routed() {
+        let mut manager = PluginManager::new();
+        manager
+            .plugins
+            .push(ManagedPlugin::in_process(test_plugin()));
+        let manager = Mutex::new(manager);
+
+        let lyrics = manager.get_lyrics(&LyricsQuery {
+            artist: "Nirvana".to_string(),
+            title: "Lithium".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(
+            lyrics.unwrap().metadata.plain.as_deref(),
+            Some("Nirvana - Lithium")
+        );
+        let unknown = LyricsQuery {
+            title: "not found".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(manager.get_lyrics(&unknown), Err(MetadataError::NotFound));
+
+        let query = SubtitleQuery {
+            title: "Inception".to_string(),
+            ..Default::default()
+        };
+        let subtitles = manager.search_subtitles(&query).unwrap();
+        assert_eq!(subtitles.len(), 2);
+        let file = manager
+            .download_subtitle(&subtitles[0].plugin, &subtitles[0].metadata.id)
+            .unwrap();
+        assert_eq!(
+            (file.language.as_str(), file.format.as_str()),
+            ("fr", "srt")
+        );
+        assert_eq!(
+            manager.download_subtitle("Other", "Inception.fr"),
+            Err(MetadataError::NoPlugin)
+        );
+
+        let artwork = manager.get_artwork(&ArtworkQuery {
+            kind: "movie".to_string(),
+            title: "Inception".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(artwork.unwrap().metadata[0].kind, "poster");
+
+        let event = ScrobbleEvent::NowPlaying {
+            track: ScrobbleTrack {
+                artist: "Nirvana".to_string(),
+                title: "Lithium".to_string(),
+                ..Default::default()
+            },
+        };
+        assert_eq!(manager.scrobble(&event), Ok(vec!["Test".to_string()]));
+    }
+
+    #[test]
+    fn wasm_modules_are_loaded_next_to_libraries() {
+        let dir = std::env::temp_dir().join("epikodi_plugin_manager_wasm");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::copy(
+            super::super::wasm::test_module("manager", 1),
+            dir.join("lyrics.wasm"),
+        )
+        .unwrap();
+
+        let mut manager = PluginManager::new();
+        manager.dir = dir.clone();
+        manager.settings = SettingsStore::new(dir.join("settings"));
+        manager.registry = PluginRegistry::load(dir.join("registry.json"));
+        manager.out_of_process = true;
+        manager.load_plugins();
+        assert_eq!(manager.plugins.len(), 1);
+        let status = manager.statuses().remove(0);
+        assert!(status.wasm && !status.out_of_process);
+        assert_eq!(status.domains, vec!["example.org".to_string()]);
+        assert!(status.approved_domains.is_empty());
+
+        // the approval is kept for the next load
+        manager.approve_domains("Wasm", true).unwrap();
+        manager.plugins.clear();
+        manager.registry = PluginRegistry::load(dir.join("registry.json"));
+        manager.load_plugins();
+        assert_eq!(manager.statuses()[0].approved_domains, status.domains);
+        let manager = Mutex::new(manager);
+
+        let query = LyricsQuery {
+            artist: "Nirvana".to_string(),
+            title: "Lithium".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(manager.get_lyrics(&query).unwrap().plugin, "Wasm");
+        // the module traps: the call fails, the plugin goes on answering
+        assert!(matches!(
+            manager.download_subtitle("Wasm", "1"),
+            Err(MetadataError::Crashed(_))
+        ));
+        assert!(manager.get_lyrics(&query).is_ok());
+    }
+
+    #[test]
+    fn waiting_for_a_busy_module_is_not_a_timeout() {
+        let module = super::super::wasm::test_module("busy", 1);
+        let plugin = WasmPlugin::load(&module, Duration::from_secs(1)).unwrap();
+        let plugin = Arc::new(Mutex::new(plugin));
+        let runner = Runner::Wasm(Arc::clone(&plugin));
+
+        // another call holds the module for longer than the timeout of this one
+        let busy = plugin.lock().unwrap();
+        let call = thread::spawn(move || runner.call("lyrics", "{}", Duration::from_millis(100)));
+        thread::sleep(Duration::from_millis(300));
+        drop(busy);
+        assert!(call.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn libraries_that_are_not_plugins_are_refused() {
+        assert!(load_plugin(Path::new("plugins/missing.so")).is_err());
+    }
+
+    #[test]
+    fn no_plugin_is_an_error() {
+        let manager = Mutex::new(PluginManager::new());
+        assert_eq!(
+            manager.get_film_metadata("Inception 2010"),
+            Err(MetadataError::NoPlugin)
+        );
     }
 }
@@ -0,0 +1,116 @@
+/*
+Choices of the user about the installed plugins, kept across restarts and reloads:
+the plugins turned off, for each capability the order in which the plugins are asked, and the
+domains the user let each WebAssembly plugin reach.
+Plugins are named by their descriptor, so renaming or updating a library keeps its choices.
+*/
+
+use crate::constants::PLUGIN_REGISTRY_FILE;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PluginRegistry {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    pub disabled: Vec<String>, // plugin names
+    #[serde(default)]
+    pub priority: BTreeMap<String, Vec<String>>, // capability -> plugin names, first asked first
+    #[serde(default)]
+    pub domains: BTreeMap<String, Vec<String>>, // plugin name -> domains approved
+}
+
+impl PluginRegistry {
+    // The saved registry, empty when there is none or it cannot be read
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let registry: Self = fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        Self { path, ..registry }
+    }
+
+    pub fn user_config() -> Self {
+        let config = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
+        Self::load(config.join(PLUGIN_REGISTRY_FILE))
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(&self.path, json)
+    }
+
+    pub fn is_enabled(&self, plugin: &str) -> bool {
+        !self.disabled.iter().any(|name| name == plugin)
+    }
+
+    pub fn set_enabled(&mut self, plugin: &str, enabled: bool) {
+        self.disabled.retain(|name| name != plugin);
+        if !enabled {
+            self.disabled.push(plugin.to_string());
+        }
+    }
+
+    // Position of the plugin for the capability, the plugins never ordered come last
+    pub fn rank(&self, capability: &str, plugin: &str) -> usize {
+        self.priority
+            .get(capability)
+            .and_then(|order| order.iter().position(|name| name == plugin))
+            .unwrap_or(usize::MAX)
+    }
+
+    pub fn set_priority(&mut self, capability: &str, plugins: Vec<String>) {
+        self.priority.insert(capability.to_string(), plugins);
+    }
+
+    pub fn approved_domains(&self, plugin: &str) -> &[String] {
+        self.domains.get(plugin).map_or(&[], Vec::as_slice)
+    }
+
+    // An empty list takes the approval back
+    pub fn set_approved_domains(&mut self, plugin: &str, domains: Vec<String>) {
+        if domains.is_empty() {
+            self.domains.remove(plugin);
+        } else {
+            self.domains.insert(plugin.to_string(), domains);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn choices_are_kept_across_loads() {
+        let path = std::env::temp_dir().join("epikodi_plugin_registry/registry.json");
+        let _ = fs::remove_file(&path);
+
+        let mut registry = PluginRegistry::load(&path);
+        assert!(registry.is_enabled("TMDB"));
+        registry.set_enabled("TMDB", false);
+        registry.set_enabled("TMDB", false);
+        registry.set_priority("artist_metadata", vec!["Test".into(), "MusicBrainz".into()]);
+        registry.set_approved_domains("Wasm", vec!["example.org".into()]);
+        registry.save().unwrap();
+
+        let registry = PluginRegistry::load(&path);
+        assert_eq!(registry.disabled, vec!["TMDB".to_string()]);
+        assert_eq!(registry.rank("artist_metadata", "MusicBrainz"), 1);
+        assert_eq!(registry.rank("artist_metadata", "Other"), usize::MAX);
+        assert_eq!(registry.rank("movie_metadata", "Test"), usize::MAX);
+        assert_eq!(
+            registry.approved_domains("Wasm"),
+            ["example.org".to_string()]
+        );
+        assert!(registry.approved_domains("Test").is_empty());
+    }
+}
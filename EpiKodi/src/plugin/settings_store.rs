@@ -0,0 +1,182 @@
+/*
+Values of the plugin settings, one JSON file per plugin under the config folder of the user,
+outside of the install and source folders. The secrets (API keys...) are kept in a second file
+only readable by the user, and the GUI only learns whether they are set.
+*/
+
+use crate::constants::PLUGIN_SETTINGS_DIR;
+use plugin_api::settings::{PluginSettings, SettingField};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone)]
+pub struct SettingsStore {
+    dir: PathBuf,
+}
+
+impl SettingsStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn user_config() -> Self {
+        let config = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
+        Self::new(config.join(PLUGIN_SETTINGS_DIR))
+    }
+
+    // Stored values of the plugin with its secrets, empty when nothing was saved yet
+    pub fn load(&self, plugin: &str) -> PluginSettings {
+        let mut settings = read(&self.values_path(plugin));
+        settings
+            .values
+            .extend(read(&self.secrets_path(plugin)).values);
+        settings
+    }
+
+    pub fn save(
+        &self,
+        plugin: &str,
+        schema: &[SettingField],
+        settings: &PluginSettings,
+    ) -> io::Result<()> {
+        let (secrets, values): (Vec<_>, Vec<_>) =
+            settings.values.clone().into_iter().partition(|(key, _)| {
+                schema
+                    .iter()
+                    .any(|field| &field.key == key && field.is_secret())
+            });
+        fs::create_dir_all(&self.dir)?;
+        fs::write(
+            self.values_path(plugin),
+            to_json(PluginSettings {
+                values: values.into_iter().collect(),
+            }),
+        )?;
+
+        let secrets_path = self.secrets_path(plugin);
+        if secrets.is_empty() {
+            return match fs::remove_file(&secrets_path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        write_private(
+            &secrets_path,
+            &to_json(PluginSettings {
+                values: secrets.into_iter().collect(),
+            }),
+        )
+    }
+
+    fn values_path(&self, plugin: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", file_stem(plugin)))
+    }
+
+    fn secrets_path(&self, plugin: &str) -> PathBuf {
+        self.dir.join(format!("{}.secrets.json", file_stem(plugin)))
+    }
+}
+
+// The plugin name as given by its descriptor, percent-encoded into a safe file name. Two names
+// never share a file, even on a case-insensitive disk: the capitals are encoded too.
+fn file_stem(plugin: &str) -> String {
+    plugin
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn read(path: &Path) -> PluginSettings {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|text| PluginSettings::from_json(&text).ok())
+        .unwrap_or_default()
+}
+
+fn to_json(settings: PluginSettings) -> String {
+    serde_json::to_string_pretty(&settings).unwrap_or_else(|_| "{}".to_string())
+}
+
+// Readable and writable by the user only, on Windows the profile folder is already private
+fn write_private(path: &Path, text: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // mode() only applies when the file is created
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plugin_api::settings::SettingValue;
+
+    fn temp_store(name: &str) -> SettingsStore {
+        let dir = std::env::temp_dir().join(format!("epikodi_settings_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        SettingsStore::new(dir)
+    }
+
+    #[test]
+    fn secrets_are_stored_apart() {
+        let store = temp_store("secrets");
+        let schema = vec![
+            SettingField::secret("api_key", "API key"),
+            SettingField::string("language", "Language"),
+        ];
+        let mut settings = PluginSettings::default();
+        settings.set("api_key", SettingValue::Text("abc".to_string()));
+        settings.set("language", SettingValue::Text("fr-FR".to_string()));
+        store.save("TMDB Movies", &schema, &settings).unwrap();
+
+        let values = fs::read_to_string(store.values_path("TMDB Movies")).unwrap();
+        assert!(values.contains("fr-FR") && !values.contains("abc"));
+        assert!(store
+            .dir
+            .join("%54%4D%44%42%20%4Dovies.secrets.json")
+            .exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(store.secrets_path("TMDB Movies"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(store.load("TMDB Movies"), settings);
+
+        // no secret left: the file goes away
+        settings.values.remove("api_key");
+        store.save("TMDB Movies", &schema, &settings).unwrap();
+        assert!(!store.secrets_path("TMDB Movies").exists());
+        assert_eq!(store.load("TMDB Movies"), settings);
+        assert_eq!(store.load("Unknown"), PluginSettings::default());
+    }
+
+    #[test]
+    fn plugins_never_share_their_files() {
+        let store = temp_store("names");
+        let schema = vec![SettingField::secret("api_key", "API key")];
+        let mut settings = PluginSettings::default();
+        settings.set("api_key", SettingValue::Text("abc".to_string()));
+        store.save("TMDB Movies", &schema, &settings).unwrap();
+
+        for name in ["tmdb_movies", "tmdb%20movies", "TMDB_Movies", "tmdb movies"] {
+            assert_eq!(store.load(name), PluginSettings::default(), "{}", name);
+        }
+        assert_eq!(file_stem("tmdb-movies_2"), "tmdb-movies_2");
+        assert_eq!(file_stem("../Ünï"), "%2E%2E%2F%C3%9Cn%C3%AF");
+    }
+}
@@ -0,0 +1,875 @@
+/*
+WebAssembly plugins: a .wasm module in the plugin folder, the same plugin crate built for
+wasm32-wasip1 instead of a native library. It exports the same functions (declare_plugin!)
+and answers with the same JSON, so the manager uses it like any other plugin.
+The module runs in wasmtime, in a sandbox: WASI without any folder, environment variable or
+argument, PLUGIN_WASM_MEMORY_MB of memory, `fuel` instructions per call and the timeout of the
+call, so it can neither read the disk nor loop forever. The timeout is an epoch deadline: a clock
+thread moves the epoch of the engine every PLUGIN_WASM_EPOCH_MS and the call traps once it is
+past, which releases the plugin for the next call. Its only way out is the `epikodi.http_fetch`
+import, which the host sends to the domains of its `allowed_domains` export (see
+plugin_api::http) once the user approved them, within what is left of the timeout and for
+PLUGIN_HTTP_FUEL of its fuel. Hosts given as an IP address or localhost are refused, and so are
+names resolving to a loopback, private or link-local address: the check is made on the addresses
+the client connects to, so a declared domain cannot be rebound to the network of the user.
+A trap (panic, fuel, time or memory exhausted) fails the call and drops the instance: a new one
+is made at the next call and given the last settings.
+*/
+
+use super::plugin_manager::MetadataError;
+use crate::constants::{
+    PLUGIN_HTTP_FUEL, PLUGIN_HTTP_MAX_BYTES, PLUGIN_HTTP_TIMEOUT_SECS, PLUGIN_WASM_EPOCH_MS,
+    PLUGIN_WASM_FUEL, PLUGIN_WASM_MEMORY_MB,
+};
+use hyper::client::connect::dns::Name;
+use plugin_api::abi::{capabilities, AbiError, PluginManifest, ABI_VERSION};
+use plugin_api::http::{domain_allowed, FetchAnswer, HttpRequest, HttpResponse, HOST_MODULE};
+use plugin_api::settings::{PluginSettings, SettingField};
+use reqwest::blocking::Client;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{Method, Url};
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use wasmtime::{
+    AsContext, AsContextMut, Caller, Config, Engine, Extern, Instance, Linker, Memory, Module,
+    Store, StoreLimits, StoreLimitsBuilder, Trap, TypedFunc,
+};
+use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+use wasmtime_wasi::WasiCtxBuilder;
+
+const DESCRIPTOR_FUNCTION: &str = "epikodi_plugin_descriptor";
+const MAX_REDIRECTS: usize = 10;
+
+// What a store of the plugin holds, seen by the host functions
+struct HostState {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+    http: Client,
+    domains: Vec<String>, // approved by the user
+    deadline: Instant,    // end of the current call, http_fetch does not wait past it
+}
+
+// A live instance of the module
+struct Running {
+    store: Store<HostState>,
+    instance: Instance,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    free_string: TypedFunc<i32, ()>,
+}
+
+pub struct WasmPlugin {
+    pub manifest: PluginManifest,
+    pub schema: Vec<SettingField>, // empty when the plugin has no settings
+    pub domains: Vec<String>,      // declared, what http_fetch may reach once approved
+    pub approved: Vec<String>,     // of the domains, those the user approved
+    pub fuel: u64,                 // of one call
+    functions: Vec<&'static str>,  // of the declared capabilities
+    module: Module,
+    linker: Linker<HostState>,
+    http: Client,
+    running: Option<Running>, // None after a trap, until the next call
+    settings: Option<PluginSettings>, // given again to a new instance
+}
+
+impl WasmPlugin {
+    // Compiles the module and reads what it declares, like load_plugin for a library.
+    // `timeout` bounds each call made to read it.
+    pub fn load(path: &Path, timeout: Duration) -> Result<Self, String> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).map_err(|e| e.to_string())?;
+        start_epoch_clock(&engine);
+        let module = Module::from_file(&engine, path)
+            .map_err(|e| format!("invalid WebAssembly module: {}", e))?;
+        let mut linker = Linker::new(&engine);
+        preview1::add_to_linker_sync(&mut linker, |state: &mut HostState| &mut state.wasi)
+            .map_err(|e| e.to_string())?;
+        linker
+            .func_wrap(HOST_MODULE, "http_fetch", http_fetch)
+            .map_err(|e| e.to_string())?;
+
+        let mut plugin = WasmPlugin {
+            manifest: PluginManifest::default(),
+            schema: Vec::new(),
+            domains: Vec::new(),
+            approved: Vec::new(),
+            fuel: PLUGIN_WASM_FUEL,
+            functions: Vec::new(),
+            module,
+            linker,
+            http: http_client(&[])?,
+            running: None,
+            settings: None,
+        };
+        let mut running = plugin.instantiate(timeout)?;
+
+        let descriptor = running
+            .call_export(DESCRIPTOR_FUNCTION, None, plugin.fuel, timeout)
+            .map_err(|e| format!("no plugin descriptor: {}", e))?;
+        plugin.manifest = read_descriptor(running.memory.data(&running.store), descriptor)
+            .map_err(|e| e.to_string())?;
+        println!(
+            "ℹ️ [PLUGIN] {} {} : {:?} (WebAssembly)",
+            plugin.manifest.name, plugin.manifest.version, plugin.manifest.capabilities
+        );
+
+        if plugin.exports("settings_schema") {
+            let schema = running
+                .call("settings_schema", None, plugin.fuel, timeout)
+                .and_then(|schema| schema.ok_or(MetadataError::NullAnswer))
+                .map_err(|e| format!("no settings schema: {}", e))?;
+            plugin.schema = serde_json::from_str(&schema)
+                .map_err(|e| format!("invalid settings schema: {}", e))?;
+        }
+        if plugin.exports("allowed_domains") {
+            let domains = running
+                .call("allowed_domains", None, plugin.fuel, timeout)
+                .and_then(|domains| domains.ok_or(MetadataError::NullAnswer))
+                .map_err(|e| format!("no allowed domains: {}", e))?;
+            plugin.domains = serde_json::from_str(&domains)
+                .map_err(|e| format!("invalid allowed domains: {}", e))?;
+        }
+
+        for capability in &plugin.manifest.capabilities {
+            for &function in capabilities::functions(capability) {
+                running
+                    .instance
+                    .get_typed_func::<i32, i32>(&mut running.store, function)
+                    .map_err(|_| {
+                        format!(
+                            "{} capability without a '{}' function",
+                            capability, function
+                        )
+                    })?;
+                plugin.functions.push(function);
+            }
+        }
+        plugin.running = Some(running);
+        Ok(plugin)
+    }
+
+    // Answer of one of the functions of the capabilities, Timeout once `timeout` is over
+    pub fn call(
+        &mut self,
+        function: &str,
+        input: &str,
+        timeout: Duration,
+    ) -> Result<String, MetadataError> {
+        if !self.functions.contains(&function) {
+            return Err(MetadataError::Plugin(format!(
+                "no '{}' function exported",
+                function
+            )));
+        }
+        if input.contains('\0') {
+            return Err(MetadataError::InvalidQuery(input.replace('\0', "")));
+        }
+        self.invoke(function, Some(input), timeout)?
+            .ok_or(MetadataError::NullAnswer)
+    }
+
+    // Gives the settings to the plugin, Err with its reason when it refuses them
+    pub fn configure(
+        &mut self,
+        settings: &PluginSettings,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        self.settings = Some(settings.clone());
+        if !self.exports("configure") {
+            return Ok(());
+        }
+        match self.invoke("configure", Some(&settings.to_json()), timeout) {
+            Ok(None) => Ok(()),
+            Ok(Some(reason)) => Err(reason),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    // Lets http_fetch reach the declared domains among `approved`, none until the user approves them
+    pub fn approve(&mut self, approved: &[String]) -> Result<(), String> {
+        self.approved = self
+            .domains
+            .iter()
+            .filter(|domain| approved.contains(domain))
+            .cloned()
+            .collect();
+        self.http = http_client(&self.approved)?;
+        if let Some(running) = &mut self.running {
+            let state = running.store.data_mut();
+            state.domains = self.approved.clone();
+            state.http = self.http.clone();
+        }
+        Ok(())
+    }
+
+    fn exports(&self, function: &str) -> bool {
+        self.module.get_export(function).is_some()
+    }
+
+    fn invoke(
+        &mut self,
+        function: &str,
+        input: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Option<String>, MetadataError> {
+        if self.running.is_none() {
+            let mut running = self.instantiate(timeout).map_err(MetadataError::Crashed)?;
+            if let Some(settings) = &self.settings {
+                if self.exports("configure") {
+                    let settings = settings.to_json();
+                    let _ = running.call("configure", Some(&settings), self.fuel, timeout);
+                }
+            }
+            self.running = Some(running);
+        }
+        let running = self.running.as_mut().ok_or(MetadataError::NullAnswer)?;
+        let result = running.call(function, input, self.fuel, timeout);
+        if matches!(
+            result,
+            Err(MetadataError::Timeout | MetadataError::Crashed(_))
+        ) {
+            // the memory of the module is in an unknown state after a trap
+            self.running = None;
+        }
+        result
+    }
+
+    fn instantiate(&self, timeout: Duration) -> Result<Running, String> {
+        let state = HostState {
+            // no preopened folder, environment or argument: nothing of the host is visible
+            wasi: WasiCtxBuilder::new()
+                .inherit_stdout()
+                .inherit_stderr()
+                .build_p1(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(PLUGIN_WASM_MEMORY_MB << 20)
+                .instances(1)
+                .build(),
+            http: self.http.clone(),
+            domains: self.approved.clone(),
+            deadline: Instant::now() + timeout,
+        };
+        let mut store = Store::new(self.module.engine(), state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.fuel).map_err(|e| e.to_string())?;
+        store.set_epoch_deadline(epoch_ticks(timeout));
+        let instance = self
+            .linker
+            .instantiate(&mut store, &self.module)
+            .map_err(|e| format!("cannot instantiate the module: {}", e.root_cause()))?;
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or("no memory exported")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "epikodi_alloc")
+            .map_err(|_| "no 'epikodi_alloc' function exported")?;
+        let free_string = instance
+            .get_typed_func::<i32, ()>(&mut store, "free_string")
+            .map_err(|_| "no 'free_string' function exported")?;
+        // what a WASI reactor runs before any other export (static constructors)
+        if let Ok(initialize) = instance.get_typed_func::<(), ()>(&mut store, "_initialize") {
+            initialize
+                .call(&mut store, ())
+                .map_err(|e| format!("initialization failed: {}", e.root_cause()))?;
+        }
+        Ok(Running {
+            store,
+            instance,
+            memory,
+            alloc,
+            free_string,
+        })
+    }
+}
+
+impl Running {
+    // A function taking and returning a string, like the C ABI: the input is copied into the
+    // memory of the module, the answer copied out and both given back to its free_string.
+    // None when the function returned null.
+    fn call(
+        &mut self,
+        function: &str,
+        input: Option<&str>,
+        fuel: u64,
+        timeout: Duration,
+    ) -> Result<Option<String>, MetadataError> {
+        let answer = self
+            .call_export(function, input, fuel, timeout)
+            .map_err(trapped)?;
+        if answer == 0 {
+            return Ok(None);
+        }
+        let text = read_string(self.memory.data(&self.store), answer);
+        self.free_string
+            .call(&mut self.store, answer)
+            .map_err(trapped)?;
+        text.map(Some)
+    }
+
+    // The pointer returned by the function
+    fn call_export(
+        &mut self,
+        function: &str,
+        input: Option<&str>,
+        fuel: u64,
+        timeout: Duration,
+    ) -> wasmtime::Result<i32> {
+        self.store.set_fuel(fuel)?;
+        self.store.set_epoch_deadline(epoch_ticks(timeout));
+        self.store.data_mut().deadline = Instant::now() + timeout;
+        let Some(input) = input else {
+            let func = self
+                .instance
+                .get_typed_func::<(), i32>(&mut self.store, function)?;
+            return func.call(&mut self.store, ());
+        };
+        let func = self
+            .instance
+            .get_typed_func::<i32, i32>(&mut self.store, function)?;
+        let input = write_string(&mut self.store, self.memory, &self.alloc, input)?;
+        let answer = func.call(&mut self.store, input)?;
+        self.free_string.call(&mut self.store, input)?;
+        Ok(answer)
+    }
+}
+
+// Out of fuel or past the epoch deadline is a timeout, any other trap a crash of the plugin
+fn trapped(error: wasmtime::Error) -> MetadataError {
+    match error.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel | Trap::Interrupt) => MetadataError::Timeout,
+        _ => MetadataError::Crashed(error.root_cause().to_string()),
+    }
+}
+
+// Moves the epoch of the engine every PLUGIN_WASM_EPOCH_MS, until the plugin is dropped
+fn start_epoch_clock(engine: &Engine) {
+    let weak = engine.weak();
+    thread::spawn(move || {
+        while let Some(engine) = weak.upgrade() {
+            engine.increment_epoch();
+            drop(engine);
+            thread::sleep(Duration::from_millis(PLUGIN_WASM_EPOCH_MS));
+        }
+    });
+}
+
+// The epoch deadline of a call, one tick at least
+fn epoch_ticks(timeout: Duration) -> u64 {
+    (timeout.as_millis() as u64)
+        .div_ceil(PLUGIN_WASM_EPOCH_MS)
+        .max(1)
+}
+
+// Copies `text` into a buffer of the epikodi_alloc of the module, the module frees it
+fn write_string(
+    mut store: impl AsContextMut,
+    memory: Memory,
+    alloc: &TypedFunc<i32, i32>,
+    text: &str,
+) -> wasmtime::Result<i32> {
+    let ptr = alloc.call(&mut store, i32::try_from(text.len())?)?;
+    if ptr == 0 {
+        return Err(wasmtime::Error::msg("epikodi_alloc returned null"));
+    }
+    memory.write(&mut store, ptr as u32 as usize, text.as_bytes())?;
+    Ok(ptr)
+}
+
+// A NUL-terminated string of the module, which must end inside its memory
+fn read_string(memory: &[u8], ptr: i32) -> Result<String, MetadataError> {
+    let outside = || MetadataError::InvalidResponse("string outside the plugin memory".to_string());
+    let bytes = memory.get(ptr as u32 as usize..).ok_or_else(outside)?;
+    let len = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or_else(outside)?;
+    String::from_utf8(bytes[..len].to_vec()).map_err(|_| MetadataError::NotUtf8)
+}
+
+fn read_u32(memory: &[u8], address: u32) -> Option<u32> {
+    let start = address as usize;
+    let bytes = memory.get(start..start.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+// The abi::PluginDescriptor as wasm32 lays it out: 32-bit fields and pointers, checked like
+// PluginManifest::from_descriptor does for a library
+fn read_descriptor(memory: &[u8], ptr: i32) -> Result<PluginManifest, AbiError> {
+    let ptr = ptr as u32;
+    if ptr == 0 {
+        return Err(AbiError::NullDescriptor);
+    }
+    let field = |offset: u32| read_u32(memory, ptr.checked_add(offset)?);
+    let abi_version = field(0).ok_or(AbiError::NullDescriptor)?;
+    if abi_version != ABI_VERSION {
+        return Err(AbiError::IncompatibleVersion(abi_version));
+    }
+    let string = |address: Option<u32>| {
+        address
+            .filter(|&address| address != 0)
+            .and_then(|address| read_string(memory, address as i32).ok())
+    };
+
+    let name = string(field(4)).unwrap_or_default();
+    if name.trim().is_empty() {
+        return Err(AbiError::MissingField("name"));
+    }
+    let list = field(16).unwrap_or(0);
+    let count = field(20).unwrap_or(0);
+    let capabilities: Vec<String> = match list {
+        0 => Vec::new(),
+        list => (0..count)
+            .map_while(|i| read_u32(memory, list.checked_add(i.checked_mul(4)?)?))
+            .filter_map(|address| string(Some(address)))
+            .collect(),
+    };
+    if capabilities.is_empty() {
+        return Err(AbiError::MissingField("capabilities"));
+    }
+
+    Ok(PluginManifest {
+        name,
+        version: string(field(8)).unwrap_or_default(),
+        author: string(field(12)).unwrap_or_default(),
+        capabilities,
+    })
+}
+
+// `epikodi.http_fetch`: an HttpRequest as JSON in, a FetchAnswer as JSON out, in a buffer of the
+// epikodi_alloc of the module. Only the plugin waits for the answer, PLUGIN_HTTP_TIMEOUT_SECS at
+// most and never past the deadline of its call. The request costs PLUGIN_HTTP_FUEL and the
+// answer a unit per byte: the epoch does not stop the module while the host waits here.
+fn http_fetch(mut caller: Caller<'_, HostState>, request: i32) -> wasmtime::Result<i32> {
+    burn_fuel(&mut caller, PLUGIN_HTTP_FUEL)?;
+    let left = caller
+        .data()
+        .deadline
+        .saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        return Err(Trap::Interrupt.into());
+    }
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::Error::msg("no memory exported"))?;
+    let alloc = caller
+        .get_export("epikodi_alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| wasmtime::Error::msg("no 'epikodi_alloc' function exported"))?
+        .typed::<i32, i32>(&caller)?;
+
+    let answer: FetchAnswer = read_string(memory.data(caller.as_context()), request)
+        .map_err(|e| e.to_string())
+        .and_then(|text| {
+            serde_json::from_str::<HttpRequest>(&text)
+                .map_err(|e| format!("invalid request: {}", e))
+        })
+        .and_then(|request| fetch(caller.data(), &request, left));
+    if Instant::now() >= caller.data().deadline {
+        return Err(Trap::Interrupt.into());
+    }
+    let answer = serde_json::to_string(&answer)?;
+    burn_fuel(&mut caller, answer.len() as u64)?;
+    write_string(&mut caller, memory, &alloc, &answer)
+}
+
+// Takes fuel from the call like instructions do, out of fuel when there is not enough
+fn burn_fuel(caller: &mut Caller<'_, HostState>, fuel: u64) -> wasmtime::Result<()> {
+    let left = caller.get_fuel()?;
+    if left < fuel {
+        caller.set_fuel(0)?;
+        return Err(Trap::OutOfFuel.into());
+    }
+    caller.set_fuel(left - fuel)
+}
+
+// `left` is what remains of the call, the request gives up sooner when it is short
+fn fetch(state: &HostState, request: &HttpRequest, left: Duration) -> FetchAnswer {
+    let url =
+        Url::parse(&request.url).map_err(|e| format!("invalid url '{}': {}", request.url, e))?;
+    check_url(&state.domains, &url)?;
+    let method = match request.method.trim() {
+        "" => Method::GET,
+        method => Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+            .map_err(|_| format!("invalid method '{}'", method))?,
+    };
+
+    let timeout = left.min(Duration::from_secs(PLUGIN_HTTP_TIMEOUT_SECS));
+    let mut builder = state.http.request(method, url).timeout(timeout);
+    for (name, value) in &request.headers {
+        builder = builder.header(name, value);
+    }
+    if let Some(body) = &request.body {
+        builder = builder.body(body.clone());
+    }
+    let response = builder.send().map_err(|e| describe(&e))?;
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect();
+    let mut body = Vec::new();
+    response
+        .take(PLUGIN_HTTP_MAX_BYTES + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| e.to_string())?;
+    if body.len() as u64 > PLUGIN_HTTP_MAX_BYTES {
+        return Err(format!(
+            "response larger than {} bytes",
+            PLUGIN_HTTP_MAX_BYTES
+        ));
+    }
+    Ok(HttpResponse {
+        status,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+// The error with its causes, the resolver tells why a host is refused in one of them
+fn describe(error: &dyn std::error::Error) -> String {
+    let mut text = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        text.push_str(": ");
+        text.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    text
+}
+
+// http or https to one of the approved domains, redirects included, named and not local
+fn check_url(domains: &[String], url: &Url) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("scheme '{}' is not allowed", url.scheme()));
+    }
+    let host = url.host_str().unwrap_or_default().trim_end_matches('.');
+    if host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+        return Err(format!("'{}' is an IP address, not a domain", host));
+    }
+    if host == "localhost" || host.ends_with(".localhost") {
+        return Err(format!("'{}' is this machine", host));
+    }
+    if !domain_allowed(domains, host) {
+        return Err(format!("'{}' is not an allowed domain of the plugin", host));
+    }
+    Ok(())
+}
+
+// Addresses a plugin may connect to: none of this machine or of the local network
+fn public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || first == 0
+                || (first == 100 && (64..128).contains(&second)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => public_address(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || first & 0xfe00 == 0xfc00 // unique local
+                    || first & 0xffc0 == 0xfe80) // link-local
+            }
+        },
+    }
+}
+
+// Resolves the hosts of the requests and of their redirects, refuses the names with an address
+// that is not public: what is connected to is what was checked
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if let Some(addr) = addrs.iter().find(|addr| !public_address(addr.ip())) {
+                return Err(
+                    format!("'{}' resolves to the local address {}", host, addr.ip()).into(),
+                );
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+fn http_client(domains: &[String]) -> Result<Client, String> {
+    let domains = domains.to_vec();
+    // a proxy would resolve the host in place of the resolver
+    let builder = reqwest::Client::builder()
+        .no_proxy()
+        .dns_resolver(Arc::new(PublicResolver));
+    reqwest::blocking::ClientBuilder::from(builder)
+        .timeout(Duration::from_secs(PLUGIN_HTTP_TIMEOUT_SECS))
+        .redirect(Policy::custom(move |attempt| {
+            match check_url(&domains, attempt.url()) {
+                Err(e) => attempt.error(e),
+                Ok(()) if attempt.previous().len() >= MAX_REDIRECTS => {
+                    attempt.error("too many redirects")
+                }
+                Ok(()) => attempt.follow(),
+            }
+        }))
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+// A module written by hand in the text format (wasmtime reads it like a binary module),
+// with the exports declare_plugin! gives a plugin built for wasm32
+#[cfg(test)]
+pub(crate) fn test_module(name: &str, memory_pages: u32) -> std::path::PathBuf {
+    const MODULE: &str = r#"(module
+  (import "epikodi" "http_fetch" (func $http_fetch (param i32) (result i32)))
+  (memory (export "memory") PAGES)
+  (global $next (mut i32) (i32.const 0x1000))
+  ;; descriptor: abi 1, name, version, no author, 3 capabilities
+  (data (i32.const 0x100) "\01\00\00\00\00\02\00\00\10\02\00\00\00\00\00\00\00\03\00\00\03\00\00\00")
+  (data (i32.const 0x200) "Wasm\00")
+  (data (i32.const 0x210) "1.0\00")
+  (data (i32.const 0x300) "\20\03\00\00\30\03\00\00\40\03\00\00")
+  (data (i32.const 0x320) "lyrics\00")
+  (data (i32.const 0x330) "subtitles\00")
+  (data (i32.const 0x340) "artwork\00")
+  (data (i32.const 0x400) "{\"schema_version\":1,\"status\":\"found\",\"value\":{\"plain\":\"la la la\"}}\00")
+  (data (i32.const 0x500) "[\"example.org\"]\00")
+  (func (export "epikodi_plugin_descriptor") (result i32) (i32.const 0x100))
+  (func (export "epikodi_alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (i32.store8 (i32.add (local.get $ptr) (local.get $len)) (i32.const 0))
+    (global.set $next (i32.add (local.get $ptr) (i32.add (local.get $len) (i32.const 1))))
+    (local.get $ptr))
+  (func (export "free_string") (param i32))
+  (func (export "allowed_domains") (result i32) (i32.const 0x500))
+  (func (export "lyrics") (param i32) (result i32) (i32.const 0x400))
+  ;; never returns
+  (func (export "search_subtitles") (param i32) (result i32) (loop $spin (br $spin)) (i32.const 0))
+  ;; traps
+  (func (export "download_subtitle") (param i32) (result i32) (unreachable))
+  ;; gives the request to the host and answers what it fetched
+  (func (export "artwork") (param i32) (result i32) (call $http_fetch (local.get 0))))
+"#;
+    let dir = std::env::temp_dir().join("epikodi_wasm_plugins");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.wasm", name));
+    std::fs::write(&path, MODULE.replace("PAGES", &memory_pages.to_string())).unwrap();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plugin_api::providers::{Lyrics, ProviderResponse, ProviderResult};
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn modules_answer_like_libraries() {
+        let mut plugin = WasmPlugin::load(&test_module("answers", 1), SECOND).unwrap();
+        assert_eq!(plugin.manifest.name, "Wasm");
+        assert_eq!(
+            plugin.manifest.capabilities,
+            vec!["lyrics", "subtitles", "artwork"]
+        );
+        assert_eq!(plugin.domains, vec!["example.org".to_string()]);
+
+        let answer = plugin
+            .call("lyrics", r#"{"title": "Lithium"}"#, SECOND)
+            .unwrap();
+        assert_eq!(
+            ProviderResponse::<Lyrics>::from_json(&answer)
+                .unwrap()
+                .result,
+            ProviderResult::Found {
+                value: Lyrics {
+                    plain: Some("la la la".to_string()),
+                    ..Default::default()
+                }
+            }
+        );
+        assert!(matches!(
+            plugin.call("scrobble", "{}", SECOND),
+            Err(MetadataError::Plugin(_))
+        ));
+        assert_eq!(plugin.configure(&PluginSettings::default(), SECOND), Ok(()));
+    }
+
+    #[test]
+    fn traps_only_fail_the_call() {
+        let mut plugin = WasmPlugin::load(&test_module("traps", 1), SECOND).unwrap();
+        plugin.fuel = 1_000_000;
+        assert_eq!(
+            plugin.call("search_subtitles", "{}", SECOND),
+            Err(MetadataError::Timeout)
+        );
+        assert!(matches!(
+            plugin.call("download_subtitle", "{}", SECOND),
+            Err(MetadataError::Crashed(_))
+        ));
+        // a new instance answers the next call
+        assert!(plugin.call("lyrics", "{}", SECOND).is_ok());
+
+        // 2 GiB of memory asked at instantiation
+        let greedy = WasmPlugin::load(&test_module("greedy", 32768), SECOND);
+        assert!(greedy.is_err_and(|e| e.starts_with("cannot instantiate")));
+    }
+
+    #[test]
+    fn calls_end_at_their_timeout_with_fuel_left() {
+        let mut plugin = WasmPlugin::load(&test_module("epoch", 1), SECOND).unwrap();
+        plugin.fuel = u64::MAX;
+        let calling = Instant::now();
+        assert_eq!(
+            plugin.call("search_subtitles", "{}", Duration::from_millis(200)),
+            Err(MetadataError::Timeout)
+        );
+        assert!(calling.elapsed() < SECOND);
+        // the plugin is free again, with a full timeout for the next call
+        assert!(plugin.call("lyrics", "{}", SECOND).is_ok());
+    }
+
+    #[test]
+    fn requests_cost_fuel_and_time() {
+        let mut plugin = WasmPlugin::load(&test_module("fetch_budget", 1), SECOND).unwrap();
+        let request = serde_json::to_string(&HttpRequest::get("https://example.org/")).unwrap();
+        plugin.fuel = PLUGIN_HTTP_FUEL / 2;
+        assert_eq!(
+            plugin.call("artwork", &request, SECOND),
+            Err(MetadataError::Timeout)
+        );
+        plugin.fuel = PLUGIN_WASM_FUEL;
+        assert_eq!(
+            plugin.call("artwork", &request, Duration::ZERO),
+            Err(MetadataError::Timeout)
+        );
+    }
+
+    #[test]
+    fn requests_only_reach_the_declared_domains() {
+        let mut plugin = WasmPlugin::load(&test_module("http", 1), SECOND).unwrap();
+        let fetched = |plugin: &mut WasmPlugin, url: &str| -> FetchAnswer {
+            let request = serde_json::to_string(&HttpRequest::get(url)).unwrap();
+            serde_json::from_str(&plugin.call("artwork", &request, SECOND).unwrap()).unwrap()
+        };
+        // nothing is reachable until the user approves the domains
+        assert!(plugin.approved.is_empty());
+        let refused = fetched(&mut plugin, "https://example.org/").unwrap_err();
+        assert!(refused.contains("not an allowed domain"));
+        plugin
+            .approve(&["example.org".to_string(), "example.com".to_string()])
+            .unwrap();
+        assert_eq!(plugin.approved, vec!["example.org".to_string()]);
+        let refused = fetched(&mut plugin, "https://example.com/secrets").unwrap_err();
+        assert!(refused.contains("not an allowed domain"));
+        let refused = fetched(&mut plugin, "file:///etc/passwd").unwrap_err();
+        assert!(refused.contains("scheme 'file'"));
+        assert!(fetched(&mut plugin, "not a url").is_err());
+
+        let example = vec!["example.org".to_string()];
+        assert!(check_url(&example, &Url::parse("http://www.example.org/a").unwrap()).is_ok());
+        assert!(check_url(
+            &example,
+            &Url::parse("https://example.org.evil.com").unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn requests_never_reach_local_addresses() {
+        let domains: Vec<String> = ["localhost", "127.0.0.1", "10.0.0.1", "[::1]", "example.org"]
+            .iter()
+            .map(|domain| domain.to_string())
+            .collect();
+        for url in [
+            "http://127.0.0.1/",
+            "http://2130706433/",
+            "http://10.0.0.1/",
+            "http://[::1]/",
+            "http://localhost/",
+            "http://api.localhost./",
+        ] {
+            assert!(
+                check_url(&domains, &Url::parse(url).unwrap()).is_err(),
+                "{}",
+                url
+            );
+        }
+
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fc00::1",
+            "fe80::1",
+            "::ffff:192.168.1.1",
+        ] {
+            assert!(!public_address(ip.parse().unwrap()), "{}", ip);
+        }
+        assert!(public_address("93.184.216.34".parse().unwrap()));
+        assert!(public_address("2606:2800:220:1::1".parse().unwrap()));
+
+        // a name the url check lets through is refused once resolved, before any connection
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!(
+            "http://localhost:{}/",
+            listener.local_addr().unwrap().port()
+        );
+        let refused = http_client(&domains).unwrap().get(&url).send().unwrap_err();
+        assert!(describe(&refused).contains("resolves to the local address"));
+        assert!(listener.accept().is_err());
+    }
+
+    #[test]
+    fn descriptors_are_checked() {
+        let mut memory = vec![0u8; 64];
+        assert_eq!(read_descriptor(&memory, 0), Err(AbiError::NullDescriptor));
+        memory[8] = 2;
+        assert_eq!(
+            read_descriptor(&memory, 8),
+            Err(AbiError::IncompatibleVersion(2))
+        );
+        memory[8] = 1;
+        assert_eq!(
+            read_descriptor(&memory, 8),
+            Err(AbiError::MissingField("name"))
+        );
+        // pointers past the end of the memory are not followed
+        memory[12..16].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(
+            read_descriptor(&memory, 8),
+            Err(AbiError::MissingField("name"))
+        );
+        assert!(read_string(&memory, 60).is_ok());
+        assert!(read_string(&memory, 64).is_err());
+    }
+}
@@ -0,0 +1,498 @@
+/*
+This file finds the media type of a file from its content, so each file is indexed with exactly
+one type: the magic bytes give the container, and for the containers that can hold sound or
+pictures (MP4/QuickTime, Matroska/WebM, Ogg) the tracks are inspected.
+The extension lists only select the files to look at, and give the type when the content is unknown.
+*/
+
+use crate::database::db::FileStamp;
+use crate::library::sources::MediaExtensions;
+use crate::media::data::MediaType;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// bytes read at the start of every file
+const HEADER_LEN: u64 = 4096;
+// Matroska puts its tracks after the seek head and the segment info, a bit further
+const MATROSKA_PROBE_LEN: u64 = 64 * 1024;
+// bigger MP4 'moov' boxes are not read, the extension decides
+const MAX_MOOV_LEN: u64 = 16 * 1024 * 1024;
+
+// Classifies each path once per scan, the music, video and image sources often share their folders.
+// A file of the database whose size and mtime did not change keeps its type, it is not read again.
+pub struct Classifier<'a> {
+    extensions: MediaExtensions,
+    known: &'a HashMap<String, FileStamp>,
+    cache: Mutex<HashMap<PathBuf, Option<MediaType>>>,
+}
+
+impl<'a> Classifier<'a> {
+    pub fn new(extensions: MediaExtensions, known: &'a HashMap<String, FileStamp>) -> Self {
+        Self {
+            extensions,
+            known,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // `size` and `mtime` as the scan stamps them (see scan::file_stamp)
+    pub fn media_type(&self, path: &Path, size: u64, mtime: i64) -> Option<MediaType> {
+        if let Some(media_type) = self.cache.lock().unwrap().get(path) {
+            return media_type.clone();
+        }
+        let known = self
+            .known
+            .get(path.to_string_lossy().as_ref())
+            .filter(|stamp| stamp.matches(size, mtime));
+        let media_type = match known {
+            // still one of the listed extensions
+            Some(stamp) => self
+                .extensions
+                .type_of(path)
+                .map(|_| stamp.media_type.clone()),
+            // read outside the lock, the workers classify in parallel
+            None => classify(path, &self.extensions),
+        };
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), media_type.clone());
+        media_type
+    }
+}
+
+// None if the extension is in no list, else the type found in the content or given by the extension
+pub fn classify(path: &Path, extensions: &MediaExtensions) -> Option<MediaType> {
+    let by_extension = extensions.type_of(path)?;
+    Some(sniff(path).unwrap_or(by_extension))
+}
+
+// Type read from the content, None when the file is empty, unreadable or of an unknown format
+pub fn sniff(path: &Path) -> Option<MediaType> {
+    let mut file = File::open(path).ok()?;
+    let header = read_at(&mut file, 0, HEADER_LEN)?;
+
+    if let Some(media_type) = sniff_header(&header) {
+        return Some(media_type);
+    }
+    if header.get(4..8) == Some(b"ftyp") {
+        return sniff_iso_media(&header, &mut file);
+    }
+    if header.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        let probe = read_at(&mut file, 0, MATROSKA_PROBE_LEN)?;
+        return sniff_matroska(&probe);
+    }
+    if header.starts_with(b"OggS") {
+        return sniff_ogg(&header);
+    }
+    None
+}
+
+// Formats recognised from their first bytes only
+fn sniff_header(header: &[u8]) -> Option<MediaType> {
+    let starts = |magic: &[u8]| header.starts_with(magic);
+    let riff_kind = header.get(8..12).filter(|_| starts(b"RIFF"));
+
+    // images
+    if starts(&[0xFF, 0xD8, 0xFF])
+        || starts(b"\x89PNG\r\n\x1a\n")
+        || starts(b"GIF87a")
+        || starts(b"GIF89a")
+        || starts(b"BM")
+        || starts(b"II*\0")
+        || starts(b"MM\0*")
+        || riff_kind == Some(b"WEBP")
+    {
+        return Some(MediaType::Image);
+    }
+
+    // sound
+    if starts(b"ID3")
+        || starts(b"fLaC")
+        || starts(b"#!AMR")
+        || riff_kind == Some(b"WAVE")
+        || (starts(b"FORM") && matches!(header.get(8..12), Some(b"AIFF") | Some(b"AIFC")))
+        // MPEG audio frame or ADTS (AAC) sync word
+        || (header.len() > 1 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0)
+    {
+        return Some(MediaType::Audio);
+    }
+
+    // video: AVI, MPEG program stream, MPEG transport stream (a sync byte every 188 bytes)
+    if riff_kind == Some(b"AVI ")
+        || starts(&[0x00, 0x00, 0x01, 0xBA])
+        || (header.len() > 188 && header[0] == 0x47 && header[188] == 0x47)
+    {
+        return Some(MediaType::Video);
+    }
+    None
+}
+
+// MP4, M4A, MOV, HEIF...: the brand first, then the handlers of the tracks in the 'moov' box
+fn sniff_iso_media(header: &[u8], file: &mut File) -> Option<MediaType> {
+    let brand = header.get(8..12)?;
+    match brand {
+        b"heic" | b"heix" | b"hevc" | b"heim" | b"heis" | b"mif1" | b"msf1" | b"avif" | b"avis" => {
+            return Some(MediaType::Image)
+        }
+        b"M4A " | b"M4B " | b"M4P " | b"F4A " => return Some(MediaType::Audio),
+        _ => {}
+    }
+
+    let moov = find_top_level_box(file, b"moov")?;
+    let mut handlers = Vec::new();
+    for trak in child_boxes(&moov, b"trak") {
+        for mdia in child_boxes(trak, b"mdia") {
+            for hdlr in child_boxes(mdia, b"hdlr") {
+                // version and flags, pre_defined, then the handler type
+                if let Some(handler) = hdlr.get(8..12) {
+                    handlers.push(handler);
+                }
+            }
+        }
+    }
+    tracks_type(
+        handlers.contains(&&b"vide"[..]),
+        handlers.contains(&&b"soun"[..]),
+    )
+}
+
+// Reads a top level box of an MP4 file, the 'moov' box is often at the end of the file
+fn find_top_level_box(file: &mut File, kind: &[u8; 4]) -> Option<Vec<u8>> {
+    let file_len = file.metadata().ok()?.len();
+    let mut pos = 0;
+    while pos + 8 <= file_len {
+        let header = read_at(file, pos, 16)?;
+        let size = u32::from_be_bytes(header.get(0..4)?.try_into().ok()?) as u64;
+        let (size, header_len) = match size {
+            0 => (file_len - pos, 8),
+            1 => (u64::from_be_bytes(header.get(8..16)?.try_into().ok()?), 16),
+            size => (size, 8),
+        };
+        if size < header_len {
+            return None;
+        }
+        if header.get(4..8)? == kind {
+            if size - header_len > MAX_MOOV_LEN {
+                return None;
+            }
+            return read_at(file, pos + header_len, size - header_len);
+        }
+        pos = pos.checked_add(size)?;
+    }
+    None
+}
+
+// Content of the direct children of `kind` in an in-memory box
+fn child_boxes<'a>(data: &'a [u8], kind: &[u8; 4]) -> Vec<&'a [u8]> {
+    let mut children = Vec::new();
+    let mut rest = data;
+    while rest.len() >= 8 {
+        let size = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let (size, header_len) = match size {
+            0 => (rest.len(), 8),
+            1 if rest.len() >= 16 => {
+                let large = u64::from_be_bytes(rest[8..16].try_into().unwrap());
+                (usize::try_from(large).unwrap_or(usize::MAX), 16)
+            }
+            size => (size, 8),
+        };
+        if size < header_len || size > rest.len() {
+            break;
+        }
+        if &rest[4..8] == kind {
+            children.push(&rest[header_len..size]);
+        }
+        rest = &rest[size..];
+    }
+    children
+}
+
+// Matroska and WebM: the codec ids of the tracks start with "V_" (video) or "A_" (audio)
+fn sniff_matroska(probe: &[u8]) -> Option<MediaType> {
+    // CodecID element (0x86), one byte size, then the id
+    let codec_id = |prefix: &[u8; 2]| {
+        probe
+            .windows(4)
+            .any(|w| w[0] == 0x86 && w[1] & 0x80 != 0 && &w[2..4] == prefix)
+    };
+    tracks_type(codec_id(b"V_"), codec_id(b"A_"))
+}
+
+// Ogg: the first page of each stream names its codec
+fn sniff_ogg(header: &[u8]) -> Option<MediaType> {
+    let contains = |codec: &[u8]| header.windows(codec.len()).any(|w| w == codec);
+    let video = contains(b"\x80theora") || contains(b"\x01video");
+    let audio = contains(b"\x01vorbis")
+        || contains(b"OpusHead")
+        || contains(b"\x7fFLAC")
+        || contains(b"Speex   ");
+    tracks_type(video, audio)
+}
+
+// A file with a picture track is a video, a file with only sound is audio
+fn tracks_type(video: bool, audio: bool) -> Option<MediaType> {
+    if video {
+        Some(MediaType::Video)
+    } else if audio {
+        Some(MediaType::Audio)
+    } else {
+        None
+    }
+}
+
+// Up to `len` bytes from `offset`, fewer at the end of the file
+fn read_at(file: &mut File, offset: u64, len: u64) -> Option<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut buffer = Vec::new();
+    file.by_ref().take(len).read_to_end(&mut buffer).ok()?;
+    Some(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("epikodi_classify_{label}_{stamp}"));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn mp4_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut data = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(content);
+        data
+    }
+
+    // ftyp, some media data, then a 'moov' with one track per handler (like a "fast start" file)
+    fn mp4_file(brand: &[u8; 4], handlers: &[&[u8; 4]]) -> Vec<u8> {
+        let mut ftyp = brand.to_vec();
+        ftyp.extend_from_slice(&[0, 0, 0, 0]);
+        ftyp.extend_from_slice(b"isommp42");
+
+        let mut moov = mp4_box(b"mvhd", &[0; 100]);
+        for handler in handlers {
+            let mut hdlr = vec![0; 8];
+            hdlr.extend_from_slice(*handler);
+            hdlr.extend_from_slice(&[0; 13]);
+            let mdia = [mp4_box(b"mdhd", &[0; 24]), mp4_box(b"hdlr", &hdlr)].concat();
+            let trak = [mp4_box(b"tkhd", &[0; 84]), mp4_box(b"mdia", &mdia)].concat();
+            moov.extend(mp4_box(b"trak", &trak));
+        }
+
+        [
+            mp4_box(b"ftyp", &ftyp),
+            mp4_box(b"mdat", &vec![0xAB; 8192]),
+            mp4_box(b"moov", &moov),
+        ]
+        .concat()
+    }
+
+    fn matroska_file(doc_type: &[u8], codecs: &[&[u8]]) -> Vec<u8> {
+        let mut data = vec![0x1A, 0x45, 0xDF, 0xA3, 0x80 | (doc_type.len() as u8 + 3)];
+        data.extend_from_slice(&[0x42, 0x82, 0x80 | doc_type.len() as u8]);
+        data.extend_from_slice(doc_type);
+        data.extend_from_slice(&[0x18, 0x53, 0x80, 0x67, 0xFF]);
+        for codec in codecs {
+            data.extend_from_slice(&[0xAE, 0xFF, 0x86, 0x80 | codec.len() as u8]);
+            data.extend_from_slice(codec);
+        }
+        data
+    }
+
+    fn ogg_file(first_packet: &[u8]) -> Vec<u8> {
+        let mut data = b"OggS\0\x02".to_vec();
+        data.extend_from_slice(&[0; 20]);
+        data.push(first_packet.len() as u8);
+        data.extend_from_slice(first_packet);
+        data
+    }
+
+    fn classify_bytes(dir: &Path, name: &str, content: &[u8]) -> Option<MediaType> {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        classify(&path, &MediaExtensions::default())
+    }
+
+    #[test]
+    fn mp4_type_comes_from_its_tracks() {
+        let dir = temp_dir("mp4");
+
+        let video = mp4_file(b"isom", &[b"vide", b"soun"]);
+        let sound_only = mp4_file(b"isom", &[b"soun"]);
+        let m4a = mp4_file(b"M4A ", &[b"soun"]);
+        let heic = mp4_file(b"heic", &[]);
+
+        assert_eq!(
+            classify_bytes(&dir, "movie.mp4", &video),
+            Some(MediaType::Video)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "podcast.mp4", &sound_only),
+            Some(MediaType::Audio)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "song.m4a", &m4a),
+            Some(MediaType::Audio)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "clip.mov", &video),
+            Some(MediaType::Video)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "photo.heic", &heic),
+            Some(MediaType::Image)
+        );
+        // no track at all: the extension decides
+        assert_eq!(
+            classify_bytes(&dir, "empty.mp4", &mp4_file(b"isom", &[])),
+            Some(MediaType::Video)
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn matroska_and_ogg_type_comes_from_their_codecs() {
+        let dir = temp_dir("matroska");
+
+        let webm_video = matroska_file(b"webm", &[b"V_VP9", b"A_OPUS"]);
+        let webm_audio = matroska_file(b"webm", &[b"A_OPUS"]);
+        let mka = matroska_file(b"matroska", &[b"A_FLAC"]);
+        assert_eq!(
+            classify_bytes(&dir, "clip.webm", &webm_video),
+            Some(MediaType::Video)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "song.webm", &webm_audio),
+            Some(MediaType::Audio)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "album.mka", &mka),
+            Some(MediaType::Audio)
+        );
+
+        let vorbis = ogg_file(b"\x01vorbis\0\0\0\0");
+        let opus = ogg_file(b"OpusHead\x01\x02");
+        let theora = ogg_file(b"\x80theora\x03\x02");
+        assert_eq!(
+            classify_bytes(&dir, "song.ogg", &vorbis),
+            Some(MediaType::Audio)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "voice.opus", &opus),
+            Some(MediaType::Audio)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "clip.ogg", &theora),
+            Some(MediaType::Video)
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn magic_bytes_win_over_the_extension() {
+        let dir = temp_dir("magic");
+
+        let mut wave = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        wave.extend_from_slice(&[0; 32]);
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8 ".to_vec();
+        webp.extend_from_slice(&[0; 32]);
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+        assert_eq!(
+            classify_bytes(&dir, "sound.wav", &wave),
+            Some(MediaType::Audio)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "photo.webp", &webp),
+            Some(MediaType::Image)
+        );
+        // a png saved with the wrong extension
+        assert_eq!(
+            classify_bytes(&dir, "cover.mp3", png),
+            Some(MediaType::Image)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "song.mp3", b"ID3\x04\0"),
+            Some(MediaType::Audio)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "frame.mp3", &[0xFF, 0xFB, 0x90, 0x00]),
+            Some(MediaType::Audio)
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn unknown_content_falls_back_to_the_extension() {
+        let dir = temp_dir("fallback");
+
+        assert_eq!(
+            classify_bytes(&dir, "song.MP3", b""),
+            Some(MediaType::Audio)
+        );
+        assert_eq!(
+            classify_bytes(&dir, "movie.mkv", b"garbage"),
+            Some(MediaType::Video)
+        );
+        assert_eq!(classify_bytes(&dir, "notes.txt", b"ID3"), None);
+
+        // an extension listed for two types: video wins, unless the user removes it
+        let mut extensions = MediaExtensions::default();
+        extensions.audio.push("mp4".to_string());
+        let path = dir.join("unknown.mp4");
+        fs::write(&path, b"").unwrap();
+        assert_eq!(classify(&path, &extensions), Some(MediaType::Video));
+        extensions.video.retain(|e| e != "mp4");
+        assert_eq!(classify(&path, &extensions), Some(MediaType::Audio));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn unchanged_files_keep_their_stored_type() {
+        let dir = temp_dir("stamps");
+        let path = dir.join("clip.mp4");
+        fs::write(&path, b"ID3\x04\0").unwrap();
+        let stamp = FileStamp {
+            size: 5,
+            mtime: 1000,
+            media_type: MediaType::Video,
+        };
+        let known = HashMap::from([(path.to_string_lossy().to_string(), stamp)]);
+
+        // the content says audio, but the file is not read while its stamps are the same
+        let classifier = Classifier::new(MediaExtensions::default(), &known);
+        assert_eq!(
+            classifier.media_type(&path, 5, 1000),
+            Some(MediaType::Video)
+        );
+        let classifier = Classifier::new(MediaExtensions::default(), &known);
+        assert_eq!(
+            classifier.media_type(&path, 5, 2000),
+            Some(MediaType::Audio)
+        );
+        // an extension no longer listed is not a media anymore
+        let mut extensions = MediaExtensions::default();
+        extensions.video.retain(|e| e != "mp4");
+        let classifier = Classifier::new(extensions, &known);
+        assert_eq!(classifier.media_type(&path, 5, 1000), None);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
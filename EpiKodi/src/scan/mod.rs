@@ -1 +1,3 @@
+pub mod classify;
 pub mod scan;
+pub mod scanner;
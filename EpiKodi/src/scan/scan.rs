@@ -2,21 +2,43 @@
 
 */
 
+use crate::database::db::FileStamp;
+use crate::library::artwork::ArtworkCache;
 use crate::library::media_library::ScannedMedia;
-use crate::library::sources::LibraryConfig;
+use crate::library::sources::{LibraryConfig, MediaSource, SourceFilter};
+use crate::media::audio::Metadata;
 use crate::media::data::MediaType;
+use crate::scan::classify::Classifier;
 
-use crate::constants::{AUDIO_EXTS, IMAGE_EXTS, SOURCE_FILE, VIDEO_EXTS};
+use crate::constants::{
+    ARTWORK_CACHE_DIR, IGNORE_MARKERS, MAX_SCAN_WORKERS, SCAN_PROGRESS_EVERY, SOURCE_FILE,
+};
 
 use crate::constants::{LOG_FILE, LOG_FILE_MEDIA_ITEMS};
 use crate::logger::logger::Logger;
 
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::UNIX_EPOCH;
 
 pub struct Scan {
     pub libraries: LibraryConfig,
     pub scan: Vec<ScannedMedia>,
+    // path -> (size, mtime) already in the database, their tags are not read again
+    pub known_files: HashMap<String, FileStamp>,
+    pub artwork: ArtworkCache, // where the embedded covers are stored
+}
+
+// Progress of one source during a scan
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanProgress {
+    pub source: PathBuf,
+    pub files_seen: usize,    // media files found so far
+    pub files_indexed: usize, // media files already read
 }
 
 impl Scan {
@@ -24,138 +46,95 @@ impl Scan {
         Self {
             libraries: LibraryConfig::load(SOURCE_FILE),
             scan: Vec::new(),
+            known_files: HashMap::new(),
+            artwork: ArtworkCache::new(ARTWORK_CACHE_DIR),
         }
     }
 
     pub fn scan_libraries(&mut self) {
         let logger = Logger::new(LOG_FILE);
+        self.scan.clear();
 
         //=========== SCAN SOURCES ===========
 
-        let music_source_paths: Vec<_> = self
-            .libraries
-            .music_sources
-            .iter()
-            .map(|source| source.path.clone())
-            .collect();
-        let video_source_paths: Vec<_> = self
-            .libraries
-            .video_sources
-            .iter()
-            .map(|source| source.path.clone())
-            .collect();
-        let image_source_paths: Vec<_> = self
-            .libraries
-            .image_sources
-            .iter()
-            .map(|source| source.path.clone())
-            .collect();
-
         logger.info("Scanning sources ...");
-        for path in music_source_paths {
-            logger.info(&format!("Scanning: {}", path.display()));
-            self.scan_audio_libraries(&path);
-        }
-        for path in video_source_paths {
-            logger.info(&format!("Scanning: {}", path.display()));
-            self.scan_video_libraries(&path);
-        }
-        for path in image_source_paths {
-            logger.info(&format!("Scanning: {}", path.display()));
-            self.scan_image_libraries(&path);
-        }
+        let never_cancelled = AtomicBool::new(false);
+        self.scan = scan_sources(
+            &self.libraries,
+            &self.known_files,
+            &self.artwork,
+            &never_cancelled,
+            &|_| {},
+        )
+        .unwrap_or_default();
         logger.info("Scanning sources end");
 
         self.debug_print_items();
     }
 
     fn scan_audio_libraries(&mut self, folder: &Path) {
-        if let Ok(entries) = fs::read_dir(folder) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-
-                if path.is_dir() {
-                    // Recurse
-                    self.scan_audio_libraries(&path);
-                    continue;
-                }
-
-                // Extension check
-                let is_audio = path
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                    .map(|ext| AUDIO_EXTS.contains(&ext.to_ascii_lowercase().as_str()))
-                    .unwrap_or(false);
-
-                if is_audio {
-                    self.scan.push(ScannedMedia {
-                        path: path.to_string_lossy().to_string(),
-                        name: path.file_name().unwrap().to_string_lossy().to_string(),
-                        duration: 0.0, //TODO: get duration
-                        media_type: MediaType::Audio,
-                    });
-                }
-            }
-        }
+        self.scan_folder(folder, MediaType::Audio);
     }
 
     pub fn scan_video_libraries(&mut self, folder: &Path) {
-        if let Ok(entries) = fs::read_dir(folder) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-
-                if path.is_dir() {
-                    // Recurse
-                    self.scan_video_libraries(&path);
-                    continue;
-                }
-
-                let is_video = path
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                    .map(|ext| VIDEO_EXTS.contains(&ext.to_ascii_lowercase().as_str()))
-                    .unwrap_or(false);
-
-                if is_video {
-                    self.scan.push(ScannedMedia {
-                        path: path.to_string_lossy().to_string(),
-                        name: path.file_name().unwrap().to_string_lossy().to_string(),
-                        duration: 0.0, //TODO: get duration
-                        media_type: MediaType::Video,
-                    });
-                }
-            }
-        }
+        self.scan_folder(folder, MediaType::Video);
     }
 
     pub fn scan_image_libraries(&mut self, folder: &Path) {
-        if let Ok(entries) = fs::read_dir(folder) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-
-                if path.is_dir() {
-                    // Recurse
-                    self.scan_image_libraries(&path);
-                    continue;
-                }
+        self.scan_folder(folder, MediaType::Image);
+    }
 
-                // Extension check
-                let is_image = path
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                    .map(|ext| IMAGE_EXTS.contains(&ext.to_ascii_lowercase().as_str()))
-                    .unwrap_or(false);
-
-                if is_image {
-                    self.scan.push(ScannedMedia {
-                        path: path.to_string_lossy().to_string(),
-                        name: path.file_name().unwrap().to_string_lossy().to_string(),
-                        duration: 0.0, //TODO: get duration
-                        media_type: MediaType::Image,
-                    });
-                }
+    // Scans a folder as a source with the default rules
+    fn scan_folder(&mut self, folder: &Path, media_type: MediaType) {
+        let job = ScanJob::new(&MediaSource::new(folder.to_path_buf()), media_type);
+        let start = vec![Work::Folder(0, folder.to_path_buf(), 0)];
+        let classifier = Classifier::new(self.libraries.extensions.clone(), &self.known_files);
+        self.scan
+            .extend(self.run_jobs(vec![job], start, &classifier));
+    }
+
+    // Scans a single file or folder that changed on disk, for every source containing it.
+    // The rules of the source apply, returns nothing if the path no longer exists or is excluded.
+    pub fn scan_path(&mut self, path: &Path) -> Vec<ScannedMedia> {
+        if !path.exists() {
+            return Vec::new();
+        }
+
+        let classifier = Classifier::new(self.libraries.extensions.clone(), &self.known_files);
+        let mut jobs = Vec::new();
+        let mut start = Vec::new();
+        for (source, media_type) in sources_by_type(&self.libraries) {
+            if !path.starts_with(&source.path) {
+                continue;
+            }
+            let job = ScanJob::new(source, media_type);
+            if let Some(work) = job.start_at(jobs.len(), path, &classifier) {
+                start.push(work);
+                jobs.push(job);
             }
         }
+
+        self.run_jobs(jobs, start, &classifier)
+    }
+
+    fn run_jobs(
+        &self,
+        jobs: Vec<ScanJob>,
+        start: Vec<Work>,
+        classifier: &Classifier,
+    ) -> Vec<ScannedMedia> {
+        let never_cancelled = AtomicBool::new(false);
+        walk(
+            &jobs,
+            start,
+            classifier,
+            &self.known_files,
+            &self.artwork,
+            &never_cancelled,
+            &|_| {},
+        )
+        .map(|results| results.into_iter().map(|(_, media)| media).collect())
+        .unwrap_or_default()
     }
 
     // pour afficher la liste des items dans la bibliotheque
@@ -177,9 +156,369 @@ impl Scan {
     }
 }
 
+// Every source with the media type it is scanned for, music then video then image
+fn sources_by_type(libraries: &LibraryConfig) -> Vec<(&MediaSource, MediaType)> {
+    let mut sources = Vec::new();
+    for (list, media_type) in [
+        (&libraries.music_sources, MediaType::Audio),
+        (&libraries.video_sources, MediaType::Video),
+        (&libraries.image_sources, MediaType::Image),
+    ] {
+        for source in list {
+            sources.push((source, media_type.clone()));
+        }
+    }
+    sources
+}
+
+// One source to walk, with its rules compiled
+struct ScanJob {
+    root: PathBuf,
+    media_type: MediaType,
+    filter: SourceFilter,
+    follow_symlinks: bool,
+}
+
+impl ScanJob {
+    fn new(source: &MediaSource, media_type: MediaType) -> Self {
+        Self {
+            root: source.path.clone(),
+            media_type,
+            filter: SourceFilter::new(source),
+            follow_symlinks: source.follow_symlinks,
+        }
+    }
+
+    // First work item to scan `path` inside this source, None if the rules hide it
+    fn start_at(&self, index: usize, path: &Path, classifier: &Classifier) -> Option<Work> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let components: Vec<_> = relative.components().collect();
+        let is_dir = path.is_dir();
+        let folders = if is_dir {
+            components.len()
+        } else {
+            components.len().saturating_sub(1)
+        };
+
+        // the source and every folder down to the path must be visible
+        let mut folder = self.root.clone();
+        if has_ignore_marker(&folder) {
+            return None;
+        }
+        for (depth, component) in components.iter().take(folders).enumerate() {
+            folder.push(component);
+            let visible = self
+                .filter
+                .accepts_dir(folder.strip_prefix(&self.root).ok()?, depth + 1);
+            if !visible || has_ignore_marker(&folder) {
+                return None;
+            }
+        }
+
+        if is_dir {
+            return Some(Work::Folder(index, path.to_path_buf(), folders));
+        }
+        let (size, mtime) = file_stamp(&fs::metadata(path).ok()?);
+        if self.filter.accepts_file(relative, size)
+            && classifier.media_type(path, size, mtime).as_ref() == Some(&self.media_type)
+        {
+            Some(Work::File(index, path.to_path_buf()))
+        } else {
+            None
+        }
+    }
+
+    // Lists a folder: sub-folders and media files accepted by the rules become new work.
+    // A file is only kept by the job of its own media type.
+    fn list_folder(
+        &self,
+        index: usize,
+        folder: &Path,
+        depth: usize,
+        queue: &WorkQueue,
+        classifier: &Classifier,
+    ) -> usize {
+        let mut files = 0;
+        if has_ignore_marker(folder) {
+            return files;
+        }
+        let Ok(entries) = fs::read_dir(folder) else {
+            return files;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_link = entry.file_type().map(|t| t.is_symlink()).unwrap_or(false);
+            if is_link && !self.follow_symlinks {
+                continue;
+            }
+            // follows the links, broken ones are skipped
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let Ok(relative) = path.strip_prefix(&self.root) else {
+                continue;
+            };
+
+            if metadata.is_dir() {
+                if self.filter.accepts_dir(relative, depth + 1) {
+                    queue.push(Work::Folder(index, path, depth + 1));
+                }
+            } else if self.filter.accepts_file(relative, metadata.len()) {
+                let (size, mtime) = file_stamp(&metadata);
+                if classifier.media_type(&path, size, mtime).as_ref() == Some(&self.media_type) {
+                    files += 1;
+                    queue.push(Work::File(index, path));
+                }
+            }
+        }
+        files
+    }
+}
+
+fn has_ignore_marker(folder: &Path) -> bool {
+    IGNORE_MARKERS
+        .iter()
+        .any(|marker| folder.join(marker).exists())
+}
+
+// Walks every source on a pool of worker threads, folders and files share one queue
+// so a big source does not keep a single thread busy. The results are sorted by source then
+// path, so the same tree always gives the same scan. Returns None if the scan was cancelled.
+pub fn scan_sources(
+    libraries: &LibraryConfig,
+    known_files: &HashMap<String, FileStamp>,
+    artwork: &ArtworkCache,
+    cancel: &AtomicBool,
+    on_progress: &(dyn Fn(ScanProgress) + Sync),
+) -> Option<Vec<ScannedMedia>> {
+    let logger = Logger::new(LOG_FILE);
+    let classifier = Classifier::new(libraries.extensions.clone(), known_files);
+
+    let mut jobs = Vec::new();
+    let mut start = Vec::new();
+    for (source, media_type) in sources_by_type(libraries) {
+        logger.info(&format!("Scanning: {}", source.path.display()));
+        let job = ScanJob::new(source, media_type);
+        if let Some(work) = job.start_at(jobs.len(), &source.path, &classifier) {
+            start.push(work);
+        }
+        jobs.push(job);
+    }
+
+    let results = walk(
+        &jobs,
+        start,
+        &classifier,
+        known_files,
+        artwork,
+        cancel,
+        on_progress,
+    );
+    if results.is_none() {
+        logger.info("Scan cancelled");
+    }
+    results.map(|results| results.into_iter().map(|(_, media)| media).collect())
+}
+
+// Runs the workers until the queue is empty, sends the final progress of each job
+fn walk(
+    jobs: &[ScanJob],
+    start: Vec<Work>,
+    classifier: &Classifier,
+    known_files: &HashMap<String, FileStamp>,
+    artwork: &ArtworkCache,
+    cancel: &AtomicBool,
+    on_progress: &(dyn Fn(ScanProgress) + Sync),
+) -> Option<Vec<(usize, ScannedMedia)>> {
+    let queue = WorkQueue::new();
+    let seen: Vec<AtomicUsize> = jobs.iter().map(|_| AtomicUsize::new(0)).collect();
+    let indexed: Vec<AtomicUsize> = jobs.iter().map(|_| AtomicUsize::new(0)).collect();
+    let results: Mutex<Vec<(usize, ScannedMedia)>> = Mutex::new(Vec::new());
+    // folders already listed, only needed when symlinks are followed (loops)
+    let visited: Mutex<HashSet<(usize, PathBuf)>> = Mutex::new(HashSet::new());
+
+    for work in start {
+        if let Work::File(index, _) = &work {
+            seen[*index].fetch_add(1, Ordering::Relaxed);
+        }
+        queue.push(work);
+    }
+
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .clamp(1, MAX_SCAN_WORKERS);
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some(work) = queue.next(cancel) {
+                    match work {
+                        Work::Folder(index, folder, depth) => {
+                            let job = &jobs[index];
+                            let first_visit = !job.follow_symlinks
+                                || fs::canonicalize(&folder).is_ok_and(|real| {
+                                    visited.lock().unwrap().insert((index, real))
+                                });
+                            if first_visit {
+                                let files =
+                                    job.list_folder(index, &folder, depth, &queue, classifier);
+                                seen[index].fetch_add(files, Ordering::Relaxed);
+                            }
+                        }
+                        Work::File(index, path) => {
+                            let job = &jobs[index];
+                            let media =
+                                scanned_media(&path, job.media_type.clone(), known_files, artwork);
+                            results.lock().unwrap().push((index, media));
+
+                            let files_indexed = indexed[index].fetch_add(1, Ordering::Relaxed) + 1;
+                            if files_indexed.is_multiple_of(SCAN_PROGRESS_EVERY) {
+                                on_progress(ScanProgress {
+                                    source: job.root.clone(),
+                                    files_seen: seen[index].load(Ordering::Relaxed),
+                                    files_indexed,
+                                });
+                            }
+                        }
+                    }
+                    queue.done();
+                }
+            });
+        }
+    });
+
+    if cancel.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    for (index, job) in jobs.iter().enumerate() {
+        on_progress(ScanProgress {
+            source: job.root.clone(),
+            files_seen: seen[index].load(Ordering::Relaxed),
+            files_indexed: indexed[index].load(Ordering::Relaxed),
+        });
+    }
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by(|a, b| (a.0, &a.1.path).cmp(&(b.0, &b.1.path)));
+    Some(results)
+}
+
+enum Work {
+    Folder(usize, PathBuf, usize), // job index, folder to list, depth below the source
+    File(usize, PathBuf),          // job index, media file to read
+}
+
+// Work shared by the scan workers. A worker stops when the queue is empty
+// and nobody is still busy, since listing a folder can add more work.
+struct WorkQueue {
+    state: Mutex<(VecDeque<Work>, usize)>, // pending work, work in progress
+    changed: Condvar,
+}
+
+impl WorkQueue {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new((VecDeque::new(), 0)),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn push(&self, work: Work) {
+        self.state.lock().unwrap().0.push_back(work);
+        self.changed.notify_one();
+    }
+
+    fn next(&self, cancel: &AtomicBool) -> Option<Work> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                self.changed.notify_all();
+                return None;
+            }
+            if let Some(work) = state.0.pop_front() {
+                state.1 += 1;
+                return Some(work);
+            }
+            if state.1 == 0 {
+                self.changed.notify_all();
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn done(&self) {
+        self.state.lock().unwrap().1 -= 1;
+        self.changed.notify_all();
+    }
+}
+
+// Size and mtime in milliseconds of a file, compared with the FileStamp of the database
+pub fn file_stamp(metadata: &fs::Metadata) -> (u64, i64) {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+    (metadata.len(), mtime)
+}
+
+// Builds the scan entry of a file, size and mtime are used to skip unchanged files.
+// The tags and duration of audio and video files are read only when the file is new or modified.
+fn scanned_media(
+    path: &Path,
+    media_type: MediaType,
+    known_files: &HashMap<String, FileStamp>,
+    artwork: &ArtworkCache,
+) -> ScannedMedia {
+    let (size, mtime) = fs::metadata(path)
+        .map(|metadata| file_stamp(&metadata))
+        .unwrap_or((0, 0));
+
+    let path_str = path.to_string_lossy().to_string();
+    let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+
+    let changed = !known_files
+        .get(&path_str)
+        .is_some_and(|stamp| stamp.matches(size, mtime));
+    let metadata = match media_type {
+        MediaType::Audio | MediaType::Video if changed => Some(Metadata::with_picture(&path_str)),
+        _ => None,
+    };
+
+    match metadata {
+        Some((metadata, picture)) => ScannedMedia {
+            path: path_str,
+            name: metadata.title.unwrap_or(file_name),
+            duration: metadata.duration,
+            media_type,
+            size,
+            mtime,
+            tags: Some(metadata.tags),
+            // only the hash is kept, the picture goes to the cache right away
+            artwork: picture.and_then(|data| artwork.store(&data)),
+        },
+        None => ScannedMedia {
+            path: path_str,
+            name: file_name,
+            duration: 0.0,
+            media_type,
+            size,
+            mtime,
+            tags: None,
+            artwork: None,
+        },
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::library::sources::MediaExtensions;
     use std::fs;
     use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -257,4 +596,298 @@ mod tests {
 
         let _ = fs::remove_dir_all(dir);
     }
+
+    #[test]
+    fn scanned_media_records_size_and_mtime() {
+        let dir = temp_dir("stamp");
+        fs::write(dir.join("song.mp3"), b"1234").unwrap();
+
+        let mut scan = Scan::new();
+        scan.scan_audio_libraries(&dir);
+
+        assert_eq!(scan.scan[0].size, 4);
+        assert!(scan.scan[0].mtime > 0);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn scanned_media_reads_tags_only_for_changed_files() {
+        let dir = temp_dir("tags");
+        let song = dir.join("song.mp3");
+        fs::write(&song, b"not really an mp3").unwrap();
+
+        let mut scan = Scan::new();
+        scan.scan_audio_libraries(&dir);
+        let first = scan.scan.remove(0);
+        // unreadable file: the tags are empty but they were read
+        assert_eq!(first.tags, Some(Default::default()));
+        assert_eq!(first.name, "song.mp3");
+        assert_eq!(first.duration, 0.0);
+
+        scan.known_files.insert(
+            first.path.clone(),
+            FileStamp {
+                size: first.size,
+                mtime: first.mtime,
+                media_type: MediaType::Audio,
+            },
+        );
+        scan.scan_audio_libraries(&dir);
+        assert_eq!(scan.scan[0].tags, None);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn scanned_media_never_reads_image_tags() {
+        let dir = temp_dir("image_tags");
+        fs::write(dir.join("cover.jpg"), b"").unwrap();
+
+        let mut scan = Scan::new();
+        scan.scan_image_libraries(&dir);
+
+        assert_eq!(scan.scan[0].tags, None);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn scan_libraries_does_not_accumulate_results() {
+        let dir = temp_dir("rescan");
+        fs::write(dir.join("song.mp3"), b"").unwrap();
+
+        let mut scan = Scan {
+            libraries: LibraryConfig {
+                sources: vec![],
+                music_sources: vec![MediaSource::new(dir.clone())],
+                video_sources: vec![],
+                image_sources: vec![],
+                extensions: MediaExtensions::default(),
+            },
+            scan: Vec::new(),
+            known_files: HashMap::new(),
+            artwork: ArtworkCache::for_tests(),
+        };
+        scan.scan_libraries();
+        scan.scan_libraries();
+
+        assert_eq!(scan.scan.len(), 1);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn scan_path_only_uses_matching_sources() {
+        let dir = temp_dir("scan_path");
+        let sub = dir.join("album");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(sub.join("song.mp3"), b"").unwrap();
+        fs::write(sub.join("cover.jpg"), b"").unwrap();
+
+        let mut scan = Scan {
+            libraries: LibraryConfig {
+                sources: vec![],
+                music_sources: vec![MediaSource::new(dir.clone())],
+                video_sources: vec![],
+                image_sources: vec![],
+                extensions: MediaExtensions::default(),
+            },
+            scan: Vec::new(),
+            known_files: HashMap::new(),
+            artwork: ArtworkCache::for_tests(),
+        };
+
+        let found = scan.scan_path(&sub);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].path.ends_with("song.mp3"));
+
+        assert_eq!(scan.scan_path(&sub.join("song.mp3")).len(), 1);
+        assert!(scan.scan_path(&sub.join("missing.mp3")).is_empty());
+        assert!(scan.scan.is_empty());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    fn music_scan(source: MediaSource) -> Scan {
+        Scan {
+            libraries: LibraryConfig {
+                sources: vec![],
+                music_sources: vec![source],
+                video_sources: vec![],
+                image_sources: vec![],
+                extensions: MediaExtensions::default(),
+            },
+            scan: Vec::new(),
+            known_files: HashMap::new(),
+            artwork: ArtworkCache::for_tests(),
+        }
+    }
+
+    fn scanned_names(scan: &Scan) -> Vec<String> {
+        let mut names: Vec<String> = scan.scan.iter().map(|m| m.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn default_rules_skip_hidden_thumbnail_and_sample_folders() {
+        let dir = temp_dir("default_rules");
+        for folder in ["album", ".hidden", "@eaDir", "Sample"] {
+            fs::create_dir_all(dir.join(folder)).unwrap();
+            fs::write(dir.join(folder).join(format!("{folder}.mp3")), b"").unwrap();
+        }
+        fs::write(dir.join("._resource_fork.mp3"), b"").unwrap();
+
+        let mut scan = music_scan(MediaSource::new(dir.clone()));
+        scan.scan_libraries();
+
+        assert_eq!(scanned_names(&scan), vec!["album.mp3"]);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn include_exclude_and_min_size_rules() {
+        let dir = temp_dir("patterns");
+        fs::create_dir_all(dir.join("live")).unwrap();
+        fs::write(dir.join("big.flac"), vec![0u8; 2048]).unwrap();
+        fs::write(dir.join("small.flac"), b"tiny").unwrap();
+        fs::write(dir.join("song.mp3"), vec![0u8; 2048]).unwrap();
+        fs::write(dir.join("live").join("concert.flac"), vec![0u8; 2048]).unwrap();
+
+        let mut source = MediaSource::new(dir.clone());
+        source.include = vec!["*.flac".to_string()];
+        source.exclude = vec!["live/**".to_string()];
+        source.min_size = 1024;
+        let mut scan = music_scan(source);
+        scan.scan_libraries();
+
+        assert_eq!(scanned_names(&scan), vec!["big.flac"]);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn max_depth_limits_recursion() {
+        let dir = temp_dir("depth");
+        let deep = dir.join("a").join("b");
+        fs::create_dir_all(&deep).unwrap();
+        fs::write(dir.join("root.mp3"), b"").unwrap();
+        fs::write(dir.join("a").join("one.mp3"), b"").unwrap();
+        fs::write(deep.join("two.mp3"), b"").unwrap();
+
+        let mut source = MediaSource::new(dir.clone());
+        source.max_depth = Some(1);
+        let mut scan = music_scan(source.clone());
+        scan.scan_libraries();
+        assert_eq!(scanned_names(&scan), vec!["one.mp3", "root.mp3"]);
+
+        // the watcher respects the depth too
+        assert!(scan.scan_path(&deep.join("two.mp3")).is_empty());
+        assert!(scan.scan_path(&deep).is_empty());
+
+        source.max_depth = Some(0);
+        let mut scan = music_scan(source);
+        scan.scan_libraries();
+        assert_eq!(scanned_names(&scan), vec!["root.mp3"]);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn ignore_marker_files_hide_their_folder() {
+        let dir = temp_dir("markers");
+        for folder in ["kept", "nomedia", "ignored"] {
+            fs::create_dir_all(dir.join(folder).join("sub")).unwrap();
+            fs::write(
+                dir.join(folder).join("sub").join(format!("{folder}.mp3")),
+                b"",
+            )
+            .unwrap();
+        }
+        fs::write(dir.join("nomedia").join(".nomedia"), b"").unwrap();
+        fs::write(dir.join("ignored").join(".ignore"), b"").unwrap();
+
+        let mut scan = music_scan(MediaSource::new(dir.clone()));
+        scan.scan_libraries();
+        assert_eq!(scanned_names(&scan), vec!["kept.mp3"]);
+
+        let hidden = dir.join("nomedia").join("sub").join("nomedia.mp3");
+        assert!(scan.scan_path(&hidden).is_empty());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_followed_only_when_enabled_and_loops_stop() {
+        let dir = temp_dir("symlinks");
+        let outside = temp_dir("symlinks_target");
+        fs::write(outside.join("linked.mp3"), b"").unwrap();
+        fs::write(dir.join("song.mp3"), b"").unwrap();
+        std::os::unix::fs::symlink(&outside, dir.join("link")).unwrap();
+        // loop: a folder pointing back to the source
+        std::os::unix::fs::symlink(&dir, dir.join("loop")).unwrap();
+
+        let mut scan = music_scan(MediaSource::new(dir.clone()));
+        scan.scan_libraries();
+        assert_eq!(scanned_names(&scan), vec!["song.mp3"]);
+
+        let mut source = MediaSource::new(dir.clone());
+        source.follow_symlinks = true;
+        let mut scan = music_scan(source);
+        scan.scan_libraries();
+        assert_eq!(scanned_names(&scan), vec!["linked.mp3", "song.mp3"]);
+
+        let _ = fs::remove_dir_all(dir);
+        let _ = fs::remove_dir_all(outside);
+    }
+
+    #[test]
+    fn a_folder_shared_by_several_types_indexes_each_file_once() {
+        let dir = temp_dir("one_type");
+        fs::write(dir.join("song.mp3"), b"ID3\x04\0").unwrap();
+        fs::write(dir.join("movie.mp4"), b"").unwrap();
+        fs::write(dir.join("cover.jpg"), [0xFF, 0xD8, 0xFF, 0xE0]).unwrap();
+        // an mp4 with only sound
+        let mut podcast = b"\0\0\0\x14ftypM4A \0\0\0\0isom".to_vec();
+        podcast.extend_from_slice(&[0; 16]);
+        fs::write(dir.join("podcast.mp4"), podcast).unwrap();
+
+        let mut scan = Scan {
+            libraries: LibraryConfig {
+                sources: vec![],
+                music_sources: vec![MediaSource::new(dir.clone())],
+                video_sources: vec![MediaSource::new(dir.clone())],
+                image_sources: vec![MediaSource::new(dir.clone())],
+                extensions: MediaExtensions::default(),
+            },
+            scan: Vec::new(),
+            known_files: HashMap::new(),
+            artwork: ArtworkCache::for_tests(),
+        };
+        scan.scan_libraries();
+
+        let mut types: Vec<(String, MediaType)> = scan
+            .scan
+            .iter()
+            .map(|m| (m.name.clone(), m.media_type.clone()))
+            .collect();
+        types.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            types,
+            vec![
+                ("cover.jpg".to_string(), MediaType::Image),
+                ("movie.mp4".to_string(), MediaType::Video),
+                ("podcast.mp4".to_string(), MediaType::Audio),
+                ("song.mp3".to_string(), MediaType::Audio),
+            ]
+        );
+
+        // the watcher gives the same answer
+        assert_eq!(scan.scan_path(&dir.join("podcast.mp4")).len(), 1);
+
+        let _ = fs::remove_dir_all(dir);
+    }
 }
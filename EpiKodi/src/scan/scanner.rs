@@ -0,0 +1,322 @@
+/*
+This file runs the full library scan in the background so the media thread keeps answering
+commands meanwhile. The library is only locked to read the sources at the start and to write
+the result at the end, in one transaction: a cancelled scan leaves the database untouched.
+Only the rows as they were when the scan started can be removed by it, the watcher keeps
+applying changes while the scan runs.
+*/
+
+use crate::constants::LOG_FILE;
+use crate::library::media_library::{MediaLibrary, ScanReport};
+use crate::logger::logger::Logger;
+use crate::plugin::events::EventBus;
+use crate::scan::scan::{scan_sources, ScanProgress};
+use crate::threading::command::Event;
+use plugin_api::events::HostEvent;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+// Sent with Event::ScanFinished
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanSummary {
+    pub report: ScanReport,
+    pub files_scanned: usize,
+    pub cancelled: bool,
+    pub elapsed_ms: u64,
+}
+
+pub struct LibraryScanner {
+    cancel: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+    pub events: Option<EventBus>, // the plugins are told when a scan is over
+}
+
+impl LibraryScanner {
+    pub fn new() -> Self {
+        Self {
+            cancel: Arc::new(AtomicBool::new(false)),
+            worker: None,
+            events: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.worker
+            .as_ref()
+            .map(|worker| !worker.is_finished())
+            .unwrap_or(false)
+    }
+
+    // Starts a full scan, a scan already running is cancelled and replaced: it finishes on its
+    // own thread without writing anything
+    pub fn start(&mut self, library: Arc<Mutex<MediaLibrary>>, evt_tx: mpsc::Sender<Event>) {
+        self.cancel();
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.cancel = Arc::clone(&cancel);
+        let events = self.events.clone();
+
+        self.worker = Some(thread::spawn(move || {
+            let progress_tx = Mutex::new(evt_tx.clone());
+            let summary = run_scan(&library, &cancel, &|progress: ScanProgress| {
+                let _ = progress_tx.lock().unwrap().send(Event::ScanProgress {
+                    source: progress.source,
+                    files_seen: progress.files_seen,
+                    files_indexed: progress.files_indexed,
+                });
+            });
+
+            if let Some(events) = events {
+                events.publish(HostEvent::LibraryScanned {
+                    added: summary.report.added_ids.len(),
+                    updated: summary.report.updated_ids.len(),
+                    removed: summary.report.removed_ids.len(),
+                    cancelled: summary.cancelled,
+                });
+            }
+            let _ = evt_tx.send(Event::ScanFinished(summary));
+        }));
+    }
+
+    // Asks the running scan to stop, it sends a cancelled ScanFinished once stopped.
+    // Never waits, the media thread keeps answering meanwhile.
+    pub fn cancel(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+// Scans every source of the library and applies the result, unless `cancel` is set before the end
+pub fn run_scan(
+    library: &Mutex<MediaLibrary>,
+    cancel: &AtomicBool,
+    on_progress: &(dyn Fn(ScanProgress) + Sync),
+) -> ScanSummary {
+    let logger = Logger::new(LOG_FILE);
+    let started = Instant::now();
+
+    // copy what the scan needs, the library stays available while the files are read
+    let (libraries, known_files, artwork) = {
+        let library = library.lock().unwrap();
+        let known_files = library.database.get_file_stamps().unwrap_or_else(|e| {
+            logger.error(&format!("Cannot read the file stamps: {}", e));
+            HashMap::new()
+        });
+        (
+            library.scan_lib.libraries.clone(),
+            known_files,
+            library.scan_lib.artwork.clone(),
+        )
+    };
+
+    let scanned = scan_sources(&libraries, &known_files, &artwork, cancel, on_progress);
+
+    let mut summary = ScanSummary::default();
+    match scanned {
+        None => {
+            logger.info("Scan cancelled, the library was not modified");
+            summary.cancelled = true;
+        }
+        Some(scanned) => {
+            summary.files_scanned = scanned.len();
+            summary.report = library
+                .lock()
+                .unwrap()
+                .apply_scan_results_since(scanned, &known_files);
+        }
+    }
+    summary.elapsed_ms = started.elapsed().as_millis() as u64;
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::db::DB;
+    use crate::library::artwork::ArtworkCache;
+    use crate::library::media_library::ScannedMedia;
+    use crate::library::sources::{LibraryConfig, MediaExtensions, MediaSource};
+    use crate::media::data::MediaType;
+    use crate::scan::scan::Scan;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("epikodi_scanner_{label}_{stamp}"));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn test_library(music: PathBuf) -> Mutex<MediaLibrary> {
+        let mut library = MediaLibrary {
+            items: HashMap::new(),
+            scan_lib: Scan {
+                libraries: LibraryConfig {
+                    sources: vec![],
+                    music_sources: vec![MediaSource::new(music)],
+                    video_sources: vec![],
+                    image_sources: vec![],
+                    extensions: MediaExtensions::default(),
+                },
+                scan: Vec::new(),
+                known_files: HashMap::new(),
+                artwork: ArtworkCache::for_tests(),
+            },
+            database: DB {
+                conn: rusqlite::Connection::open_in_memory().unwrap(),
+                media_rows: Vec::new(),
+            },
+        };
+        library.database.init_db().unwrap();
+        Mutex::new(library)
+    }
+
+    #[test]
+    fn run_scan_indexes_nested_folders_and_reports_progress() {
+        let dir = temp_dir("run");
+        for album in 0..4 {
+            let sub = dir.join(format!("album{album}")).join("cd1");
+            fs::create_dir_all(&sub).unwrap();
+            for track in 0..10 {
+                fs::write(sub.join(format!("{track}.mp3")), b"").unwrap();
+            }
+        }
+        let library = test_library(dir.clone());
+        let progress = Mutex::new(Vec::new());
+
+        let summary = run_scan(&library, &AtomicBool::new(false), &|p| {
+            progress.lock().unwrap().push(p)
+        });
+
+        assert!(!summary.cancelled);
+        assert_eq!(summary.files_scanned, 40);
+        assert_eq!(summary.report.added_ids.len(), 40);
+        assert_eq!(library.lock().unwrap().get_all_media().len(), 40);
+
+        let progress = progress.into_inner().unwrap();
+        let last = progress.last().unwrap();
+        assert_eq!(last.source, dir);
+        assert_eq!((last.files_seen, last.files_indexed), (40, 40));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn cancelled_scan_leaves_database_untouched() {
+        let dir = temp_dir("cancel");
+        fs::write(dir.join("song.mp3"), b"").unwrap();
+        let library = test_library(dir.clone());
+
+        let summary = run_scan(&library, &AtomicBool::new(true), &|_| {});
+
+        assert!(summary.cancelled);
+        assert!(summary.report.is_empty());
+        let mut library = library.lock().unwrap();
+        assert!(library.database.get_all_media().unwrap().is_empty());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn rows_added_during_the_scan_are_kept() {
+        let dir = temp_dir("during");
+        let library = test_library(dir.clone());
+        let old = dir.join("old.mp3").to_string_lossy().into_owned();
+        let added = dir.join("added.mp3").to_string_lossy().into_owned();
+        let scanned = |path: &str| ScannedMedia {
+            path: path.to_string(),
+            name: "x".to_string(),
+            duration: 0.0,
+            media_type: MediaType::Audio,
+            size: 1,
+            mtime: 1,
+            tags: None,
+            artwork: None,
+        };
+        library
+            .lock()
+            .unwrap()
+            .database
+            .upsert_scanned_media(vec![scanned(&old)])
+            .unwrap();
+
+        // what the scan saw at its start, then the watcher adds a file it does not see
+        let known_files = library.lock().unwrap().database.get_file_stamps().unwrap();
+        library
+            .lock()
+            .unwrap()
+            .database
+            .upsert_scanned_media(vec![scanned(&added)])
+            .unwrap();
+        let report = library
+            .lock()
+            .unwrap()
+            .apply_scan_results_since(Vec::new(), &known_files);
+
+        assert_eq!(report.removed_ids.len(), 1);
+        let mut library = library.lock().unwrap();
+        let rows = library.database.get_all_media().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].path, added);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn scanner_sends_progress_then_finished() {
+        let dir = temp_dir("events");
+        fs::write(dir.join("song.mp3"), b"").unwrap();
+        let library = Arc::new(test_library(dir.clone()));
+        let (evt_tx, evt_rx) = mpsc::channel();
+
+        let mut scanner = LibraryScanner::new();
+        scanner.start(Arc::clone(&library), evt_tx);
+
+        let mut got_progress = false;
+        loop {
+            match evt_rx.recv_timeout(std::time::Duration::from_secs(5)) {
+                Ok(Event::ScanProgress { files_indexed, .. }) => {
+                    got_progress = true;
+                    assert_eq!(files_indexed, 1);
+                }
+                Ok(Event::ScanFinished(summary)) => {
+                    assert_eq!(summary.report.added_ids.len(), 1);
+                    break;
+                }
+                Ok(_) => {}
+                Err(_) => panic!("Expected ScanFinished event"),
+            }
+        }
+        assert!(got_progress);
+        // the worker ends right after sending ScanFinished
+        for _ in 0..100 {
+            if !scanner.is_running() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(!scanner.is_running());
+
+        // cancelling only asks, the scan reports that it stopped
+        let (evt_tx, evt_rx) = mpsc::channel();
+        scanner.start(Arc::clone(&library), evt_tx);
+        scanner.cancel();
+        loop {
+            match evt_rx.recv_timeout(std::time::Duration::from_secs(5)) {
+                Ok(Event::ScanFinished(_)) => break,
+                Ok(_) => {}
+                Err(_) => panic!("Expected ScanFinished event"),
+            }
+        }
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
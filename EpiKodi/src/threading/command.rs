@@ -1,8 +1,20 @@
 /*
 This file defines commands and events for media playback control.
 */
+use crate::library::media_library::ScanReport;
+use crate::library::music::{Album, AlbumTrack, Artist};
+use crate::library::nfo::NfoExport;
+use crate::library::search::{SearchFilters, SearchResult};
+use crate::library::series::{Episode, Show};
+use crate::library::sources::MediaSource;
 use crate::media::data::MediaInfo;
 use crate::media::data::MediaType;
+use crate::plugin::plugin_manager::{MetadataError, PluginMetadata, PluginStatus};
+use crate::scan::scanner::ScanSummary;
+use crate::threading::provider_lookup::SubtitleError;
+use plugin_api::metadata::{Metadata, MetadataImage};
+use plugin_api::providers::{Lyrics, SubtitleResult};
+use plugin_api::settings::PluginSettings;
 use std::path::PathBuf;
 
 pub enum Command {
@@ -10,7 +22,10 @@ pub enum Command {
 
     AddSource(PathBuf, MediaType),    // path, media type
     RemoveSource(PathBuf, MediaType), // path, media type
-    Reload(),
+    UpdateSource(MediaSource),        // new scan rules of an existing source
+    Reload(),                         // full rescan in the background
+    CancelScan(),                     // stops the running scan, the database is left as it was
+    ExportNfo(bool),                  // writes the .nfo sidecars, overwrite existing files
     /*
     TODO
 
@@ -25,6 +40,12 @@ pub enum Command {
     GetMediaFromType(MediaType),     // media type
     GetMediaFromTag(String),         // tag name
     GetMediaFromPlaylist(i64),       // playlist id
+    Search(String, SearchFilters),   // query, filters
+    GetShows(),                      // every show with its next episode to watch
+    GetShowEpisodes(i64),            // show id
+    GetArtists(),                    // every album artist and track artist
+    GetAlbums(Option<i64>),          // albums of an artist id, or every album
+    GetAlbumTracks(i64),             // album id
     UpdateMediaState(i64, i32, f64), // media id, status, time_stop
     /*
     TODO:
@@ -68,8 +89,20 @@ pub enum Command {
     delete playlist
     */
     GetArtistMetadataFromPlugin(String), // artist name
-    GetfilmMetadataFromPlugin(String),   // artist name
+    GetfilmMetadataFromPlugin(String),   // film name, cleaned like a file name
+    GetMovieMetadata(i64),               // media id, looked up with its parsed title and year
+    RefreshMovieMetadata(i64),           // media id, asks the plugins again even if cached
     GetPluginHistory,
+    GetPlugins(),                               // manifests of the loaded plugins
+    SavePluginSettings(String, PluginSettings), // plugin name, values of its settings form
+    SetPluginEnabled(String, bool),             // plugin name, kept across restarts
+    ApprovePluginDomains(String, bool),         // plugin name, lets it reach its declared domains or none
+    SetPluginPriority(String, Vec<String>),     // capability, plugin names asked first
+    ReloadPlugins(),                            // picks up the libraries added or replaced
+    GetLyrics(i64),                             // media id of a track with artist and title tags
+    SearchSubtitles(i64, Vec<String>),          // media id of a video, languages ("fr"...)
+    DownloadSubtitle(i64, String, String, bool), // media id, plugin, id of its SubtitleResult, overwrite
+    GetArtwork(i64),                            // media id
 }
 
 pub enum Event {
@@ -79,7 +112,42 @@ pub enum Event {
     Info(MediaInfo),
     IDList(Vec<i64>),
     MediaList(Vec<MediaInfo>),
+    SearchResults(Vec<SearchResult>),
+    ShowList(Vec<Show>),
+    EpisodeList(i64, Vec<Episode>), // show id, episodes sorted by season and number
+    ArtistList(Vec<Artist>),
+    AlbumList(Option<i64>, Vec<Album>), // artist id (None for every album), albums
+    AlbumTrackList(i64, Vec<AlbumTrack>), // album id, tracks sorted by disc and number
+    LibraryChanged(ScanReport),         // files changed on disk, sent by the watcher
+    ScanProgress {
+        source: PathBuf,
+        files_seen: usize,
+        files_indexed: usize,
+    },
+    ScanFinished(ScanSummary),
+    NfoExported(NfoExport),
     M3UList(Vec<crate::iptv::parser::TVChannel>),
     PlaylistList(Vec<(i64, String)>),
-    PluginDataReceived(String),
+    PluginList(Vec<PluginStatus>),
+    Metadata {
+        query: String, // artist name or movie lookup query
+        result: Result<Metadata, MetadataError>,
+        cached_at: Option<i64>, // unix seconds of the cached answer, None when just fetched
+    },
+    Lyrics {
+        media_id: i64,
+        result: Result<PluginMetadata<Lyrics>, MetadataError>,
+    },
+    Subtitles {
+        media_id: i64,
+        result: Result<Vec<PluginMetadata<SubtitleResult>>, MetadataError>,
+    },
+    SubtitleSaved {
+        media_id: i64,
+        result: Result<PathBuf, SubtitleError>, // path of the subtitle next to the video
+    },
+    Artwork {
+        media_id: i64,
+        result: Result<PluginMetadata<Vec<MetadataImage>>, MetadataError>,
+    },
 }
@@ -4,27 +4,65 @@ This file manages the media thread, which handles media playback commands
 
 use super::command::Command;
 use super::command::Event;
+use super::metadata_lookup::MetadataLookup;
+use super::playback_events::{event_media, PlaybackTracker};
+use super::provider_lookup::{scrobble_track, ProviderLookup, ScrobbleTracker};
+use super::worker_pool::WorkerPool;
+use crate::config::AppConfig;
+use crate::constants::PLUGIN_WORKERS;
 use crate::library::media_library::MediaLibrary;
+use crate::library::metadata_cache::now;
+use crate::library::movies::parse_movie_name;
 use crate::media::data::MediaType;
 
 use crate::music_download::MusicDownloader;
 
-use crate::plugin::plugin_manager::PluginManager;
+use crate::plugin::events::EventBus;
+use crate::plugin::plugin_manager::{parse_response, PluginCalls, PluginManager};
+use crate::scan::scanner::LibraryScanner;
+use crate::watcher::watcher::LibraryWatcher;
+use plugin_api::events::HostEvent;
 
 use std::path::PathBuf;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
 pub fn launch_media_thread(cmd_rx: mpsc::Receiver<Command>, evt_tx: mpsc::Sender<Event>) {
     let library = Arc::new(Mutex::new(MediaLibrary::new()));
     let lib_thread = Arc::clone(&library);
+    let config = AppConfig::load();
     let mut plugin_manager = PluginManager::new();
+    plugin_manager.timeout = Duration::from_secs(config.plugin_timeout_secs);
+    plugin_manager.out_of_process = config.plugins_out_of_process;
     plugin_manager.load_plugins();
+    // the plugins get the events on their own threads, publishing never waits for them
+    let plugin_events = EventBus::start(plugin_manager.subscribers.clone());
+    let plugin_manager = plugin_manager.shared();
+    // the lookups run there, a slow plugin holds a worker and not the commands behind it
+    let workers = WorkerPool::new(PLUGIN_WORKERS);
+    let metadata_lookup = MetadataLookup::new(
+        Arc::clone(&lib_thread),
+        Arc::clone(&plugin_manager),
+        evt_tx.clone(),
+        config.metadata_ttl_hours,
+        workers.clone(),
+    );
+    let provider_lookup = ProviderLookup::new(
+        Arc::clone(&lib_thread),
+        Arc::clone(&plugin_manager),
+        evt_tx.clone(),
+    );
 
     // let media_thread =
     thread::spawn(move || {
         let mut library = lib_thread.lock().unwrap();
-        library.init();
+        // the media already in the database are available right away, the scan runs in the background
+        library.open();
+
+        // keeps the library up to date when files change on disk
+        let mut watcher = LibraryWatcher::start(Arc::clone(&lib_thread), evt_tx.clone());
+        watcher.watch_sources(&library.scan_lib.libraries);
 
         
 
@@ -51,6 +89,12 @@ pub fn launch_media_thread(cmd_rx: mpsc::Receiver<Command>, evt_tx: mpsc::Sender
 
         drop(library);
 
+        let mut scanner = LibraryScanner::new();
+        scanner.events = Some(plugin_events.clone());
+        scanner.start(Arc::clone(&lib_thread), evt_tx.clone());
+        let mut scrobbles = ScrobbleTracker::default();
+        let mut playback = PlaybackTracker::default();
+
         loop {
             // TODO handle errors
             match cmd_rx.recv() {
@@ -69,22 +113,45 @@ pub fn launch_media_thread(cmd_rx: mpsc::Receiver<Command>, evt_tx: mpsc::Sender
                     library.add_source(path.clone(), MediaType::Video);
                     library.add_source(path.clone(), MediaType::Audio);
                     library.add_source(path, MediaType::Image);
+                    watcher.watch_sources(&library.scan_lib.libraries);
 
                     evt_tx
                         .send(Event::MediaList(library.get_all_media()))
                         .unwrap();
+                    drop(library);
+
+                    scanner.start(Arc::clone(&lib_thread), evt_tx.clone());
                 }
 
                 Ok(Command::AddSource(path, media_type)) => {
                     let mut library = lib_thread.lock().unwrap();
 
                     library.add_source(path, media_type);
+                    watcher.watch_sources(&library.scan_lib.libraries);
+                    drop(library);
+
+                    scanner.start(Arc::clone(&lib_thread), evt_tx.clone());
                 }
 
                 Ok(Command::RemoveSource(path, media_type)) => {
                     let mut library = lib_thread.lock().unwrap();
 
                     library.remove_source(path, media_type);
+                    watcher.watch_sources(&library.scan_lib.libraries);
+                    drop(library);
+
+                    scanner.start(Arc::clone(&lib_thread), evt_tx.clone());
+                }
+
+                Ok(Command::UpdateSource(source)) => {
+                    let mut library = lib_thread.lock().unwrap();
+
+                    library.scan_lib.libraries.update_source(source);
+                    watcher.watch_sources(&library.scan_lib.libraries);
+                    drop(library);
+
+                    // the rules changed: files now excluded are removed by the rescan
+                    scanner.start(Arc::clone(&lib_thread), evt_tx.clone());
                 }
 
                 Ok(Command::GetAllMedia()) => {
@@ -112,38 +179,102 @@ pub fn launch_media_thread(cmd_rx: mpsc::Receiver<Command>, evt_tx: mpsc::Sender
                     evt_tx.send(Event::IDList(media_list)).unwrap();
                 }
 
+                Ok(Command::Search(query, filters)) => {
+                    let library = lib_thread.lock().unwrap();
+                    let results = library.search(&query, &filters);
+                    evt_tx.send(Event::SearchResults(results)).unwrap();
+                }
+
+                Ok(Command::GetShows()) => {
+                    let library = lib_thread.lock().unwrap();
+                    evt_tx.send(Event::ShowList(library.get_shows())).unwrap();
+                }
+
+                Ok(Command::GetShowEpisodes(show_id)) => {
+                    let library = lib_thread.lock().unwrap();
+                    let episodes = library.get_show_episodes(show_id);
+                    evt_tx.send(Event::EpisodeList(show_id, episodes)).unwrap();
+                }
+
+                Ok(Command::GetArtists()) => {
+                    let library = lib_thread.lock().unwrap();
+                    evt_tx.send(Event::ArtistList(library.get_artists())).unwrap();
+                }
+
+                Ok(Command::GetAlbums(artist_id)) => {
+                    let library = lib_thread.lock().unwrap();
+                    let albums = library.get_albums(artist_id);
+                    evt_tx.send(Event::AlbumList(artist_id, albums)).unwrap();
+                }
+
+                Ok(Command::GetAlbumTracks(album_id)) => {
+                    let library = lib_thread.lock().unwrap();
+                    let tracks = library.get_album_tracks(album_id);
+                    evt_tx.send(Event::AlbumTrackList(album_id, tracks)).unwrap();
+                }
+
                 Ok(Command::UpdateMediaState(media_id, status, time_stop)) => {
                     let mut library = lib_thread.lock().unwrap();
                     library.update_media_status_and_time(media_id, status, time_stop, 0.0);
                 }
 
+                // the GUI asks for the media list again when the scan is finished
                 Ok(Command::Reload()) => {
+                    scanner.start(Arc::clone(&lib_thread), evt_tx.clone());
+                }
+
+                Ok(Command::CancelScan()) => {
+                    scanner.cancel();
+                }
+
+                Ok(Command::ExportNfo(overwrite)) => {
                     let mut library = lib_thread.lock().unwrap();
-                    library.reload();
-                    let media_list = library.get_all_media();
-                    evt_tx.send(Event::MediaList(media_list)).unwrap();
+                    let export = library.export_nfo(overwrite);
+                    evt_tx.send(Event::NfoExported(export)).unwrap();
                 }
 
                 Ok(Command::Play(id)) => {
                     let mut library = lib_thread.lock().unwrap();
                     library.play_id(id);
                     evt_tx.send(Event::NowPlaying(id)).unwrap();
+
+                    if let Some(info) = library.info_id(id) {
+                        // the scrobblers are told on a worker, like the lookups
+                        if let Some(track) = scrobble_track(&info) {
+                            let event = scrobbles.start(id, track, now());
+                            let lookup = provider_lookup.clone();
+                            workers.execute(move || lookup.scrobble(event));
+                        }
+                        plugin_events.publish(playback.start(&info, 0.0));
+                    }
                 }
 
                 Ok(Command::Pause(id)) => {
                     let mut library = lib_thread.lock().unwrap();
                     library.pause_id(id);
+                    if let Some(info) = library.info_id(id) {
+                        plugin_events.publish(playback.pause(&info, info.last_position));
+                    }
                 }
 
                 Ok(Command::Resume(id)) => {
                     let mut library = lib_thread.lock().unwrap();
                     library.resume_id(id);
                     evt_tx.send(Event::NowPlaying(id)).unwrap();
+                    if let Some(info) = library.info_id(id) {
+                        plugin_events.publish(playback.start(&info, info.last_position));
+                    }
                 }
 
                 Ok(Command::Stop(id)) => {
                     let mut library = lib_thread.lock().unwrap();
                     library.stop_id(id);
+                    if let Some(event) = library
+                        .info_id(id)
+                        .and_then(|info| playback.stop(&info, info.last_position))
+                    {
+                        plugin_events.publish(event);
+                    }
                 }
 
                 Ok(Command::Info(id)) => {
@@ -166,6 +297,13 @@ pub fn launch_media_thread(cmd_rx: mpsc::Receiver<Command>, evt_tx: mpsc::Sender
                 Ok(Command::AddTagToMedia(media_id, tag_id)) => {
                     let mut library = lib_thread.lock().unwrap();
                     library.add_tag_to_media(media_id, tag_id);
+                    let tag = library.get_all_tags().into_iter().find(|(id, _)| *id == tag_id);
+                    if let (Some(info), Some((_, tag))) = (library.info_id(media_id), tag) {
+                        plugin_events.publish(HostEvent::TagAdded {
+                            media: event_media(&info),
+                            tag,
+                        });
+                    }
                 }
 
                 Ok(Command::RemoveTagFromMedia(media_id, tag_id)) => {
@@ -235,33 +373,111 @@ pub fn launch_media_thread(cmd_rx: mpsc::Receiver<Command>, evt_tx: mpsc::Sender
                 Ok(Command::GetPluginHistory) => {
                     let lib = lib_thread.lock().unwrap();
                     if let Ok(history) = lib.database.get_all_artist_metadata() {
-                        for entry in history {
-                            evt_tx.send(Event::PluginDataReceived(entry)).unwrap();
+                        for (name, info) in history {
+                            evt_tx
+                                .send(Event::Metadata {
+                                    query: name,
+                                    result: parse_response(&info),
+                                    cached_at: None,
+                                })
+                                .unwrap();
                         }
                     }
                 }
 
-                Ok(Command::GetArtistMetadataFromPlugin(name)) => {
-                    let mut lib = lib_thread.lock().unwrap();
-                    let history = lib.database.get_all_artist_metadata().unwrap_or_default();
-
-                    if let Some(cached) = history.iter().find(|h| h.contains(&name)) {
-                        evt_tx
-                            .send(Event::PluginDataReceived(cached.clone()))
-                            .unwrap();
-                    } else {
-                        drop(lib);
-                        let response = plugin_manager.get_metadata(name.as_str());
-
-                        let mut lib = lib_thread.lock().unwrap();
-                        let _ = lib.database.save_artist_metadata(&name, &response);
-                        evt_tx.send(Event::PluginDataReceived(response)).unwrap();
+                Ok(Command::GetPlugins()) => {
+                    let statuses = plugin_manager.lock().unwrap().statuses();
+                    evt_tx.send(Event::PluginList(statuses)).unwrap();
+                }
+
+                // the refusal is kept in the status of the plugin, shown under its form
+                Ok(Command::SavePluginSettings(name, values)) => {
+                    let manager = Arc::clone(&plugin_manager);
+                    let evt_tx = evt_tx.clone();
+                    workers.execute(move || {
+                        if let Err(e) = manager.save_settings(&name, values) {
+                            println!("⚠️ [PLUGIN] Réglages de {} refusés : {}", name, e);
+                        }
+                        let statuses = manager.lock().unwrap().statuses();
+                        let _ = evt_tx.send(Event::PluginList(statuses));
+                    });
+                }
+
+                Ok(Command::SetPluginEnabled(name, enabled)) => {
+                    let mut manager = plugin_manager.lock().unwrap();
+                    if let Err(e) = manager.set_enabled(&name, enabled) {
+                        println!("⚠️ [PLUGIN] {} : {}", name, e);
+                    }
+                    evt_tx.send(Event::PluginList(manager.statuses())).unwrap();
+                }
+
+                Ok(Command::ApprovePluginDomains(name, approved)) => {
+                    let mut manager = plugin_manager.lock().unwrap();
+                    if let Err(e) = manager.approve_domains(&name, approved) {
+                        println!("⚠️ [PLUGIN] Domaines de {} : {}", name, e);
+                    }
+                    evt_tx.send(Event::PluginList(manager.statuses())).unwrap();
+                }
+
+                Ok(Command::SetPluginPriority(capability, plugins)) => {
+                    let mut manager = plugin_manager.lock().unwrap();
+                    if let Err(e) = manager.set_priority(&capability, plugins) {
+                        println!("⚠️ [PLUGIN] Ordre pour {} refusé : {}", capability, e);
                     }
+                    evt_tx.send(Event::PluginList(manager.statuses())).unwrap();
+                }
+
+                // the lookups in flight end with the old plugins, the next ones get the new ones
+                Ok(Command::ReloadPlugins()) => {
+                    let manager = Arc::clone(&plugin_manager);
+                    let evt_tx = evt_tx.clone();
+                    workers.execute(move || {
+                        manager.reload_plugins();
+                        let statuses = manager.lock().unwrap().statuses();
+                        println!("🔄 [PLUGIN] {} plugin(s) rechargé(s)", statuses.len());
+                        let _ = evt_tx.send(Event::PluginList(statuses));
+                    });
+                }
+
+                Ok(Command::GetLyrics(media_id)) => {
+                    let lookup = provider_lookup.clone();
+                    workers.execute(move || lookup.lyrics(media_id));
+                }
+
+                Ok(Command::SearchSubtitles(media_id, languages)) => {
+                    let lookup = provider_lookup.clone();
+                    workers.execute(move || lookup.subtitles(media_id, languages));
+                }
+
+                Ok(Command::DownloadSubtitle(media_id, plugin, id, overwrite)) => {
+                    let lookup = provider_lookup.clone();
+                    workers.execute(move || {
+                        lookup.download_subtitle(media_id, &plugin, &id, overwrite)
+                    });
+                }
+
+                Ok(Command::GetArtwork(media_id)) => {
+                    let lookup = provider_lookup.clone();
+                    workers.execute(move || lookup.artwork(media_id));
+                }
+
+                // the lookup reads the cache here and asks the plugins on its own thread,
+                // a slow plugin does not hold the commands behind it
+                Ok(Command::GetArtistMetadataFromPlugin(name)) => {
+                    metadata_lookup.artist(&name);
                 }
 
                 Ok(Command::GetfilmMetadataFromPlugin(name)) => {
-                    let response = plugin_manager.get_film_metadata(name.as_str());
-                    evt_tx.send(Event::PluginDataReceived(response)).unwrap();
+                    let query = parse_movie_name(&name).lookup_query();
+                    metadata_lookup.movie(&query, false);
+                }
+
+                Ok(Command::GetMovieMetadata(media_id)) => {
+                    metadata_lookup.media(media_id, false);
+                }
+
+                Ok(Command::RefreshMovieMetadata(media_id)) => {
+                    metadata_lookup.media(media_id, true);
                 }
 
                 Ok(Command::UpdateProgress(id, pos, total_duration)) => {
@@ -276,6 +492,16 @@ pub fn launch_media_thread(cmd_rx: mpsc::Receiver<Command>, evt_tx: mpsc::Sender
 
                     let mut library = lib_thread.lock().unwrap();
                     library.update_media_status_and_time(id, 1, pos as f64, total_duration);
+
+                    if let Some(event) = scrobbles.progress(id, pos, total_duration) {
+                        let lookup = provider_lookup.clone();
+                        workers.execute(move || lookup.scrobble(event));
+                    }
+                    if let Some(info) = library.info_id(id) {
+                        for event in playback.progress(&info, pos, total_duration) {
+                            plugin_events.publish(event);
+                        }
+                    }
                 }
 
                 Err(_) => break,
@@ -287,6 +513,7 @@ pub fn launch_media_thread(cmd_rx: mpsc::Receiver<Command>, evt_tx: mpsc::Sender
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::plugin::plugin_manager::MetadataError;
     use std::sync::mpsc;
     use std::thread;
     use std::time::Duration;
@@ -302,6 +529,16 @@ mod tests {
         (cmd_tx, evt_rx)
     }
 
+    // The startup scan runs in the background, its events are skipped
+    fn recv_event(evt_rx: &mpsc::Receiver<Event>) -> Result<Event, mpsc::RecvTimeoutError> {
+        loop {
+            match evt_rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(Event::ScanProgress { .. }) | Ok(Event::ScanFinished(_)) => continue,
+                other => return other,
+            }
+        }
+    }
+
     #[test]
     fn test_add_source_command() {
         let (cmd_tx, _evt_rx) = setup_thread();
@@ -323,7 +560,7 @@ mod tests {
 
         cmd_tx.send(Command::GetAllMedia()).unwrap();
 
-        match evt_rx.recv_timeout(Duration::from_secs(1)) {
+        match recv_event(&evt_rx) {
             Ok(Event::MediaList(_)) => {}
             _ => panic!("Expected MediaList event"),
         }
@@ -336,7 +573,7 @@ mod tests {
 
         cmd_tx.send(Command::GetMediaFromPath(path)).unwrap();
 
-        match evt_rx.recv_timeout(Duration::from_secs(1)) {
+        match recv_event(&evt_rx) {
             Ok(Event::MediaList(_)) => {}
             _ => panic!("Expected MediaList event"),
         }
@@ -352,7 +589,7 @@ mod tests {
             ))
             .unwrap();
 
-        match evt_rx.recv_timeout(Duration::from_secs(1)) {
+        match recv_event(&evt_rx) {
             Ok(Event::MediaList(_)) => {}
             _ => panic!("Expected MediaList event"),
         }
@@ -366,7 +603,7 @@ mod tests {
             .send(Command::GetMediaFromTag("action".to_string()))
             .unwrap();
 
-        match evt_rx.recv_timeout(Duration::from_secs(1)) {
+        match recv_event(&evt_rx) {
             Ok(Event::IDList(_)) => {}
             _ => panic!("Expected IDList event"),
         }
@@ -378,19 +615,135 @@ mod tests {
 
         cmd_tx.send(Command::GetMediaFromPlaylist(1)).unwrap();
 
-        match evt_rx.recv_timeout(Duration::from_secs(1)) {
+        match recv_event(&evt_rx) {
             Ok(Event::IDList(_)) => {}
             _ => panic!("Expected IDList event"),
         }
     }
 
+    #[test]
+    fn test_search_command() {
+        let (cmd_tx, evt_rx) = setup_thread();
+
+        cmd_tx
+            .send(Command::Search(
+                "anything".to_string(),
+                crate::library::search::SearchFilters::default(),
+            ))
+            .unwrap();
+
+        match recv_event(&evt_rx) {
+            Ok(Event::SearchResults(_)) => {}
+            _ => panic!("Expected SearchResults event"),
+        }
+    }
+
+    #[test]
+    fn test_series_commands() {
+        let (cmd_tx, evt_rx) = setup_thread();
+
+        cmd_tx.send(Command::GetShows()).unwrap();
+        match recv_event(&evt_rx) {
+            Ok(Event::ShowList(_)) => {}
+            _ => panic!("Expected ShowList event"),
+        }
+
+        cmd_tx.send(Command::GetShowEpisodes(1)).unwrap();
+        match recv_event(&evt_rx) {
+            Ok(Event::EpisodeList(1, _)) => {}
+            _ => panic!("Expected EpisodeList event"),
+        }
+    }
+
+    #[test]
+    fn test_music_commands() {
+        let (cmd_tx, evt_rx) = setup_thread();
+
+        cmd_tx.send(Command::GetArtists()).unwrap();
+        match recv_event(&evt_rx) {
+            Ok(Event::ArtistList(_)) => {}
+            _ => panic!("Expected ArtistList event"),
+        }
+
+        cmd_tx.send(Command::GetAlbums(None)).unwrap();
+        match recv_event(&evt_rx) {
+            Ok(Event::AlbumList(None, _)) => {}
+            _ => panic!("Expected AlbumList event"),
+        }
+
+        cmd_tx.send(Command::GetAlbumTracks(1)).unwrap();
+        match recv_event(&evt_rx) {
+            Ok(Event::AlbumTrackList(1, _)) => {}
+            _ => panic!("Expected AlbumTrackList event"),
+        }
+    }
+
+    #[test]
+    fn test_get_plugins() {
+        let (cmd_tx, evt_rx) = setup_thread();
+
+        cmd_tx.send(Command::GetPlugins()).unwrap();
+        match recv_event(&evt_rx) {
+            // only the plugins with a compatible descriptor are listed
+            Ok(Event::PluginList(list)) => {
+                assert!(list.iter().all(|p| !p.manifest.name.is_empty()))
+            }
+            _ => panic!("Expected PluginList event"),
+        }
+    }
+
+    #[test]
+    fn test_movie_metadata_of_unknown_media() {
+        let (cmd_tx, evt_rx) = setup_thread();
+
+        cmd_tx.send(Command::GetMovieMetadata(-1)).unwrap();
+        match recv_event(&evt_rx) {
+            Ok(Event::Metadata { result, .. }) => assert_eq!(result, Err(MetadataError::NotFound)),
+            _ => panic!("Expected Metadata event"),
+        }
+    }
+
+    #[test]
+    fn test_lyrics_and_subtitles_of_unknown_media() {
+        let (cmd_tx, evt_rx) = setup_thread();
+
+        cmd_tx.send(Command::GetLyrics(-1)).unwrap();
+        match recv_event(&evt_rx) {
+            Ok(Event::Lyrics { media_id: -1, result }) => assert_eq!(result, Err(MetadataError::NotFound)),
+            _ => panic!("Expected Lyrics event"),
+        }
+        cmd_tx.send(Command::SearchSubtitles(-1, vec!["fr".to_string()])).unwrap();
+        match recv_event(&evt_rx) {
+            Ok(Event::Subtitles { media_id: -1, result }) => assert_eq!(result, Err(MetadataError::NotFound)),
+            _ => panic!("Expected Subtitles event"),
+        }
+    }
+
+    #[test]
+    fn test_reload_and_cancel_scan_commands() {
+        let (cmd_tx, evt_rx) = setup_thread();
+
+        cmd_tx.send(Command::Reload()).unwrap();
+        cmd_tx.send(Command::CancelScan()).unwrap();
+        cmd_tx.send(Command::Reload()).unwrap();
+
+        // the last scan is not cancelled and runs to the end
+        loop {
+            match evt_rx.recv_timeout(Duration::from_secs(10)) {
+                Ok(Event::ScanFinished(summary)) if !summary.cancelled => break,
+                Ok(_) => {}
+                Err(_) => panic!("Expected ScanFinished event"),
+            }
+        }
+    }
+
     #[test]
     fn test_play_command() {
         let (cmd_tx, evt_rx) = setup_thread();
 
         cmd_tx.send(Command::Play(1)).unwrap();
 
-        match evt_rx.recv_timeout(Duration::from_secs(1)) {
+        match recv_event(&evt_rx) {
             Ok(Event::NowPlaying(id)) => assert_eq!(id, 1),
             _ => panic!("Expected NowPlaying event"),
         }
@@ -411,7 +764,7 @@ mod tests {
 
         cmd_tx.send(Command::Resume(1)).unwrap();
 
-        match evt_rx.recv_timeout(Duration::from_secs(1)) {
+        match recv_event(&evt_rx) {
             Ok(Event::NowPlaying(id)) => assert_eq!(id, 1),
             _ => panic!("Expected NowPlaying event"),
         }
@@ -433,7 +786,7 @@ mod tests {
 
         cmd_tx.send(Command::Info(1)).unwrap();
 
-        match evt_rx.recv_timeout(Duration::from_secs(1)) {
+        match recv_event(&evt_rx) {
             Ok(Event::Info(_)) => {}
             _ => panic!("Expected Info event"),
         }
@@ -458,7 +811,7 @@ mod tests {
             .send(Command::GetTagId("action".to_string()))
             .unwrap();
 
-        match evt_rx.recv_timeout(Duration::from_secs(1)) {
+        match recv_event(&evt_rx) {
             Ok(Event::Data(_)) => {}
             _ => panic!("Expected Data event"),
         }
@@ -502,7 +855,7 @@ mod tests {
             .send(Command::GetPlaylistId("My Playlist".to_string()))
             .unwrap();
 
-        match evt_rx.recv_timeout(Duration::from_secs(1)) {
+        match recv_event(&evt_rx) {
             Ok(Event::Data(_)) => {}
             _ => panic!("Expected Data event"),
         }
@@ -525,7 +878,7 @@ mod tests {
             .send(Command::GetTagId("action".to_string()))
             .unwrap();
 
-        match evt_rx.recv_timeout(Duration::from_secs(1)) {
+        match recv_event(&evt_rx) {
             Ok(Event::Data(_)) => {}
             _ => panic!("Expected Data event"),
         }
@@ -0,0 +1,370 @@
+/*
+This file answers the movie metadata lookups of the media thread through the metadata_cache
+table: a fresh answer is sent without calling the plugins, a stale one is sent right away and
+fetched again by a worker of the pool, and the GUI can force a new fetch of one movie.
+The artist lookups go through artist_metadata by normalized name, an artist no plugin knows
+is not asked again before ARTIST_NOT_FOUND_TTL_HOURS.
+The plugins are never asked on the caller's thread, and a query already being fetched is not
+fetched twice: the running fetch sends its answer for every caller.
+*/
+
+use super::command::Event;
+use super::worker_pool::{Task, WorkerPool};
+use crate::constants::{ARTIST_NOT_FOUND_TTL_HOURS, LOG_FILE};
+use crate::library::media_library::MediaLibrary;
+use crate::library::metadata_cache::{normalize_key, now, CacheKind, CachedMetadata, Freshness};
+use crate::logger::logger::Logger;
+use crate::plugin::plugin_manager::{parse_response, MetadataError, PluginCalls, PluginManager};
+use plugin_api::metadata::{Metadata, MetadataResponse};
+
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+
+#[derive(Clone)]
+pub struct MetadataLookup {
+    library: Arc<Mutex<MediaLibrary>>,
+    plugins: Arc<Mutex<PluginManager>>,
+    evt_tx: mpsc::Sender<Event>,
+    ttl_secs: i64,
+    workers: WorkerPool,
+    // queries being fetched, true once a caller waits for the errors too
+    in_flight: Arc<Mutex<HashMap<(CacheKind, String), bool>>>,
+}
+
+impl MetadataLookup {
+    pub fn new(
+        library: Arc<Mutex<MediaLibrary>>,
+        plugins: Arc<Mutex<PluginManager>>,
+        evt_tx: mpsc::Sender<Event>,
+        ttl_hours: u64,
+        workers: WorkerPool,
+    ) -> Self {
+        Self {
+            library,
+            plugins,
+            evt_tx,
+            ttl_secs: (ttl_hours * 3600) as i64,
+            workers,
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Sends an Event::Metadata for the query, `force_refresh` skips the cache.
+    // Returns the job asking the plugins, if any.
+    pub fn movie(&self, query: &str, force_refresh: bool) -> Option<Task> {
+        let cached = match force_refresh {
+            true => None,
+            false => self
+                .library
+                .lock()
+                .unwrap()
+                .database
+                .get_cached_metadata(CacheKind::Movie, query)
+                .unwrap_or_default(),
+        };
+
+        let Some(entry) = cached else {
+            return self.fetch(CacheKind::Movie, query, true);
+        };
+
+        self.send(
+            query,
+            parse_response(&entry.response),
+            Some(entry.fetched_at),
+        );
+        match entry.freshness(now(), self.ttl_secs) {
+            Freshness::Fresh => None,
+            // the stale answer stays on screen and in the cache if the plugins fail
+            Freshness::Stale => self.fetch(CacheKind::Movie, query, false),
+        }
+    }
+
+    // Sends an Event::Metadata for the artist, the plugins are only asked for an artist
+    // never looked up or not found for a while. Returns the job asking them, if any.
+    pub fn artist(&self, name: &str) -> Option<Task> {
+        let cached = self
+            .library
+            .lock()
+            .unwrap()
+            .database
+            .get_artist_metadata_by_name(name)
+            .unwrap_or_default();
+        let not_found_ttl = (ARTIST_NOT_FOUND_TTL_HOURS * 3600) as i64;
+        if let Some(entry) = cached {
+            if entry.freshness(now(), not_found_ttl) == Freshness::Fresh {
+                self.send(
+                    name,
+                    parse_response(&entry.response),
+                    Some(entry.fetched_at),
+                );
+                return None;
+            }
+        }
+        self.fetch(CacheKind::Artist, name, true)
+    }
+
+    // Same as movie(), with the title and year parsed from the path of the media
+    pub fn media(&self, media_id: i64, force_refresh: bool) -> Option<Task> {
+        let movie = self.library.lock().unwrap().get_movie_name(media_id);
+        match movie {
+            Some(movie) => self.movie(&movie.lookup_query(), force_refresh),
+            None => {
+                self.send("", Err(MetadataError::NotFound), None);
+                None
+            }
+        }
+    }
+
+    // Asks the plugins on a worker and sends their answer, unless the query is already
+    // being fetched. `report_errors` false only sends what is worth caching.
+    fn fetch(&self, kind: CacheKind, query: &str, report_errors: bool) -> Option<Task> {
+        let key = (kind, normalize_key(query));
+        {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if let Some(waiting) = in_flight.get_mut(&key) {
+                *waiting |= report_errors;
+                return None;
+            }
+            in_flight.insert(key.clone(), report_errors);
+        }
+
+        let lookup = self.clone();
+        let query = query.to_string();
+        Some(self.workers.execute(move || {
+            let result = match kind {
+                CacheKind::Movie => lookup.fetch_movie(&query),
+                CacheKind::Artist => lookup.fetch_artist(&query),
+            };
+            let report_errors = lookup.in_flight.lock().unwrap().remove(&key);
+            match result {
+                Err(e) if e != MetadataError::NotFound && report_errors != Some(true) => {
+                    Logger::new(LOG_FILE).error(&format!("Cannot refresh '{}': {}", query, e))
+                }
+                result => lookup.send(&query, result, None),
+            }
+        }))
+    }
+
+    // Asks the plugins and caches what they answered, errors are not cached
+    fn fetch_artist(&self, name: &str) -> Result<Metadata, MetadataError> {
+        let result = self.plugins.get_metadata(name);
+        let response = match &result {
+            Ok(found) => Some(MetadataResponse::found(Metadata::Artist(
+                found.metadata.clone(),
+            ))),
+            Err(MetadataError::NotFound) => Some(MetadataResponse::not_found()),
+            Err(_) => None,
+        };
+        if let Some(response) = response {
+            let mut library = self.library.lock().unwrap();
+            if let Err(e) = library.database.upsert_artist_metadata(name, &response) {
+                Logger::new(LOG_FILE).error(&format!("Cannot cache '{}': {}", name, e));
+            }
+        }
+        result.map(|found| Metadata::Artist(found.metadata))
+    }
+
+    // Asks the plugins and caches what they answered, errors are not cached
+    fn fetch_movie(&self, query: &str) -> Result<Metadata, MetadataError> {
+        let result = self.plugins.get_film_metadata(query);
+        let entry = match &result {
+            Ok(found) => Some(CachedMetadata::found(
+                CacheKind::Movie,
+                query,
+                &found.plugin,
+                Metadata::Movie(found.metadata.clone()),
+            )),
+            Err(MetadataError::NotFound) => {
+                Some(CachedMetadata::not_found(CacheKind::Movie, query))
+            }
+            Err(_) => None,
+        };
+        if let Some(entry) = entry {
+            let mut library = self.library.lock().unwrap();
+            if let Err(e) = library.database.save_cached_metadata(&entry) {
+                Logger::new(LOG_FILE).error(&format!("Cannot cache '{}': {}", query, e));
+            }
+        }
+        result.map(|found| Metadata::Movie(found.metadata))
+    }
+
+    fn send(&self, query: &str, result: Result<Metadata, MetadataError>, cached_at: Option<i64>) {
+        let _ = self.evt_tx.send(Event::Metadata {
+            query: query.to_string(),
+            result,
+            cached_at,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::db::DB;
+    use crate::library::artwork::ArtworkCache;
+    use crate::library::sources::LibraryConfig;
+    use crate::scan::scan::Scan;
+    use plugin_api::metadata::{ArtistMetadata, MovieMetadata};
+    use std::collections::HashMap;
+
+    const QUERY: &str = "Inception (2010)";
+
+    // Lookup without any plugin loaded: every fetch fails with NoPlugin
+    fn setup() -> (MetadataLookup, mpsc::Receiver<Event>) {
+        let mut database = DB {
+            conn: rusqlite::Connection::open_in_memory().unwrap(),
+            media_rows: Vec::new(),
+        };
+        database.init_db().unwrap();
+        let library = MediaLibrary {
+            items: HashMap::new(),
+            scan_lib: Scan {
+                libraries: LibraryConfig::load("db/sources.json"),
+                scan: Vec::new(),
+                known_files: HashMap::new(),
+                artwork: ArtworkCache::for_tests(),
+            },
+            database,
+        };
+        let (evt_tx, evt_rx) = mpsc::channel();
+        let lookup = MetadataLookup::new(
+            Arc::new(Mutex::new(library)),
+            Arc::new(Mutex::new(PluginManager::new())),
+            evt_tx,
+            1,
+            WorkerPool::new(1),
+        );
+        (lookup, evt_rx)
+    }
+
+    fn cache_inception(lookup: &MetadataLookup, fetched_at: i64) -> Metadata {
+        let movie = Metadata::Movie(MovieMetadata {
+            title: "Inception".to_string(),
+            year: Some(2010),
+            ..Default::default()
+        });
+        let mut entry = CachedMetadata::found(CacheKind::Movie, QUERY, "TMDB", movie.clone());
+        entry.fetched_at = fetched_at;
+        let mut library = lookup.library.lock().unwrap();
+        library.database.save_cached_metadata(&entry).unwrap();
+        movie
+    }
+
+    fn next_event(
+        evt_rx: &mpsc::Receiver<Event>,
+    ) -> (Result<Metadata, MetadataError>, Option<i64>) {
+        match evt_rx.try_recv() {
+            Ok(Event::Metadata {
+                result, cached_at, ..
+            }) => (result, cached_at),
+            _ => panic!("Expected Metadata event"),
+        }
+    }
+
+    #[test]
+    fn fresh_answers_are_served_from_the_cache() {
+        let (lookup, evt_rx) = setup();
+        let fetched_at = now();
+        let movie = cache_inception(&lookup, fetched_at);
+
+        assert!(lookup.movie("inception  (2010)", false).is_none());
+        assert_eq!(next_event(&evt_rx), (Ok(movie), Some(fetched_at)));
+    }
+
+    #[test]
+    fn stale_answers_are_kept_when_the_refresh_fails() {
+        let (lookup, evt_rx) = setup();
+        let movie = cache_inception(&lookup, 0);
+
+        let refresh = lookup.movie(QUERY, false).expect("stale entry refreshed");
+        assert_eq!(next_event(&evt_rx), (Ok(movie), Some(0)));
+        refresh.wait();
+        assert!(evt_rx.try_recv().is_err());
+
+        let library = lookup.library.lock().unwrap();
+        let cached = library
+            .database
+            .get_cached_metadata(CacheKind::Movie, QUERY);
+        assert_eq!(cached.unwrap().unwrap().fetched_at, 0);
+    }
+
+    #[test]
+    fn artists_are_looked_up_by_exact_name() {
+        let (lookup, evt_rx) = setup();
+        let museum = Metadata::Artist(ArtistMetadata {
+            name: "Museum".to_string(),
+            ..Default::default()
+        });
+        lookup
+            .library
+            .lock()
+            .unwrap()
+            .database
+            .upsert_artist_metadata("Museum", &MetadataResponse::found(museum.clone()))
+            .unwrap();
+
+        assert!(lookup.artist("museum ").is_none());
+        assert!(matches!(next_event(&evt_rx), (Ok(found), Some(_)) if found == museum));
+        // "Muse" is not "Museum": the plugins are asked
+        lookup.artist("Muse").unwrap().wait();
+        assert_eq!(next_event(&evt_rx), (Err(MetadataError::NoPlugin), None));
+    }
+
+    #[test]
+    fn not_found_artists_are_not_asked_again_before_the_ttl() {
+        let (lookup, evt_rx) = setup();
+        {
+            let mut library = lookup.library.lock().unwrap();
+            let database = &mut library.database;
+            database
+                .upsert_artist_metadata("Muse", &MetadataResponse::not_found())
+                .unwrap();
+        }
+        assert!(lookup.artist("Muse").is_none());
+        assert!(matches!(
+            next_event(&evt_rx),
+            (Err(MetadataError::NotFound), Some(_))
+        ));
+
+        lookup
+            .library
+            .lock()
+            .unwrap()
+            .database
+            .conn
+            .execute("UPDATE artist_metadata SET last_updated = '2000-01-01'", [])
+            .unwrap();
+        lookup.artist("Muse").unwrap().wait();
+        assert_eq!(next_event(&evt_rx), (Err(MetadataError::NoPlugin), None));
+    }
+
+    #[test]
+    fn forced_refresh_asks_the_plugins() {
+        let (lookup, evt_rx) = setup();
+        cache_inception(&lookup, now());
+
+        let fetch = lookup.movie(QUERY, true).expect("plugins asked");
+        fetch.wait();
+        assert_eq!(next_event(&evt_rx), (Err(MetadataError::NoPlugin), None));
+    }
+
+    #[test]
+    fn queries_being_fetched_are_not_fetched_twice() {
+        let (lookup, evt_rx) = setup();
+        let movie = cache_inception(&lookup, 0);
+        let key = (CacheKind::Movie, normalize_key(QUERY));
+        lookup.in_flight.lock().unwrap().insert(key.clone(), false);
+
+        // the stale answer is sent, the running refresh will send the new one
+        assert!(lookup.movie(QUERY, false).is_none());
+        assert_eq!(next_event(&evt_rx), (Ok(movie), Some(0)));
+        // a forced refresh waits for the same fetch, and wants its errors
+        assert!(lookup.movie("inception (2010)", true).is_none());
+        assert!(evt_rx.try_recv().is_err());
+        assert_eq!(lookup.in_flight.lock().unwrap().get(&key), Some(&true));
+
+        lookup.in_flight.lock().unwrap().clear();
+        lookup.movie(QUERY, true).unwrap().wait();
+        assert!(lookup.in_flight.lock().unwrap().is_empty());
+    }
+}
@@ -1,2 +1,6 @@
 pub mod command;
 pub mod media_thread;
+pub mod metadata_lookup;
+pub mod playback_events;
+pub mod provider_lookup;
+pub mod worker_pool;
@@ -0,0 +1,144 @@
+/*
+This file turns what the player does into the host events given to the plugins
+(see plugin/events.rs): a media started, paused, in progress or finished, and a tag put on it.
+A play is finished once, when it goes past EPISODE_WATCHED_RATIO of the media or is stopped.
+*/
+
+use crate::constants::EPISODE_WATCHED_RATIO;
+use crate::media::data::{MediaInfo, MediaType};
+use plugin_api::events::{EventMedia, HostEvent};
+
+pub fn event_media(info: &MediaInfo) -> EventMedia {
+    let kind = match info.media_type {
+        MediaType::Audio => "audio",
+        MediaType::Video => "video",
+        MediaType::Image => "image",
+    };
+    EventMedia {
+        id: info.id,
+        kind: kind.to_string(),
+        path: info.path.clone(),
+        title: info.title.clone(),
+        artist: info.artist.clone(),
+        album: info.album.clone(),
+        duration: info.duration,
+    }
+}
+
+// The media being played, so each play is finished once
+#[derive(Default)]
+pub struct PlaybackTracker {
+    playing: Option<i64>,
+    finished: bool,
+}
+
+impl PlaybackTracker {
+    // Played from the start or resumed at `position`
+    pub fn start(&mut self, info: &MediaInfo, position: f32) -> HostEvent {
+        if self.playing != Some(info.id) || position == 0.0 {
+            self.finished = false;
+        }
+        self.playing = Some(info.id);
+        HostEvent::MediaStarted {
+            media: event_media(info),
+            position,
+        }
+    }
+
+    pub fn pause(&self, info: &MediaInfo, position: f32) -> HostEvent {
+        HostEvent::MediaPaused {
+            media: event_media(info),
+            position,
+        }
+    }
+
+    // The progress, then the end of the play when it is watched far enough
+    pub fn progress(&mut self, info: &MediaInfo, position: f32, duration: f32) -> Vec<HostEvent> {
+        let mut events = vec![HostEvent::MediaProgress {
+            media: event_media(info),
+            position,
+            duration,
+        }];
+        if watched(position, duration) {
+            events.extend(self.finish(info, position, true));
+        }
+        events
+    }
+
+    pub fn stop(&mut self, info: &MediaInfo, position: f32) -> Option<HostEvent> {
+        let duration = info.duration.unwrap_or(0.0);
+        self.finish(info, position, watched(position, duration))
+    }
+
+    fn finish(&mut self, info: &MediaInfo, position: f32, completed: bool) -> Option<HostEvent> {
+        if self.finished && self.playing == Some(info.id) {
+            return None;
+        }
+        self.playing = Some(info.id);
+        self.finished = true;
+        Some(HostEvent::MediaFinished {
+            media: event_media(info),
+            position,
+            completed,
+        })
+    }
+}
+
+fn watched(position: f32, duration: f32) -> bool {
+    duration > 0.0 && position as f64 >= duration as f64 * EPISODE_WATCHED_RATIO
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> MediaInfo {
+        MediaInfo {
+            id: 7,
+            path: "music/Nirvana/Lithium.mp3".to_string(),
+            title: Some("Lithium".to_string()),
+            artist: Some("Nirvana".to_string()),
+            album: None,
+            duration: Some(257.0),
+            media_type: MediaType::Audio,
+            last_position: 0.0,
+            tags: Vec::new(),
+            artwork: None,
+        }
+    }
+
+    fn is_finished(events: &[HostEvent]) -> bool {
+        events.iter().any(|event| {
+            matches!(
+                event,
+                HostEvent::MediaFinished {
+                    completed: true,
+                    ..
+                }
+            )
+        })
+    }
+
+    #[test]
+    fn plays_are_finished_once() {
+        let mut tracker = PlaybackTracker::default();
+        let started = tracker.start(&track(), 0.0);
+        assert!(matches!(started, HostEvent::MediaStarted { media, .. } if media.kind == "audio"));
+
+        assert!(!is_finished(&tracker.progress(&track(), 100.0, 257.0)));
+        assert!(is_finished(&tracker.progress(&track(), 240.0, 257.0)));
+        assert!(!is_finished(&tracker.progress(&track(), 250.0, 257.0)));
+        assert_eq!(tracker.stop(&track(), 257.0), None);
+
+        // played again from the start
+        tracker.start(&track(), 0.0);
+        assert_eq!(
+            tracker.stop(&track(), 30.0),
+            Some(HostEvent::MediaFinished {
+                media: event_media(&track()),
+                position: 30.0,
+                completed: false,
+            })
+        );
+    }
+}
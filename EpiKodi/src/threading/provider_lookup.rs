@@ -0,0 +1,410 @@
+/*
+This file answers the lookups of the capabilities other than metadata (lyrics, subtitles,
+artwork) for a media of the library, and sends the music played to the scrobbler plugins.
+The queries are built from the tags and the parsed file name of the media. Every call runs on
+the thread of the caller, the media thread runs them on its worker pool.
+*/
+
+use super::command::Event;
+use crate::constants::{LOG_FILE, SCROBBLE_AFTER_SECS, SCROBBLE_MIN_TRACK_SECS, SUBTITLE_EXTS};
+use crate::library::media_library::MediaLibrary;
+use crate::library::series::parse_episode_path;
+use crate::logger::logger::Logger;
+use crate::media::data::{MediaInfo, MediaType};
+use crate::plugin::plugin_manager::{MetadataError, PluginCalls, PluginManager};
+use plugin_api::providers::{
+    ArtworkQuery, LyricsQuery, ScrobbleEvent, ScrobbleTrack, SubtitleFile, SubtitleQuery,
+};
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+
+// Why a downloaded subtitle was not saved
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubtitleError {
+    Exists(PathBuf), // the GUI asks before replacing it
+    Failed(String),
+}
+
+impl fmt::Display for SubtitleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubtitleError::Exists(path) => write!(f, "{} already exists", path.display()),
+            SubtitleError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ProviderLookup {
+    library: Arc<Mutex<MediaLibrary>>,
+    plugins: Arc<Mutex<PluginManager>>,
+    evt_tx: mpsc::Sender<Event>,
+}
+
+impl ProviderLookup {
+    pub fn new(
+        library: Arc<Mutex<MediaLibrary>>,
+        plugins: Arc<Mutex<PluginManager>>,
+        evt_tx: mpsc::Sender<Event>,
+    ) -> Self {
+        Self {
+            library,
+            plugins,
+            evt_tx,
+        }
+    }
+
+    // Sends an Event::Lyrics for the track
+    pub fn lyrics(&self, media_id: i64) {
+        let result = self
+            .info(media_id)
+            .and_then(|info| lyrics_query(&info))
+            .and_then(|query| self.plugins.get_lyrics(&query));
+        let _ = self.evt_tx.send(Event::Lyrics { media_id, result });
+    }
+
+    // Sends an Event::Subtitles with the subtitles of every plugin, in the given languages
+    pub fn subtitles(&self, media_id: i64, languages: Vec<String>) {
+        let result = self
+            .subtitle_query(media_id, languages)
+            .and_then(|query| self.plugins.search_subtitles(&query));
+        let _ = self.evt_tx.send(Event::Subtitles { media_id, result });
+    }
+
+    // Downloads a subtitle found by `subtitles` next to the video, sends an Event::SubtitleSaved.
+    // A file of the same name is only replaced with `overwrite`.
+    pub fn download_subtitle(&self, media_id: i64, plugin: &str, id: &str, overwrite: bool) {
+        let failed = |e: MetadataError| SubtitleError::Failed(e.to_string());
+        let result = self.info(media_id).map_err(failed).and_then(|info| {
+            let file = self.plugins.download_subtitle(plugin, id).map_err(failed)?;
+            save_subtitle(Path::new(&info.path), &file, overwrite)
+        });
+        let _ = self.evt_tx.send(Event::SubtitleSaved { media_id, result });
+    }
+
+    // Sends an Event::Artwork: the album or artist of a track, the movie or show of a video
+    pub fn artwork(&self, media_id: i64) {
+        let result = self
+            .info(media_id)
+            .and_then(|info| self.artwork_query(media_id, &info))
+            .and_then(|query| self.plugins.get_artwork(&query));
+        let _ = self.evt_tx.send(Event::Artwork { media_id, result });
+    }
+
+    // Nothing is sent back, a scrobble that failed is only logged
+    pub fn scrobble(&self, event: ScrobbleEvent) {
+        match self.plugins.scrobble(&event) {
+            Ok(_) | Err(MetadataError::NoPlugin) => {}
+            Err(e) => Logger::new(LOG_FILE).error(&format!("Scrobble not sent: {}", e)),
+        }
+    }
+
+    fn info(&self, media_id: i64) -> Result<MediaInfo, MetadataError> {
+        self.library
+            .lock()
+            .unwrap()
+            .info_id(media_id)
+            .ok_or(MetadataError::NotFound)
+    }
+
+    fn subtitle_query(
+        &self,
+        media_id: i64,
+        languages: Vec<String>,
+    ) -> Result<SubtitleQuery, MetadataError> {
+        let info = self.info(media_id)?;
+        if info.media_type != MediaType::Video {
+            return Err(MetadataError::InvalidQuery(info.path));
+        }
+        if let Some(episode) = parse_episode_path(Path::new(&info.path)) {
+            return Ok(SubtitleQuery {
+                title: episode.show,
+                season: Some(episode.season),
+                episode: Some(episode.episode),
+                languages,
+                ..Default::default()
+            });
+        }
+        let movie = self.library.lock().unwrap().get_movie_name(media_id);
+        let movie = movie.ok_or(MetadataError::NotFound)?;
+        Ok(SubtitleQuery {
+            title: movie.title,
+            year: movie.year,
+            languages,
+            ..Default::default()
+        })
+    }
+
+    fn artwork_query(
+        &self,
+        media_id: i64,
+        info: &MediaInfo,
+    ) -> Result<ArtworkQuery, MetadataError> {
+        let query = match info.media_type {
+            MediaType::Audio => match (&info.album, &info.artist) {
+                (Some(album), artist) => ArtworkQuery {
+                    kind: "album".to_string(),
+                    title: album.clone(),
+                    artist: artist.clone(),
+                    ..Default::default()
+                },
+                (None, Some(artist)) => ArtworkQuery {
+                    kind: "artist".to_string(),
+                    title: artist.clone(),
+                    ..Default::default()
+                },
+                (None, None) => return Err(MetadataError::InvalidQuery(info.path.clone())),
+            },
+            MediaType::Video => match parse_episode_path(Path::new(&info.path)) {
+                Some(episode) => ArtworkQuery {
+                    kind: "show".to_string(),
+                    title: episode.show,
+                    ..Default::default()
+                },
+                None => {
+                    let movie = self.library.lock().unwrap().get_movie_name(media_id);
+                    let movie = movie.ok_or(MetadataError::NotFound)?;
+                    ArtworkQuery {
+                        kind: "movie".to_string(),
+                        title: movie.title,
+                        year: movie.year,
+                        ..Default::default()
+                    }
+                }
+            },
+            _ => return Err(MetadataError::InvalidQuery(info.path.clone())),
+        };
+        Ok(query)
+    }
+}
+
+fn lyrics_query(info: &MediaInfo) -> Result<LyricsQuery, MetadataError> {
+    let track =
+        scrobble_track(info).ok_or_else(|| MetadataError::InvalidQuery(info.path.clone()))?;
+    Ok(LyricsQuery {
+        artist: track.artist,
+        title: track.title,
+        album: track.album,
+        duration: track.duration,
+    })
+}
+
+// A music track with its artist and title tags, None for anything else
+pub fn scrobble_track(info: &MediaInfo) -> Option<ScrobbleTrack> {
+    if info.media_type != MediaType::Audio {
+        return None;
+    }
+    Some(ScrobbleTrack {
+        artist: info
+            .artist
+            .clone()
+            .filter(|artist| !artist.trim().is_empty())?,
+        title: info
+            .title
+            .clone()
+            .filter(|title| !title.trim().is_empty())?,
+        album: info.album.clone(),
+        duration: info.duration.map(|duration| duration.round() as u32),
+    })
+}
+
+// movie.mkv -> movie.fr.srt, where the players look for it. The language comes from the plugin
+// and is reduced to letters, digits and dashes so it stays in the file name, the format must be
+// one of SUBTITLE_EXTS.
+fn save_subtitle(
+    video: &Path,
+    file: &SubtitleFile,
+    overwrite: bool,
+) -> Result<PathBuf, SubtitleError> {
+    let clean = |text: &str| -> String {
+        text.chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect::<String>()
+            .to_ascii_lowercase()
+    };
+    let failed = |e: String| SubtitleError::Failed(format!("cannot save the subtitle: {}", e));
+    let stem = video
+        .file_stem()
+        .ok_or_else(|| failed("the media has no file name".to_string()))?
+        .to_string_lossy();
+    let format = match file.format.to_ascii_lowercase() {
+        format if format.is_empty() => "srt".to_string(),
+        format if SUBTITLE_EXTS.contains(&format.as_str()) => format,
+        format => return Err(failed(format!("unknown format '{}'", format))),
+    };
+    let name = match clean(&file.language) {
+        language if language.is_empty() => format!("{}.{}", stem, format),
+        language => format!("{}.{}.{}", stem, language, format),
+    };
+    let path = video.with_file_name(name);
+    if path == video {
+        return Err(failed("it would replace the video".to_string()));
+    }
+
+    let mut options = OpenOptions::new();
+    match overwrite {
+        true => options.write(true).create(true).truncate(true),
+        false => options.write(true).create_new(true),
+    };
+    let written = options
+        .open(&path)
+        .and_then(|mut out| out.write_all(file.content.as_bytes()));
+    match written {
+        Ok(()) => Ok(path),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(SubtitleError::Exists(path)),
+        Err(e) => Err(failed(e.to_string())),
+    }
+}
+
+struct Playing {
+    media_id: i64,
+    track: ScrobbleTrack,
+    started_at: i64,
+    scrobbled: bool,
+}
+
+// The track being played, so each play is scrobbled once
+#[derive(Default)]
+pub struct ScrobbleTracker {
+    playing: Option<Playing>,
+}
+
+impl ScrobbleTracker {
+    // A track starts: the scrobblers show it as now playing
+    pub fn start(&mut self, media_id: i64, track: ScrobbleTrack, started_at: i64) -> ScrobbleEvent {
+        self.playing = Some(Playing {
+            media_id,
+            track: track.clone(),
+            started_at,
+            scrobbled: false,
+        });
+        ScrobbleEvent::NowPlaying { track }
+    }
+
+    // Played once past half of the track or SCROBBLE_AFTER_SECS,
+    // a track shorter than SCROBBLE_MIN_TRACK_SECS never is
+    pub fn progress(
+        &mut self,
+        media_id: i64,
+        position: f32,
+        duration: f32,
+    ) -> Option<ScrobbleEvent> {
+        let playing = self
+            .playing
+            .as_mut()
+            .filter(|playing| playing.media_id == media_id && !playing.scrobbled)?;
+        if duration < SCROBBLE_MIN_TRACK_SECS
+            || position < (duration / 2.0).min(SCROBBLE_AFTER_SECS)
+        {
+            return None;
+        }
+        playing.scrobbled = true;
+        Some(ScrobbleEvent::Played {
+            track: playing.track.clone(),
+            started_at: playing.started_at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> ScrobbleTrack {
+        ScrobbleTrack {
+            artist: "Nirvana".to_string(),
+            title: "Lithium".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tracks_are_scrobbled_once_half_played() {
+        let mut tracker = ScrobbleTracker::default();
+        assert_eq!(tracker.progress(1, 200.0, 257.0), None);
+
+        let event = tracker.start(1, track(), 1_700_000_000);
+        assert_eq!(event, ScrobbleEvent::NowPlaying { track: track() });
+        assert_eq!(tracker.progress(1, 100.0, 257.0), None);
+        assert_eq!(tracker.progress(2, 200.0, 257.0), None);
+        assert_eq!(
+            tracker.progress(1, 130.0, 257.0),
+            Some(ScrobbleEvent::Played {
+                track: track(),
+                started_at: 1_700_000_000
+            })
+        );
+        assert_eq!(tracker.progress(1, 200.0, 257.0), None);
+
+        // long tracks after 4 minutes, short ones never
+        tracker.start(2, track(), 0);
+        assert!(tracker.progress(2, 240.0, 3600.0).is_some());
+        tracker.start(3, track(), 0);
+        assert_eq!(tracker.progress(3, 20.0, 25.0), None);
+    }
+
+    #[test]
+    fn subtitles_are_saved_next_to_the_video() {
+        let dir = std::env::temp_dir().join("epikodi_subtitles");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let video = dir.join("Inception (2010).mkv");
+
+        let file = SubtitleFile {
+            language: "pt-BR".to_string(),
+            format: "srt".to_string(),
+            content: "1\n00:00:01,000 --> 00:00:02,000\nOlá\n".to_string(),
+        };
+        let path = save_subtitle(&video, &file, false).unwrap();
+        assert_eq!(path, dir.join("Inception (2010).pt-br.srt"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), file.content);
+
+        // an existing file is only replaced once the user said so
+        let mut other = file.clone();
+        other.content = "2\n".to_string();
+        assert_eq!(
+            save_subtitle(&video, &other, false),
+            Err(SubtitleError::Exists(path.clone()))
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), file.content);
+        assert_eq!(save_subtitle(&video, &other, true), Ok(path.clone()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), other.content);
+
+        // what the plugin says cannot leave the folder
+        let file = SubtitleFile {
+            language: "../../etc".to_string(),
+            format: "VTT".to_string(),
+            content: "x".to_string(),
+        };
+        let path = save_subtitle(&video, &file, false).unwrap();
+        assert_eq!(path, dir.join("Inception (2010).etc.vtt"));
+
+        // nor be anything else than a subtitle, the video first
+        for format in ["mkv", "/passwd", "exe"] {
+            let file = SubtitleFile {
+                language: String::new(),
+                format: format.to_string(),
+                content: "x".to_string(),
+            };
+            assert!(matches!(
+                save_subtitle(&video, &file, true),
+                Err(SubtitleError::Failed(_))
+            ));
+        }
+        assert!(!video.exists());
+        let odd_video = dir.join("Odd.srt");
+        let file = SubtitleFile {
+            language: String::new(),
+            format: "srt".to_string(),
+            ..file
+        };
+        assert!(matches!(
+            save_subtitle(&odd_video, &file, true),
+            Err(SubtitleError::Failed(_))
+        ));
+    }
+}
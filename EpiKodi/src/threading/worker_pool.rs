@@ -0,0 +1,97 @@
+/*
+This file holds the threads running the plugin lookups of the media thread: a fixed number of
+workers take the jobs from one queue, in order. A slow plugin holds one worker, never the
+command loop, and a burst of commands does not start a thread each.
+*/
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Cheap to clone, the workers stop once every clone is dropped and the queue is empty
+#[derive(Clone)]
+pub struct WorkerPool {
+    jobs: mpsc::Sender<Job>,
+}
+
+// Tells when a job is over, for the callers (and tests) that wait for it
+pub struct Task {
+    done: mpsc::Receiver<()>,
+}
+
+impl Task {
+    // Returns once the job ran, or panicked
+    pub fn wait(self) {
+        let _ = self.done.recv();
+    }
+}
+
+impl WorkerPool {
+    pub fn new(workers: usize) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..workers.max(1) {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || loop {
+                // the lock is only held to take the next job
+                let job = queue.lock().unwrap().recv();
+                match job {
+                    // a job that panics loses its answer, not the worker
+                    Ok(job) => {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                    }
+                    Err(_) => break,
+                }
+            });
+        }
+        Self { jobs }
+    }
+
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) -> Task {
+        let (done_tx, done) = mpsc::channel();
+        let _ = self.jobs.send(Box::new(move || {
+            job();
+            let _ = done_tx.send(());
+        }));
+        Task { done }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn jobs_run_on_the_workers_only() {
+        let pool = WorkerPool::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<Task> = (0..6)
+            .map(|_| {
+                let running = Arc::clone(&running);
+                let most = Arc::clone(&most);
+                pool.execute(move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        tasks.into_iter().for_each(Task::wait);
+        assert!(most.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn a_panicking_job_does_not_stop_the_pool() {
+        let pool = WorkerPool::new(1);
+        pool.execute(|| panic!("broken job")).wait();
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(42).unwrap()).wait();
+        assert_eq!(rx.recv(), Ok(42));
+    }
+}
@@ -0,0 +1,417 @@
+/*
+This file watches the library sources on disk (inotify on Linux) and applies the
+created / renamed / deleted files to the library without a full rescan.
+*/
+
+use crate::constants::{LOG_FILE, WATCH_DEBOUNCE_MS};
+use crate::library::media_library::MediaLibrary;
+use crate::library::sources::LibraryConfig;
+use crate::logger::logger::Logger;
+use crate::threading::command::Event;
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileChange {
+    Upsert(PathBuf),        // created, renamed to or modified (file or folder)
+    Remove(PathBuf),        // deleted or moved out of the sources (file or folder)
+    Move(PathBuf, PathBuf), // renamed from, to (file or folder)
+}
+
+impl FileChange {
+    // A move touches both of its paths
+    pub fn paths(&self) -> Vec<&PathBuf> {
+        match self {
+            FileChange::Upsert(path) | FileChange::Remove(path) => vec![path],
+            FileChange::Move(from, to) => vec![from, to],
+        }
+    }
+}
+
+// Translates a raw notify event into library changes
+pub fn changes_from_event(event: &notify::Event) -> Vec<FileChange> {
+    match event.kind {
+        EventKind::Create(_) => event
+            .paths
+            .iter()
+            .cloned()
+            .map(FileChange::Upsert)
+            .collect(),
+        EventKind::Remove(_) => event
+            .paths
+            .iter()
+            .cloned()
+            .map(FileChange::Remove)
+            .collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => event
+            .paths
+            .iter()
+            .cloned()
+            .map(FileChange::Remove)
+            .collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => event
+            .paths
+            .iter()
+            .cloned()
+            .map(FileChange::Upsert)
+            .collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+            vec![FileChange::Move(
+                event.paths[0].clone(),
+                event.paths[1].clone(),
+            )]
+        }
+        // the backend could not tell which side of the rename this is
+        EventKind::Modify(ModifyKind::Name(_)) => event
+            .paths
+            .iter()
+            .map(|path| {
+                if path.exists() {
+                    FileChange::Upsert(path.clone())
+                } else {
+                    FileChange::Remove(path.clone())
+                }
+            })
+            .collect(),
+        // access time / permissions changes are not interesting
+        EventKind::Modify(ModifyKind::Metadata(_)) => Vec::new(),
+        EventKind::Modify(_) => event
+            .paths
+            .iter()
+            .cloned()
+            .map(FileChange::Upsert)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Groups the events of a burst (copying an album fires hundreds of them),
+// only the last change of each path is kept, in the order they happened.
+// Every move is kept, a later one can move the same files again.
+pub struct Debouncer {
+    pending: Vec<FileChange>,
+    renamed_from: HashMap<usize, PathBuf>, // first half of a rename, by tracker
+    last_event: Option<Instant>,
+    delay: Duration,
+}
+
+impl Debouncer {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            pending: Vec::new(),
+            renamed_from: HashMap::new(),
+            last_event: None,
+            delay,
+        }
+    }
+
+    // Pairs the two halves of a rename by their tracker into a move, when the backend
+    // does not send the whole rename at once
+    pub fn push_event(&mut self, event: &notify::Event, now: Instant) {
+        if let (EventKind::Modify(ModifyKind::Name(mode)), Some(tracker)) =
+            (event.kind, event.tracker())
+        {
+            match (mode, event.paths.first()) {
+                (RenameMode::From, Some(from)) => {
+                    self.renamed_from.insert(tracker, from.clone());
+                }
+                (RenameMode::To, Some(to)) => {
+                    if let Some(from) = self.renamed_from.remove(&tracker) {
+                        self.push(FileChange::Move(from, to.clone()), now);
+                        return;
+                    }
+                }
+                (RenameMode::Both, _) => {
+                    self.renamed_from.remove(&tracker);
+                }
+                _ => {}
+            }
+        }
+        for change in changes_from_event(event) {
+            self.push(change, now);
+        }
+    }
+
+    pub fn push(&mut self, change: FileChange, now: Instant) {
+        let paths = change.paths();
+        self.pending.retain(|c| match c {
+            // the same move reported twice (inotify sends the halves and the whole rename)
+            FileChange::Move(..) => c != &change,
+            _ => !paths.contains(&c.paths()[0]),
+        });
+        self.pending.push(change);
+        self.last_event = Some(now);
+    }
+
+    // true once nothing happened for `delay`
+    pub fn is_ready(&self, now: Instant) -> bool {
+        match self.last_event {
+            Some(last) => !self.pending.is_empty() && now.duration_since(last) >= self.delay,
+            None => false,
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<FileChange> {
+        self.last_event = None;
+        // a rename whose other half never came was moved in or out of the sources
+        self.renamed_from.clear();
+        std::mem::take(&mut self.pending)
+    }
+}
+
+pub struct LibraryWatcher {
+    watcher: Option<RecommendedWatcher>,
+    watched: Vec<PathBuf>,
+}
+
+impl LibraryWatcher {
+    // Starts the debounce thread, it applies the changes to the library and
+    // sends Event::LibraryChanged to the GUI.
+    pub fn start(library: Arc<Mutex<MediaLibrary>>, evt_tx: mpsc::Sender<Event>) -> Self {
+        let logger = Logger::new(LOG_FILE);
+        let (tx, rx) = mpsc::channel::<notify::Result<notify::Event>>();
+
+        let watcher = match notify::recommended_watcher(tx) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                logger.error(&format!("Cannot start the file watcher: {}", e));
+                None
+            }
+        };
+
+        thread::spawn(move || {
+            let logger = Logger::new(LOG_FILE);
+            let mut debouncer = Debouncer::new(Duration::from_millis(WATCH_DEBOUNCE_MS));
+
+            loop {
+                match rx.recv_timeout(Duration::from_millis(WATCH_DEBOUNCE_MS / 2)) {
+                    Ok(Ok(event)) => debouncer.push_event(&event, Instant::now()),
+                    Ok(Err(e)) => logger.error(&format!("File watcher error: {}", e)),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // the watcher was dropped
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+
+                if debouncer.is_ready(Instant::now()) {
+                    let changes = debouncer.take();
+                    logger.debug(&format!("Applying {} file changes", changes.len()));
+
+                    let report = library.lock().unwrap().apply_file_changes(&changes);
+                    if !report.is_empty() && evt_tx.send(Event::LibraryChanged(report)).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self {
+            watcher,
+            watched: Vec::new(),
+        }
+    }
+
+    // Watches every source of the config, call it again when the sources change
+    pub fn watch_sources(&mut self, config: &LibraryConfig) {
+        let logger = Logger::new(LOG_FILE);
+        let Some(watcher) = self.watcher.as_mut() else {
+            return;
+        };
+
+        let mut wanted: Vec<PathBuf> = Vec::new();
+        for source in config
+            .music_sources
+            .iter()
+            .chain(config.video_sources.iter())
+            .chain(config.image_sources.iter())
+        {
+            if !wanted.contains(&source.path) {
+                wanted.push(source.path.clone());
+            }
+        }
+
+        for path in self.watched.iter().filter(|p| !wanted.contains(p)) {
+            let _ = watcher.unwatch(path);
+        }
+
+        let mut watched = Vec::new();
+        for path in wanted {
+            if self.watched.contains(&path) {
+                watched.push(path);
+                continue;
+            }
+            match watcher.watch(&path, RecursiveMode::Recursive) {
+                Ok(_) => {
+                    logger.info(&format!("Watching {}", path.display()));
+                    watched.push(path);
+                }
+                Err(e) => logger.error(&format!("Cannot watch {}: {}", path.display(), e)),
+            }
+        }
+        self.watched = watched;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange, MetadataKind, RemoveKind};
+
+    fn event(kind: EventKind, paths: &[&str]) -> notify::Event {
+        let mut event = notify::Event::new(kind);
+        for path in paths {
+            event = event.add_path(PathBuf::from(path));
+        }
+        event
+    }
+
+    #[test]
+    fn create_and_remove_events() {
+        assert_eq!(
+            changes_from_event(&event(EventKind::Create(CreateKind::File), &["/m/a.mp3"])),
+            vec![FileChange::Upsert(PathBuf::from("/m/a.mp3"))]
+        );
+        assert_eq!(
+            changes_from_event(&event(EventKind::Remove(RemoveKind::Folder), &["/m/album"])),
+            vec![FileChange::Remove(PathBuf::from("/m/album"))]
+        );
+    }
+
+    #[test]
+    fn rename_events() {
+        let both = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &["/m/old.mp3", "/m/new.mp3"],
+        );
+        assert_eq!(
+            changes_from_event(&both),
+            vec![FileChange::Move(
+                PathBuf::from("/m/old.mp3"),
+                PathBuf::from("/m/new.mp3")
+            )]
+        );
+
+        let from = event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+            &["/m/old.mp3"],
+        );
+        assert_eq!(
+            changes_from_event(&from),
+            vec![FileChange::Remove(PathBuf::from("/m/old.mp3"))]
+        );
+    }
+
+    #[test]
+    fn rename_halves_are_paired_into_a_move() {
+        let now = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(500));
+        let rename = |mode, paths: &[&str]| {
+            event(EventKind::Modify(ModifyKind::Name(mode)), paths).set_tracker(7)
+        };
+
+        // what inotify sends: both halves, then the whole rename
+        debouncer.push_event(&rename(RenameMode::From, &["/m/old"]), now);
+        debouncer.push_event(&rename(RenameMode::To, &["/m/new"]), now);
+        debouncer.push_event(&rename(RenameMode::Both, &["/m/old", "/m/new"]), now);
+        // moved out of the sources, the other half never comes
+        debouncer.push_event(
+            &event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+                &["/m/gone.mp3"],
+            )
+            .set_tracker(8),
+            now,
+        );
+
+        assert_eq!(
+            debouncer.take(),
+            vec![
+                FileChange::Move(PathBuf::from("/m/old"), PathBuf::from("/m/new")),
+                FileChange::Remove(PathBuf::from("/m/gone.mp3")),
+            ]
+        );
+    }
+
+    #[test]
+    fn debouncer_keeps_every_move() {
+        let now = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(500));
+
+        debouncer.push(FileChange::Remove(PathBuf::from("/m/b.mp3")), now);
+        debouncer.push(
+            FileChange::Move(PathBuf::from("/m/a.mp3"), PathBuf::from("/m/b.mp3")),
+            now,
+        );
+        debouncer.push(FileChange::Upsert(PathBuf::from("/m/b.mp3")), now);
+        debouncer.push(
+            FileChange::Move(PathBuf::from("/m/b.mp3"), PathBuf::from("/m/c.mp3")),
+            now,
+        );
+
+        assert_eq!(
+            debouncer.take(),
+            vec![
+                FileChange::Move(PathBuf::from("/m/a.mp3"), PathBuf::from("/m/b.mp3")),
+                FileChange::Move(PathBuf::from("/m/b.mp3"), PathBuf::from("/m/c.mp3")),
+            ]
+        );
+    }
+
+    #[test]
+    fn metadata_events_are_ignored() {
+        let touch = event(
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::AccessTime)),
+            &["/m/a.mp3"],
+        );
+        assert!(changes_from_event(&touch).is_empty());
+
+        let write = event(
+            EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+            &["/m/a.mp3"],
+        );
+        assert_eq!(changes_from_event(&write).len(), 1);
+    }
+
+    #[test]
+    fn debouncer_waits_for_quiet_period() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(500));
+        assert!(!debouncer.is_ready(start));
+
+        debouncer.push(FileChange::Upsert(PathBuf::from("/m/a.mp3")), start);
+        debouncer.push(
+            FileChange::Upsert(PathBuf::from("/m/b.mp3")),
+            start + Duration::from_millis(400),
+        );
+
+        assert!(!debouncer.is_ready(start + Duration::from_millis(600)));
+        assert!(debouncer.is_ready(start + Duration::from_millis(900)));
+        assert_eq!(debouncer.take().len(), 2);
+        assert!(!debouncer.is_ready(start + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn debouncer_keeps_last_change_per_path() {
+        let now = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_millis(500));
+
+        debouncer.push(FileChange::Upsert(PathBuf::from("/m/a.mp3")), now);
+        debouncer.push(FileChange::Upsert(PathBuf::from("/m/b.mp3")), now);
+        debouncer.push(FileChange::Remove(PathBuf::from("/m/a.mp3")), now);
+
+        assert_eq!(
+            debouncer.take(),
+            vec![
+                FileChange::Upsert(PathBuf::from("/m/b.mp3")),
+                FileChange::Remove(PathBuf::from("/m/a.mp3")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+/*
+Loads the real test plugin (test_plugin/ next to EpiKodi/) the way the host does and gives every
+string it answers back to its free_string. The plugin counts the strings it handed over, the CI
+also runs this test under the leak sanitizer to catch what the count cannot see.
+*/
+
+use libloading::{Library, Symbol};
+use plugin_api::abi::{DescriptorFunc, PluginManifest, DESCRIPTOR_SYMBOL};
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+type CallFunc = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+type FreeFunc = unsafe extern "C" fn(*mut c_char);
+type CountFunc = extern "C" fn() -> usize;
+
+fn build_test_plugin() -> PathBuf {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../test_plugin");
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--quiet", "--offline", "--manifest-path"])
+        .arg(dir.join("Cargo.toml"))
+        .status()
+        .expect("cannot run cargo");
+    assert!(status.success(), "test_plugin does not build");
+    dir.join("target/debug")
+        .join(format!("{}test_plugin{}", DLL_PREFIX, DLL_SUFFIX))
+}
+
+// Calls an export and hands the answer back to the plugin, None for a null answer
+fn call(library: &Library, symbol: &[u8], request: &str) -> Option<Vec<u8>> {
+    let request = CString::new(request).unwrap();
+    unsafe {
+        let func: Symbol<CallFunc> = library.get(symbol).unwrap();
+        let free: Symbol<FreeFunc> = library.get(b"free_string\0").unwrap();
+        let answer = func(request.as_ptr());
+        if answer.is_null() {
+            return None;
+        }
+        let bytes = CStr::from_ptr(answer).to_bytes().to_vec();
+        free(answer);
+        Some(bytes)
+    }
+}
+
+#[test]
+fn every_answer_goes_back_to_the_plugin() {
+    let library = unsafe { Library::new(build_test_plugin()) }.unwrap();
+    let manifest = unsafe {
+        let descriptor: Symbol<DescriptorFunc> = library.get(DESCRIPTOR_SYMBOL).unwrap();
+        PluginManifest::from_descriptor(descriptor())
+    };
+    assert_eq!(manifest.unwrap().name, "Test");
+
+    let found = call(&library, b"metadata\0", "Muse").unwrap();
+    assert!(String::from_utf8(found).unwrap().contains("Muse"));
+    assert!(call(&library, b"metadata\0", "not found").is_some());
+    assert!(String::from_utf8(call(&library, b"metadata\0", "not utf8").unwrap()).is_err());
+    assert_eq!(call(&library, b"metadata\0", "null answer"), None);
+
+    let lyrics = r#"{"artist": "Muse", "title": "Uprising"}"#;
+    assert!(call(&library, b"lyrics\0", lyrics).is_some());
+    assert!(call(&library, b"lyrics\0", "not json").is_some());
+
+    let live_strings: Symbol<CountFunc> =
+        unsafe { library.get(b"test_plugin_live_strings\0") }.unwrap();
+    assert_eq!(live_strings(), 0);
+    // the plugin statics (its descriptor) would look leaked once the library is unloaded
+    std::mem::forget(library);
+}
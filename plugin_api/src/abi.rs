@@ -0,0 +1,252 @@
+/*
+Stable C ABI between the host and the plugins.
+Every plugin exports `epikodi_plugin_descriptor`, returning a #[repr(C)] PluginDescriptor that
+lives as long as the library. The host reads `abi_version` first and only reads the other fields
+when it matches ABI_VERSION, so a plugin built against another plugin_api is refused instead of
+being called with the wrong signatures.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::os::raw::c_char;
+
+// Bump when the descriptor layout or the signature of an exported function changes
+pub const ABI_VERSION: u32 = 1;
+
+pub const DESCRIPTOR_SYMBOL: &[u8] = b"epikodi_plugin_descriptor\0";
+
+// What a plugin can do, a plugin can have several
+pub mod capabilities {
+    pub const ARTIST_METADATA: &str = "artist_metadata"; // exports `metadata`
+    pub const MOVIE_METADATA: &str = "movie_metadata"; // exports `metadata`
+    pub const LYRICS: &str = "lyrics"; // exports `lyrics`
+    pub const SUBTITLES: &str = "subtitles"; // exports `search_subtitles` and `download_subtitle`
+    pub const ARTWORK: &str = "artwork"; // exports `artwork`
+    pub const SCROBBLER: &str = "scrobbler"; // exports `scrobble`
+    pub const EVENTS: &str = "events"; // exports `subscriptions` and `on_event`
+
+    pub const ALL: [&str; 7] = [
+        ARTIST_METADATA,
+        MOVIE_METADATA,
+        LYRICS,
+        SUBTITLES,
+        ARTWORK,
+        SCROBBLER,
+        EVENTS,
+    ];
+
+    // Functions the host resolves for a capability, all taking and returning a JSON string.
+    // Empty for a capability this plugin_api does not know.
+    pub fn functions(capability: &str) -> &'static [&'static str] {
+        match capability {
+            ARTIST_METADATA | MOVIE_METADATA => &["metadata"],
+            LYRICS => &["lyrics"],
+            SUBTITLES => &["search_subtitles", "download_subtitle"],
+            ARTWORK => &["artwork"],
+            SCROBBLER => &["scrobble"],
+            EVENTS => &["subscriptions", "on_event"],
+            _ => &[],
+        }
+    }
+}
+
+// Field order and types are part of the ABI: only add fields at the end, with an ABI_VERSION bump
+#[repr(C)]
+pub struct PluginDescriptor {
+    pub abi_version: u32,
+    pub name: *const c_char,
+    pub version: *const c_char,
+    pub author: *const c_char,
+    pub capabilities: *const *const c_char, // capability_count UTF-8 strings
+    pub capability_count: usize,
+}
+
+pub type DescriptorFunc = unsafe extern "C" fn() -> *const PluginDescriptor;
+
+// Owned copy of a descriptor, what the host keeps and shows
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PluginManifest {
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AbiError {
+    NullDescriptor,
+    IncompatibleVersion(u32), // ABI version of the plugin
+    MissingField(&'static str),
+}
+
+impl fmt::Display for AbiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AbiError::NullDescriptor => write!(f, "null descriptor"),
+            AbiError::IncompatibleVersion(version) => write!(
+                f,
+                "plugin ABI version {} is not supported (host is {}), rebuild it against this plugin_api",
+                version, ABI_VERSION
+            ),
+            AbiError::MissingField(field) => write!(f, "missing field '{}'", field),
+        }
+    }
+}
+
+impl std::error::Error for AbiError {}
+
+impl PluginManifest {
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Reads and checks the descriptor returned by a plugin.
+    ///
+    /// # Safety
+    /// `descriptor` must be null or point to a PluginDescriptor whose `abi_version` is readable;
+    /// when the version matches, its pointers must be null or valid for the declared lengths.
+    pub unsafe fn from_descriptor(descriptor: *const PluginDescriptor) -> Result<Self, AbiError> {
+        if descriptor.is_null() {
+            return Err(AbiError::NullDescriptor);
+        }
+        let abi_version = unsafe { (*descriptor).abi_version };
+        if abi_version != ABI_VERSION {
+            return Err(AbiError::IncompatibleVersion(abi_version));
+        }
+
+        let descriptor = unsafe { &*descriptor };
+        let name = unsafe { read_string(descriptor.name) }.unwrap_or_default();
+        if name.trim().is_empty() {
+            return Err(AbiError::MissingField("name"));
+        }
+        let capabilities = match descriptor.capabilities.is_null() {
+            true => Vec::new(),
+            false => unsafe {
+                std::slice::from_raw_parts(descriptor.capabilities, descriptor.capability_count)
+            }
+            .iter()
+            .filter_map(|&capability| unsafe { read_string(capability) })
+            .collect(),
+        };
+        if capabilities.is_empty() {
+            return Err(AbiError::MissingField("capabilities"));
+        }
+
+        Ok(Self {
+            name,
+            version: unsafe { read_string(descriptor.version) }.unwrap_or_default(),
+            author: unsafe { read_string(descriptor.author) }.unwrap_or_default(),
+            capabilities,
+        })
+    }
+}
+
+unsafe fn read_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+// Descriptor built by a plugin from its manifest, with the strings it points to.
+// Keep it in a static (see ffi::descriptor), the host reads it for as long as the library is loaded.
+pub struct OwnedDescriptor {
+    descriptor: PluginDescriptor,
+    _strings: Vec<CString>,
+    _capabilities: Vec<*const c_char>,
+}
+
+// The pointers only target the strings owned by the struct, which are never modified
+unsafe impl Send for OwnedDescriptor {}
+unsafe impl Sync for OwnedDescriptor {}
+
+impl OwnedDescriptor {
+    pub fn new(manifest: &PluginManifest) -> Self {
+        let c_string = |s: &str| CString::new(s.replace('\0', "")).unwrap_or_default();
+        let name = c_string(&manifest.name);
+        let version = c_string(&manifest.version);
+        let author = c_string(&manifest.author);
+        let capabilities: Vec<CString> =
+            manifest.capabilities.iter().map(|c| c_string(c)).collect();
+        let capability_ptrs: Vec<*const c_char> = capabilities.iter().map(|c| c.as_ptr()).collect();
+
+        // moving a CString or a Vec into the struct does not move the bytes they point to
+        let descriptor = PluginDescriptor {
+            abi_version: ABI_VERSION,
+            name: name.as_ptr(),
+            version: version.as_ptr(),
+            author: author.as_ptr(),
+            capabilities: capability_ptrs.as_ptr(),
+            capability_count: capability_ptrs.len(),
+        };
+        let mut strings = vec![name, version, author];
+        strings.extend(capabilities);
+        Self {
+            descriptor,
+            _strings: strings,
+            _capabilities: capability_ptrs,
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const PluginDescriptor {
+        &self.descriptor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn musicbrainz() -> PluginManifest {
+        PluginManifest {
+            name: "MusicBrainz".to_string(),
+            version: "1.0.0".to_string(),
+            author: "EpiKodi".to_string(),
+            capabilities: vec![capabilities::ARTIST_METADATA.to_string()],
+        }
+    }
+
+    #[test]
+    fn descriptors_round_trip() {
+        let manifest = musicbrainz();
+        let descriptor = OwnedDescriptor::new(&manifest);
+        let read = unsafe { PluginManifest::from_descriptor(descriptor.as_ptr()) };
+        assert_eq!(read, Ok(manifest));
+        assert!(read.unwrap().has_capability(capabilities::ARTIST_METADATA));
+    }
+
+    #[test]
+    fn incompatible_descriptors_are_refused() {
+        assert_eq!(
+            unsafe { PluginManifest::from_descriptor(std::ptr::null()) },
+            Err(AbiError::NullDescriptor)
+        );
+
+        // only the version is read, the other fields of an older layout may be anything
+        let old = PluginDescriptor {
+            abi_version: 0,
+            name: std::ptr::dangling(),
+            version: std::ptr::null(),
+            author: std::ptr::null(),
+            capabilities: std::ptr::null(),
+            capability_count: 12,
+        };
+        assert_eq!(
+            unsafe { PluginManifest::from_descriptor(&old) },
+            Err(AbiError::IncompatibleVersion(0))
+        );
+
+        let mut manifest = musicbrainz();
+        manifest.capabilities.clear();
+        let descriptor = OwnedDescriptor::new(&manifest);
+        assert_eq!(
+            unsafe { PluginManifest::from_descriptor(descriptor.as_ptr()) },
+            Err(AbiError::MissingField("capabilities"))
+        );
+    }
+}
@@ -0,0 +1,148 @@
+/*
+What happens in the host, told to the plugins of the `events` capability: playback of a media,
+end of a library scan, a tag put on a media. A plugin lists the kinds it wants in its
+`subscriptions` answer and only gets those, as a PluginEvent in JSON given to `on_event`.
+The events come late and may be dropped when the plugin is too slow to take them all,
+they are not meant for answers the user waits for.
+*/
+
+use crate::metadata::SchemaError;
+use crate::providers::Answer;
+use serde::{Deserialize, Serialize};
+
+// Kinds of events, what a plugin subscribes to
+pub mod kinds {
+    pub const MEDIA_STARTED: &str = "media_started";
+    pub const MEDIA_PAUSED: &str = "media_paused";
+    pub const MEDIA_PROGRESS: &str = "media_progress";
+    pub const MEDIA_FINISHED: &str = "media_finished";
+    pub const LIBRARY_SCANNED: &str = "library_scanned";
+    pub const TAG_ADDED: &str = "tag_added";
+
+    pub const ALL: [&str; 6] = [
+        MEDIA_STARTED,
+        MEDIA_PAUSED,
+        MEDIA_PROGRESS,
+        MEDIA_FINISHED,
+        LIBRARY_SCANNED,
+        TAG_ADDED,
+    ];
+}
+
+// The media an event is about
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventMedia {
+    pub id: i64,
+    pub kind: String, // "audio", "video", "image"
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<f32>, // seconds
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HostEvent {
+    MediaStarted {
+        media: EventMedia,
+        position: f32, // seconds, not 0 when resumed
+    },
+    MediaPaused {
+        media: EventMedia,
+        position: f32,
+    },
+    MediaProgress {
+        media: EventMedia,
+        position: f32,
+        duration: f32,
+    },
+    MediaFinished {
+        media: EventMedia,
+        position: f32,
+        completed: bool, // played to the end, false when stopped before
+    },
+    LibraryScanned {
+        added: usize,
+        updated: usize,
+        removed: usize,
+        cancelled: bool,
+    },
+    TagAdded {
+        media: EventMedia,
+        tag: String,
+    },
+}
+
+impl HostEvent {
+    // One of `kinds`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HostEvent::MediaStarted { .. } => kinds::MEDIA_STARTED,
+            HostEvent::MediaPaused { .. } => kinds::MEDIA_PAUSED,
+            HostEvent::MediaProgress { .. } => kinds::MEDIA_PROGRESS,
+            HostEvent::MediaFinished { .. } => kinds::MEDIA_FINISHED,
+            HostEvent::LibraryScanned { .. } => kinds::LIBRARY_SCANNED,
+            HostEvent::TagAdded { .. } => kinds::TAG_ADDED,
+        }
+    }
+}
+
+// What `on_event` gets: the event and when it happened, it may be delivered some time after
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PluginEvent {
+    pub sent_at: i64, // unix seconds
+    #[serde(flatten)]
+    pub event: HostEvent,
+}
+
+// Answer of `subscriptions`. Kinds unknown to the host are ignored, not refused,
+// so a plugin can subscribe to the events of a newer host.
+impl Answer for Vec<String> {
+    fn validate(&self) -> Result<(), SchemaError> {
+        match self.iter().any(|kind| kind.trim().is_empty()) {
+            true => Err(SchemaError::InvalidField(
+                "subscriptions",
+                "empty kind".into(),
+            )),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_tagged_with_their_kind() {
+        let event = PluginEvent {
+            sent_at: 1_700_000_000,
+            event: HostEvent::MediaFinished {
+                media: EventMedia {
+                    id: 3,
+                    kind: "audio".to_string(),
+                    title: Some("Lithium".to_string()),
+                    ..Default::default()
+                },
+                position: 250.0,
+                completed: true,
+            },
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains(r#""event":"media_finished""#));
+        assert!(json.contains(r#""sent_at":1700000000"#));
+        assert_eq!(serde_json::from_str::<PluginEvent>(&json).unwrap(), event);
+        assert_eq!(event.event.kind(), kinds::MEDIA_FINISHED);
+
+        let scanned = HostEvent::LibraryScanned {
+            added: 1,
+            updated: 0,
+            removed: 2,
+            cancelled: false,
+        };
+        let json = serde_json::to_string(&scanned).unwrap();
+        assert!(json.contains(&format!(r#""event":"{}""#, scanned.kind())));
+    }
+}
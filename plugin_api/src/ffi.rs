@@ -0,0 +1,642 @@
+/*
+FFI side of a plugin: what the `declare_plugin!` macro exports around a `Plugin` implementation.
+No panic crosses the C boundary, every string given to the host is allocated here and freed
+by the `free_string` export of the same library.
+*/
+
+use crate::Plugin;
+use crate::abi::{OwnedDescriptor, PluginDescriptor};
+use crate::metadata::MetadataResponse;
+use crate::providers::{self, Answer, ProviderResponse};
+use crate::settings::PluginSettings;
+use serde::de::DeserializeOwned;
+use std::any::Any;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Exports the symbols the host loads for the plugin type: `epikodi_plugin_descriptor`,
+/// `metadata`, `settings_schema`, `configure`, `free_string` and the functions of the other
+/// capabilities (`lyrics`, `search_subtitles`, `download_subtitle`, `artwork`, `scrobble`,
+/// `subscriptions`, `on_event`).
+/// The host only calls the functions of the capabilities the plugin declares.
+///
+/// Built for `wasm32-wasip1`, the same exports make a WebAssembly plugin, with two more the
+/// host of a module needs: `allowed_domains` and `epikodi_alloc`, which gives the host room in
+/// the memory of the module for the strings it passes (see http::fetch).
+///
+/// The plugin is built on the first call, with `Default::default()` or the given constructor,
+/// and kept for the lifetime of the library, so it must be `Send + Sync`.
+///
+/// ```ignore
+/// #[derive(Default)]
+/// struct MyProvider;
+/// impl plugin_api::Plugin for MyProvider { /* ... */ }
+/// plugin_api::declare_plugin!(MyProvider);
+/// ```
+#[macro_export]
+macro_rules! declare_plugin {
+    ($plugin:ty) => {
+        $crate::declare_plugin!($plugin, <$plugin as ::std::default::Default>::default);
+    };
+    ($plugin:ty, $constructor:expr) => {
+        fn __epikodi_plugin() -> &'static $plugin {
+            static PLUGIN: ::std::sync::OnceLock<$plugin> = ::std::sync::OnceLock::new();
+            PLUGIN.get_or_init($constructor)
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn epikodi_plugin_descriptor() -> *const $crate::abi::PluginDescriptor {
+            static DESCRIPTOR: ::std::sync::OnceLock<$crate::abi::OwnedDescriptor> =
+                ::std::sync::OnceLock::new();
+            $crate::ffi::descriptor(&DESCRIPTOR, __epikodi_plugin)
+        }
+
+        /// # Safety
+        /// `query` must be null or a NUL-terminated string, see plugin_api::ffi::metadata.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn metadata(
+            query: *const ::std::os::raw::c_char,
+        ) -> *mut ::std::os::raw::c_char {
+            unsafe { $crate::ffi::metadata(__epikodi_plugin, query) }
+        }
+
+        /// # Safety
+        /// `request` must be null or a NUL-terminated string, see plugin_api::ffi::provide.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn lyrics(
+            request: *const ::std::os::raw::c_char,
+        ) -> *mut ::std::os::raw::c_char {
+            unsafe { $crate::ffi::lyrics(__epikodi_plugin, request) }
+        }
+
+        /// # Safety
+        /// `request` must be null or a NUL-terminated string, see plugin_api::ffi::provide.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn search_subtitles(
+            request: *const ::std::os::raw::c_char,
+        ) -> *mut ::std::os::raw::c_char {
+            unsafe { $crate::ffi::search_subtitles(__epikodi_plugin, request) }
+        }
+
+        /// # Safety
+        /// `request` must be null or a NUL-terminated string, see plugin_api::ffi::provide.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn download_subtitle(
+            request: *const ::std::os::raw::c_char,
+        ) -> *mut ::std::os::raw::c_char {
+            unsafe { $crate::ffi::download_subtitle(__epikodi_plugin, request) }
+        }
+
+        /// # Safety
+        /// `request` must be null or a NUL-terminated string, see plugin_api::ffi::provide.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn artwork(
+            request: *const ::std::os::raw::c_char,
+        ) -> *mut ::std::os::raw::c_char {
+            unsafe { $crate::ffi::artwork(__epikodi_plugin, request) }
+        }
+
+        /// # Safety
+        /// `request` must be null or a NUL-terminated string, see plugin_api::ffi::provide.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn scrobble(
+            request: *const ::std::os::raw::c_char,
+        ) -> *mut ::std::os::raw::c_char {
+            unsafe { $crate::ffi::scrobble(__epikodi_plugin, request) }
+        }
+
+        /// # Safety
+        /// `request` must be null or a NUL-terminated string, see plugin_api::ffi::provide.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn subscriptions(
+            request: *const ::std::os::raw::c_char,
+        ) -> *mut ::std::os::raw::c_char {
+            unsafe { $crate::ffi::subscriptions(__epikodi_plugin, request) }
+        }
+
+        /// # Safety
+        /// `request` must be null or a NUL-terminated string, see plugin_api::ffi::provide.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn on_event(
+            request: *const ::std::os::raw::c_char,
+        ) -> *mut ::std::os::raw::c_char {
+            unsafe { $crate::ffi::on_event(__epikodi_plugin, request) }
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn settings_schema() -> *mut ::std::os::raw::c_char {
+            $crate::ffi::settings_schema(__epikodi_plugin)
+        }
+
+        /// # Safety
+        /// `settings` must be null or a NUL-terminated string, see plugin_api::ffi::configure.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn configure(
+            settings: *const ::std::os::raw::c_char,
+        ) -> *mut ::std::os::raw::c_char {
+            unsafe { $crate::ffi::configure(__epikodi_plugin, settings) }
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn allowed_domains() -> *mut ::std::os::raw::c_char {
+            $crate::ffi::allowed_domains(__epikodi_plugin)
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn epikodi_alloc(len: usize) -> *mut ::std::os::raw::c_char {
+            $crate::ffi::alloc(len)
+        }
+
+        /// # Safety
+        /// `s` must be null or a string returned by this library, see plugin_api::ffi::free_string.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn free_string(s: *mut ::std::os::raw::c_char) {
+            unsafe { $crate::ffi::free_string(s) }
+        }
+    };
+}
+
+// Descriptor of the plugin, null if building the plugin panicked (the host refuses it)
+pub fn descriptor<P: Plugin>(
+    cell: &'static std::sync::OnceLock<OwnedDescriptor>,
+    plugin: fn() -> &'static P,
+) -> *const PluginDescriptor {
+    if let Some(descriptor) = cell.get() {
+        return descriptor.as_ptr();
+    }
+    match panic::catch_unwind(|| OwnedDescriptor::new(&plugin().manifest())) {
+        Ok(descriptor) => cell.get_or_init(|| descriptor).as_ptr(),
+        Err(_) => ptr::null(),
+    }
+}
+
+/// Answers a metadata lookup of the host, a bad query or a panic becomes an error answer.
+///
+/// # Safety
+/// `query` must be null or point to a NUL-terminated string valid for the duration of the call.
+pub unsafe fn metadata<P: Plugin>(plugin: fn() -> &'static P, query: *const c_char) -> *mut c_char {
+    if query.is_null() {
+        return into_c_string(MetadataResponse::error("null query").to_json());
+    }
+    let query = match unsafe { CStr::from_ptr(query) }.to_str() {
+        Ok(query) => query,
+        Err(_) => return into_c_string(MetadataResponse::error("query is not UTF-8").to_json()),
+    };
+
+    let answer = panic::catch_unwind(AssertUnwindSafe(|| plugin().metadata(query)));
+    into_c_string(answer.unwrap_or_else(|payload| {
+        MetadataResponse::error(format!("plugin panicked: {}", panic_message(&payload))).to_json()
+    }))
+}
+
+/// Answers a request of one of the other capabilities: the JSON request is parsed, given to
+/// `call`, and its result sent back as a providers::ProviderResponse. A bad request or a panic
+/// becomes an error answer.
+///
+/// # Safety
+/// `request` must be null or point to a NUL-terminated string valid for the duration of the call.
+pub unsafe fn provide<Q: DeserializeOwned, A: Answer>(
+    request: *const c_char,
+    call: impl FnOnce(&Q) -> Result<Option<A>, String>,
+) -> *mut c_char {
+    let error = |message: String| into_c_string(ProviderResponse::<A>::error(message).to_json());
+    if request.is_null() {
+        return error("null request".to_string());
+    }
+    let request = match unsafe { CStr::from_ptr(request) }.to_str() {
+        Ok(text) => serde_json::from_str::<Q>(text).map_err(|e| format!("invalid request: {}", e)),
+        Err(_) => Err("request is not UTF-8".to_string()),
+    };
+    let request = match request {
+        Ok(request) => request,
+        Err(message) => return error(message),
+    };
+
+    match panic::catch_unwind(AssertUnwindSafe(|| call(&request))) {
+        Ok(result) => into_c_string(ProviderResponse::from_result(result).to_json()),
+        Err(payload) => error(format!("plugin panicked: {}", panic_message(&payload))),
+    }
+}
+
+/// # Safety
+/// See `provide`.
+pub unsafe fn lyrics<P: Plugin>(plugin: fn() -> &'static P, request: *const c_char) -> *mut c_char {
+    unsafe { provide(request, |query| plugin().lyrics(query)) }
+}
+
+/// # Safety
+/// See `provide`.
+pub unsafe fn search_subtitles<P: Plugin>(
+    plugin: fn() -> &'static P,
+    request: *const c_char,
+) -> *mut c_char {
+    unsafe {
+        provide(request, |query| {
+            plugin().search_subtitles(query).map(providers::non_empty)
+        })
+    }
+}
+
+/// # Safety
+/// See `provide`.
+pub unsafe fn download_subtitle<P: Plugin>(
+    plugin: fn() -> &'static P,
+    request: *const c_char,
+) -> *mut c_char {
+    unsafe {
+        provide(request, |download: &providers::SubtitleDownload| {
+            plugin().download_subtitle(&download.id)
+        })
+    }
+}
+
+/// # Safety
+/// See `provide`.
+pub unsafe fn artwork<P: Plugin>(
+    plugin: fn() -> &'static P,
+    request: *const c_char,
+) -> *mut c_char {
+    unsafe {
+        provide(request, |query| {
+            plugin().artwork(query).map(providers::non_empty)
+        })
+    }
+}
+
+/// # Safety
+/// See `provide`.
+pub unsafe fn scrobble<P: Plugin>(
+    plugin: fn() -> &'static P,
+    request: *const c_char,
+) -> *mut c_char {
+    unsafe { provide(request, |event| plugin().scrobble(event).map(Some)) }
+}
+
+/// # Safety
+/// See `provide`. The request is any JSON object, nothing is read from it.
+pub unsafe fn subscriptions<P: Plugin>(
+    plugin: fn() -> &'static P,
+    request: *const c_char,
+) -> *mut c_char {
+    unsafe {
+        provide(request, |_: &serde_json::Value| {
+            Ok(Some(plugin().subscriptions()))
+        })
+    }
+}
+
+/// # Safety
+/// See `provide`.
+pub unsafe fn on_event<P: Plugin>(
+    plugin: fn() -> &'static P,
+    request: *const c_char,
+) -> *mut c_char {
+    unsafe { provide(request, |event| plugin().on_event(event).map(Some)) }
+}
+
+// Settings schema of the plugin as JSON, null if building it panicked
+pub fn settings_schema<P: Plugin>(plugin: fn() -> &'static P) -> *mut c_char {
+    match panic::catch_unwind(|| serde_json::to_string(&plugin().settings_schema())) {
+        Ok(Ok(schema)) => into_c_string(schema),
+        _ => ptr::null_mut(),
+    }
+}
+
+// Domains of http::fetch as a JSON list, null if building it panicked
+pub fn allowed_domains<P: Plugin>(plugin: fn() -> &'static P) -> *mut c_char {
+    match panic::catch_unwind(|| serde_json::to_string(&plugin().allowed_domains())) {
+        Ok(Ok(domains)) => into_c_string(domains),
+        _ => ptr::null_mut(),
+    }
+}
+
+// `len` bytes for the host to fill, followed by a NUL, to free with `free_string`.
+// The host must not write a NUL in them, the string would be freed with the wrong size.
+pub fn alloc(len: usize) -> *mut c_char {
+    CString::new(vec![b' '; len]).unwrap_or_default().into_raw()
+}
+
+/// Gives the settings (PluginSettings as JSON) to the plugin: null when it accepts them,
+/// otherwise the reason, to free with `free_string`.
+///
+/// # Safety
+/// `settings` must be null or point to a NUL-terminated string valid for the duration of the call.
+pub unsafe fn configure<P: Plugin>(
+    plugin: fn() -> &'static P,
+    settings: *const c_char,
+) -> *mut c_char {
+    if settings.is_null() {
+        return into_c_string("null settings".to_string());
+    }
+    let text = unsafe { CStr::from_ptr(settings) }.to_string_lossy();
+    let settings = match PluginSettings::from_json(&text) {
+        Ok(settings) => settings,
+        Err(e) => return into_c_string(format!("invalid settings: {}", e)),
+    };
+
+    match panic::catch_unwind(AssertUnwindSafe(|| plugin().configure(&settings))) {
+        Ok(Ok(())) => ptr::null_mut(),
+        Ok(Err(reason)) => into_c_string(reason),
+        Err(payload) => into_c_string(format!("plugin panicked: {}", panic_message(&payload))),
+    }
+}
+
+/// Frees a string returned by any function of the plugin.
+///
+/// # Safety
+/// `s` must be null or come from `into_c_string` of this library, and not be freed twice.
+pub unsafe fn free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+// The NUL bytes are dropped rather than cutting the string short
+pub fn into_c_string(s: String) -> *mut c_char {
+    CString::new(s.replace('\0', ""))
+        .unwrap_or_default()
+        .into_raw()
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::{PluginManifest, capabilities};
+    use crate::events::{HostEvent, PluginEvent, kinds};
+    use crate::metadata::MetadataResult;
+    use crate::providers::{Lyrics, LyricsQuery, ProviderResult, SubtitleFile};
+    use crate::settings::SettingField;
+
+    #[derive(Default)]
+    struct Echo;
+
+    impl Plugin for Echo {
+        fn name(&self) -> String {
+            "Echo".to_string()
+        }
+        fn version(&self) -> String {
+            "0.1.0".to_string()
+        }
+        fn capabilities(&self) -> Vec<String> {
+            vec![capabilities::ARTIST_METADATA.to_string()]
+        }
+        fn metadata(&self, name: &str) -> String {
+            if name == "panic" {
+                panic!("boom");
+            }
+            MetadataResponse::error(name).to_json()
+        }
+        fn lyrics(&self, query: &LyricsQuery) -> Result<Option<Lyrics>, String> {
+            match query.title.as_str() {
+                "" => Ok(None),
+                title => Ok(Some(Lyrics {
+                    plain: Some(title.to_string()),
+                    ..Default::default()
+                })),
+            }
+        }
+        fn subscriptions(&self) -> Vec<String> {
+            vec![kinds::LIBRARY_SCANNED.to_string()]
+        }
+        fn on_event(&self, event: &PluginEvent) -> Result<(), String> {
+            match event.event {
+                HostEvent::LibraryScanned { .. } => Ok(()),
+                _ => Err("not subscribed".to_string()),
+            }
+        }
+        fn settings_schema(&self) -> Vec<SettingField> {
+            vec![SettingField::string("greeting", "Greeting")]
+        }
+        fn configure(&self, settings: &PluginSettings) -> Result<(), String> {
+            match settings.text("greeting") {
+                Some("panic") => panic!("bad greeting"),
+                Some(_) => Ok(()),
+                None => Err("greeting is not set".to_string()),
+            }
+        }
+    }
+
+    fn echo() -> &'static Echo {
+        &Echo
+    }
+
+    // Answer of the plugin as the host reads it, then freed like the host does
+    fn call(query: *const c_char) -> MetadataResult {
+        unsafe {
+            let answer = metadata(echo, query);
+            let text = CStr::from_ptr(answer).to_str().unwrap().to_string();
+            free_string(answer);
+            MetadataResponse::from_json(&text).unwrap().result
+        }
+    }
+
+    fn error(message: &str) -> MetadataResult {
+        MetadataResult::Error {
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn queries_reach_the_plugin() {
+        let query = CString::new("Daft Punk").unwrap();
+        assert_eq!(call(query.as_ptr()), error("Daft Punk"));
+    }
+
+    #[test]
+    fn bad_queries_and_panics_become_errors() {
+        assert_eq!(call(ptr::null()), error("null query"));
+        let invalid = [0xff_u8, 0xfe, 0];
+        assert_eq!(
+            call(invalid.as_ptr() as *const c_char),
+            error("query is not UTF-8")
+        );
+        let query = CString::new("panic").unwrap();
+        assert_eq!(call(query.as_ptr()), error("plugin panicked: boom"));
+    }
+
+    // Answer of a capability function as the host reads it
+    fn provided<A: Answer>(answer: *mut c_char) -> ProviderResult<A> {
+        let text = unsafe { CStr::from_ptr(answer) }
+            .to_str()
+            .unwrap()
+            .to_string();
+        unsafe { free_string(answer) };
+        ProviderResponse::from_json(&text).unwrap().result
+    }
+
+    #[test]
+    fn capability_requests_reach_the_plugin() {
+        let request = CString::new(r#"{"artist": "Nirvana", "title": "Lithium"}"#).unwrap();
+        assert_eq!(
+            provided(unsafe { lyrics(echo, request.as_ptr()) }),
+            ProviderResult::Found {
+                value: Lyrics {
+                    plain: Some("Lithium".to_string()),
+                    ..Default::default()
+                }
+            }
+        );
+        let request = CString::new("{}").unwrap();
+        assert_eq!(
+            provided::<Lyrics>(unsafe { lyrics(echo, request.as_ptr()) }),
+            ProviderResult::NotFound
+        );
+
+        let error = |answer: ProviderResult<SubtitleFile>| match answer {
+            ProviderResult::Error { message } => message,
+            other => panic!("unexpected answer {:?}", other),
+        };
+        assert_eq!(
+            error(unsafe { provided(download_subtitle(echo, ptr::null())) }),
+            "null request"
+        );
+        let request = CString::new("Lithium").unwrap();
+        assert!(
+            error(unsafe { provided(download_subtitle(echo, request.as_ptr())) })
+                .starts_with("invalid request")
+        );
+        // a capability the plugin does not implement
+        let request = CString::new(r#"{"id": "1"}"#).unwrap();
+        assert!(
+            error(unsafe { provided(download_subtitle(echo, request.as_ptr())) })
+                .contains("not implemented")
+        );
+    }
+
+    #[test]
+    fn host_events_reach_the_plugin() {
+        let request = CString::new("{}").unwrap();
+        assert_eq!(
+            provided(unsafe { subscriptions(echo, request.as_ptr()) }),
+            ProviderResult::Found {
+                value: vec![kinds::LIBRARY_SCANNED.to_string()]
+            }
+        );
+
+        let event = |event: HostEvent| {
+            let json = serde_json::to_string(&PluginEvent { sent_at: 0, event }).unwrap();
+            let request = CString::new(json).unwrap();
+            provided::<()>(unsafe { on_event(echo, request.as_ptr()) })
+        };
+        let scanned = HostEvent::LibraryScanned {
+            added: 3,
+            updated: 0,
+            removed: 0,
+            cancelled: false,
+        };
+        assert_eq!(event(scanned), ProviderResult::Found { value: () });
+        let tagged = HostEvent::TagAdded {
+            media: Default::default(),
+            tag: "favori".to_string(),
+        };
+        assert_eq!(
+            event(tagged),
+            ProviderResult::Error {
+                message: "not subscribed".to_string()
+            }
+        );
+    }
+
+    // Answer of configure as the host reads it, None when the settings are accepted
+    fn configure_with(settings: &str) -> Option<String> {
+        let settings = CString::new(settings).unwrap();
+        unsafe {
+            let answer = configure(echo, settings.as_ptr());
+            if answer.is_null() {
+                return None;
+            }
+            let text = CStr::from_ptr(answer).to_string_lossy().into_owned();
+            free_string(answer);
+            Some(text)
+        }
+    }
+
+    #[test]
+    fn settings_reach_the_plugin() {
+        unsafe {
+            let schema = settings_schema(echo);
+            let text = CStr::from_ptr(schema).to_str().unwrap().to_string();
+            free_string(schema);
+            let schema: Vec<SettingField> = serde_json::from_str(&text).unwrap();
+            assert_eq!(schema, vec![SettingField::string("greeting", "Greeting")]);
+        }
+
+        assert_eq!(configure_with(r#"{"greeting": "hello"}"#), None);
+        assert_eq!(
+            configure_with("{}"),
+            Some("greeting is not set".to_string())
+        );
+        assert!(
+            configure_with("greeting")
+                .unwrap()
+                .starts_with("invalid settings")
+        );
+        assert_eq!(
+            configure_with(r#"{"greeting": "panic"}"#),
+            Some("plugin panicked: bad greeting".to_string())
+        );
+    }
+
+    // What a plugin crate gets from the macro
+    mod exported {
+        use super::Echo;
+        crate::declare_plugin!(Echo);
+    }
+
+    #[test]
+    fn declared_plugins_export_the_abi() {
+        let manifest =
+            unsafe { PluginManifest::from_descriptor(exported::epikodi_plugin_descriptor()) };
+        assert_eq!(
+            manifest.map(|m| m.capabilities),
+            Ok(vec![capabilities::ARTIST_METADATA.to_string()])
+        );
+
+        let query = CString::new("Nirvana").unwrap();
+        unsafe {
+            let answer = exported::metadata(query.as_ptr());
+            let text = CStr::from_ptr(answer).to_string_lossy().into_owned();
+            exported::free_string(answer);
+            assert_eq!(
+                MetadataResponse::from_json(&text).unwrap().result,
+                error("Nirvana")
+            );
+        }
+    }
+
+    #[test]
+    fn host_strings_are_allocated_by_the_plugin() {
+        unsafe {
+            let buffer = exported::epikodi_alloc(5);
+            ptr::copy_nonoverlapping(b"hello".as_ptr(), buffer as *mut u8, 5);
+            assert_eq!(CStr::from_ptr(buffer).to_str(), Ok("hello"));
+            exported::free_string(buffer);
+
+            let domains = exported::allowed_domains();
+            assert_eq!(CStr::from_ptr(domains).to_str(), Ok("[]"));
+            exported::free_string(domains);
+        }
+    }
+
+    #[test]
+    fn descriptor_is_built_once() {
+        static CELL: std::sync::OnceLock<OwnedDescriptor> = std::sync::OnceLock::new();
+        let first = descriptor(&CELL, echo);
+        assert_eq!(first, descriptor(&CELL, echo));
+        let manifest = unsafe { PluginManifest::from_descriptor(first) }.unwrap();
+        assert_eq!(manifest.name, "Echo");
+        unsafe { free_string(ptr::null_mut()) };
+    }
+}
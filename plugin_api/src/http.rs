@@ -0,0 +1,124 @@
+/*
+HTTP for the WebAssembly plugins, which have no network of their own: `fetch` hands the request
+to the host (import `epikodi.http_fetch`), which only sends it to the domains the plugin declared
+in Plugin::allowed_domains and the user approved, their subdomains included, over http or https.
+IP addresses, localhost and names resolving to a local address are always refused.
+Native plugins keep using the HTTP client of their choice, `fetch` is an error for them.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Name of the host module the WebAssembly plugins import from
+pub const HOST_MODULE: &str = "epikodi";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpRequest {
+    pub method: String, // "GET" when empty
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: String, // UTF-8 text, invalid bytes are replaced
+}
+
+// What the host answers to `http_fetch`: the response, or why it was not sent or failed
+pub type FetchAnswer = Result<HttpResponse, String>;
+
+impl HttpRequest {
+    pub fn get(url: impl Into<String>) -> Self {
+        Self {
+            method: "GET".to_string(),
+            url: url.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+// `host` is one of the declared domains or a subdomain of one, case ignored
+pub fn domain_allowed(domains: &[String], host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    domains.iter().any(|domain| {
+        let domain = domain.trim().trim_matches('.').to_ascii_lowercase();
+        !domain.is_empty()
+            && (host == domain
+                || host
+                    .strip_suffix(&domain)
+                    .is_some_and(|sub| sub.ends_with('.')))
+    })
+}
+
+#[cfg(target_family = "wasm")]
+mod host {
+    use std::os::raw::c_char;
+
+    #[link(wasm_import_module = "epikodi")]
+    unsafe extern "C" {
+        // Takes an HttpRequest as JSON, returns a FetchAnswer as JSON allocated with the
+        // `epikodi_alloc` export of the plugin
+        pub fn http_fetch(request: *const c_char) -> *mut c_char;
+    }
+}
+
+/// Sends the request through the host, only from a WebAssembly plugin.
+#[cfg(target_family = "wasm")]
+pub fn fetch(request: &HttpRequest) -> FetchAnswer {
+    use std::ffi::CString;
+
+    let json = serde_json::to_string(request).map_err(|e| e.to_string())?;
+    let json = CString::new(json).map_err(|e| e.to_string())?;
+    let answer = unsafe { host::http_fetch(json.as_ptr()) };
+    if answer.is_null() {
+        return Err("no answer from the host".to_string());
+    }
+    // allocated by epikodi_alloc of this module, so it is ours to free
+    let answer = unsafe { CString::from_raw(answer) };
+    serde_json::from_str::<FetchAnswer>(&answer.to_string_lossy())
+        .map_err(|e| format!("invalid answer from the host: {}", e))?
+}
+
+/// Sends the request through the host, only from a WebAssembly plugin.
+#[cfg(not(target_family = "wasm"))]
+pub fn fetch(_request: &HttpRequest) -> FetchAnswer {
+    Err("http::fetch is only for WebAssembly plugins".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn declared_domains_include_their_subdomains() {
+        let domains = vec!["musicbrainz.org".to_string(), " .LRCLIB.net ".to_string()];
+        assert!(domain_allowed(&domains, "musicbrainz.org"));
+        assert!(domain_allowed(&domains, "coverartarchive.musicbrainz.org"));
+        assert!(domain_allowed(&domains, "lrclib.net."));
+        assert!(domain_allowed(&domains, "API.lrclib.net"));
+        assert!(!domain_allowed(&domains, "evilmusicbrainz.org"));
+        assert!(!domain_allowed(&domains, "musicbrainz.org.evil.com"));
+        assert!(!domain_allowed(&[String::new()], "localhost"));
+
+        let answer: FetchAnswer = Err("not allowed".to_string());
+        let json = serde_json::to_string(&answer).unwrap();
+        assert_eq!(serde_json::from_str::<FetchAnswer>(&json).unwrap(), answer);
+        assert!(fetch(&HttpRequest::get("https://musicbrainz.org")).is_err());
+    }
+}
@@ -1,3 +1,11 @@
+pub mod abi;
+pub mod events;
+pub mod ffi;
+pub mod http;
+pub mod metadata;
+pub mod providers;
+pub mod settings;
+
 // This defines the trait that plugins must implement
 pub trait Greeter {
     fn greet(&self, name: &str) -> String;
@@ -6,8 +14,84 @@ pub trait Greeter {
 pub trait Plugin {
     fn name(&self) -> String;
     fn version(&self) -> String;
-    fn plugin_type(&self) -> String; // "metadata",
+    fn author(&self) -> String {
+        String::new()
+    }
+    fn capabilities(&self) -> Vec<String>; // abi::capabilities
 
-    //get artist metadata by name
+    //get artist metadata by name, answered with a metadata::MetadataResponse as JSON
     fn metadata(&self, name: &str) -> String;
+
+    // The other capabilities, only called when declared in capabilities(). Ok(None) is not found.
+    fn lyrics(&self, _query: &providers::LyricsQuery) -> Result<Option<providers::Lyrics>, String> {
+        Err(unsupported(abi::capabilities::LYRICS))
+    }
+
+    // An empty list is not found
+    fn search_subtitles(
+        &self,
+        _query: &providers::SubtitleQuery,
+    ) -> Result<Vec<providers::SubtitleResult>, String> {
+        Err(unsupported(abi::capabilities::SUBTITLES))
+    }
+
+    // `id` comes from a SubtitleResult of this plugin
+    fn download_subtitle(&self, _id: &str) -> Result<Option<providers::SubtitleFile>, String> {
+        Err(unsupported(abi::capabilities::SUBTITLES))
+    }
+
+    // Images in order of preference, an empty list is not found
+    fn artwork(
+        &self,
+        _query: &providers::ArtworkQuery,
+    ) -> Result<Vec<metadata::MetadataImage>, String> {
+        Err(unsupported(abi::capabilities::ARTWORK))
+    }
+
+    // Playback of a track, sent to every scrobbler
+    fn scrobble(&self, _event: &providers::ScrobbleEvent) -> Result<(), String> {
+        Err(unsupported(abi::capabilities::SCROBBLER))
+    }
+
+    // Kinds of host events the plugin wants (events::kinds), asked once after loading
+    fn subscriptions(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    // One of the events subscribed to, never on the thread of the player
+    fn on_event(&self, _event: &events::PluginEvent) -> Result<(), String> {
+        Err(unsupported(abi::capabilities::EVENTS))
+    }
+
+    // Settings the host shows in a form for this plugin
+    fn settings_schema(&self) -> Vec<settings::SettingField> {
+        Vec::new()
+    }
+
+    // Domains a WebAssembly build may reach with http::fetch once the user approved them,
+    // subdomains included. Nothing else is reachable from the sandbox, native plugins are
+    // not restricted.
+    fn allowed_domains(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    // Values of the settings (defaults included), given after loading and after every change,
+    // before the next metadata call. An error is shown to the user, the plugin stays loaded.
+    fn configure(&self, _settings: &settings::PluginSettings) -> Result<(), String> {
+        Ok(())
+    }
+
+    // What the plugin exports in its abi::PluginDescriptor
+    fn manifest(&self) -> abi::PluginManifest {
+        abi::PluginManifest {
+            name: self.name(),
+            version: self.version(),
+            author: self.author(),
+            capabilities: self.capabilities(),
+        }
+    }
+}
+
+fn unsupported(capability: &str) -> String {
+    format!("the '{}' capability is not implemented", capability)
 }
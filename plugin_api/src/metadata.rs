@@ -0,0 +1,297 @@
+/*
+Versioned JSON schema of the metadata returned by the plugins.
+A plugin answers every lookup with a MetadataResponse serialized as JSON, the host checks
+the schema version and the required fields before using it.
+Unknown fields are ignored, so a newer plugin can add fields without breaking older hosts.
+*/
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::fmt;
+
+// Bump when a field changes meaning or a required field is added
+pub const METADATA_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataResponse {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub result: MetadataResult,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum MetadataResult {
+    Found { metadata: Box<Metadata> },
+    NotFound,
+    Error { message: String }, // the source could not be queried (network, api key...)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Metadata {
+    Artist(ArtistMetadata),
+    Movie(MovieMetadata),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArtistMetadata {
+    pub name: String,
+    pub sort_name: Option<String>,
+    pub artist_type: Option<String>, // "Person", "Group"...
+    pub country: Option<String>,
+    pub begin: Option<String>, // as precise as the source: "1987", "1987-03-20"
+    pub end: Option<String>,
+    pub overview: Option<String>,
+    pub genres: Vec<String>,
+    pub images: Vec<MetadataImage>,
+    pub external_ids: ExternalIds,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MovieMetadata {
+    pub title: String,
+    pub original_title: Option<String>,
+    pub year: Option<u32>,
+    pub release_date: Option<String>, // "2010-07-16"
+    pub overview: Option<String>,
+    pub genres: Vec<String>,
+    pub rating: Option<f32>, // out of 10
+    pub vote_count: Option<u32>,
+    pub runtime: Option<u32>, // minutes
+    pub images: Vec<MetadataImage>,
+    pub external_ids: ExternalIds,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataImage {
+    pub kind: String, // "poster", "fanart", "thumb", "logo"...
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExternalIds {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub musicbrainz: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tmdb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imdb: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaError {
+    Json(String),
+    UnsupportedVersion(u32),
+    MissingField(&'static str),
+    InvalidField(&'static str, String), // field, reason
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaError::Json(e) => write!(f, "invalid JSON: {}", e),
+            SchemaError::UnsupportedVersion(version) => write!(
+                f,
+                "schema version {} is not supported (1 to {})",
+                version, METADATA_SCHEMA_VERSION
+            ),
+            SchemaError::MissingField(field) => write!(f, "missing field '{}'", field),
+            SchemaError::InvalidField(field, reason) => {
+                write!(f, "invalid field '{}': {}", field, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+impl MetadataResponse {
+    pub fn found(metadata: Metadata) -> Self {
+        Self::new(MetadataResult::Found {
+            metadata: Box::new(metadata),
+        })
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(MetadataResult::NotFound)
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(MetadataResult::Error {
+            message: message.into(),
+        })
+    }
+
+    fn new(result: MetadataResult) -> Self {
+        Self {
+            schema_version: METADATA_SCHEMA_VERSION,
+            result,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|e| {
+            json!({
+                "schema_version": METADATA_SCHEMA_VERSION,
+                "status": "error",
+                "message": e.to_string(),
+            })
+            .to_string()
+        })
+    }
+
+    // Parses the answer of a plugin, the version is checked before the rest of the document
+    pub fn from_json(text: &str) -> Result<Self, SchemaError> {
+        let value: Value =
+            serde_json::from_str(text).map_err(|e| SchemaError::Json(e.to_string()))?;
+        let version = value
+            .get("schema_version")
+            .ok_or(SchemaError::MissingField("schema_version"))?
+            .as_u64()
+            .ok_or_else(|| SchemaError::InvalidField("schema_version", "not a number".into()))?;
+        if version == 0 || version > METADATA_SCHEMA_VERSION as u64 {
+            return Err(SchemaError::UnsupportedVersion(
+                version.min(u32::MAX as u64) as u32,
+            ));
+        }
+
+        let response: Self =
+            serde_json::from_value(value).map_err(|e| SchemaError::Json(e.to_string()))?;
+        if let MetadataResult::Found { metadata } = &response.result {
+            metadata.validate()?;
+        }
+        Ok(response)
+    }
+}
+
+impl Metadata {
+    pub fn validate(&self) -> Result<(), SchemaError> {
+        match self {
+            Metadata::Artist(artist) => {
+                if artist.name.trim().is_empty() {
+                    return Err(SchemaError::MissingField("name"));
+                }
+                validate_images(&artist.images)
+            }
+            Metadata::Movie(movie) => {
+                if movie.title.trim().is_empty() {
+                    return Err(SchemaError::MissingField("title"));
+                }
+                if let Some(rating) = movie.rating
+                    && !(0.0..=10.0).contains(&rating)
+                {
+                    return Err(SchemaError::InvalidField(
+                        "rating",
+                        format!("{} is not between 0 and 10", rating),
+                    ));
+                }
+                if let Some(year) = movie.year
+                    && !(1870..=2200).contains(&year)
+                {
+                    return Err(SchemaError::InvalidField("year", year.to_string()));
+                }
+                validate_images(&movie.images)
+            }
+        }
+    }
+}
+
+pub(crate) fn validate_images(images: &[MetadataImage]) -> Result<(), SchemaError> {
+    for image in images {
+        if !image.url.starts_with("http://") && !image.url.starts_with("https://") {
+            return Err(SchemaError::InvalidField("images", image.url.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inception() -> MovieMetadata {
+        MovieMetadata {
+            title: "Inception".to_string(),
+            year: Some(2010),
+            rating: Some(8.4),
+            images: vec![MetadataImage {
+                kind: "poster".to_string(),
+                url: "https://image.tmdb.org/t/p/w500/inception.jpg".to_string(),
+            }],
+            external_ids: ExternalIds {
+                tmdb: Some(27205),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        for response in [
+            MetadataResponse::found(Metadata::Movie(inception())),
+            MetadataResponse::found(Metadata::Artist(ArtistMetadata {
+                name: "Nirvana".to_string(),
+                genres: vec!["grunge".to_string()],
+                ..Default::default()
+            })),
+            MetadataResponse::not_found(),
+            MetadataResponse::error("timeout"),
+        ] {
+            let json = response.to_json();
+            assert_eq!(MetadataResponse::from_json(&json), Ok(response));
+        }
+    }
+
+    #[test]
+    fn reads_the_documented_format() {
+        let json = r#"{
+            "schema_version": 1,
+            "status": "found",
+            "metadata": {"kind": "artist", "name": "Nirvana", "country": "US", "future_field": 3}
+        }"#;
+        let response = MetadataResponse::from_json(json).unwrap();
+        let MetadataResult::Found { metadata } = response.result else {
+            panic!("unexpected result {:?}", response.result);
+        };
+        match *metadata {
+            Metadata::Artist(artist) => {
+                assert_eq!(artist.name, "Nirvana");
+                assert_eq!(artist.country.as_deref(), Some("US"));
+                assert!(artist.genres.is_empty());
+            }
+            other => panic!("unexpected metadata {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_answers() {
+        let error = |json: &str| MetadataResponse::from_json(json).unwrap_err();
+
+        assert!(matches!(error("artist not found"), SchemaError::Json(_)));
+        assert_eq!(
+            error(r#"{"status": "not_found"}"#),
+            SchemaError::MissingField("schema_version")
+        );
+        assert_eq!(
+            error(r#"{"schema_version": 99, "status": "not_found"}"#),
+            SchemaError::UnsupportedVersion(99)
+        );
+        assert_eq!(
+            error(r#"{"schema_version": 1, "status": "found", "metadata": {"kind": "movie"}}"#),
+            SchemaError::MissingField("title")
+        );
+
+        let mut movie = inception();
+        movie.rating = Some(84.0);
+        let json = MetadataResponse::found(Metadata::Movie(movie)).to_json();
+        assert!(matches!(
+            MetadataResponse::from_json(&json),
+            Err(SchemaError::InvalidField("rating", _))
+        ));
+    }
+}
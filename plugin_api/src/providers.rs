@@ -0,0 +1,302 @@
+/*
+Requests and answers of the capabilities other than metadata: lyrics, subtitles, artwork and
+scrobbling. The host sends the request as JSON to the function of the capability
+(see abi::capabilities::functions), the plugin answers with a ProviderResponse as JSON.
+Like the metadata, unknown fields are ignored and the schema version is checked first.
+*/
+
+use crate::metadata::{ExternalIds, MetadataImage, SchemaError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+// Bump when a field changes meaning or a required field is added
+pub const PROVIDER_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LyricsQuery {
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    pub duration: Option<u32>, // seconds, helps picking the right version
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lyrics {
+    pub plain: Option<String>,
+    pub synced: Option<String>, // LRC: "[00:12.34] line"
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubtitleQuery {
+    pub title: String,
+    pub year: Option<u32>,
+    pub season: Option<u32>, // season and episode for an episode of a show
+    pub episode: Option<u32>,
+    pub languages: Vec<String>, // "fr", "en"... empty for any
+    pub external_ids: ExternalIds,
+}
+
+// One subtitle a provider can download, `id` only means something to that provider
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubtitleResult {
+    pub id: String,
+    pub language: String,
+    pub name: String,   // release name, shown to pick the matching one
+    pub format: String, // "srt", "ass", "vtt"
+    pub downloads: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubtitleDownload {
+    pub id: String, // SubtitleResult::id
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubtitleFile {
+    pub language: String,
+    pub format: String,
+    pub content: String, // UTF-8 text of the file
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArtworkQuery {
+    pub kind: String, // "movie", "show", "artist", "album"
+    pub title: String,
+    pub artist: Option<String>, // for an album
+    pub year: Option<u32>,
+    pub external_ids: ExternalIds,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScrobbleTrack {
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    pub duration: Option<u32>, // seconds
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ScrobbleEvent {
+    NowPlaying {
+        track: ScrobbleTrack,
+    },
+    Played {
+        track: ScrobbleTrack,
+        started_at: i64, // unix seconds
+    },
+}
+
+// What a capability answers, checked by the host before use
+pub trait Answer: Serialize + DeserializeOwned {
+    fn validate(&self) -> Result<(), SchemaError> {
+        Ok(())
+    }
+}
+
+impl Answer for Lyrics {
+    fn validate(&self) -> Result<(), SchemaError> {
+        let empty = |text: &Option<String>| text.as_deref().is_none_or(|t| t.trim().is_empty());
+        match empty(&self.plain) && empty(&self.synced) {
+            true => Err(SchemaError::MissingField("plain")),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Answer for Vec<SubtitleResult> {
+    fn validate(&self) -> Result<(), SchemaError> {
+        match self.iter().any(|result| result.id.is_empty()) {
+            true => Err(SchemaError::MissingField("id")),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Answer for SubtitleFile {
+    fn validate(&self) -> Result<(), SchemaError> {
+        match self.content.trim().is_empty() {
+            true => Err(SchemaError::MissingField("content")),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Answer for Vec<MetadataImage> {
+    fn validate(&self) -> Result<(), SchemaError> {
+        crate::metadata::validate_images(self)
+    }
+}
+
+// Nothing to answer but whether the event was taken
+impl Answer for () {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProviderResponse<T> {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub result: ProviderResult<T>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ProviderResult<T> {
+    Found { value: T },
+    NotFound,
+    Error { message: String },
+}
+
+impl<T: Answer> ProviderResponse<T> {
+    pub fn found(value: T) -> Self {
+        Self::new(ProviderResult::Found { value })
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(ProviderResult::NotFound)
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(ProviderResult::Error {
+            message: message.into(),
+        })
+    }
+
+    // What the Plugin methods return: None is not_found
+    pub fn from_result(result: Result<Option<T>, String>) -> Self {
+        match result {
+            Ok(Some(value)) => Self::found(value),
+            Ok(None) => Self::not_found(),
+            Err(message) => Self::error(message),
+        }
+    }
+
+    fn new(result: ProviderResult<T>) -> Self {
+        Self {
+            schema_version: PROVIDER_SCHEMA_VERSION,
+            result,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|e| {
+            json!({
+                "schema_version": PROVIDER_SCHEMA_VERSION,
+                "status": "error",
+                "message": e.to_string(),
+            })
+            .to_string()
+        })
+    }
+
+    // Parses the answer of a plugin, the version is checked before the rest of the document
+    pub fn from_json(text: &str) -> Result<Self, SchemaError> {
+        let value: Value =
+            serde_json::from_str(text).map_err(|e| SchemaError::Json(e.to_string()))?;
+        let version = value
+            .get("schema_version")
+            .ok_or(SchemaError::MissingField("schema_version"))?
+            .as_u64()
+            .ok_or_else(|| SchemaError::InvalidField("schema_version", "not a number".into()))?;
+        if version == 0 || version > PROVIDER_SCHEMA_VERSION as u64 {
+            return Err(SchemaError::UnsupportedVersion(
+                version.min(u32::MAX as u64) as u32,
+            ));
+        }
+
+        let response: Self =
+            serde_json::from_value(value).map_err(|e| SchemaError::Json(e.to_string()))?;
+        if let ProviderResult::Found { value } = &response.result {
+            value.validate()?;
+        }
+        Ok(response)
+    }
+}
+
+// An empty list is not_found, so the host asks the next plugin
+pub fn non_empty<T>(list: Vec<T>) -> Option<Vec<T>> {
+    match list.is_empty() {
+        true => None,
+        false => Some(list),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_round_trip() {
+        let lyrics = Lyrics {
+            synced: Some("[00:12.00] Load up on guns".to_string()),
+            ..Default::default()
+        };
+        let response = ProviderResponse::found(lyrics);
+        assert_eq!(
+            ProviderResponse::from_json(&response.to_json()),
+            Ok(response)
+        );
+
+        let subtitles = vec![SubtitleResult {
+            id: "42".to_string(),
+            language: "fr".to_string(),
+            format: "srt".to_string(),
+            ..Default::default()
+        }];
+        let response = ProviderResponse::from_result(Ok(non_empty(subtitles)));
+        assert_eq!(
+            ProviderResponse::from_json(&response.to_json()),
+            Ok(response)
+        );
+
+        let taken = ProviderResponse::<()>::from_result(Ok(Some(())));
+        assert_eq!(ProviderResponse::from_json(&taken.to_json()), Ok(taken));
+        let none = ProviderResponse::<Vec<MetadataImage>>::from_result(Ok(non_empty(Vec::new())));
+        assert_eq!(none.result, ProviderResult::NotFound);
+    }
+
+    #[test]
+    fn rejects_invalid_answers() {
+        assert_eq!(
+            ProviderResponse::<Lyrics>::from_json(
+                r#"{"schema_version": 1, "status": "found", "value": {"plain": " "}}"#
+            ),
+            Err(SchemaError::MissingField("plain"))
+        );
+        assert_eq!(
+            ProviderResponse::<SubtitleFile>::from_json(
+                r#"{"schema_version": 2, "status": "not_found"}"#
+            ),
+            Err(SchemaError::UnsupportedVersion(2))
+        );
+        let images = r#"{"schema_version": 1, "status": "found",
+            "value": [{"kind": "poster", "url": "file:///etc/passwd"}]}"#;
+        assert!(matches!(
+            ProviderResponse::<Vec<MetadataImage>>::from_json(images),
+            Err(SchemaError::InvalidField("images", _))
+        ));
+    }
+
+    #[test]
+    fn scrobble_events_are_tagged() {
+        let event = ScrobbleEvent::NowPlaying {
+            track: ScrobbleTrack {
+                artist: "Nirvana".to_string(),
+                title: "Lithium".to_string(),
+                ..Default::default()
+            },
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains(r#""event":"now_playing""#));
+        assert_eq!(serde_json::from_str::<ScrobbleEvent>(&json).unwrap(), event);
+    }
+}
@@ -0,0 +1,212 @@
+/*
+Settings of a plugin (API key, language, contact address...).
+The plugin declares a schema, the host stores the values per plugin and hands them to the plugin
+with `configure` after loading it and after every change. Both cross the C boundary as JSON.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SettingKind {
+    String,
+    Secret, // stored apart by the host and never shown back in clear
+    Enum { options: Vec<String> },
+    Boolean,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SettingValue {
+    Boolean(bool),
+    Text(String), // String, Secret and Enum settings
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingField {
+    pub key: String,
+    pub label: String,
+    #[serde(default)]
+    pub description: String,
+    pub kind: SettingKind,
+    #[serde(default)]
+    pub default: Option<SettingValue>,
+}
+
+impl SettingField {
+    fn new(key: &str, label: &str, kind: SettingKind) -> Self {
+        Self {
+            key: key.to_string(),
+            label: label.to_string(),
+            description: String::new(),
+            kind,
+            default: None,
+        }
+    }
+
+    pub fn string(key: &str, label: &str) -> Self {
+        Self::new(key, label, SettingKind::String)
+    }
+
+    pub fn secret(key: &str, label: &str) -> Self {
+        Self::new(key, label, SettingKind::Secret)
+    }
+
+    pub fn choice(key: &str, label: &str, options: &[&str]) -> Self {
+        let options = options.iter().map(|option| option.to_string()).collect();
+        Self::new(key, label, SettingKind::Enum { options })
+    }
+
+    pub fn boolean(key: &str, label: &str) -> Self {
+        Self::new(key, label, SettingKind::Boolean)
+    }
+
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        self
+    }
+
+    pub fn with_default(mut self, default: SettingValue) -> Self {
+        self.default = Some(default);
+        self
+    }
+
+    pub fn is_secret(&self) -> bool {
+        self.kind == SettingKind::Secret
+    }
+
+    // A boolean for a Boolean setting, one of the options for an Enum, any text otherwise
+    pub fn check(&self, value: &SettingValue) -> Result<(), String> {
+        match (&self.kind, value) {
+            (SettingKind::Boolean, SettingValue::Boolean(_)) => Ok(()),
+            (SettingKind::Boolean, _) => Err(format!("'{}' expects true or false", self.key)),
+            (SettingKind::Enum { options }, SettingValue::Text(text)) if options.contains(text) => {
+                Ok(())
+            }
+            (SettingKind::Enum { options }, _) => Err(format!(
+                "'{}' expects one of {}",
+                self.key,
+                options.join(", ")
+            )),
+            (_, SettingValue::Text(_)) => Ok(()),
+            (_, SettingValue::Boolean(_)) => Err(format!("'{}' expects a text", self.key)),
+        }
+    }
+}
+
+// Values by setting key
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PluginSettings {
+    pub values: BTreeMap<String, SettingValue>,
+}
+
+impl PluginSettings {
+    // Text of a setting, None when unset or empty
+    pub fn text(&self, key: &str) -> Option<&str> {
+        match self.values.get(key) {
+            Some(SettingValue::Text(text)) if !text.trim().is_empty() => Some(text.trim()),
+            _ => None,
+        }
+    }
+
+    pub fn boolean(&self, key: &str) -> Option<bool> {
+        match self.values.get(key) {
+            Some(SettingValue::Boolean(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: SettingValue) {
+        self.values.insert(key.to_string(), value);
+    }
+
+    // What the plugin gets: the values matching the schema, the defaults for the others.
+    // Values of settings the plugin no longer declares are left out.
+    pub fn resolve(&self, schema: &[SettingField]) -> PluginSettings {
+        let values = schema
+            .iter()
+            .filter_map(|field| {
+                let value = self
+                    .values
+                    .get(&field.key)
+                    .filter(|value| field.check(value).is_ok())
+                    .or(field.default.as_ref())?;
+                Some((field.key.clone(), value.clone()))
+            })
+            .collect();
+        PluginSettings { values }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Vec<SettingField> {
+        vec![
+            SettingField::secret("api_key", "API key"),
+            SettingField::choice("language", "Language", &["fr-FR", "en-US"])
+                .with_default(SettingValue::Text("fr-FR".to_string())),
+            SettingField::boolean("adult", "Adult movies")
+                .with_default(SettingValue::Boolean(false)),
+        ]
+    }
+
+    #[test]
+    fn values_are_checked_against_their_kind() {
+        let schema = schema();
+        assert!(
+            schema[0]
+                .check(&SettingValue::Text("abc".to_string()))
+                .is_ok()
+        );
+        assert!(
+            schema[1]
+                .check(&SettingValue::Text("de-DE".to_string()))
+                .is_err()
+        );
+        assert!(
+            schema[2]
+                .check(&SettingValue::Text("yes".to_string()))
+                .is_err()
+        );
+        assert!(schema[2].check(&SettingValue::Boolean(true)).is_ok());
+    }
+
+    #[test]
+    fn stored_values_are_resolved_with_the_defaults() {
+        let stored = PluginSettings::from_json(
+            r#"{"api_key": "abc", "language": "de-DE", "removed": "old value"}"#,
+        )
+        .unwrap();
+        let settings = stored.resolve(&schema());
+
+        assert_eq!(settings.text("api_key"), Some("abc"));
+        // an option the plugin no longer offers falls back to the default
+        assert_eq!(settings.text("language"), Some("fr-FR"));
+        assert_eq!(settings.boolean("adult"), Some(false));
+        assert!(!settings.values.contains_key("removed"));
+        assert_eq!(
+            PluginSettings::from_json(&settings.to_json()).unwrap(),
+            settings
+        );
+    }
+
+    #[test]
+    fn schemas_round_trip_as_json() {
+        let json = serde_json::to_string(&schema()).unwrap();
+        assert!(json.contains(r#""type":"enum""#));
+        let read: Vec<SettingField> = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, schema());
+    }
+}
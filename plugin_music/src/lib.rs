@@ -1,8 +1,9 @@
 use plugin_api::Plugin;
+use plugin_api::abi::capabilities;
+use plugin_api::metadata::{ArtistMetadata, ExternalIds, Metadata, MetadataResponse};
+use plugin_api::settings::{PluginSettings, SettingField};
 use serde::Deserialize;
-use std::ffi::{CStr, CString};
-use std::os::raw::c_char;
-use std::panic;
+use std::sync::RwLock;
 use std::time::Duration;
 
 // --- STRUCTURES JSON (Pour lire la réponse de MusicBrainz) ---
@@ -13,12 +14,18 @@ struct ArtistSearchResult {
 
 #[derive(Deserialize, Debug)]
 struct Artist {
+    id: String,
     name: String,
+    #[serde(rename = "sort-name")]
+    sort_name: Option<String>,
     #[serde(rename = "type")]
     artist_type: Option<String>,
     country: Option<String>,
+    disambiguation: Option<String>,
     #[serde(rename = "life-span")]
     life_span: Option<LifeSpan>,
+    #[serde(default)]
+    tags: Vec<Tag>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -27,8 +34,27 @@ struct LifeSpan {
     end: Option<String>,
 }
 
+#[derive(Deserialize, Debug)]
+struct Tag {
+    name: String,
+}
+
 // --- COEUR DU PLUGIN ---
-struct MusicBrainzMetadata;
+#[derive(Default)]
+struct MusicBrainzMetadata {
+    settings: RwLock<PluginSettings>, // donnés par NeoKodi avec configure()
+}
+
+impl MusicBrainzMetadata {
+    // MusicBrainz demande un User-Agent qui permette de contacter l'application
+    fn user_agent(&self) -> String {
+        let settings = self.settings.read().unwrap();
+        match settings.text("contact") {
+            Some(contact) => format!("NeoKodiPlugin/1.0 ( {} )", contact),
+            None => "NeoKodiPlugin/1.0 (educational-purpose)".to_string(),
+        }
+    }
+}
 
 impl Plugin for MusicBrainzMetadata {
     fn name(&self) -> String {
@@ -37,8 +63,22 @@ impl Plugin for MusicBrainzMetadata {
     fn version(&self) -> String {
         "1.0.0".to_string()
     }
-    fn plugin_type(&self) -> String {
-        "metadata".to_string()
+    fn author(&self) -> String {
+        "EpiKodi".to_string()
+    }
+    fn capabilities(&self) -> Vec<String> {
+        vec![capabilities::ARTIST_METADATA.to_string()]
+    }
+
+    fn settings_schema(&self) -> Vec<SettingField> {
+        let contact = SettingField::string("contact", "Contact (e-mail ou URL)")
+            .with_description("Ajouté au User-Agent demandé par MusicBrainz");
+        vec![contact]
+    }
+
+    fn configure(&self, settings: &PluginSettings) -> Result<(), String> {
+        *self.settings.write().unwrap() = settings.clone();
+        Ok(())
     }
 
     fn metadata(&self, artist_name: &str) -> String {
@@ -48,21 +88,26 @@ impl Plugin for MusicBrainzMetadata {
             artist_name
         );
 
-        match search_artist(artist_name) {
-            Ok(info) => {
+        let response = match search_artist(artist_name, &self.user_agent()) {
+            Ok(Some(artist)) => {
                 println!("DLL [INTERNAL]: Succès ! Données récupérées.");
-                info
+                MetadataResponse::found(Metadata::Artist(artist))
             }
+            Ok(None) => MetadataResponse::not_found(),
             Err(e) => {
                 println!("DLL [INTERNAL]: Erreur -> {}", e);
-                format!("Erreur plugin : {}", e)
+                MetadataResponse::error(e.to_string())
             }
-        }
+        };
+        response.to_json()
     }
 }
 
 // --- FONCTION DE RECHERCHE HTTP (Version stable avec UREQ) ---
-fn search_artist(name: &str) -> Result<String, Box<dyn std::error::Error>> {
+fn search_artist(
+    name: &str,
+    user_agent: &str,
+) -> Result<Option<ArtistMetadata>, Box<dyn std::error::Error>> {
     println!("DLL [INTERNAL]: Préparation requête...");
 
     let url = format!(
@@ -76,106 +121,35 @@ fn search_artist(name: &str) -> Result<String, Box<dyn std::error::Error>> {
         .timeout_write(Duration::from_secs(5))
         .build();
 
-    let response = agent
-        .get(&url)
-        .set("User-Agent", "NeoKodiPlugin/1.0 (educational-purpose)")
-        .call();
-
-    match response {
-        Ok(resp) => {
-            println!("DLL [INTERNAL]: Réponse reçue ! Traitement JSON...");
-            let result: ArtistSearchResult = resp.into_json()?;
-
-            if let Some(artist) = result.artists.first() {
-                // Construction du texte affiché dans l'appli
-                let mut info = format!("🎵 Artiste : {}", artist.name);
-                if let Some(t) = &artist.artist_type {
-                    info.push_str(&format!("\nType : {}", t));
-                }
-                if let Some(c) = &artist.country {
-                    info.push_str(&format!("\nPays : {}", c));
-                }
-
-                if let Some(ls) = &artist.life_span {
-                    if let Some(b) = &ls.begin {
-                        info.push_str(&format!("\nDébut : {}", b));
-                    }
-                    if let Some(e) = &ls.end {
-                        info.push_str(&format!(" - Fin : {}", e));
-                    }
-                }
-
-                Ok(info)
-            } else {
-                Ok("Aucun artiste trouvé sur MusicBrainz.".to_string())
-            }
-        }
-        Err(e) => {
-            println!("DLL [INTERNAL]: Echec HTTP -> {:?}", e);
-            // On renvoie l'erreur sous forme de texte pour qu'elle s'affiche dans l'appli
-            Ok(format!("Erreur de connexion : {:?}", e))
+    let response = agent.get(&url).set("User-Agent", user_agent).call();
+
+    // Une erreur de connexion remonte à l'application comme une erreur du plugin
+    let result: ArtistSearchResult = response?.into_json()?;
+    println!("DLL [INTERNAL]: Réponse reçue !");
+
+    Ok(result.artists.into_iter().next().map(|artist| {
+        let life_span = artist.life_span.unwrap_or(LifeSpan {
+            begin: None,
+            end: None,
+        });
+        ArtistMetadata {
+            name: artist.name,
+            sort_name: artist.sort_name,
+            artist_type: artist.artist_type,
+            country: artist.country,
+            begin: life_span.begin,
+            end: life_span.end,
+            overview: artist.disambiguation.filter(|d| !d.is_empty()),
+            genres: artist.tags.into_iter().map(|tag| tag.name).collect(),
+            images: Vec::new(), // MusicBrainz ne fournit pas d'images
+            external_ids: ExternalIds {
+                musicbrainz: Some(artist.id),
+                ..Default::default()
+            },
         }
-    }
+    }))
 }
 
 // --- FONCTIONS EXPORTÉES (Le pont vers l'application) ---
-// C'est ce que l'EXE charge. J'ai ajouté name/version pour être complet.
-
-fn to_c_string(s: String) -> *mut c_char {
-    CString::new(s).unwrap_or_default().into_raw()
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn name() -> *mut c_char {
-    to_c_string("MusicBrainz".to_string())
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn version() -> *mut c_char {
-    to_c_string("1.0.0".to_string())
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn plugin_type() -> *mut c_char {
-    // Sécurité anti-crash
-    let result = panic::catch_unwind(|| {
-        let p = MusicBrainzMetadata;
-        p.plugin_type()
-    });
-    match result {
-        Ok(s) => to_c_string(s),
-        Err(_) => to_c_string("error".to_string()),
-    }
-}
-
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn metadata(name_ptr: *const c_char) -> *mut c_char {
-    // 🛡️ FILET DE SÉCURITÉ CRITIQUE
-    // Empêche le plugin de faire crasher toute l'application en cas de panique
-    let result = panic::catch_unwind(|| {
-        let artist_name = unsafe {
-            if name_ptr.is_null() {
-                "Inconnu"
-            } else {
-                CStr::from_ptr(name_ptr).to_str().unwrap_or("Inconnu")
-            }
-        };
-
-        let plugin = MusicBrainzMetadata;
-        plugin.metadata(artist_name)
-    });
-
-    match result {
-        Ok(s) => to_c_string(s),
-        Err(_) => to_c_string("💥 ERREUR INTERNE DU PLUGIN".to_string()),
-    }
-}
-
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn free_string(s: *mut c_char) {
-    unsafe {
-        if !s.is_null() {
-            let _ = CString::from_raw(s);
-        }
-    }
-}
+// Descripteur, metadata et free_string générés par plugin_api, paniques comprises
+plugin_api::declare_plugin!(MusicBrainzMetadata);
@@ -0,0 +1,331 @@
+/*
+Plugin used by the tests of the host (EpiKodi/src/plugin/plugin_manager.rs).
+The exports are written by hand instead of with declare_plugin! to return what a broken plugin
+could: a null pointer, bytes that are not UTF-8, a call that takes too long or a crash.
+Every string handed to the host is counted until it comes back through free_string, so the tests
+can check that nothing leaks. Its settings: a prefix added to the artist names it finds, and a
+token that is refused when it is "refused".
+The other capabilities answer made-up lyrics, subtitles and artwork from the query, and count
+the scrobbles they get. It subscribes to every host event but the progress, counts the ones it
+gets and takes SLOW_CALL on the events about a media titled SLOW_QUERY. The events about a media
+titled BLOCKED_QUERY wait until the host test calls test_plugin_release.
+Its version is TEST_PLUGIN_VERSION at build time, the tests build another one to swap it in.
+*/
+
+use plugin_api::abi::{OwnedDescriptor, PluginDescriptor, PluginManifest, capabilities};
+use plugin_api::events::{HostEvent, PluginEvent, kinds};
+use plugin_api::ffi;
+use plugin_api::metadata::{ArtistMetadata, Metadata, MetadataImage, MetadataResponse};
+use plugin_api::providers::{
+    ArtworkQuery, Lyrics, LyricsQuery, ScrobbleEvent, SubtitleDownload, SubtitleFile,
+    SubtitleQuery, SubtitleResult,
+};
+use plugin_api::settings::{PluginSettings, SettingField};
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::Duration;
+
+// Queries with a special answer, any other query is found as an artist of that name
+pub const NULL_QUERY: &str = "null answer";
+pub const NOT_UTF8_QUERY: &str = "not utf8";
+pub const NOT_FOUND_QUERY: &str = "not found";
+pub const SLOW_QUERY: &str = "slow"; // answers null after SLOW_CALL
+pub const CRASH_QUERY: &str = "crash"; // aborts the process, only asked out of process
+pub const PRINT_QUERY: &str = "print"; // prints half a line on stdout, then found
+pub const BLOCKED_QUERY: &str = "blocked"; // on_event only, waits for test_plugin_release
+
+pub const SLOW_CALL: Duration = Duration::from_secs(2);
+
+pub const REFUSED_TOKEN: &str = "refused";
+
+static LIVE_STRINGS: AtomicUsize = AtomicUsize::new(0);
+static SCROBBLES: AtomicUsize = AtomicUsize::new(0);
+static EVENTS: AtomicUsize = AtomicUsize::new(0);
+static PREFIX: Mutex<String> = Mutex::new(String::new());
+// on_event calls waiting for BLOCKED_QUERY, and whether they may go on
+static BLOCKED: Mutex<(usize, bool)> = Mutex::new((0, false));
+static BLOCKED_CHANGED: Condvar = Condvar::new();
+
+fn give(bytes: Vec<u8>) -> *mut c_char {
+    LIVE_STRINGS.fetch_add(1, Ordering::SeqCst);
+    CString::new(bytes).unwrap_or_default().into_raw()
+}
+
+// Answer built by plugin_api::ffi, handed over as a counted string
+fn counted(answer: *mut c_char) -> *mut c_char {
+    give(unsafe { CString::from_raw(answer) }.into_bytes())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn epikodi_plugin_descriptor() -> *const PluginDescriptor {
+    static DESCRIPTOR: OnceLock<OwnedDescriptor> = OnceLock::new();
+    DESCRIPTOR
+        .get_or_init(|| {
+            OwnedDescriptor::new(&PluginManifest {
+                name: "Test".to_string(),
+                version: option_env!("TEST_PLUGIN_VERSION")
+                    .unwrap_or("0.1.0")
+                    .to_string(),
+                author: "EpiKodi".to_string(),
+                capabilities: vec![
+                    capabilities::ARTIST_METADATA.to_string(),
+                    capabilities::MOVIE_METADATA.to_string(),
+                    capabilities::LYRICS.to_string(),
+                    capabilities::SUBTITLES.to_string(),
+                    capabilities::ARTWORK.to_string(),
+                    capabilities::SCROBBLER.to_string(),
+                    capabilities::EVENTS.to_string(),
+                ],
+            })
+        })
+        .as_ptr()
+}
+
+/// # Safety
+/// `query` must be null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn metadata(query: *const c_char) -> *mut c_char {
+    if query.is_null() {
+        return std::ptr::null_mut();
+    }
+    let query = unsafe { CStr::from_ptr(query) }.to_string_lossy();
+    match query.as_ref() {
+        NULL_QUERY => std::ptr::null_mut(),
+        NOT_UTF8_QUERY => give(vec![0xff, 0xfe, b'!']),
+        NOT_FOUND_QUERY => give(MetadataResponse::not_found().to_json().into_bytes()),
+        SLOW_QUERY => {
+            std::thread::sleep(SLOW_CALL);
+            std::ptr::null_mut()
+        }
+        CRASH_QUERY => std::process::abort(),
+        PRINT_QUERY => {
+            use std::io::Write;
+            print!("{{\"type\": ");
+            let _ = std::io::stdout().flush();
+            give(
+                MetadataResponse::found(Metadata::Artist(ArtistMetadata {
+                    name: PRINT_QUERY.to_string(),
+                    ..Default::default()
+                }))
+                .to_json()
+                .into_bytes(),
+            )
+        }
+        name => {
+            let artist = ArtistMetadata {
+                name: format!("{}{}", PREFIX.lock().unwrap(), name),
+                ..Default::default()
+            };
+            give(
+                MetadataResponse::found(Metadata::Artist(artist))
+                    .to_json()
+                    .into_bytes(),
+            )
+        }
+    }
+}
+
+/// # Safety
+/// `request` must be null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lyrics(request: *const c_char) -> *mut c_char {
+    counted(unsafe {
+        ffi::provide(request, |query: &LyricsQuery| match query.title.as_str() {
+            NOT_FOUND_QUERY => Ok(None),
+            title => Ok(Some(Lyrics {
+                plain: Some(format!("{} - {}", query.artist, title)),
+                ..Default::default()
+            })),
+        })
+    })
+}
+
+/// # Safety
+/// `request` must be null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn search_subtitles(request: *const c_char) -> *mut c_char {
+    counted(unsafe {
+        ffi::provide(request, |query: &SubtitleQuery| {
+            if query.title == NOT_FOUND_QUERY {
+                return Ok(None);
+            }
+            let results: Vec<SubtitleResult> = ["fr", "en"]
+                .into_iter()
+                .map(|language| SubtitleResult {
+                    id: format!("{}.{}", query.title, language),
+                    language: language.to_string(),
+                    name: query.title.clone(),
+                    format: "srt".to_string(),
+                    downloads: None,
+                })
+                .collect();
+            Ok(Some(results))
+        })
+    })
+}
+
+/// # Safety
+/// `request` must be null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn download_subtitle(request: *const c_char) -> *mut c_char {
+    counted(unsafe {
+        ffi::provide(request, |download: &SubtitleDownload| {
+            let Some((_, language)) = download.id.rsplit_once('.') else {
+                return Ok(None);
+            };
+            Ok(Some(SubtitleFile {
+                language: language.to_string(),
+                format: "srt".to_string(),
+                content: format!("1\n00:00:01,000 --> 00:00:02,000\n{}\n", download.id),
+            }))
+        })
+    })
+}
+
+/// # Safety
+/// `request` must be null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn artwork(request: *const c_char) -> *mut c_char {
+    counted(unsafe {
+        ffi::provide(request, |query: &ArtworkQuery| {
+            Ok(Some(vec![MetadataImage {
+                kind: "poster".to_string(),
+                url: format!("https://example.org/{}.jpg", query.title),
+            }]))
+        })
+    })
+}
+
+/// # Safety
+/// `request` must be null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn scrobble(request: *const c_char) -> *mut c_char {
+    counted(unsafe {
+        ffi::provide(request, |_: &ScrobbleEvent| {
+            SCROBBLES.fetch_add(1, Ordering::SeqCst);
+            Ok(Some(()))
+        })
+    })
+}
+
+/// # Safety
+/// `request` must be null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn subscriptions(request: *const c_char) -> *mut c_char {
+    counted(unsafe {
+        ffi::provide(request, |_: &serde_json::Value| {
+            let subscribed = kinds::ALL
+                .into_iter()
+                .filter(|&kind| kind != kinds::MEDIA_PROGRESS);
+            Ok(Some(subscribed.map(str::to_string).collect::<Vec<_>>()))
+        })
+    })
+}
+
+/// # Safety
+/// `request` must be null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn on_event(request: *const c_char) -> *mut c_char {
+    counted(unsafe {
+        ffi::provide(request, |event: &PluginEvent| {
+            if let HostEvent::MediaStarted { media, .. } = &event.event
+                && media.title.as_deref() == Some(SLOW_QUERY)
+            {
+                std::thread::sleep(SLOW_CALL);
+            }
+            if let HostEvent::MediaStarted { media, .. } = &event.event
+                && media.title.as_deref() == Some(BLOCKED_QUERY)
+            {
+                let mut blocked = BLOCKED.lock().unwrap();
+                blocked.0 += 1;
+                BLOCKED_CHANGED.notify_all();
+                while !blocked.1 {
+                    blocked = BLOCKED_CHANGED.wait(blocked).unwrap();
+                }
+                blocked.0 -= 1;
+            }
+            EVENTS.fetch_add(1, Ordering::SeqCst);
+            Ok(Some(()))
+        })
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn settings_schema() -> *mut c_char {
+    let schema = vec![
+        SettingField::string("prefix", "Prefix"),
+        SettingField::secret("token", "Token"),
+    ];
+    give(serde_json::to_vec(&schema).unwrap_or_default())
+}
+
+/// # Safety
+/// `settings` must be null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn configure(settings: *const c_char) -> *mut c_char {
+    if settings.is_null() {
+        return give(b"null settings".to_vec());
+    }
+    let text = unsafe { CStr::from_ptr(settings) }.to_string_lossy();
+    let Ok(settings) = PluginSettings::from_json(&text) else {
+        return give(b"invalid settings".to_vec());
+    };
+    if settings.text("token") == Some(REFUSED_TOKEN) {
+        return give(b"token refused".to_vec());
+    }
+    *PREFIX.lock().unwrap() = settings.text("prefix").unwrap_or_default().to_string();
+    std::ptr::null_mut()
+}
+
+/// # Safety
+/// `s` must be null or a string returned by this library, freed once.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free_string(s: *mut c_char) {
+    if !s.is_null() {
+        LIVE_STRINGS.fetch_sub(1, Ordering::SeqCst);
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+// Scrobble events received since the library was loaded
+#[unsafe(no_mangle)]
+pub extern "C" fn test_plugin_scrobbles() -> usize {
+    SCROBBLES.load(Ordering::SeqCst)
+}
+
+// Host events received since the library was loaded
+#[unsafe(no_mangle)]
+pub extern "C" fn test_plugin_events() -> usize {
+    EVENTS.load(Ordering::SeqCst)
+}
+
+// Waits until an on_event call is blocked, returns how many are
+#[unsafe(no_mangle)]
+pub extern "C" fn test_plugin_wait_blocked() -> usize {
+    let mut blocked = BLOCKED.lock().unwrap();
+    while blocked.0 == 0 {
+        blocked = BLOCKED_CHANGED.wait(blocked).unwrap();
+    }
+    blocked.0
+}
+
+// Lets every blocked on_event call end, and the next ones go through
+#[unsafe(no_mangle)]
+pub extern "C" fn test_plugin_release() {
+    BLOCKED.lock().unwrap().1 = true;
+    BLOCKED_CHANGED.notify_all();
+}
+
+// SLOW_CALL for the host tests waiting for a slow call to end
+#[unsafe(no_mangle)]
+pub extern "C" fn test_plugin_slow_call_ms() -> u64 {
+    SLOW_CALL.as_millis() as u64
+}
+
+// Strings given to the host and not freed yet
+#[unsafe(no_mangle)]
+pub extern "C" fn test_plugin_live_strings() -> usize {
+    LIVE_STRINGS.load(Ordering::SeqCst)
+}
@@ -1,14 +1,17 @@
+use plugin_api::abi::capabilities;
+use plugin_api::metadata::{ExternalIds, Metadata, MetadataImage, MetadataResponse, MovieMetadata};
+use plugin_api::settings::{PluginSettings, SettingField, SettingValue};
 use plugin_api::Plugin;
 use reqwest;
 use serde::{Deserialize, Serialize};
 use std::env;
-use std::ffi::{CStr, CString};
-use std::os::raw::c_char;
+use std::sync::RwLock;
+use std::time::Duration;
 
 // Structs to deserialize the JSON response from TMDb
 #[derive(Debug, Serialize, Deserialize)]
 struct Movie {
-    id: u32,
+    id: u64,
     title: String,
     #[serde(rename = "release_date")]
     release_date: String,
@@ -25,28 +28,60 @@ struct SearchResponse {
     total_results: u32,
 }
 
-#[derive(Deserialize, Debug)]
-struct ArtistSearchResult {
-    artists: Vec<Artist>,
+// /movie/{id} with append_to_response=external_ids
+#[derive(Debug, Deserialize)]
+struct MovieDetails {
+    id: u64,
+    title: String,
+    original_title: Option<String>,
+    release_date: Option<String>,
+    overview: Option<String>,
+    #[serde(default)]
+    genres: Vec<Genre>,
+    vote_average: Option<f32>,
+    vote_count: Option<u32>,
+    runtime: Option<u32>,
+    poster_path: Option<String>,
+    backdrop_path: Option<String>,
+    imdb_id: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
-struct Artist {
+#[derive(Debug, Deserialize)]
+struct Genre {
     name: String,
-    #[serde(rename = "type")]
-    artist_type: Option<String>,
-    country: Option<String>,
-    #[serde(rename = "life-span")]
-    life_span: Option<LifeSpan>,
 }
 
-#[derive(Deserialize, Debug)]
-struct LifeSpan {
-    begin: Option<String>,
-    end: Option<String>,
+const IMAGE_BASE_URL: &str = "https://image.tmdb.org/t/p/original";
+
+// langues proposées dans les réglages, la première est celle par défaut
+const LANGUAGES: [&str; 7] = [
+    "fr-FR", "en-US", "es-ES", "de-DE", "it-IT", "pt-BR", "ja-JP",
+];
+// utilisée quand aucune clé n'est enregistrée dans NeoKodi
+const API_KEY_ENV: &str = "TMDB_API_KEY";
+
+#[derive(Default)]
+struct TMDBMetadata {
+    settings: RwLock<PluginSettings>, // donnés par NeoKodi avec configure()
 }
 
-struct TMDBMetadata;
+impl TMDBMetadata {
+    fn api_key(&self) -> Option<String> {
+        let settings = self.settings.read().unwrap();
+        match settings.text("api_key") {
+            Some(key) => Some(key.to_string()),
+            None => env::var(API_KEY_ENV).ok().filter(|key| !key.is_empty()),
+        }
+    }
+
+    fn language(&self) -> String {
+        let settings = self.settings.read().unwrap();
+        settings
+            .text("language")
+            .unwrap_or(LANGUAGES[0])
+            .to_string()
+    }
+}
 
 impl Plugin for TMDBMetadata {
     fn name(&self) -> String {
@@ -57,76 +92,153 @@ impl Plugin for TMDBMetadata {
         "1.0.0".to_string()
     }
 
-    fn plugin_type(&self) -> String {
-        "film_metadata".to_string()
+    fn author(&self) -> String {
+        "EpiKodi".to_string()
+    }
+
+    fn capabilities(&self) -> Vec<String> {
+        vec![capabilities::MOVIE_METADATA.to_string()]
+    }
+
+    fn settings_schema(&self) -> Vec<SettingField> {
+        vec![
+            SettingField::secret("api_key", "Clé API TMDB")
+                .with_description("https://www.themoviedb.org/settings/api"),
+            SettingField::choice("language", "Langue des fiches", &LANGUAGES)
+                .with_default(SettingValue::Text(LANGUAGES[0].to_string())),
+        ]
+    }
+
+    fn configure(&self, settings: &PluginSettings) -> Result<(), String> {
+        *self.settings.write().unwrap() = settings.clone();
+        match self.api_key() {
+            Some(_) => Ok(()),
+            None => Err(format!(
+                "clé API TMDB manquante (ou variable {})",
+                API_KEY_ENV
+            )),
+        }
     }
 
     fn metadata(&self, film_name: &str) -> String {
-        match search_film(film_name) {
-            Ok(info) => info,
-            Err(e) => format!("Error searching for '{}': {}", film_name, e),
+        let Some(api_key) = self.api_key() else {
+            return MetadataResponse::error("TMDB API key is not set").to_json();
+        };
+        let response = match search_film(film_name, &api_key, &self.language()) {
+            Ok(Some(movie)) => MetadataResponse::found(Metadata::Movie(movie)),
+            Ok(None) => MetadataResponse::not_found(),
+            Err(e) => {
+                MetadataResponse::error(format!("Error searching for '{}': {}", film_name, e))
+            }
+        };
+        response.to_json()
+    }
+}
+
+// EpiKodi sends "Title (Year)" when the year is known, TMDB wants it apart
+fn split_year(name: &str) -> (&str, Option<&str>) {
+    let trimmed = name.trim_end();
+    if let Some(rest) = trimmed.strip_suffix(')') {
+        if let Some((title, year)) = rest.rsplit_once(" (") {
+            if year.len() == 4 && year.chars().all(|c| c.is_ascii_digit()) {
+                return (title.trim(), Some(year));
+            }
         }
     }
+    (trimmed, None)
 }
 
-fn search_film(name: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let movie_query = name;
-    // Vérifie bien que cette clé est valide !
-    let api_key = "94de72cdcd31f017c82bc13fd54979b0";
+fn search_film(
+    name: &str,
+    api_key: &str,
+    language: &str,
+) -> Result<Option<MovieMetadata>, Box<dyn std::error::Error>> {
+    let (movie_query, year) = split_year(name);
 
     println!("🔍 [TMDB DLL] Recherche lancée pour : '{}'", movie_query);
 
-    let url = format!(
-        "https://api.themoviedb.org/3/search/movie?api_key={}&query={}&language=fr-FR", // J'ai ajouté language=fr-FR pour toi ;)
+    let mut url = format!(
+        "https://api.themoviedb.org/3/search/movie?api_key={}&query={}&language={}",
         api_key,
-        urlencoding::encode(&movie_query)
+        urlencoding::encode(&movie_query),
+        language
     );
+    if let Some(year) = year {
+        url.push_str(&format!("&year={}", year));
+    }
 
-    println!("🌍 [TMDB DLL] URL appelée : {}", url); // Attention, ça affiche ta clé API dans la console
-
-    let response = reqwest::blocking::get(&url)?;
+    // sans timeout un serveur qui ne répond pas bloque l'appel du plugin
+    let client = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let response = client.get(&url).send()?;
 
     let status = response.status();
     println!("📡 [TMDB DLL] Statut HTTP : {}", status);
 
     if !status.is_success() {
-        println!("❌ [TMDB DLL] Erreur API !");
-        return Ok(format!("Erreur API TMDB: {}", status));
+        return Err(format!("Erreur API TMDB: {}", status).into());
     }
 
-    let raw_body = response.text()?;
-    println!("📦 [TMDB DLL] Réponse brute : {}", raw_body);
+    let search_results: SearchResponse = response.json()?;
+    let Some(movie) = search_results.results.first() else {
+        println!("⚠️ [TMDB DLL] Aucun résultat trouvé dans le JSON.");
+        return Ok(None);
+    };
+    println!(
+        "✅ [TMDB DLL] {} films trouvés.",
+        search_results.results.len()
+    );
 
-    // On re-parse le JSON depuis la string
-    let search_results: SearchResponse = serde_json::from_str(&raw_body)?;
+    // la recherche ne donne ni les genres ni la durée, on lit la fiche du premier film
+    let url = format!(
+        "https://api.themoviedb.org/3/movie/{}?api_key={}&language={}&append_to_response=external_ids",
+        movie.id, api_key, language
+    );
+    let response = client.get(&url).send()?.error_for_status()?;
+    let details: MovieDetails = response.json()?;
+    Ok(Some(to_metadata(details)))
+}
 
-    if search_results.results.is_empty() {
-        println!("⚠️ [TMDB DLL] Aucun résultat trouvé dans le JSON.");
-        Ok(format!("Aucun film trouvé pour '{}'", movie_query))
-    } else {
-        println!(
-            "✅ [TMDB DLL] {} films trouvés.",
-            search_results.results.len()
-        );
-
-        let mut result = String::new();
-        for (index, movie) in search_results.results.iter().take(1).enumerate() {
-            result.push_str(&format!(
-                "🎬 {} ({})\n",
-                movie.title,
-                movie.release_date.split('-').next().unwrap_or("????")
-            ));
-            result.push_str(&format!("⭐ Note: {}/10\n", movie.vote_average));
-
-            // Tu peux aussi afficher un synopsis un peu plus long si tu n'as qu'un seul film
-            let overview = if movie.overview.len() > 500 {
-                format!("{}...", &movie.overview[0..500])
-            } else {
-                movie.overview.clone()
-            };
-            result.push_str(&format!("📝 {}\n", overview));
+fn to_metadata(details: MovieDetails) -> MovieMetadata {
+    let release_date = details.release_date.filter(|date| !date.is_empty());
+    let year = release_date
+        .as_deref()
+        .and_then(|date| date.split('-').next())
+        .and_then(|year| year.parse().ok());
+
+    let mut images = Vec::new();
+    for (kind, path) in [
+        ("poster", details.poster_path),
+        ("fanart", details.backdrop_path),
+    ] {
+        if let Some(path) = path {
+            images.push(MetadataImage {
+                kind: kind.to_string(),
+                url: format!("{}{}", IMAGE_BASE_URL, path),
+            });
         }
-        Ok(result)
+    }
+
+    MovieMetadata {
+        title: details.title,
+        original_title: details.original_title,
+        year,
+        release_date,
+        overview: details.overview.filter(|overview| !overview.is_empty()),
+        genres: details.genres.into_iter().map(|genre| genre.name).collect(),
+        // TMDB renvoie 0 quand personne n'a voté
+        rating: details
+            .vote_average
+            .filter(|_| details.vote_count.unwrap_or(0) > 0),
+        vote_count: details.vote_count,
+        runtime: details.runtime.filter(|minutes| *minutes > 0),
+        images,
+        external_ids: ExternalIds {
+            tmdb: Some(details.id),
+            imdb: details.imdb_id.filter(|id| !id.is_empty()),
+            ..Default::default()
+        },
     }
 }
 
@@ -138,61 +250,8 @@ fn search_film(name: &str) -> Result<String, Box<dyn std::error::Error>> {
     Ok(())
 }*/
 
-// Export a C-compatible function that can be called via libloading
-#[unsafe(no_mangle)]
-pub extern "C" fn name() -> *mut c_char {
-    let metadata = TMDBMetadata;
-    let name = metadata.name();
-
-    CString::new(name).unwrap().into_raw()
-}
-
-// Export a C-compatible function that can be called via libloading
-#[unsafe(no_mangle)]
-pub extern "C" fn version() -> *mut c_char {
-    let metadata = TMDBMetadata;
-    let version = metadata.version();
-
-    CString::new(version).unwrap().into_raw()
-}
-
-// Export a C-compatible function that can be called via libloading
-#[unsafe(no_mangle)]
-pub extern "C" fn plugin_type() -> *mut c_char {
-    let metadata = TMDBMetadata;
-    let plugin_type = metadata.plugin_type();
-
-    CString::new(plugin_type).unwrap().into_raw()
-}
-
-// Export a C-compatible function that can be called via libloading
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn metadata(name_ptr: *const c_char) -> *mut c_char {
-    // Safety: We assume the caller passes a valid C string
-    let film_name = unsafe {
-        if name_ptr.is_null() {
-            "invalid name"
-        } else {
-            CStr::from_ptr(name_ptr).to_str().unwrap_or("invalid name")
-        }
-    };
-
-    let metadata = TMDBMetadata;
-    let result = metadata.metadata(film_name);
-
-    // Convert result to C string
-    CString::new(result).unwrap().into_raw()
-}
-
-// Helper function to free strings allocated by the plugin
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn free_string(s: *mut c_char) {
-    unsafe {
-        if !s.is_null() {
-            let _ = CString::from_raw(s);
-        }
-    }
-}
+// Exported symbols (descriptor, metadata, free_string) generated by plugin_api
+plugin_api::declare_plugin!(TMDBMetadata);
 
 /*#[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {